@@ -1,9 +1,22 @@
 use anyhow::{bail, Result};
+use embedded_hal_bus::i2c::MutexDevice;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::gpio::PinDriver;
+use esp_idf_svc::hal::i2c::{config, I2cDriver};
 use esp_idf_svc::hal::prelude::Peripherals;
 use log::info;
-use sensesp::rgbled::{RGB8, WS2812RMT};
+use sensesp::application::Application;
+use sensesp::bilge::{BilgeLimits, BilgeMonitor};
+use sensesp::digital::{DigitalInputState, InterruptInput};
+use sensesp::i2c::provision::{detect, provision};
+use sensesp::i2c::scan::{scan, Probe};
+use sensesp::rgbled::WS2812RMT;
+use sensesp::sensor::Attachable;
+use sensesp::signalk::SignalKOutput;
+use sensesp::status::{StatusIndicator, SystemState};
 use sensesp::wifi::wifi;
+use std::sync::Mutex;
+use std::time::Duration;
 use toml_cfg::toml_config;
 
 #[derive(Debug)]
@@ -13,6 +26,8 @@ pub struct Config {
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    #[default(false)]
+    auto_discover: bool,
 }
 
 fn main() -> Result<()> {
@@ -28,9 +43,9 @@ fn main() -> Result<()> {
 
     log::info!("Hello, world!");
 
-    // Start the LED off yellow
-    let mut led = WS2812RMT::new(peripherals.pins.gpio2, peripherals.rmt.channel0)?;
-    led.set_pixel(RGB8::new(50, 50, 0))?;
+    // The status LED starts off showing the booting state
+    let led = WS2812RMT::new(peripherals.pins.gpio2, peripherals.rmt.channel0)?;
+    let status = StatusIndicator::spawn(led, Duration::from_millis(50))?;
 
     // The constant `CONFIG` is auto-generated by `toml_config`.
     let app_config = CONFIG;
@@ -38,6 +53,7 @@ fn main() -> Result<()> {
     log::info!("{:#?}", CONFIG);
 
     // Connect to the Wi-Fi network
+    status.set(SystemState::WifiConnecting);
     let _wifi = match wifi(
         app_config.wifi_ssid,
         app_config.wifi_psk,
@@ -46,22 +62,42 @@ fn main() -> Result<()> {
     ) {
         Ok(inner) => inner,
         Err(err) => {
-            // Red!
-            led.set_pixel(RGB8::new(50, 0, 0))?;
+            status.finish(SystemState::Error);
             bail!("Could not connect to Wi-Fi network: {:?}", err)
         }
     };
 
-    loop {
-        // Blue!
-        led.set_pixel(RGB8::new(0, 0, 50))?;
-        // Wait...
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        info!("Hello, world!");
+    status.set(SystemState::WifiConnected);
+
+    let output = SignalKOutput::new();
 
-        // Green!
-        led.set_pixel(RGB8::new(0, 50, 0))?;
-        // Wait...
-        std::thread::sleep(std::time::Duration::from_secs(1));
+    // The bilge float switch pulls the pin low while the pump runs
+    let float_switch = InterruptInput::new(PinDriver::input(peripherals.pins.gpio18)?)?;
+    let mut pump = DigitalInputState::new(float_switch, true, Some(Duration::from_secs(10)));
+    let bilge = BilgeMonitor::new(&output, "main", pump.attach(), BilgeLimits::default());
+    let mut app = Application::new().register(pump).register(bilge);
+
+    // Start drivers for any known sensors plugged into the I2C bus
+    if app_config.auto_discover {
+        let i2c = I2cDriver::new(
+            peripherals.i2c0,
+            peripherals.pins.gpio21,
+            peripherals.pins.gpio22,
+            &config::Config::default(),
+        )?;
+        // Every driver holds a handle to the bus for the life of the program
+        let bus: &'static Mutex<I2cDriver<'static>> = Box::leak(Box::new(Mutex::new(i2c)));
+        let found = scan(&mut *bus.lock().unwrap(), Probe::Auto, true);
+        let detected = detect(&mut *bus.lock().unwrap(), &found);
+        info!("Provisioning {:?}", detected);
+        app = provision(app, &output, &detected, || MutexDevice::new(bus));
+    }
+
+    loop {
+        app.tick();
+        for update in output.take_pending() {
+            info!("{} = {}", update.path, update.value);
+        }
+        std::thread::sleep(Duration::from_millis(10));
     }
 }
@@ -2,15 +2,17 @@ use anyhow::{bail, Result};
 use esp_idf_hal::gpio::PinDriver;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::prelude::Peripherals;
+use sensesp::i2c::bus::{DeviceSettings, SharedBus};
+use sensesp::i2c::health::{EspBusRecovery, MonitoredBus};
 use sensesp::i2c::I2CDisplayInterface;
+use sensesp::sensor::{poll_update, Attachable, Updates};
 use sensesp::wifi::wifi;
 use toml_cfg::toml_config;
 
 use esp_idf_svc::hal::i2c::config;
-use esp_idf_svc::hal::i2c::I2cDriver;
 
-use core::cell::RefCell;
-use embedded_hal_bus::i2c as i2c_bus;
+use std::task::Poll;
+use std::time::Duration;
 
 use embedded_graphics::{
     mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
@@ -56,19 +58,31 @@ fn main() -> Result<()> {
         ;
     log::info!("{:?}", &config);
 
-    // Initialize I2C driver
-    let i2c = I2cDriver::new(peripherals.i2c1, sda, scl, &config)?;
+    // Initialize I2C driver, restarting it whenever the bus locks up
+    let recovery = EspBusRecovery::new(
+        peripherals.i2c1,
+        sda.into(),
+        scl.into(),
+        config,
+        Duration::from_millis(100),
+    );
+    let mut i2c = MonitoredBus::new(recovery)?;
+    let mut bus_stats = Updates::new(i2c.attach());
 
     log::info!("Creating I2C bus to share wire with multiple devices");
-    let i2c_ref_cell = RefCell::new(i2c);
+    let bus = SharedBus::new(i2c);
+    // The displays run in fast mode, any other device on the bus keeps the 100 kHz default
+    let display_settings = DeviceSettings::new()
+        .frequency(400_000)
+        .timeout(Duration::from_millis(100));
 
     log::info!("Creating Display interface...");
-    let interface = I2CDisplayInterface::new(i2c_bus::RefCellDevice::new(&i2c_ref_cell));
+    let interface = I2CDisplayInterface::new(bus.device_with(display_settings));
     let mut display1 = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
         .into_buffered_graphics_mode();
     display1.init().unwrap();
 
-    let interface = I2CDisplayInterface::new(i2c_bus::RefCellDevice::new(&i2c_ref_cell));
+    let interface = I2CDisplayInterface::new(bus.device_with(display_settings));
     let mut display2 = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
         .into_buffered_graphics_mode();
     display2.init().unwrap();
@@ -107,6 +121,15 @@ fn main() -> Result<()> {
     };
 
     loop {
+        if let Poll::Ready(Some(stats)) = poll_update(&mut bus_stats) {
+            log::warn!(
+                "I2C errors: {} NACKs, {} bus errors, {} recoveries",
+                stats.nacks(),
+                stats.errors(),
+                stats.recoveries
+            );
+        }
+
         match display1.clear(BinaryColor::Off) {
             Ok(_) => (),
             Err(e) => bail!("Fail clearing display: {:?}", e),
@@ -1,9 +1,11 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
 use esp_idf_hal::gpio::PinDriver;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::prelude::Peripherals;
+use eyeball::shared::Observable;
+use sensesp::dashboard::{Dashboard, Page, Widget};
 use sensesp::wifi::wifi;
 use toml_cfg::toml_config;
 
@@ -101,27 +103,29 @@ fn main() -> Result<()> {
         }
     };
 
+    let counter = Observable::new(0.0f32);
+    let mut dashboard = Dashboard::new()
+        .page(
+            Page::new()
+                .widget(Widget::value("Loop", "", counter.subscribe()).decimals(0))
+                .widget(Widget::bar("Cycle", 0.0, 9.0, counter.subscribe()))
+                .widget(Widget::sparkline("History", 32, counter.subscribe())),
+        )
+        .page(Page::new().widget(Widget::big_number("Loop", "", counter.subscribe()).decimals(0)))
+        .rotate(Duration::from_secs(10));
+
     let mut i = 0;
+    let start = Instant::now();
     std::thread::sleep(Duration::from_secs(5));
     loop {
-        match display.clear(BinaryColor::Off) {
-            Ok(_) => (),
-            Err(e) => bail!("Fail clearing display: {:?}", e),
-        };
-
-        Text::with_baseline("Hello world!", Point::zero(), text_style, Baseline::Top)
-            .draw(&mut display)
-            .unwrap();
-        Text::with_baseline(
-            &format!("Loop: {}", &i),
-            Point::new(0, 16),
-            text_style,
-            Baseline::Top,
-        )
-        .draw(&mut display)
-        .unwrap();
+        counter.set((i % 10) as f32);
 
-        display.flush().unwrap();
+        // Only redraw and flush when a widget changed or the page rotated
+        match dashboard.refresh(&mut display, start.elapsed()) {
+            Ok(true) => display.flush().unwrap(),
+            Ok(false) => (),
+            Err(e) => bail!("Fail drawing dashboard: {:?}", e),
+        };
 
         i += 1;
 
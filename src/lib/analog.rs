@@ -0,0 +1,216 @@
+//! Analog input sensors backed by the ESP32 ADC
+use crate::sensor::{Attachable, SensESPSensor};
+#[cfg(target_os = "espidf")]
+use core::borrow::Borrow;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::adc::{
+    attenuation,
+    oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+    ADCPin,
+};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::sys::{adc_atten_t, EspError};
+use eyeball::{shared::Observable, Subscriber};
+use std::time::{Duration, SystemTime};
+
+/// A source of calibrated ADC readings in millivolts.
+pub trait AdcReader {
+    type Error: core::fmt::Debug;
+
+    fn read_millivolts(&mut self) -> Result<u16, Self::Error>;
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d, T, M> AdcReader for AdcChannelDriver<'d, T, M>
+where
+    T: ADCPin,
+    M: Borrow<AdcDriver<'d, T::Adc>>,
+{
+    type Error = EspError;
+
+    fn read_millivolts(&mut self) -> Result<u16, Self::Error> {
+        // With calibration enabled the driver applies the eFuse curve and returns millivolts
+        self.read()
+    }
+}
+
+/// Input attenuation, which selects the measurable voltage range of the pin.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Attenuation {
+    /// Roughly 100 - 950 mV
+    DB0,
+    /// Roughly 100 - 1250 mV
+    DB2_5,
+    /// Roughly 150 - 1750 mV
+    DB6,
+    /// Roughly 150 - 2450 mV
+    DB11,
+}
+
+#[cfg(target_os = "espidf")]
+impl From<Attenuation> for adc_atten_t {
+    fn from(value: Attenuation) -> Self {
+        match value {
+            Attenuation::DB0 => attenuation::NONE,
+            Attenuation::DB2_5 => attenuation::DB_2_5,
+            Attenuation::DB6 => attenuation::DB_6,
+            Attenuation::DB11 => attenuation::DB_11,
+        }
+    }
+}
+
+/// Channel configuration with eFuse calibration enabled, so reads are reported in millivolts.
+#[cfg(target_os = "espidf")]
+pub fn channel_config(attenuation: Attenuation) -> AdcChannelConfig {
+    AdcChannelConfig {
+        attenuation: attenuation.into(),
+        calibration: true,
+        ..Default::default()
+    }
+}
+
+/// Take `samples` consecutive readings and return their mean in millivolts.
+pub fn oversample<A: AdcReader>(adc: &mut A, samples: u16) -> Result<f32, A::Error> {
+    let samples = samples.max(1);
+    let mut sum: u32 = 0;
+    for _ in 0..samples {
+        sum += adc.read_millivolts()? as u32;
+    }
+    Ok(sum as f32 / samples as f32)
+}
+
+/// Periodically samples an ADC channel and emits the averaged voltage in millivolts.
+pub struct AnalogInput<A: AdcReader> {
+    observable: Observable<f32>,
+    adc: A,
+    samples: u16,
+    duration: Duration,
+    last_measurement: SystemTime,
+}
+
+impl<A: AdcReader> AnalogInput<A> {
+    /// Create a new analog input averaging `samples` reads every `duration`.
+    pub fn new(adc: A, samples: u16, duration: Duration) -> Self {
+        let observable = Observable::new(0.0);
+        let last_measurement = SystemTime::now() - duration;
+        AnalogInput::<A> {
+            observable,
+            adc,
+            samples,
+            duration,
+            last_measurement,
+        }
+    }
+
+    /// Consume the sensor and return the underlying ADC channel
+    pub fn release(self) -> A {
+        self.adc
+    }
+}
+
+impl<A: AdcReader> Attachable<f32> for AnalogInput<A> {
+    fn attach(&mut self) -> Subscriber<f32> {
+        self.observable.subscribe()
+    }
+}
+
+impl<A: AdcReader> SensESPSensor for AnalogInput<A> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        match now.duration_since(self.last_measurement) {
+            Ok(d) => {
+                if d >= self.duration {
+                    match oversample(&mut self.adc, self.samples) {
+                        Ok(mv) => {
+                            self.observable.set(mv);
+                        }
+                        Err(e) => log::error!("ADC read error on SensESP-rs tick: {:?}", e),
+                    }
+                    self.last_measurement = now;
+                }
+            }
+            Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays a fixed list of readings, failing once they run out.
+    struct FakeAdc {
+        readings: Vec<u16>,
+        reads: usize,
+    }
+
+    impl FakeAdc {
+        fn new(readings: &[u16]) -> Self {
+            FakeAdc {
+                readings: readings.to_vec(),
+                reads: 0,
+            }
+        }
+    }
+
+    impl AdcReader for FakeAdc {
+        type Error = &'static str;
+
+        fn read_millivolts(&mut self) -> Result<u16, Self::Error> {
+            let reading = self.readings.get(self.reads).copied().ok_or("no reading");
+            self.reads += 1;
+            reading
+        }
+    }
+
+    #[test]
+    fn oversample_averages_readings() {
+        let mut adc = FakeAdc::new(&[1000, 1001, 1003, 1004]);
+        assert_eq!(oversample(&mut adc, 4), Ok(1002.0));
+        assert_eq!(adc.reads, 4);
+    }
+
+    #[test]
+    fn oversample_takes_at_least_one_reading() {
+        let mut adc = FakeAdc::new(&[3300]);
+        assert_eq!(oversample(&mut adc, 0), Ok(3300.0));
+        assert_eq!(adc.reads, 1);
+    }
+
+    #[test]
+    fn oversample_does_not_overflow() {
+        let mut adc = FakeAdc::new(&[u16::MAX; 1000]);
+        assert_eq!(oversample(&mut adc, 1000), Ok(u16::MAX as f32));
+    }
+
+    #[test]
+    fn oversample_stops_at_the_first_error() {
+        let mut adc = FakeAdc::new(&[1000, 1000]);
+        assert_eq!(oversample(&mut adc, 5), Err("no reading"));
+        assert_eq!(adc.reads, 3);
+    }
+
+    #[test]
+    fn analog_input_samples_once_per_duration() {
+        let adc = FakeAdc::new(&[500, 700, 900, 1100]);
+        let mut input = AnalogInput::new(adc, 2, Duration::from_secs(3600));
+        let subscriber = input.attach();
+
+        input.tick();
+        assert_eq!(subscriber.get(), 600.0);
+        input.tick();
+        assert_eq!(subscriber.get(), 600.0);
+        assert_eq!(input.release().reads, 2);
+    }
+
+    #[test]
+    fn analog_input_keeps_the_last_value_on_error() {
+        let adc = FakeAdc::new(&[500]);
+        let mut input = AnalogInput::new(adc, 2, Duration::ZERO);
+        let subscriber = input.attach();
+
+        input.tick();
+        assert_eq!(subscriber.get(), 0.0);
+    }
+}
@@ -21,3 +21,9 @@ impl Application {
         }
     }
 }
+
+impl Default for Application {
+    fn default() -> Self {
+        Self::new()
+    }
+}
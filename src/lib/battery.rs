@@ -0,0 +1,539 @@
+//! Battery state of charge from coulomb counting, corrected by resting voltage
+use crate::clock::{Clock, SystemClock};
+use crate::config::{self, ConfigStore};
+use crate::curve::Curve;
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, Update, Value};
+use eyeball::{shared::Observable, Subscriber};
+use std::task::Poll;
+use std::time::Duration;
+
+/// Minimum time between saves of the state of charge, sparing the flash a write per reading.
+pub const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// A battery's ratings and how its state of charge is tracked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatterySpec {
+    /// Capacity in Ah at the rated discharge time
+    pub capacity: f32,
+    /// Discharge time the capacity is rated at, usually 20 h
+    pub rated_hours: f32,
+    /// Peukert exponent, 1.0 for an ideal battery
+    pub peukert: f32,
+    /// Fraction of the charging current that ends up stored
+    pub charge_efficiency: f32,
+    /// Current in A, either way, below which the battery is resting
+    pub rest_current: f32,
+    /// Time at rest before the voltage is trusted as open circuit voltage
+    pub rest_time: Duration,
+    /// Open circuit voltage to state of charge
+    pub ocv: Curve,
+}
+
+impl BatterySpec {
+    /// A 12 V flooded lead acid battery of `capacity` Ah at 20 h.
+    pub fn new(capacity: f32) -> Self {
+        BatterySpec {
+            capacity,
+            rated_hours: 20.0,
+            peukert: 1.25,
+            charge_efficiency: 0.9,
+            rest_current: capacity / 200.0,
+            rest_time: Duration::from_secs(3600),
+            ocv: Curve::new()
+                .point(11.31, 0.0)
+                .point(11.51, 0.1)
+                .point(11.81, 0.3)
+                .point(12.10, 0.5)
+                .point(12.37, 0.7)
+                .point(12.62, 0.9)
+                .point(12.73, 1.0),
+        }
+    }
+
+    pub fn rated_hours(mut self, hours: f32) -> Self {
+        self.rated_hours = hours;
+        self
+    }
+
+    pub fn peukert(mut self, exponent: f32) -> Self {
+        self.peukert = exponent;
+        self
+    }
+
+    pub fn charge_efficiency(mut self, efficiency: f32) -> Self {
+        self.charge_efficiency = efficiency;
+        self
+    }
+
+    pub fn rest(mut self, current: f32, time: Duration) -> Self {
+        self.rest_current = current;
+        self.rest_time = time;
+        self
+    }
+
+    /// Replace the lead acid open circuit voltage curve, e.g. for lithium or 24 V banks.
+    pub fn ocv(mut self, curve: Curve) -> Self {
+        self.ocv = curve;
+        self
+    }
+
+    /// The discharge current that drains the battery in exactly the rated time.
+    fn rated_current(&self) -> f32 {
+        self.capacity / self.rated_hours
+    }
+
+    /// A discharge current scaled by Peukert's law to the rate it drains the rated capacity.
+    fn effective_current(&self, discharge: f32) -> f32 {
+        discharge * (discharge / self.rated_current()).powf(self.peukert - 1.0)
+    }
+}
+
+/// Tracks state of charge from current and voltage readings.
+///
+/// Current is positive while charging, as in Signal K.
+#[derive(Debug, Clone)]
+pub struct SocEstimator {
+    spec: BatterySpec,
+    soc: f32,
+    resting: Duration,
+}
+
+impl SocEstimator {
+    pub fn new(spec: BatterySpec, soc: f32) -> Self {
+        SocEstimator {
+            spec,
+            soc: soc.clamp(0.0, 1.0),
+            resting: Duration::ZERO,
+        }
+    }
+
+    pub fn spec(&self) -> &BatterySpec {
+        &self.spec
+    }
+
+    /// State of charge, 0 to 1
+    pub fn soc(&self) -> f32 {
+        self.soc
+    }
+
+    pub fn set_soc(&mut self, soc: f32) {
+        self.soc = soc.clamp(0.0, 1.0);
+    }
+
+    /// Whether the battery has rested long enough for its voltage to be trusted
+    pub fn is_rested(&self) -> bool {
+        self.resting >= self.spec.rest_time
+    }
+
+    /// Account for `current` flowing for `elapsed`, then resync to `voltage` if the battery is
+    /// rested. Returns the new state of charge.
+    ///
+    /// A current that isn't finite, from a failed reading, is ignored along with its interval.
+    /// A voltage that isn't finite only skips the resync.
+    pub fn update(&mut self, current: f32, voltage: f32, elapsed: Duration) -> f32 {
+        if !current.is_finite() {
+            return self.soc;
+        }
+        let hours = elapsed.as_secs_f32() / 3600.0;
+        let amp_hours = match current < 0.0 {
+            true => -self.spec.effective_current(-current) * hours,
+            false => current * self.spec.charge_efficiency * hours,
+        };
+        self.soc = (self.soc + amp_hours / self.spec.capacity).clamp(0.0, 1.0);
+
+        match current.abs() < self.spec.rest_current {
+            true => self.resting += elapsed,
+            false => self.resting = Duration::ZERO,
+        }
+        if self.is_rested() && voltage.is_finite() {
+            let soc = self.spec.ocv.apply(voltage);
+            if !soc.is_nan() {
+                self.soc = soc.clamp(0.0, 1.0);
+            }
+        }
+        self.soc
+    }
+
+    /// Time until empty at a steady `current`, `None` unless discharging or when a trickle
+    /// would take longer than a `Duration` holds.
+    pub fn time_to_empty(&self, current: f32) -> Option<Duration> {
+        match current < 0.0 {
+            true => {
+                let hours = self.soc * self.spec.capacity / self.spec.effective_current(-current);
+                Duration::try_from_secs_f32(hours * 3600.0).ok()
+            }
+            false => None,
+        }
+    }
+}
+
+/// Publishes a battery's `voltage`, `current`, `capacity.stateOfCharge` and
+/// `capacity.timeRemaining` under `electrical.batteries.<id>`.
+///
+/// The state of charge is kept under the key `soc.<id>`, so ids can be up to 11 characters,
+/// and is saved every save interval. Time remaining is null unless discharging.
+pub struct BatteryMonitor<S: ConfigStore, C: Clock = SystemClock> {
+    path: String,
+    output: SignalKOutput,
+    current: Updates<f32>,
+    voltage: Updates<f32>,
+    estimator: SocEstimator,
+    store: S,
+    key: String,
+    clock: C,
+    save_interval: Duration,
+    last_current: Option<f32>,
+    last_voltage: f32,
+    last_update: Duration,
+    last_save: Duration,
+    observable: Observable<f32>,
+}
+
+impl<S: ConfigStore> BatteryMonitor<S> {
+    pub fn new(
+        output: &SignalKOutput,
+        id: &str,
+        current: Subscriber<f32>,
+        voltage: Subscriber<f32>,
+        spec: BatterySpec,
+        store: S,
+    ) -> Self {
+        Self::with_clock(
+            output,
+            id,
+            current,
+            voltage,
+            spec,
+            store,
+            SystemClock::new(),
+        )
+    }
+}
+
+impl<S: ConfigStore, C: Clock> BatteryMonitor<S, C> {
+    /// Continue from the saved state of charge, or start full if none was saved or it is out
+    /// of range.
+    pub fn with_clock(
+        output: &SignalKOutput,
+        id: &str,
+        current: Subscriber<f32>,
+        voltage: Subscriber<f32>,
+        spec: BatterySpec,
+        store: S,
+        clock: C,
+    ) -> Self {
+        let key = format!("soc.{}", id);
+        let soc = match config::load::<S, f32>(&store, &key) {
+            Some(soc) if (0.0..=1.0).contains(&soc) => soc,
+            Some(soc) => {
+                log::error!("Ignoring saved state of charge {} for {}", soc, key);
+                1.0
+            }
+            None => 1.0,
+        };
+        let now = clock.now();
+        BatteryMonitor::<S, C> {
+            path: format!("electrical.batteries.{}", id),
+            output: output.clone(),
+            last_voltage: voltage.get(),
+            current: current.into(),
+            voltage: voltage.into(),
+            estimator: SocEstimator::new(spec, soc),
+            store,
+            key,
+            clock,
+            save_interval: DEFAULT_SAVE_INTERVAL,
+            last_current: None,
+            last_update: now,
+            last_save: now,
+            observable: Observable::new(soc),
+        }
+    }
+
+    pub fn save_interval(mut self, interval: Duration) -> Self {
+        self.save_interval = interval;
+        self
+    }
+
+    pub fn estimator(&self) -> &SocEstimator {
+        &self.estimator
+    }
+
+    /// Override the state of charge, e.g. after a full charge, and save it.
+    pub fn set_soc(&mut self, soc: f32) {
+        self.estimator.set_soc(soc);
+        self.observable.set(self.estimator.soc());
+        self.save(self.clock.now());
+    }
+
+    fn save(&mut self, now: Duration) {
+        match config::save(&self.store, &self.key, &self.estimator.soc()) {
+            Ok(_) => self.last_save = now,
+            Err(e) => log::error!("Battery state of charge save error: {:?}", e),
+        }
+    }
+
+    fn publish(&self, name: &str, value: impl Into<Value>) {
+        self.output
+            .publish(Update::new(format!("{}.{}", self.path, name), value));
+    }
+}
+
+impl<S: ConfigStore, C: Clock> Attachable<f32> for BatteryMonitor<S, C> {
+    fn attach(&mut self) -> Subscriber<f32> {
+        self.observable.subscribe()
+    }
+}
+
+impl<S: ConfigStore, C: Clock> SensESPSensor for BatteryMonitor<S, C> {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(voltage)) = poll_update(&mut self.voltage) {
+            self.last_voltage = voltage;
+            self.publish("voltage", voltage);
+        }
+
+        if let Poll::Ready(Some(current)) = poll_update(&mut self.current) {
+            let now = self.clock.now();
+            // The previous reading is taken to have held until this one
+            if let Some(previous) = self.last_current {
+                let elapsed = now.saturating_sub(self.last_update);
+                let soc = self.estimator.update(previous, self.last_voltage, elapsed);
+                self.observable.set(soc);
+            }
+            self.last_current = Some(current);
+            self.last_update = now;
+
+            self.publish("current", current);
+            self.publish("capacity.stateOfCharge", self.estimator.soc());
+            let remaining = self
+                .estimator
+                .time_to_empty(current)
+                .map(|t| t.as_secs_f32());
+            self.publish("capacity.timeRemaining", remaining);
+
+            if now.saturating_sub(self.last_save) >= self.save_interval {
+                self.save(now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::config::MemoryStore;
+    use crate::testing::assert_close;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// 100 Ah at 20 h: 5 A rated current, resting below 0.5 A.
+    fn spec() -> BatterySpec {
+        BatterySpec::new(100.0)
+    }
+
+    /// Hold `current` for `duration` with a reading every minute.
+    fn profile(estimator: &mut SocEstimator, current: f32, voltage: f32, duration: Duration) {
+        for _ in 0..duration.as_secs() / 60 {
+            estimator.update(current, voltage, MINUTE);
+        }
+    }
+
+    #[test]
+    fn rated_discharge_removes_rated_capacity() {
+        let mut estimator = SocEstimator::new(spec(), 1.0);
+        profile(&mut estimator, -5.0, 12.2, HOUR * 10);
+        assert_close(estimator.soc(), 0.5, 1e-4);
+        profile(&mut estimator, -5.0, 11.8, HOUR * 10);
+        assert_close(estimator.soc(), 0.0, 1e-4);
+        // Never below empty
+        profile(&mut estimator, -5.0, 11.0, HOUR);
+        assert_eq!(estimator.soc(), 0.0);
+    }
+
+    #[test]
+    fn heavy_discharge_costs_more_than_its_amp_hours() {
+        let mut estimator = SocEstimator::new(spec(), 1.0);
+        profile(&mut estimator, -10.0, 12.2, HOUR);
+        // 10 A is twice the rated current, 2^0.25 times as costly
+        assert_close(estimator.soc(), 1.0 - 0.1 * 2f32.powf(0.25), 1e-4);
+
+        let ideal = spec().peukert(1.0);
+        let mut estimator = SocEstimator::new(ideal, 1.0);
+        profile(&mut estimator, -10.0, 12.2, HOUR);
+        assert_close(estimator.soc(), 0.9, 1e-4);
+    }
+
+    #[test]
+    fn charging_is_derated_by_efficiency() {
+        let mut estimator = SocEstimator::new(spec(), 0.5);
+        profile(&mut estimator, 10.0, 13.8, HOUR);
+        assert_close(estimator.soc(), 0.59, 1e-4);
+        // Never above full
+        profile(&mut estimator, 20.0, 14.4, HOUR * 5);
+        assert_eq!(estimator.soc(), 1.0);
+    }
+
+    #[test]
+    fn resting_voltage_resyncs_the_estimate() {
+        let mut estimator = SocEstimator::new(spec(), 1.0);
+        profile(&mut estimator, -5.0, 12.0, HOUR * 10);
+        assert_close(estimator.soc(), 0.5, 1e-4);
+
+        // A small load doesn't disturb the resting voltage, which reads 90 %
+        profile(&mut estimator, -0.2, 12.62, HOUR - MINUTE);
+        assert!(!estimator.is_rested());
+        assert!(estimator.soc() < 0.5);
+        profile(&mut estimator, -0.2, 12.62, MINUTE);
+        assert!(estimator.is_rested());
+        assert_close(estimator.soc(), 0.9, 1e-4);
+
+        // Any real load restarts the rest
+        estimator.update(-5.0, 12.62, MINUTE);
+        assert!(!estimator.is_rested());
+    }
+
+    #[test]
+    fn failed_readings_are_ignored() {
+        let mut estimator = SocEstimator::new(spec(), 0.5);
+        for current in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(estimator.update(current, 12.2, HOUR), 0.5);
+        }
+        assert!(!estimator.is_rested());
+
+        // A failed voltage reading while rested keeps the counted charge
+        profile(&mut estimator, 0.0, 12.62, HOUR);
+        assert_close(estimator.soc(), 0.9, 1e-4);
+        for voltage in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_close(estimator.update(0.0, voltage, MINUTE), 0.9, 1e-4);
+        }
+    }
+
+    #[test]
+    fn time_to_empty_follows_the_discharge_rate() {
+        let estimator = SocEstimator::new(spec(), 0.5);
+        assert_eq!(estimator.time_to_empty(-5.0), Some(HOUR * 10));
+        let heavy = estimator.time_to_empty(-10.0).unwrap();
+        assert_close(heavy.as_secs_f32() / 3600.0, 5.0 / 2f32.powf(0.25), 1e-3);
+        assert_eq!(estimator.time_to_empty(0.0), None);
+        assert_eq!(estimator.time_to_empty(5.0), None);
+        assert_eq!(estimator.time_to_empty(f32::NAN), None);
+        assert_eq!(
+            SocEstimator::new(spec(), 0.0).time_to_empty(-5.0),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn time_to_empty_at_a_trickle_is_unknown() {
+        let estimator = SocEstimator::new(spec(), 1.0);
+        assert_eq!(estimator.time_to_empty(-1e-20), None);
+        assert_eq!(estimator.time_to_empty(-1e-30), None);
+    }
+
+    struct Bench {
+        clock: ManualClock,
+        store: MemoryStore,
+        output: SignalKOutput,
+        current: Observable<f32>,
+        voltage: Observable<f32>,
+    }
+
+    impl Bench {
+        fn new() -> Self {
+            Bench {
+                clock: ManualClock::new(),
+                store: MemoryStore::new(),
+                output: SignalKOutput::new(),
+                current: Observable::new(0.0),
+                voltage: Observable::new(12.6),
+            }
+        }
+
+        fn monitor(&self) -> BatteryMonitor<MemoryStore, ManualClock> {
+            BatteryMonitor::with_clock(
+                &self.output,
+                "house",
+                self.current.subscribe(),
+                self.voltage.subscribe(),
+                spec(),
+                self.store.clone(),
+                self.clock.clone(),
+            )
+        }
+
+        fn reading(&self, monitor: &mut BatteryMonitor<MemoryStore, ManualClock>, current: f32) {
+            self.current.set(current);
+            monitor.tick();
+        }
+
+        fn published(&self, name: &str) -> Option<Value> {
+            let path = format!("electrical.batteries.house.{}", name);
+            self.output
+                .take_pending()
+                .into_iter()
+                .rev()
+                .find(|u| u.path == path)
+                .map(|u| u.value)
+        }
+    }
+
+    #[test]
+    fn monitor_counts_charge_between_readings() {
+        let bench = Bench::new();
+        let mut monitor = bench.monitor();
+        bench.reading(&mut monitor, -5.0);
+        assert_eq!(
+            bench.published("capacity.timeRemaining"),
+            Some(Value::from(72_000.0f32))
+        );
+
+        bench.clock.advance(HOUR);
+        bench.reading(&mut monitor, 2.0);
+        assert_close(monitor.estimator().soc(), 0.95, 1e-4);
+        assert_close(monitor.attach().get(), 0.95, 1e-4);
+        assert_eq!(bench.published("capacity.timeRemaining"), Some(Value::Null));
+        // Saved with the first reading after the save interval
+        let saved: f32 = config::load(&bench.store, "soc.house").unwrap();
+        assert_close(saved, 0.95, 1e-4);
+    }
+
+    #[test]
+    fn monitor_continues_from_the_saved_soc() {
+        let bench = Bench::new();
+        config::save(&bench.store, "soc.house", &0.4f32).unwrap();
+        let mut monitor = bench.monitor();
+        assert_eq!(monitor.estimator().soc(), 0.4);
+        assert_eq!(monitor.attach().get(), 0.4);
+
+        monitor.set_soc(1.0);
+        assert_eq!(config::load(&bench.store, "soc.house"), Some(1.0f32));
+    }
+
+    #[test]
+    fn monitor_starts_full_from_an_invalid_saved_soc() {
+        let bench = Bench::new();
+        for saved in ["1.5", "-0.1", "NaN", "inf", "half"] {
+            bench.store.set("soc.house", saved).unwrap();
+            let mut monitor = bench.monitor();
+            assert_eq!(monitor.estimator().soc(), 1.0, "{}", saved);
+            assert_eq!(monitor.attach().get(), 1.0, "{}", saved);
+        }
+    }
+
+    #[test]
+    fn monitor_skips_failed_current_readings() {
+        let bench = Bench::new();
+        config::save(&bench.store, "soc.house", &0.5f32).unwrap();
+        let mut monitor = bench.monitor();
+        bench.reading(&mut monitor, f32::NAN);
+        bench.clock.advance(HOUR);
+        bench.reading(&mut monitor, -5.0);
+        assert_eq!(monitor.estimator().soc(), 0.5);
+        bench.clock.advance(HOUR);
+        bench.reading(&mut monitor, -5.0);
+        assert_close(monitor.estimator().soc(), 0.45, 1e-4);
+    }
+}
@@ -0,0 +1,412 @@
+//! Bilge pump cycle counting and alarms
+use crate::clock::{Clock, SystemClock};
+use crate::sensor::{poll_update, SensESPSensor, Updates};
+use crate::signalk::{AlarmState, SignalKOutput, Update, Value};
+use eyeball::Subscriber;
+use std::collections::VecDeque;
+use std::task::Poll;
+use std::time::Duration;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// When a bilge pump's behaviour raises an alarm, by default more than 6 cycles an hour or
+/// more than 3 minutes of continuous running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BilgeLimits {
+    /// Pump starts within the last hour
+    pub max_cycles_per_hour: u32,
+    /// Longest the pump may run without stopping
+    pub max_run_time: Duration,
+}
+
+impl Default for BilgeLimits {
+    fn default() -> Self {
+        BilgeLimits {
+            max_cycles_per_hour: 6,
+            max_run_time: Duration::from_secs(180),
+        }
+    }
+}
+
+/// Watches a pump-running stream, such as a float switch on a `DigitalInputState`.
+///
+/// Publishes `bilge.<id>.running` on every change, `bilge.<id>.cycles` when the pump starts
+/// and `bilge.<id>.runTime`, the total in seconds, when it stops. Exceeding a limit raises an
+/// alarm on `notifications.bilge.<id>.cycleRate` or `notifications.bilge.<id>.runTime`, which
+/// returns to normal once the condition clears.
+pub struct BilgeMonitor<C: Clock = SystemClock> {
+    subscriber: Updates<bool>,
+    path: String,
+    output: SignalKOutput,
+    limits: BilgeLimits,
+    clock: C,
+    running: bool,
+    started: Duration,
+    starts: VecDeque<Duration>,
+    cycles: u32,
+    run_time: Duration,
+    cycle_alarm: AlarmState,
+    run_time_alarm: AlarmState,
+}
+
+impl BilgeMonitor {
+    pub fn new(
+        output: &SignalKOutput,
+        id: &str,
+        subscriber: Subscriber<bool>,
+        limits: BilgeLimits,
+    ) -> Self {
+        Self::with_clock(output, id, subscriber, limits, SystemClock::new())
+    }
+}
+
+impl<C: Clock> BilgeMonitor<C> {
+    pub fn with_clock(
+        output: &SignalKOutput,
+        id: &str,
+        subscriber: Subscriber<bool>,
+        limits: BilgeLimits,
+        clock: C,
+    ) -> Self {
+        BilgeMonitor::<C> {
+            // A pump already running is timed from now but not counted as a cycle
+            running: subscriber.get(),
+            subscriber: subscriber.into(),
+            path: format!("bilge.{}", id),
+            output: output.clone(),
+            limits,
+            started: clock.now(),
+            clock,
+            starts: VecDeque::new(),
+            cycles: 0,
+            run_time: Duration::ZERO,
+            cycle_alarm: AlarmState::Normal,
+            run_time_alarm: AlarmState::Normal,
+        }
+    }
+
+    /// Pump starts since the monitor was created
+    pub fn cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    /// Pump starts within the last hour
+    pub fn cycles_last_hour(&self) -> u32 {
+        self.starts.len() as u32
+    }
+
+    /// Total running time, including the current run
+    pub fn run_time(&self) -> Duration {
+        match self.running {
+            true => self.run_time + self.clock.now().saturating_sub(self.started),
+            false => self.run_time,
+        }
+    }
+
+    pub fn cycle_alarm(&self) -> AlarmState {
+        self.cycle_alarm
+    }
+
+    pub fn run_time_alarm(&self) -> AlarmState {
+        self.run_time_alarm
+    }
+
+    fn publish(&self, name: &str, value: impl Into<Value>) {
+        self.output
+            .publish(Update::new(format!("{}.{}", self.path, name), value));
+    }
+
+    fn notify(&self, name: &str, state: AlarmState, message: String) {
+        self.output.publish(Update::new(
+            format!("notifications.{}.{}", self.path, name),
+            Value::Notification { state, message },
+        ));
+    }
+
+    fn set_running(&mut self, running: bool, now: Duration) {
+        self.running = running;
+        self.publish("running", running);
+        match running {
+            true => {
+                self.started = now;
+                self.starts.push_back(now);
+                self.cycles += 1;
+                self.publish("cycles", self.cycles as f32);
+            }
+            false => {
+                self.run_time += now.saturating_sub(self.started);
+                self.publish("runTime", self.run_time.as_secs_f32());
+            }
+        }
+    }
+
+    fn check_limits(&mut self, now: Duration) {
+        while self
+            .starts
+            .front()
+            .is_some_and(|start| now.saturating_sub(*start) >= HOUR)
+        {
+            self.starts.pop_front();
+        }
+
+        let cycles = self.cycles_last_hour();
+        let state = match cycles > self.limits.max_cycles_per_hour {
+            true => AlarmState::Alarm,
+            false => AlarmState::Normal,
+        };
+        if state != self.cycle_alarm {
+            self.cycle_alarm = state;
+            let message = format!("Bilge pump started {} times in the last hour", cycles);
+            self.notify("cycleRate", state, message);
+        }
+
+        let run = now.saturating_sub(self.started);
+        let state = match self.running && run > self.limits.max_run_time {
+            true => AlarmState::Alarm,
+            false => AlarmState::Normal,
+        };
+        if state != self.run_time_alarm {
+            self.run_time_alarm = state;
+            let message = match state {
+                AlarmState::Normal => "Bilge pump stopped".to_string(),
+                _ => format!("Bilge pump running for {} s", run.as_secs()),
+            };
+            self.notify("runTime", state, message);
+        }
+    }
+}
+
+impl<C: Clock> SensESPSensor for BilgeMonitor<C> {
+    fn tick(&mut self) {
+        let now = self.clock.now();
+        // Inputs with a heartbeat repeat their state, only changes count
+        match poll_update(&mut self.subscriber) {
+            Poll::Ready(Some(running)) if running != self.running => self.set_running(running, now),
+            _ => (),
+        }
+        self.check_limits(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use eyeball::shared::Observable;
+
+    const SECOND: Duration = Duration::from_secs(1);
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    struct Bilge {
+        clock: ManualClock,
+        output: SignalKOutput,
+        pump: Observable<bool>,
+        monitor: BilgeMonitor<ManualClock>,
+    }
+
+    impl Bilge {
+        fn new(limits: BilgeLimits) -> Self {
+            Self::running(false, limits)
+        }
+
+        fn running(running: bool, limits: BilgeLimits) -> Self {
+            let clock = ManualClock::new();
+            let output = SignalKOutput::new();
+            let pump = Observable::new(running);
+            let monitor =
+                BilgeMonitor::with_clock(&output, "main", pump.subscribe(), limits, clock.clone());
+            Bilge {
+                clock,
+                output,
+                pump,
+                monitor,
+            }
+        }
+
+        fn set(&mut self, running: bool) {
+            self.pump.set(running);
+            self.monitor.tick();
+        }
+
+        fn wait(&mut self, duration: Duration) {
+            self.clock.advance(duration);
+            self.monitor.tick();
+        }
+
+        /// Run the pump for `run`, then leave it off for the rest of `period`.
+        fn cycle(&mut self, run: Duration, period: Duration) {
+            self.set(true);
+            self.wait(run);
+            self.set(false);
+            self.wait(period - run);
+        }
+
+        /// Notifications published since the last call, by name.
+        fn notifications(&self, name: &str) -> Vec<(AlarmState, String)> {
+            let path = format!("notifications.bilge.main.{}", name);
+            self.output
+                .take_pending()
+                .into_iter()
+                .filter(|u| u.path == path)
+                .map(|u| match u.value {
+                    Value::Notification { state, message } => (state, message),
+                    other => panic!("{} is {:?}", path, other),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn pump_changes_are_published() {
+        let mut bilge = Bilge::new(BilgeLimits::default());
+        bilge.set(true);
+        bilge.wait(SECOND * 30);
+        bilge.set(false);
+        let updates: Vec<(String, Value)> = bilge
+            .output
+            .take_pending()
+            .into_iter()
+            .map(|u| (u.path, u.value))
+            .collect();
+        assert_eq!(
+            updates,
+            vec![
+                ("bilge.main.running".to_string(), Value::from(true)),
+                ("bilge.main.cycles".to_string(), Value::from(1.0f32)),
+                ("bilge.main.running".to_string(), Value::from(false)),
+                ("bilge.main.runTime".to_string(), Value::from(30.0f32)),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_states_are_not_cycles() {
+        let mut bilge = Bilge::new(BilgeLimits::default());
+        bilge.set(false);
+        assert!(bilge.output.take_pending().is_empty());
+        bilge.set(true);
+        bilge.wait(SECOND);
+        bilge.set(true);
+        bilge.set(true);
+        assert_eq!(bilge.monitor.cycles(), 1);
+    }
+
+    #[test]
+    fn run_time_adds_up_across_cycles() {
+        let mut bilge = Bilge::new(BilgeLimits::default());
+        for _ in 0..3 {
+            bilge.cycle(SECOND * 20, MINUTE * 5);
+        }
+        assert_eq!(bilge.monitor.run_time(), SECOND * 60);
+        // The current run counts before it ends
+        bilge.set(true);
+        bilge.clock.advance(SECOND * 10);
+        assert_eq!(bilge.monitor.run_time(), SECOND * 70);
+        assert_eq!(bilge.monitor.cycles(), 4);
+    }
+
+    #[test]
+    fn cycles_older_than_an_hour_drop_out_of_the_window() {
+        let mut bilge = Bilge::new(BilgeLimits::default());
+        for _ in 0..4 {
+            bilge.cycle(SECOND * 10, MINUTE * 10);
+        }
+        assert_eq!(bilge.monitor.cycles_last_hour(), 4);
+
+        // The first start was 40 minutes ago
+        bilge.wait(MINUTE * 20 - SECOND);
+        assert_eq!(bilge.monitor.cycles_last_hour(), 4);
+        bilge.wait(SECOND);
+        assert_eq!(bilge.monitor.cycles_last_hour(), 3);
+        bilge.wait(MINUTE * 30);
+        assert_eq!(bilge.monitor.cycles_last_hour(), 0);
+        assert_eq!(bilge.monitor.cycles(), 4);
+    }
+
+    #[test]
+    fn cycle_rate_alarm_is_raised_and_cleared() {
+        let mut bilge = Bilge::new(BilgeLimits::default());
+        for _ in 0..6 {
+            bilge.cycle(SECOND * 30, MINUTE * 5);
+        }
+        assert_eq!(bilge.monitor.cycle_alarm(), AlarmState::Normal);
+        assert!(bilge.notifications("cycleRate").is_empty());
+
+        bilge.set(true);
+        assert_eq!(bilge.monitor.cycle_alarm(), AlarmState::Alarm);
+        assert_eq!(
+            bilge.notifications("cycleRate"),
+            vec![(
+                AlarmState::Alarm,
+                "Bilge pump started 7 times in the last hour".to_string()
+            )]
+        );
+        // Raised once, not on every tick
+        bilge.wait(SECOND);
+        bilge.set(false);
+        assert!(bilge.notifications("cycleRate").is_empty());
+
+        // The first start ages out an hour after it happened
+        bilge.wait(MINUTE * 30);
+        assert_eq!(bilge.monitor.cycle_alarm(), AlarmState::Normal);
+        assert_eq!(
+            bilge.notifications("cycleRate"),
+            vec![(
+                AlarmState::Normal,
+                "Bilge pump started 6 times in the last hour".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn run_time_alarm_is_raised_past_the_limit_and_cleared_on_stop() {
+        let limits = BilgeLimits {
+            max_cycles_per_hour: 6,
+            max_run_time: MINUTE * 3,
+        };
+        let mut bilge = Bilge::new(limits);
+        bilge.set(true);
+        bilge.wait(MINUTE * 3);
+        assert_eq!(bilge.monitor.run_time_alarm(), AlarmState::Normal);
+        bilge.wait(SECOND);
+        assert_eq!(bilge.monitor.run_time_alarm(), AlarmState::Alarm);
+        assert_eq!(
+            bilge.notifications("runTime"),
+            vec![(
+                AlarmState::Alarm,
+                "Bilge pump running for 181 s".to_string()
+            )]
+        );
+        bilge.wait(MINUTE);
+        assert!(bilge.notifications("runTime").is_empty());
+
+        bilge.set(false);
+        assert_eq!(bilge.monitor.run_time_alarm(), AlarmState::Normal);
+        assert_eq!(
+            bilge.notifications("runTime"),
+            vec![(AlarmState::Normal, "Bilge pump stopped".to_string())]
+        );
+
+        // Each run is timed on its own
+        bilge.set(true);
+        bilge.wait(MINUTE * 2);
+        bilge.set(false);
+        bilge.set(true);
+        bilge.wait(MINUTE * 2);
+        assert_eq!(bilge.monitor.run_time_alarm(), AlarmState::Normal);
+    }
+
+    #[test]
+    fn pump_running_at_start_is_timed_but_not_counted() {
+        let limits = BilgeLimits {
+            max_cycles_per_hour: 1,
+            max_run_time: MINUTE,
+        };
+        let mut bilge = Bilge::running(true, limits);
+        bilge.wait(MINUTE * 2);
+        assert_eq!(bilge.monitor.cycles(), 0);
+        assert_eq!(bilge.monitor.run_time(), MINUTE * 2);
+        assert_eq!(bilge.monitor.run_time_alarm(), AlarmState::Alarm);
+        assert_eq!(bilge.monitor.cycle_alarm(), AlarmState::Normal);
+    }
+}
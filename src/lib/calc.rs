@@ -0,0 +1,398 @@
+//! Values derived from several measurements, in Signal K units: m/s, radians, K, Pa and ratios
+use crate::combine::CombineLatest;
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, Update};
+use eyeball::{shared::Observable, Subscriber};
+use std::f32::consts::TAU;
+use std::task::Poll;
+
+/// Magnus coefficients over water, valid from -45 to 60 °C.
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+
+/// Specific gas constants in J/(kg·K).
+const DRY_AIR: f32 = 287.058;
+const WATER_VAPOUR: f32 = 461.495;
+
+const ZERO_CELSIUS: f32 = 273.15;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct TrueWind {
+    pub speed: f32,
+    /// Relative to the bow, positive to starboard, -π to π
+    pub angle: f32,
+    /// Relative to north, 0 to 2π
+    pub direction: f32,
+}
+
+/// Remove the boat's own motion from the apparent wind.
+///
+/// With speed through water the result is the wind over the water; pass speed over ground
+/// instead for the wind over the ground.
+pub fn true_wind(
+    apparent_speed: f32,
+    apparent_angle: f32,
+    boat_speed: f32,
+    heading: f32,
+) -> TrueWind {
+    let along = apparent_speed * apparent_angle.cos() - boat_speed;
+    let across = apparent_speed * apparent_angle.sin();
+    let speed = along.hypot(across);
+    let angle = match speed > 0.0 {
+        true => across.atan2(along),
+        false => 0.0,
+    };
+    TrueWind {
+        speed,
+        angle,
+        direction: (heading + angle).rem_euclid(TAU),
+    }
+}
+
+/// Water vapour pressure at saturation, in Pa.
+pub fn saturation_vapour_pressure(temperature: f32) -> f32 {
+    let celsius = temperature - ZERO_CELSIUS;
+    611.2 * (MAGNUS_B * celsius / (MAGNUS_C + celsius)).exp()
+}
+
+/// The temperature at which the air would be saturated.
+pub fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    let celsius = temperature - ZERO_CELSIUS;
+    // Bone dry air has no dew point, keep the result finite
+    let gamma = humidity.clamp(0.001, 1.0).ln() + MAGNUS_B * celsius / (MAGNUS_C + celsius);
+    MAGNUS_C * gamma / (MAGNUS_B - gamma) + ZERO_CELSIUS
+}
+
+/// How hot the air feels, using the US National Weather Service regression.
+pub fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    let t = (temperature - ZERO_CELSIUS) * 1.8 + 32.0;
+    let rh = humidity.clamp(0.0, 1.0) * 100.0;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let fahrenheit = match (simple + t) / 2.0 < 80.0 {
+        true => simple,
+        false => {
+            let mut hi = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+                - 0.224_755_4 * t * rh
+                - 0.006_837_83 * t * t
+                - 0.054_817_17 * rh * rh
+                + 0.001_228_74 * t * t * rh
+                + 0.000_852_82 * t * rh * rh
+                - 0.000_001_99 * t * t * rh * rh;
+            if rh < 13.0 && (80.0..=112.0).contains(&t) {
+                hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+            } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+                hi += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+            }
+            hi
+        }
+    };
+    (fahrenheit - 32.0) / 1.8 + ZERO_CELSIUS
+}
+
+/// Density of humid air in kg/m³.
+pub fn air_density(temperature: f32, pressure: f32, humidity: f32) -> f32 {
+    let vapour = humidity.clamp(0.0, 1.0) * saturation_vapour_pressure(temperature);
+    (pressure - vapour) / (DRY_AIR * temperature) + vapour / (WATER_VAPOUR * temperature)
+}
+
+/// Applies a function to the latest values of several inputs whenever any of them changes.
+pub struct Calculation<T, F>
+where
+    F: Fn(&[f32]) -> T,
+{
+    inputs: CombineLatest<f32>,
+    combined: Updates<Vec<f32>>,
+    observable: Observable<T>,
+    func: F,
+}
+
+impl<T, F> Calculation<T, F>
+where
+    T: Clone,
+    F: Fn(&[f32]) -> T,
+{
+    /// `func` gets the inputs' values in the order they are given here.
+    pub fn new(inputs: Vec<Subscriber<f32>>, func: F) -> Self {
+        let mut inputs = CombineLatest::new(inputs);
+        let combined = inputs.attach();
+        let observable = Observable::new(func(&combined.get()));
+        Calculation::<T, F> {
+            inputs,
+            combined: combined.into(),
+            observable,
+            func,
+        }
+    }
+}
+
+impl<T, F> Attachable<T> for Calculation<T, F>
+where
+    T: Clone,
+    F: Fn(&[f32]) -> T,
+{
+    fn attach(&mut self) -> Subscriber<T> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T, F> SensESPSensor for Calculation<T, F>
+where
+    T: Clone,
+    F: Fn(&[f32]) -> T,
+{
+    fn tick(&mut self) {
+        self.inputs.tick();
+        if let Poll::Ready(Some(values)) = poll_update(&mut self.combined) {
+            self.observable.set((self.func)(&values));
+        }
+    }
+}
+
+pub fn true_wind_calculation(
+    apparent_speed: Subscriber<f32>,
+    apparent_angle: Subscriber<f32>,
+    boat_speed: Subscriber<f32>,
+    heading: Subscriber<f32>,
+) -> Calculation<TrueWind, impl Fn(&[f32]) -> TrueWind> {
+    Calculation::new(
+        vec![apparent_speed, apparent_angle, boat_speed, heading],
+        |v| true_wind(v[0], v[1], v[2], v[3]),
+    )
+}
+
+pub fn dew_point_calculation(
+    temperature: Subscriber<f32>,
+    humidity: Subscriber<f32>,
+) -> Calculation<f32, impl Fn(&[f32]) -> f32> {
+    Calculation::new(vec![temperature, humidity], |v| dew_point(v[0], v[1]))
+}
+
+pub fn heat_index_calculation(
+    temperature: Subscriber<f32>,
+    humidity: Subscriber<f32>,
+) -> Calculation<f32, impl Fn(&[f32]) -> f32> {
+    Calculation::new(vec![temperature, humidity], |v| heat_index(v[0], v[1]))
+}
+
+pub fn air_density_calculation(
+    temperature: Subscriber<f32>,
+    pressure: Subscriber<f32>,
+    humidity: Subscriber<f32>,
+) -> Calculation<f32, impl Fn(&[f32]) -> f32> {
+    Calculation::new(vec![temperature, pressure, humidity], |v| {
+        air_density(v[0], v[1], v[2])
+    })
+}
+
+/// Publishes true wind to `environment.wind.speedTrue`, `angleTrueWater` and `directionTrue`.
+pub struct TrueWindPaths {
+    subscriber: Updates<TrueWind>,
+    output: SignalKOutput,
+}
+
+impl TrueWindPaths {
+    pub fn new(output: &SignalKOutput, subscriber: Subscriber<TrueWind>) -> Self {
+        TrueWindPaths {
+            subscriber: subscriber.into(),
+            output: output.clone(),
+        }
+    }
+}
+
+impl SensESPSensor for TrueWindPaths {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(wind)) = poll_update(&mut self.subscriber) {
+            self.output
+                .publish(Update::new("environment.wind.speedTrue", wind.speed));
+            self.output
+                .publish(Update::new("environment.wind.angleTrueWater", wind.angle));
+            self.output.publish(Update::new(
+                "environment.wind.directionTrue",
+                wind.direction,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signalk::Value;
+    use crate::testing::assert_close;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    fn celsius(c: f32) -> f32 {
+        c + ZERO_CELSIUS
+    }
+
+    fn fahrenheit(f: f32) -> f32 {
+        (f - 32.0) / 1.8 + ZERO_CELSIUS
+    }
+
+    #[test]
+    fn motoring_through_calm_air_has_no_true_wind() {
+        let wind = true_wind(5.0, 0.0, 5.0, 1.0);
+        assert_close(wind.speed, 0.0, 1e-6);
+        assert_eq!(wind.angle, 0.0);
+        assert_close(wind.direction, 1.0, 1e-6);
+    }
+
+    #[test]
+    fn true_wind_removes_boat_speed() {
+        // 10 m/s from starboard beam, sailing north at 5 m/s
+        let wind = true_wind(5f32.hypot(10.0), 10f32.atan2(5.0), 5.0, 0.0);
+        assert_close(wind.speed, 10.0, 1e-4);
+        assert_close(wind.angle, FRAC_PI_2, 1e-4);
+        assert_close(wind.direction, FRAC_PI_2, 1e-4);
+
+        // Stopped, wind on the port beam
+        let wind = true_wind(10.0, -FRAC_PI_2, 0.0, 6.0);
+        assert_close(wind.speed, 10.0, 1e-5);
+        assert_close(wind.angle, -FRAC_PI_2, 1e-5);
+        assert_close(wind.direction, 6.0 - FRAC_PI_2, 1e-5);
+
+        // Running before a wind faster than the boat
+        let wind = true_wind(3.0, PI, 5.0, 0.0);
+        assert_close(wind.speed, 8.0, 1e-4);
+        assert_close(wind.angle.abs(), PI, 1e-4);
+    }
+
+    #[test]
+    fn true_wind_direction_wraps_around_north() {
+        let wind = true_wind(10.0, -FRAC_PI_2, 0.0, 0.5);
+        assert_close(wind.direction, TAU + 0.5 - FRAC_PI_2, 1e-5);
+        let wind = true_wind(10.0, FRAC_PI_2, 0.0, TAU - 0.5);
+        assert_close(wind.direction, FRAC_PI_2 - 0.5, 1e-5);
+        assert!((0.0..TAU).contains(&wind.direction));
+    }
+
+    #[test]
+    fn saturation_vapour_pressure_matches_tables() {
+        assert_close(saturation_vapour_pressure(celsius(0.0)), 611.2, 0.1);
+        assert_close(saturation_vapour_pressure(celsius(20.0)), 2339.0, 10.0);
+    }
+
+    #[test]
+    fn dew_point_matches_tables() {
+        assert_close(dew_point(celsius(20.0), 0.5), celsius(9.26), 0.05);
+        assert_close(dew_point(celsius(30.0), 0.8), celsius(26.17), 0.05);
+        assert_close(dew_point(celsius(-10.0), 0.6), celsius(-16.31), 0.05);
+    }
+
+    #[test]
+    fn saturated_air_is_at_its_dew_point() {
+        assert_close(dew_point(celsius(25.0), 1.0), celsius(25.0), 0.01);
+        // Readings over 100% are clamped
+        assert_close(dew_point(celsius(25.0), 1.05), celsius(25.0), 0.01);
+    }
+
+    #[test]
+    fn dry_air_has_a_finite_dew_point() {
+        let dry = dew_point(celsius(25.0), 0.0);
+        assert!(dry.is_finite());
+        assert!(dry < celsius(-40.0));
+        assert_eq!(dew_point(celsius(25.0), -0.1), dry);
+    }
+
+    #[test]
+    fn mild_heat_index_uses_the_simple_formula() {
+        assert_close(heat_index(fahrenheit(70.0), 0.5), fahrenheit(69.05), 0.01);
+        assert_close(heat_index(fahrenheit(80.0), 0.4), fahrenheit(79.58), 0.01);
+    }
+
+    #[test]
+    fn hot_heat_index_uses_the_regression() {
+        // The NWS table gives 106 °F and 121 °F
+        assert_close(heat_index(fahrenheit(90.0), 0.7), fahrenheit(105.92), 0.02);
+        assert_close(heat_index(fahrenheit(96.0), 0.65), fahrenheit(121.03), 0.02);
+        // Humid but outside the 80 - 87 °F adjustment
+        assert_close(heat_index(fahrenheit(88.0), 1.0), fahrenheit(121.21), 0.02);
+    }
+
+    #[test]
+    fn dry_heat_index_is_adjusted_down() {
+        assert_close(heat_index(fahrenheit(95.0), 0.05), fahrenheit(88.18), 0.02);
+        assert_close(heat_index(fahrenheit(100.0), 0.1), fahrenheit(94.12), 0.02);
+    }
+
+    #[test]
+    fn humid_heat_index_is_adjusted_up() {
+        assert_close(heat_index(fahrenheit(82.0), 1.0), fahrenheit(96.04), 0.02);
+        assert_close(heat_index(fahrenheit(86.0), 0.9), fahrenheit(105.39), 0.02);
+    }
+
+    #[test]
+    fn air_density_matches_standard_atmosphere() {
+        assert_close(air_density(celsius(15.0), 101_325.0, 0.0), 1.225, 0.001);
+        assert_close(air_density(celsius(0.0), 101_325.0, 0.0), 1.292, 0.001);
+        // Water vapour is lighter than dry air
+        assert_close(air_density(celsius(30.0), 101_325.0, 1.0), 1.145, 0.003);
+        assert!(
+            air_density(celsius(30.0), 101_325.0, 1.0) < air_density(celsius(30.0), 101_325.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn calculation_follows_its_inputs() {
+        let temperature = Observable::new(celsius(20.0));
+        let humidity = Observable::new(0.5);
+        let mut calculation = dew_point_calculation(temperature.subscribe(), humidity.subscribe());
+        let subscriber = calculation.attach();
+        assert_close(subscriber.get(), celsius(9.26), 0.05);
+        let mut updates = Updates::new(subscriber);
+
+        calculation.tick();
+        assert_eq!(poll_update(&mut updates), Poll::Pending);
+
+        humidity.set(1.0);
+        calculation.tick();
+        match poll_update(&mut updates) {
+            Poll::Ready(Some(v)) => assert_close(v, celsius(20.0), 0.01),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        temperature.set(celsius(25.0));
+        calculation.tick();
+        match poll_update(&mut updates) {
+            Poll::Ready(Some(v)) => assert_close(v, celsius(25.0), 0.01),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn true_wind_is_published_to_signalk() {
+        let apparent_speed = Observable::new(10.0);
+        let apparent_angle = Observable::new(-FRAC_PI_2);
+        let boat_speed = Observable::new(0.0);
+        let heading = Observable::new(1.0);
+        let mut calculation = true_wind_calculation(
+            apparent_speed.subscribe(),
+            apparent_angle.subscribe(),
+            boat_speed.subscribe(),
+            heading.subscribe(),
+        );
+        let output = SignalKOutput::new();
+        let mut paths = TrueWindPaths::new(&output, calculation.attach());
+
+        heading.set(2.0);
+        calculation.tick();
+        paths.tick();
+        let updates = output.take_pending();
+        let number = |path: &str| match updates.iter().find(|u| u.path == path) {
+            Some(Update {
+                value: Value::Number(n),
+                ..
+            }) => *n as f32,
+            other => panic!("{} is {:?}", path, other),
+        };
+        assert_close(number("environment.wind.speedTrue"), 10.0, 1e-5);
+        assert_close(number("environment.wind.angleTrueWater"), -FRAC_PI_2, 1e-5);
+        assert_close(
+            number("environment.wind.directionTrue"),
+            2.0 - FRAC_PI_2,
+            1e-5,
+        );
+        assert_eq!(updates.len(), 3);
+    }
+}
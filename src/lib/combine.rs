@@ -0,0 +1,354 @@
+//! Transforms that join several sensor streams into one
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use eyeball::{shared::Observable, Subscriber};
+use std::collections::VecDeque;
+use std::task::Poll;
+
+/// Values held by a [`Merge`] waiting to be published, beyond which the oldest are dropped.
+const MAX_QUEUED: usize = 16;
+
+/// The latest value of every input whenever any of them changes.
+pub fn combine_latest<T: Clone>(subscribers: Vec<Subscriber<T>>) -> CombineLatest<T> {
+    CombineLatest::new(subscribers)
+}
+
+/// One value from each input, paired once both have published.
+pub fn zip<A: Clone, B: Clone>(a: Subscriber<A>, b: Subscriber<B>) -> Zip<A, B> {
+    Zip::new(a, b)
+}
+
+/// Every value of every input, in one stream.
+pub fn merge<T: Clone>(subscribers: Vec<Subscriber<T>>) -> Merge<T> {
+    Merge::new(subscribers)
+}
+
+/// The latest value of `value` each time `trigger` publishes.
+pub fn sample<T: Clone, U: Clone>(value: Subscriber<T>, trigger: Subscriber<U>) -> Sample<T, U> {
+    Sample::new(value, trigger)
+}
+
+/// Publishes the latest value of every input whenever any of them changes.
+///
+/// Starts from each input's current value, so the first output doesn't wait for every source
+/// to publish.
+pub struct CombineLatest<T> {
+    subscribers: Vec<Updates<T>>,
+    latest: Vec<T>,
+    observable: Observable<Vec<T>>,
+}
+
+impl<T: Clone> CombineLatest<T> {
+    pub fn new(subscribers: Vec<Subscriber<T>>) -> Self {
+        let latest: Vec<T> = subscribers.iter().map(|s| s.get()).collect();
+        CombineLatest::<T> {
+            observable: Observable::new(latest.clone()),
+            subscribers: subscribers.into_iter().map(Updates::new).collect(),
+            latest,
+        }
+    }
+}
+
+impl<T: Clone> Attachable<Vec<T>> for CombineLatest<T> {
+    fn attach(&mut self) -> Subscriber<Vec<T>> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T: Clone> SensESPSensor for CombineLatest<T> {
+    fn tick(&mut self) {
+        let mut changed = false;
+        for (subscriber, latest) in self.subscribers.iter_mut().zip(self.latest.iter_mut()) {
+            if let Poll::Ready(Some(value)) = poll_update(subscriber) {
+                *latest = value;
+                changed = true;
+            }
+        }
+        if changed {
+            self.observable.set(self.latest.clone());
+        }
+    }
+}
+
+/// Pairs the values of two inputs: waits for both to publish, publishes the pair and starts
+/// over. An input that publishes twice before the other replaces its first value.
+pub struct Zip<A, B> {
+    a: Updates<A>,
+    b: Updates<B>,
+    pending: (Option<A>, Option<B>),
+    observable: Observable<Option<(A, B)>>,
+}
+
+impl<A: Clone, B: Clone> Zip<A, B> {
+    pub fn new(a: Subscriber<A>, b: Subscriber<B>) -> Self {
+        Zip::<A, B> {
+            a: a.into(),
+            b: b.into(),
+            pending: (None, None),
+            observable: Observable::new(None),
+        }
+    }
+}
+
+impl<A: Clone, B: Clone> Attachable<Option<(A, B)>> for Zip<A, B> {
+    fn attach(&mut self) -> Subscriber<Option<(A, B)>> {
+        self.observable.subscribe()
+    }
+}
+
+impl<A: Clone, B: Clone> SensESPSensor for Zip<A, B> {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(a)) = poll_update(&mut self.a) {
+            self.pending.0 = Some(a);
+        }
+        if let Poll::Ready(Some(b)) = poll_update(&mut self.b) {
+            self.pending.1 = Some(b);
+        }
+        match std::mem::take(&mut self.pending) {
+            (Some(a), Some(b)) => {
+                self.observable.set(Some((a, b)));
+            }
+            pending => self.pending = pending,
+        }
+    }
+}
+
+/// Interleaves inputs of the same type.
+///
+/// Values that arrive on the same tick are published one per tick, in input order, so a
+/// subscriber polling every tick sees each of them.
+pub struct Merge<T> {
+    subscribers: Vec<Updates<T>>,
+    queue: VecDeque<T>,
+    observable: Observable<Option<T>>,
+}
+
+impl<T: Clone> Merge<T> {
+    pub fn new(subscribers: Vec<Subscriber<T>>) -> Self {
+        Merge::<T> {
+            subscribers: subscribers.into_iter().map(Updates::new).collect(),
+            queue: VecDeque::new(),
+            observable: Observable::new(None),
+        }
+    }
+}
+
+impl<T: Clone> Attachable<Option<T>> for Merge<T> {
+    fn attach(&mut self) -> Subscriber<Option<T>> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T: Clone> SensESPSensor for Merge<T> {
+    fn tick(&mut self) {
+        for subscriber in self.subscribers.iter_mut() {
+            if let Poll::Ready(Some(value)) = poll_update(subscriber) {
+                if self.queue.len() >= MAX_QUEUED {
+                    self.queue.pop_front();
+                }
+                self.queue.push_back(value);
+            }
+        }
+        if let Some(value) = self.queue.pop_front() {
+            self.observable.set(Some(value));
+        }
+    }
+}
+
+/// Publishes the latest value of one input whenever a second input, the trigger, publishes.
+pub struct Sample<T, U> {
+    value: Updates<T>,
+    trigger: Updates<U>,
+    latest: T,
+    observable: Observable<T>,
+}
+
+impl<T: Clone, U: Clone> Sample<T, U> {
+    pub fn new(value: Subscriber<T>, trigger: Subscriber<U>) -> Self {
+        let latest = value.get();
+        Sample::<T, U> {
+            observable: Observable::new(latest.clone()),
+            value: value.into(),
+            trigger: trigger.into(),
+            latest,
+        }
+    }
+}
+
+impl<T: Clone, U: Clone> Attachable<T> for Sample<T, U> {
+    fn attach(&mut self) -> Subscriber<T> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T: Clone, U: Clone> SensESPSensor for Sample<T, U> {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(value)) = poll_update(&mut self.value) {
+            self.latest = value;
+        }
+        if let Poll::Ready(Some(_)) = poll_update(&mut self.trigger) {
+            self.observable.set(self.latest.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::updates;
+
+    #[test]
+    fn combine_latest_starts_from_current_values() {
+        let a = Observable::new(1);
+        let b = Observable::new(10);
+        let mut combined = combine_latest(vec![a.subscribe(), b.subscribe()]);
+        assert_eq!(combined.attach().get(), vec![1, 10]);
+        let mut output = updates(&mut combined);
+
+        combined.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        b.set(11);
+        combined.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(vec![1, 11])));
+        a.set(2);
+        b.set(12);
+        combined.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(vec![2, 12])));
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+    }
+
+    #[test]
+    fn zip_waits_for_both_inputs() {
+        let a = Observable::new(0u8);
+        let b = Observable::new(false);
+        let mut zipped = zip(a.subscribe(), b.subscribe());
+        assert_eq!(zipped.attach().get(), None);
+        let mut output = updates(&mut zipped);
+
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        a.set(1);
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        b.set(true);
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(Some((1, true)))));
+
+        // Each pair starts over
+        b.set(false);
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        a.set(3);
+        zipped.tick();
+        assert_eq!(
+            poll_update(&mut output),
+            Poll::Ready(Some(Some((3, false))))
+        );
+    }
+
+    #[test]
+    fn zip_keeps_the_latest_unpaired_value() {
+        let a = Observable::new(0u8);
+        let b = Observable::new('-');
+        let mut zipped = zip(a.subscribe(), b.subscribe());
+        let mut output = updates(&mut zipped);
+
+        a.set(1);
+        zipped.tick();
+        a.set(2);
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        b.set('x');
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(Some((2, 'x')))));
+
+        // Both on the same tick pair at once
+        a.set(4);
+        b.set('y');
+        zipped.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(Some((4, 'y')))));
+    }
+
+    #[test]
+    fn merge_publishes_one_value_per_tick_in_input_order() {
+        let a = Observable::new(0);
+        let b = Observable::new(0);
+        let c = Observable::new(0);
+        let mut merged = merge(vec![a.subscribe(), b.subscribe(), c.subscribe()]);
+        assert_eq!(merged.attach().get(), None);
+        let mut output = updates(&mut merged);
+
+        merged.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        c.set(3);
+        a.set(1);
+        b.set(2);
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            merged.tick();
+            if let Poll::Ready(Some(value)) = poll_update(&mut output) {
+                seen.push(value);
+            }
+        }
+        assert_eq!(seen, vec![Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn merge_drops_the_oldest_values_when_it_falls_behind() {
+        let a = Observable::new(0);
+        let b = Observable::new(0);
+        let mut merged = merge(vec![a.subscribe(), b.subscribe()]);
+        let mut output = updates(&mut merged);
+
+        // Two values in and one out every tick
+        let mut seen = Vec::new();
+        for i in 0..40 {
+            a.set(2 * i);
+            b.set(2 * i + 1);
+            merged.tick();
+            if let Poll::Ready(Some(Some(value))) = poll_update(&mut output) {
+                seen.push(value);
+            }
+        }
+        let published = seen.len();
+        assert_eq!(published, 40);
+        for _ in 0..40 {
+            merged.tick();
+            if let Poll::Ready(Some(Some(value))) = poll_update(&mut output) {
+                seen.push(value);
+            }
+        }
+        // The tick that publishes frees one place in the full queue
+        assert_eq!(seen.len() - published, MAX_QUEUED - 1);
+        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(seen.last(), Some(&79));
+    }
+
+    #[test]
+    fn sample_publishes_the_latest_value_on_each_trigger() {
+        let value = Observable::new(5.0);
+        let trigger = Observable::new(());
+        let mut sampled = sample(value.subscribe(), trigger.subscribe());
+        assert_eq!(sampled.attach().get(), 5.0);
+        let mut output = updates(&mut sampled);
+
+        value.set(6.0);
+        sampled.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        value.set(7.0);
+        sampled.tick();
+        trigger.set(());
+        sampled.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(7.0)));
+
+        // Triggers repeat an unchanged value
+        trigger.set(());
+        sampled.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(7.0)));
+
+        // A value and trigger on the same tick publish the new value
+        value.set(8.0);
+        trigger.set(());
+        sampled.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(8.0)));
+    }
+}
@@ -0,0 +1,151 @@
+//! Settings and state that persist across reboots
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::EspError;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// String values stored under short keys.
+///
+/// Stores are shared by cloning, every component that persists something holds its own handle.
+/// Keep keys to 15 characters, the longest NVS accepts.
+pub trait ConfigStore {
+    type Error: fmt::Debug;
+
+    fn get(&self, key: &str) -> Result<Option<String>, Self::Error>;
+
+    fn set(&self, key: &str, value: &str) -> Result<(), Self::Error>;
+
+    fn remove(&self, key: &str) -> Result<(), Self::Error>;
+}
+
+/// Read and parse the value stored under `key`.
+///
+/// Returns `None` when there is no value; read errors and values that fail to parse are logged
+/// and also treated as missing.
+pub fn load<S: ConfigStore, T: FromStr>(store: &S, key: &str) -> Option<T> {
+    match store.get(key) {
+        Ok(Some(value)) => match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                log::error!("Ignoring invalid config value for {}: {:?}", key, value);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("Config read error for {}: {:?}", key, e);
+            None
+        }
+    }
+}
+
+/// Store `value` under `key` in its `Display` form.
+pub fn save<S: ConfigStore, T: fmt::Display>(
+    store: &S,
+    key: &str,
+    value: &T,
+) -> Result<(), S::Error> {
+    store.set(key, &value.to_string())
+}
+
+/// A store that lives in RAM, for tests and devices without flash to spare.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    values: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ConfigStore for MemoryStore {
+    type Error = core::convert::Infallible;
+
+    fn get(&self, key: &str) -> Result<Option<String>, Self::Error> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Self::Error> {
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// A store in a namespace of the default NVS partition.
+#[cfg(target_os = "espidf")]
+#[derive(Clone)]
+pub struct NvsStore {
+    nvs: Arc<Mutex<EspNvs<NvsDefault>>>,
+}
+
+#[cfg(target_os = "espidf")]
+impl NvsStore {
+    pub fn new(partition: EspDefaultNvsPartition, namespace: &str) -> Result<Self, EspError> {
+        Ok(NvsStore {
+            nvs: Arc::new(Mutex::new(EspNvs::new(partition, namespace, true)?)),
+        })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl ConfigStore for NvsStore {
+    type Error = EspError;
+
+    fn get(&self, key: &str) -> Result<Option<String>, Self::Error> {
+        let nvs = self.nvs.lock().unwrap();
+        match nvs.str_len(key)? {
+            Some(len) => {
+                let mut buf = vec![0; len];
+                Ok(nvs.get_str(key, &mut buf)?.map(str::to_string))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.nvs.lock().unwrap().set_str(key, value)
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Self::Error> {
+        self.nvs.lock().unwrap().remove(key)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip_through_a_store() {
+        let store = MemoryStore::new();
+        save(&store, "hours", &3600.5f64).unwrap();
+        assert_eq!(load::<_, f64>(&store, "hours"), Some(3600.5));
+        // Clones share the values
+        assert_eq!(load::<_, f64>(&store.clone(), "hours"), Some(3600.5));
+    }
+
+    #[test]
+    fn missing_or_invalid_values_load_as_none() {
+        let store = MemoryStore::new();
+        assert_eq!(load::<_, f32>(&store, "soc"), None);
+        store.set("soc", "full").unwrap();
+        assert_eq!(load::<_, f32>(&store, "soc"), None);
+        store.remove("soc").unwrap();
+        assert_eq!(store.get("soc"), Ok(None));
+    }
+}
@@ -0,0 +1,214 @@
+//! Piecewise linear calibration curves
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use eyeball::{shared::Observable, Subscriber};
+use std::fmt;
+use std::str::FromStr;
+use std::task::Poll;
+
+/// Maps readings to values by interpolating between calibration points.
+///
+/// Readings outside the points are clamped to the first or last value, a sender can't read
+/// beyond its end stops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    points: Vec<(f32, f32)>,
+}
+
+impl Curve {
+    pub fn new() -> Self {
+        Curve { points: Vec::new() }
+    }
+
+    /// Add a calibration point mapping `input` to `output`.
+    pub fn point(mut self, input: f32, output: f32) -> Self {
+        let index = self.points.partition_point(|(x, _)| *x < input);
+        self.points.insert(index, (input, output));
+        self
+    }
+
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points
+    }
+
+    /// The interpolated output for `input`, NaN for a curve without points or a NaN input.
+    pub fn apply(&self, input: f32) -> f32 {
+        let index = self.points.partition_point(|(x, _)| *x < input);
+        match (index, self.points.len()) {
+            (_, 0) => f32::NAN,
+            _ if input.is_nan() => f32::NAN,
+            (0, _) => self.points[0].1,
+            (i, len) if i == len => self.points[len - 1].1,
+            (i, _) => {
+                let (x0, y0) = self.points[i - 1];
+                let (x1, y1) = self.points[i];
+                y0 + (y1 - y0) * (input - x0) / (x1 - x0)
+            }
+        }
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Written as `input:output` pairs separated by commas, e.g. `240:0,33:1`.
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(x, y)| format!("{}:{}", x, y))
+            .collect();
+        write!(f, "{}", points.join(","))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCurveError;
+
+impl FromStr for Curve {
+    type Err = ParseCurveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut curve = Curve::new();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (x, y) = pair.split_once(':').ok_or(ParseCurveError)?;
+            let x = x.trim().parse().map_err(|_| ParseCurveError)?;
+            let y = y.trim().parse().map_err(|_| ParseCurveError)?;
+            curve = curve.point(x, y);
+        }
+        Ok(curve)
+    }
+}
+
+/// Applies a curve to every value of a stream.
+pub struct CurveInterpolator {
+    subscriber: Updates<f32>,
+    curve: Curve,
+    observable: Observable<f32>,
+}
+
+impl CurveInterpolator {
+    pub fn new(subscriber: Subscriber<f32>, curve: Curve) -> Self {
+        CurveInterpolator {
+            observable: Observable::new(curve.apply(subscriber.get())),
+            subscriber: subscriber.into(),
+            curve,
+        }
+    }
+
+    pub fn set_curve(&mut self, curve: Curve) {
+        self.curve = curve;
+    }
+}
+
+impl Attachable<f32> for CurveInterpolator {
+    fn attach(&mut self) -> Subscriber<f32> {
+        self.observable.subscribe()
+    }
+}
+
+impl SensESPSensor for CurveInterpolator {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(value)) = poll_update(&mut self.subscriber) {
+            self.observable.set(self.curve.apply(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve() -> Curve {
+        Curve::new()
+            .point(0.0, 0.0)
+            .point(10.0, 100.0)
+            .point(20.0, 150.0)
+    }
+
+    #[test]
+    fn points_are_kept_in_input_order() {
+        let curve = Curve::new()
+            .point(20.0, 150.0)
+            .point(0.0, 0.0)
+            .point(10.0, 100.0);
+        assert_eq!(curve.points(), &[(0.0, 0.0), (10.0, 100.0), (20.0, 150.0)]);
+        assert_eq!(curve, self::curve());
+    }
+
+    #[test]
+    fn apply_interpolates_between_points() {
+        let curve = curve();
+        assert_eq!(curve.apply(0.0), 0.0);
+        assert_eq!(curve.apply(5.0), 50.0);
+        assert_eq!(curve.apply(10.0), 100.0);
+        assert_eq!(curve.apply(15.0), 125.0);
+        assert_eq!(curve.apply(20.0), 150.0);
+    }
+
+    #[test]
+    fn apply_follows_falling_curves() {
+        // A sender reading 240 ohms empty and 33 ohms full
+        let curve = Curve::new().point(240.0, 0.0).point(33.0, 1.0);
+        assert_eq!(curve.apply(240.0), 0.0);
+        assert!((curve.apply(136.5) - 0.5).abs() < 1e-6);
+        assert_eq!(curve.apply(33.0), 1.0);
+    }
+
+    #[test]
+    fn apply_clamps_outside_the_points() {
+        let curve = curve();
+        assert_eq!(curve.apply(-5.0), 0.0);
+        assert_eq!(curve.apply(25.0), 150.0);
+        assert_eq!(curve.apply(f32::NEG_INFINITY), 0.0);
+        assert_eq!(curve.apply(f32::INFINITY), 150.0);
+    }
+
+    #[test]
+    fn apply_without_an_answer_is_nan() {
+        assert!(Curve::new().apply(1.0).is_nan());
+        assert!(curve().apply(f32::NAN).is_nan());
+        // A single point maps everything to its output
+        assert_eq!(Curve::new().point(3.0, 7.0).apply(-100.0), 7.0);
+    }
+
+    #[test]
+    fn curve_round_trips_through_text() {
+        assert_eq!(curve().to_string(), "0:0,10:100,20:150");
+        assert_eq!("0:0,10:100,20:150".parse(), Ok(curve()));
+        assert_eq!(" 20 : 150 , 0:0,10:100, ".parse(), Ok(curve()));
+        assert_eq!("".parse(), Ok(Curve::new()));
+        assert_eq!(Curve::new().to_string(), "");
+        let fractions = Curve::new().point(0.25, -1.5);
+        assert_eq!(fractions.to_string().parse(), Ok(fractions));
+    }
+
+    #[test]
+    fn malformed_text_is_rejected() {
+        for text in ["1", "1:", ":1", "a:1", "1:b", "1:2:3", "1;2"] {
+            assert_eq!(text.parse::<Curve>(), Err(ParseCurveError), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn interpolator_applies_the_curve_to_each_value() {
+        let input = Observable::new(5.0);
+        let mut interpolator = CurveInterpolator::new(input.subscribe(), curve());
+        let mut output = Updates::new(interpolator.attach());
+        assert_eq!(interpolator.attach().get(), 50.0);
+
+        input.set(15.0);
+        interpolator.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(125.0)));
+
+        interpolator.set_curve(Curve::new().point(0.0, 0.0).point(20.0, 2.0));
+        interpolator.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        input.set(15.0);
+        interpolator.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(1.5)));
+    }
+}
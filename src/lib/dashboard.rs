@@ -0,0 +1,614 @@
+//! Sensor dashboards for small monochrome displays such as the SSD1306
+use crate::sensor::{poll_update, Updates};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Polyline, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use eyeball::Subscriber;
+use std::collections::VecDeque;
+use std::task::Poll;
+use std::time::Duration;
+
+const SMALL: MonoTextStyle<'static, BinaryColor> = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+const LARGE: MonoTextStyle<'static, BinaryColor> = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+
+/// How a widget presents its stream.
+#[derive(Debug, Clone)]
+enum Kind {
+    Value {
+        label: String,
+        unit: String,
+    },
+    Bar {
+        label: String,
+        min: f32,
+        max: f32,
+    },
+    Sparkline {
+        label: String,
+        history: VecDeque<f32>,
+        capacity: usize,
+    },
+    BigNumber {
+        label: String,
+        unit: String,
+    },
+}
+
+/// A sensor stream bound to a way of drawing it.
+pub struct Widget {
+    subscriber: Updates<f32>,
+    value: f32,
+    decimals: usize,
+    kind: Kind,
+}
+
+impl Widget {
+    fn new(subscriber: Subscriber<f32>, mut kind: Kind) -> Self {
+        // Start from the stream's current value rather than waiting for the next one
+        let value = subscriber.get();
+        if let Kind::Sparkline { history, .. } = &mut kind {
+            history.push_back(value);
+        }
+        Self {
+            subscriber: subscriber.into(),
+            value,
+            decimals: 1,
+            kind,
+        }
+    }
+
+    /// One line of text: `label: value unit`.
+    pub fn value(label: &str, unit: &str, subscriber: Subscriber<f32>) -> Self {
+        Self::new(
+            subscriber,
+            Kind::Value {
+                label: label.to_string(),
+                unit: unit.to_string(),
+            },
+        )
+    }
+
+    /// A horizontal gauge filled in proportion to the value between `min` and `max`.
+    pub fn bar(label: &str, min: f32, max: f32, subscriber: Subscriber<f32>) -> Self {
+        Self::new(
+            subscriber,
+            Kind::Bar {
+                label: label.to_string(),
+                min,
+                max,
+            },
+        )
+    }
+
+    /// A line graph of the last `capacity` values, scaled to their own range.
+    pub fn sparkline(label: &str, capacity: usize, subscriber: Subscriber<f32>) -> Self {
+        Self::new(
+            subscriber,
+            Kind::Sparkline {
+                label: label.to_string(),
+                history: VecDeque::with_capacity(capacity),
+                capacity: capacity.max(2),
+            },
+        )
+    }
+
+    /// The value in a large font under a small label, best given a page of its own.
+    pub fn big_number(label: &str, unit: &str, subscriber: Subscriber<f32>) -> Self {
+        Self::new(
+            subscriber,
+            Kind::BigNumber {
+                label: label.to_string(),
+                unit: unit.to_string(),
+            },
+        )
+    }
+
+    /// Number of decimal places shown, 1 by default.
+    pub fn decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    pub fn current(&self) -> f32 {
+        self.value
+    }
+
+    /// Take the latest value from the stream, returning true when the widget needs redrawing.
+    pub fn update(&mut self) -> bool {
+        let value = match poll_update(&mut self.subscriber) {
+            Poll::Ready(Some(value)) => value,
+            _ => return false,
+        };
+        let changed = match &mut self.kind {
+            Kind::Sparkline {
+                history, capacity, ..
+            } => {
+                if history.len() >= *capacity {
+                    history.pop_front();
+                }
+                history.push_back(value);
+                true
+            }
+            _ => self.value != value,
+        };
+        self.value = value;
+        changed
+    }
+
+    /// Height in pixels the widget takes in a page.
+    pub fn height(&self) -> u32 {
+        match self.kind {
+            Kind::Value { .. } => 11,
+            Kind::Bar { .. } => 12,
+            Kind::Sparkline { .. } => 24,
+            Kind::BigNumber { .. } => 32,
+        }
+    }
+
+    fn text(&self) -> String {
+        format!("{:.*}", self.decimals, self.value)
+    }
+
+    /// Draw the widget into `area` of the target.
+    pub fn draw<D>(&self, target: &mut D, area: Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let origin = area.top_left;
+        match &self.kind {
+            Kind::Value { label, unit } => {
+                let line = format!("{}: {} {}", label, self.text(), unit);
+                Text::with_baseline(line.trim_end(), origin, SMALL, Baseline::Top).draw(target)?;
+            }
+            Kind::Bar { label, min, max } => {
+                let next = Text::with_baseline(label, origin, SMALL, Baseline::Top).draw(target)?;
+                let left = next.x + 4;
+                let width = (area.top_left.x + area.size.width as i32 - left).max(0) as u32;
+                let outline = Rectangle::new(Point::new(left, origin.y), Size::new(width, 10));
+                outline
+                    .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                    .draw(target)?;
+                let fraction = match max > min {
+                    true => ((self.value - min) / (max - min)).clamp(0.0, 1.0),
+                    false => 0.0,
+                };
+                let fill = (width.saturating_sub(4) as f32 * fraction).round() as u32;
+                Rectangle::new(Point::new(left + 2, origin.y + 2), Size::new(fill, 6))
+                    .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                    .draw(target)?;
+            }
+            Kind::Sparkline { label, history, .. } => {
+                let line = format!("{} {}", label, self.text());
+                Text::with_baseline(&line, origin, SMALL, Baseline::Top).draw(target)?;
+                let graph = Rectangle::new(
+                    origin + Point::new(0, 11),
+                    Size::new(area.size.width, area.size.height.saturating_sub(12)),
+                );
+                let points = sparkline_points(history, graph);
+                if points.len() > 1 {
+                    Polyline::new(&points)
+                        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                        .draw(target)?;
+                }
+            }
+            Kind::BigNumber { label, unit } => {
+                Text::with_baseline(label, origin, SMALL, Baseline::Top).draw(target)?;
+                let next = Text::with_baseline(
+                    &self.text(),
+                    origin + Point::new(0, 11),
+                    LARGE,
+                    Baseline::Top,
+                )
+                .draw(target)?;
+                Text::with_baseline(unit, next + Point::new(2, 6), SMALL, Baseline::Top)
+                    .draw(target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scale a history of values onto the pixels of `area`, oldest on the left.
+pub fn sparkline_points(history: &VecDeque<f32>, area: Rectangle) -> Vec<Point> {
+    if history.len() < 2 || area.size.width < 2 || area.size.height < 2 {
+        return Vec::new();
+    }
+    let min = history.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = history.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let span = max - min;
+    let x_step = (area.size.width - 1) as f32 / (history.len() - 1) as f32;
+    let bottom = (area.size.height - 1) as f32;
+    history
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let level = match span > 0.0 {
+                true => (v - min) / span,
+                false => 0.5,
+            };
+            area.top_left
+                + Point::new(
+                    (i as f32 * x_step).round() as i32,
+                    (bottom - level * bottom).round() as i32,
+                )
+        })
+        .collect()
+}
+
+/// Widgets stacked top to bottom on one screen.
+#[derive(Default)]
+pub struct Page {
+    widgets: Vec<Widget>,
+}
+
+impl Page {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn widget(mut self, widget: Widget) -> Self {
+        self.widgets.push(widget);
+        self
+    }
+
+    fn update(&mut self) -> bool {
+        let mut changed = false;
+        for w in &mut self.widgets {
+            changed |= w.update();
+        }
+        changed
+    }
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let bounds = target.bounding_box();
+        let mut top = bounds.top_left.y;
+        for w in &self.widgets {
+            let area = Rectangle::new(
+                Point::new(bounds.top_left.x, top),
+                Size::new(bounds.size.width, w.height()),
+            );
+            w.draw(target, area)?;
+            top += w.height() as i32;
+        }
+        Ok(())
+    }
+}
+
+/// A set of pages shown one at a time, optionally rotating on a fixed interval.
+pub struct Dashboard {
+    pages: Vec<Page>,
+    current: usize,
+    rotation: Option<Duration>,
+    shown_at: Duration,
+    dirty: bool,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            current: 0,
+            rotation: None,
+            shown_at: Duration::ZERO,
+            dirty: true,
+        }
+    }
+
+    pub fn page(mut self, page: Page) -> Self {
+        self.pages.push(page);
+        self
+    }
+
+    /// Advance to the next page every `interval`.
+    pub fn rotate(mut self, interval: Duration) -> Self {
+        self.rotation = Some(interval);
+        self
+    }
+
+    pub fn current_page(&self) -> usize {
+        self.current
+    }
+
+    /// Show the page at `index`, wrapping past the last page.
+    pub fn show_page(&mut self, index: usize, now: Duration) {
+        if !self.pages.is_empty() {
+            self.current = index % self.pages.len();
+            self.shown_at = now;
+            self.dirty = true;
+        }
+    }
+
+    /// Read every widget and rotate pages at time `now`, returning true when the screen
+    /// needs redrawing.
+    ///
+    /// Widgets on hidden pages are read too, so sparklines keep their history.
+    pub fn update(&mut self, now: Duration) -> bool {
+        for (i, page) in self.pages.iter_mut().enumerate() {
+            let changed = page.update();
+            self.dirty |= changed && i == self.current;
+        }
+        let elapsed = now.saturating_sub(self.shown_at);
+        if self.rotation.is_some_and(|interval| elapsed >= interval) {
+            self.show_page(self.current + 1, now);
+        }
+        self.dirty
+    }
+
+    /// Clear the target and draw the current page.
+    pub fn draw<D>(&mut self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        target.clear(BinaryColor::Off)?;
+        if let Some(page) = self.pages.get(self.current) {
+            page.draw(target)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Update and redraw only if something changed, returning whether the target was drawn
+    /// so buffered displays know to flush.
+    pub fn refresh<D>(&mut self, target: &mut D, now: Duration) -> Result<bool, D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        match self.update(now) {
+            true => self.draw(target).map(|_| true),
+            false => Ok(false),
+        }
+    }
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::mock_display::MockDisplay;
+    use eyeball::shared::Observable;
+
+    fn display() -> MockDisplay<BinaryColor> {
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        display.set_allow_out_of_bounds_drawing(true);
+        display
+    }
+
+    /// A cleared display with `draw` applied, to compare a dashboard against.
+    fn expected(draw: impl FnOnce(&mut MockDisplay<BinaryColor>)) -> MockDisplay<BinaryColor> {
+        let mut display = display();
+        display.clear(BinaryColor::Off).unwrap();
+        draw(&mut display);
+        display
+    }
+
+    fn text(display: &mut MockDisplay<BinaryColor>, s: &str, x: i32, y: i32) -> Point {
+        Text::with_baseline(s, Point::new(x, y), SMALL, Baseline::Top)
+            .draw(display)
+            .unwrap()
+    }
+
+    fn outline(display: &mut MockDisplay<BinaryColor>, x: i32, y: i32, w: u32, h: u32) {
+        Rectangle::new(Point::new(x, y), Size::new(w, h))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(display)
+            .unwrap();
+    }
+
+    fn fill(display: &mut MockDisplay<BinaryColor>, x: i32, y: i32, w: u32, h: u32) {
+        Rectangle::new(Point::new(x, y), Size::new(w, h))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(display)
+            .unwrap();
+    }
+
+    fn draw(dashboard: &mut Dashboard) -> MockDisplay<BinaryColor> {
+        let mut display = display();
+        dashboard.draw(&mut display).unwrap();
+        display
+    }
+
+    #[test]
+    fn values_stack_down_the_page() {
+        let temperature = Observable::new(21.04f32);
+        let voltage = Observable::new(12.345f32);
+        let mut dashboard = Dashboard::new().page(
+            Page::new()
+                .widget(Widget::value("Temp", "C", temperature.subscribe()))
+                .widget(Widget::value("Volts", "", voltage.subscribe()).decimals(2)),
+        );
+        assert_eq!(
+            draw(&mut dashboard),
+            expected(|d| {
+                text(d, "Temp: 21.0 C", 0, 0);
+                text(d, "Volts: 12.35", 0, 11);
+            })
+        );
+    }
+
+    #[test]
+    fn bar_fills_in_proportion() {
+        let level = Observable::new(5.0f32);
+        let mut dashboard = Dashboard::new().page(Page::new().widget(Widget::bar(
+            "B",
+            0.0,
+            10.0,
+            level.subscribe(),
+        )));
+        // The label is 6 pixels wide, leaving a 54 pixel gauge after a 4 pixel gap
+        let gauge = |filled| {
+            expected(move |d| {
+                text(d, "B", 0, 0);
+                outline(d, 10, 0, 54, 10);
+                fill(d, 12, 2, filled, 6);
+            })
+        };
+        assert_eq!(draw(&mut dashboard), gauge(25));
+
+        level.set(20.0);
+        assert!(dashboard.update(Duration::ZERO));
+        assert_eq!(draw(&mut dashboard), gauge(50));
+
+        level.set(-3.0);
+        assert!(dashboard.update(Duration::ZERO));
+        assert_eq!(draw(&mut dashboard), gauge(0));
+    }
+
+    #[test]
+    fn big_number_shows_label_value_and_unit() {
+        let rpm = Observable::new(1850.4f32);
+        let mut dashboard = Dashboard::new().page(
+            Page::new().widget(Widget::big_number("RPM", "rpm", rpm.subscribe()).decimals(0)),
+        );
+        assert_eq!(
+            draw(&mut dashboard),
+            expected(|d| {
+                text(d, "RPM", 0, 0);
+                let next = Text::with_baseline("1850", Point::new(0, 11), LARGE, Baseline::Top)
+                    .draw(d)
+                    .unwrap();
+                text(d, "rpm", next.x + 2, next.y + 6);
+            })
+        );
+    }
+
+    #[test]
+    fn sparkline_scales_history_to_the_area() {
+        let area = Rectangle::new(Point::new(0, 20), Size::new(11, 5));
+        let history: VecDeque<f32> = [0.0, 5.0, 10.0].into();
+        assert_eq!(
+            sparkline_points(&history, area),
+            [Point::new(0, 24), Point::new(5, 22), Point::new(10, 20)]
+        );
+        // A flat history runs through the middle
+        let flat: VecDeque<f32> = [3.0, 3.0].into();
+        assert_eq!(
+            sparkline_points(&flat, area),
+            [Point::new(0, 22), Point::new(10, 22)]
+        );
+        let single: VecDeque<f32> = [3.0].into();
+        assert!(sparkline_points(&single, area).is_empty());
+    }
+
+    #[test]
+    fn sparkline_keeps_its_capacity() {
+        let speed = Observable::new(0.0f32);
+        let mut widget = Widget::sparkline("S", 3, speed.subscribe());
+        for v in [1.0, 2.0, 3.0, 4.0] {
+            speed.set(v);
+            assert!(widget.update());
+        }
+        let Kind::Sparkline { history, .. } = &widget.kind else {
+            unreachable!()
+        };
+        assert_eq!(history, &VecDeque::from([2.0, 3.0, 4.0]));
+
+        // Equal values still extend the graph
+        speed.set(4.0);
+        assert!(widget.update());
+        assert_eq!(widget.current(), 4.0);
+    }
+
+    #[test]
+    fn refresh_draws_only_on_change() {
+        let value = Observable::new(1.0f32);
+        let mut dashboard =
+            Dashboard::new().page(Page::new().widget(Widget::value("T", "", value.subscribe())));
+
+        let mut first = display();
+        assert_eq!(dashboard.refresh(&mut first, Duration::ZERO), Ok(true));
+        assert_eq!(
+            first,
+            expected(|d| {
+                text(d, "T: 1.0", 0, 0);
+            })
+        );
+
+        let mut untouched = display();
+        assert_eq!(
+            dashboard.refresh(&mut untouched, Duration::from_secs(1)),
+            Ok(false)
+        );
+        // Setting the same value is not a change
+        value.set(1.0);
+        assert_eq!(
+            dashboard.refresh(&mut untouched, Duration::from_secs(2)),
+            Ok(false)
+        );
+        assert_eq!(untouched, display());
+
+        value.set(2.5);
+        let mut changed = display();
+        assert_eq!(
+            dashboard.refresh(&mut changed, Duration::from_secs(3)),
+            Ok(true)
+        );
+        assert_eq!(
+            changed,
+            expected(|d| {
+                text(d, "T: 2.5", 0, 0);
+            })
+        );
+    }
+
+    #[test]
+    fn pages_rotate_and_hidden_pages_stay_current() {
+        let shown = Observable::new(1.0f32);
+        let hidden = Observable::new(10.0f32);
+        let mut dashboard = Dashboard::new()
+            .page(Page::new().widget(Widget::value("A", "", shown.subscribe())))
+            .page(Page::new().widget(Widget::value("B", "", hidden.subscribe())))
+            .rotate(Duration::from_secs(5));
+        assert!(dashboard.refresh(&mut display(), Duration::ZERO).unwrap());
+
+        // A change on the hidden page does not redraw the current one
+        hidden.set(20.0);
+        assert!(!dashboard
+            .refresh(&mut display(), Duration::from_secs(1))
+            .unwrap());
+
+        assert!(dashboard
+            .refresh(&mut display(), Duration::from_secs(5))
+            .unwrap());
+        assert_eq!(dashboard.current_page(), 1);
+        assert_eq!(
+            draw(&mut dashboard),
+            expected(|d| {
+                text(d, "B: 20.0", 0, 0);
+            })
+        );
+
+        // Rotation wraps past the last page
+        assert!(dashboard
+            .refresh(&mut display(), Duration::from_secs(10))
+            .unwrap());
+        assert_eq!(dashboard.current_page(), 0);
+
+        dashboard.show_page(3, Duration::from_secs(11));
+        assert_eq!(dashboard.current_page(), 1);
+    }
+
+    #[test]
+    fn empty_dashboard_clears_the_display() {
+        let mut dashboard = Dashboard::default();
+        assert!(dashboard.update(Duration::ZERO));
+        assert_eq!(draw(&mut dashboard), expected(|_| ()));
+        dashboard.show_page(2, Duration::ZERO);
+        assert_eq!(dashboard.current_page(), 0);
+    }
+}
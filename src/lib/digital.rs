@@ -0,0 +1,905 @@
+//! Digital input sensors and output actuators
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+#[cfg(target_os = "espidf")]
+use embedded_hal::digital::ErrorType;
+use embedded_hal::digital::{InputPin as HalInputPin, OutputPin as HalOutputPin, PinState};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::gpio::{Input, InputPin, InterruptType, PinDriver};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::pcnt::{
+    PcntChannel, PcntChannelConfig, PcntControlMode, PcntCountMode, PcntDriver, PcntEvent,
+    PcntEventType, PinIndex,
+};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::sys::EspError;
+use eyeball::{shared::Observable, Subscriber};
+#[cfg(target_os = "espidf")]
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+#[cfg(target_os = "espidf")]
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+/// The PCNT counter restarts from zero when it reaches this limit.
+#[cfg(any(target_os = "espidf", test))]
+const PCNT_LIMIT: i16 = i16::MAX;
+
+/// A source of edge counts, such as a GPIO interrupt or the PCNT peripheral.
+pub trait PulseSource {
+    /// Return the number of edges seen since the previous call and reset the count.
+    fn take_count(&mut self) -> u32;
+}
+
+/// Subscribe `callback` to the pin's interrupt and keep the interrupt armed.
+///
+/// esp-idf-hal disables the pin interrupt every time it fires, so it is re-armed from the
+/// handler itself to avoid missing edges between ticks.
+#[cfg(target_os = "espidf")]
+fn subscribe_armed<T, F>(
+    pin: &mut PinDriver<'_, T, Input>,
+    edge: InterruptType,
+    mut callback: F,
+) -> Result<(), EspError>
+where
+    T: InputPin,
+    F: FnMut() + Send + 'static,
+{
+    let pin_number = pin.pin();
+    pin.set_interrupt_type(edge)?;
+    // SAFETY: the handlers only touch atomics and the interrupt enable of their own pin
+    unsafe {
+        pin.subscribe(move || {
+            callback();
+            esp_idf_hal::sys::gpio_intr_enable(pin_number);
+        })?;
+    }
+    pin.enable_interrupt()
+}
+
+/// Counts edges on a GPIO pin from its interrupt handler.
+#[cfg(target_os = "espidf")]
+pub struct InterruptPulseSource<'d, T: InputPin> {
+    _pin: PinDriver<'d, T, Input>,
+    count: Arc<AtomicU32>,
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d, T: InputPin> InterruptPulseSource<'d, T> {
+    pub fn new(mut pin: PinDriver<'d, T, Input>, edge: InterruptType) -> Result<Self, EspError> {
+        let count = Arc::new(AtomicU32::new(0));
+        let isr_count = count.clone();
+        subscribe_armed(&mut pin, edge, move || {
+            isr_count.fetch_add(1, Ordering::Relaxed);
+        })?;
+
+        Ok(Self { _pin: pin, count })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl<T: InputPin> PulseSource for InterruptPulseSource<'_, T> {
+    fn take_count(&mut self) -> u32 {
+        self.count.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Counts rising edges with one unit of the ESP32 pulse counter peripheral.
+///
+/// The counter is never cleared, which would drop the edges arriving between the read and
+/// the clear. Instead every read is compared to the previous one, and the interrupt raised
+/// when the 16 bit counter reaches its limit extends it to 32 bits.
+#[cfg(target_os = "espidf")]
+pub struct PcntPulseSource<'d> {
+    driver: PcntDriver<'d>,
+    wraps: Arc<AtomicU32>,
+    last: u32,
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d> PcntPulseSource<'d> {
+    /// Configure channel 0 of `driver` to count rising edges on its first pin.
+    ///
+    /// `filter` ignores glitches shorter than the given number of APB clock cycles (max 1023).
+    pub fn new(mut driver: PcntDriver<'d>, filter: u16) -> Result<Self, EspError> {
+        driver.channel_config(
+            PcntChannel::Channel0,
+            PinIndex::Pin0,
+            PinIndex::Pin1,
+            &PcntChannelConfig {
+                lctrl_mode: PcntControlMode::Keep,
+                hctrl_mode: PcntControlMode::Keep,
+                pos_mode: PcntCountMode::Increment,
+                neg_mode: PcntCountMode::Hold,
+                counter_h_lim: PCNT_LIMIT,
+                counter_l_lim: 0,
+            },
+        )?;
+        if filter > 0 {
+            driver.set_filter_value(filter.min(1023))?;
+            driver.filter_enable()?;
+        }
+
+        let wraps = Arc::new(AtomicU32::new(0));
+        let isr_wraps = wraps.clone();
+        // SAFETY: the handler only touches an atomic
+        unsafe {
+            driver.subscribe(move |status| {
+                if PcntEventType::from_repr_truncated(status).contains(PcntEvent::HighLimit) {
+                    isr_wraps.fetch_add(1, Ordering::Relaxed);
+                }
+            })?;
+        }
+        driver.event_enable(PcntEvent::HighLimit)?;
+        driver.intr_enable()?;
+
+        driver.counter_pause()?;
+        driver.counter_clear()?;
+        driver.counter_resume()?;
+
+        Ok(Self {
+            driver,
+            wraps,
+            last: 0,
+        })
+    }
+
+    /// Edges counted since the unit was configured, modulo 2^32.
+    fn total(&self) -> Result<u32, EspError> {
+        loop {
+            let wraps = self.wraps.load(Ordering::Acquire);
+            let counter = self.driver.get_counter_value()?;
+            // Read again if the counter wrapped in between
+            if self.wraps.load(Ordering::Acquire) == wraps {
+                return Ok(pcnt_total(wraps, counter));
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl PulseSource for PcntPulseSource<'_> {
+    fn take_count(&mut self) -> u32 {
+        match self.total() {
+            Ok(total) => count_since(&mut self.last, total),
+            Err(e) => {
+                log::error!("Pulse counter read error: {:?}", e);
+                0
+            }
+        }
+    }
+}
+
+/// Extend the PCNT counter with the number of times it reached its limit.
+#[cfg(any(target_os = "espidf", test))]
+fn pcnt_total(wraps: u32, counter: i16) -> u32 {
+    wraps
+        .wrapping_mul(PCNT_LIMIT as u32)
+        .wrapping_add(counter.max(0) as u32)
+}
+
+/// Edges counted between `last` and `total`, and move `last` forward.
+///
+/// The counter restarts from zero a moment before its interrupt counts the wrap, so a total
+/// that appears to have gone back is left to catch up on the next read.
+#[cfg(any(target_os = "espidf", test))]
+fn count_since(last: &mut u32, total: u32) -> u32 {
+    match last.wrapping_sub(total) {
+        behind if behind > 0 && behind <= PCNT_LIMIT as u32 => 0,
+        _ => {
+            let count = total.wrapping_sub(*last);
+            *last = total;
+            count
+        }
+    }
+}
+
+/// Convert a pulse count over `elapsed` into a rate, scaled by `multiplier`.
+///
+/// A multiplier of 1.0 yields Hz, 60.0 / pulses-per-revolution yields RPM.
+pub fn pulse_rate(count: u32, elapsed: Duration, multiplier: f32) -> f32 {
+    let secs = elapsed.as_secs_f32();
+    if secs <= 0.0 {
+        return 0.0;
+    }
+    count as f32 / secs * multiplier
+}
+
+/// Reports the pulses counted per interval and the resulting frequency.
+pub struct DigitalInputCounter<P: PulseSource> {
+    counts: Observable<u32>,
+    frequency: Observable<f32>,
+    source: P,
+    multiplier: f32,
+    duration: Duration,
+    last_measurement: SystemTime,
+}
+
+impl<P: PulseSource> DigitalInputCounter<P> {
+    /// Create a counter reporting every `duration`, with the frequency scaled by `multiplier`.
+    pub fn new(source: P, multiplier: f32, duration: Duration) -> Self {
+        DigitalInputCounter::<P> {
+            counts: Observable::new(0),
+            frequency: Observable::new(0.0),
+            source,
+            multiplier,
+            duration,
+            last_measurement: SystemTime::now(),
+        }
+    }
+
+    /// Subscribe to the scaled frequency, e.g. Hz or RPM.
+    pub fn attach_frequency(&mut self) -> Subscriber<f32> {
+        self.frequency.subscribe()
+    }
+
+    /// Read the pulses accumulated over `elapsed` and publish the count and frequency.
+    pub fn update(&mut self, elapsed: Duration) {
+        let count = self.source.take_count();
+        self.counts.set(count);
+        self.frequency
+            .set(pulse_rate(count, elapsed, self.multiplier));
+    }
+}
+
+impl<P: PulseSource> Attachable<u32> for DigitalInputCounter<P> {
+    fn attach(&mut self) -> Subscriber<u32> {
+        self.counts.subscribe()
+    }
+}
+
+impl<P: PulseSource> SensESPSensor for DigitalInputCounter<P> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        match now.duration_since(self.last_measurement) {
+            Ok(d) => {
+                if d >= self.duration {
+                    self.update(d);
+                    self.last_measurement = now;
+                }
+            }
+            Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+/// A pin that can report whether its level changed since it was last asked.
+pub trait EdgeNotify {
+    /// Return true if an edge was seen since the previous call and clear the flag.
+    fn take_edge(&mut self) -> bool;
+}
+
+/// A GPIO input that flags level changes from its interrupt handler.
+#[cfg(target_os = "espidf")]
+pub struct InterruptInput<'d, T: InputPin> {
+    pin: PinDriver<'d, T, Input>,
+    edge: Arc<AtomicBool>,
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d, T: InputPin> InterruptInput<'d, T> {
+    pub fn new(mut pin: PinDriver<'d, T, Input>) -> Result<Self, EspError> {
+        let edge = Arc::new(AtomicBool::new(false));
+        let isr_edge = edge.clone();
+        subscribe_armed(&mut pin, InterruptType::AnyEdge, move || {
+            isr_edge.store(true, Ordering::Relaxed);
+        })?;
+
+        Ok(Self { pin, edge })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d, T: InputPin> ErrorType for InterruptInput<'d, T> {
+    type Error = <PinDriver<'d, T, Input> as ErrorType>::Error;
+}
+
+#[cfg(target_os = "espidf")]
+impl<T: InputPin> HalInputPin for InterruptInput<'_, T> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        HalInputPin::is_high(&mut self.pin)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        HalInputPin::is_low(&mut self.pin)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl<T: InputPin> EdgeNotify for InterruptInput<'_, T> {
+    fn take_edge(&mut self) -> bool {
+        self.edge.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Emits the state of a digital input whenever its level changes.
+pub struct DigitalInputState<P>
+where
+    P: HalInputPin + EdgeNotify,
+{
+    observable: Observable<bool>,
+    pin: P,
+    active_low: bool,
+    heartbeat: Option<Duration>,
+    last_emit: SystemTime,
+    value: bool,
+}
+
+impl<P> DigitalInputState<P>
+where
+    P: HalInputPin + EdgeNotify,
+{
+    /// Create a new input state sensor.
+    ///
+    /// With `active_low` set a low level reads as `true`. If `heartbeat` is given the current
+    /// state is re-emitted at that interval even when the pin has not changed.
+    pub fn new(mut pin: P, active_low: bool, heartbeat: Option<Duration>) -> Self {
+        let value = read_state(&mut pin, active_low).unwrap_or(false);
+        DigitalInputState::<P> {
+            observable: Observable::new(value),
+            pin,
+            active_low,
+            heartbeat,
+            last_emit: SystemTime::now(),
+            value,
+        }
+    }
+}
+
+fn read_state<P: HalInputPin>(pin: &mut P, active_low: bool) -> Option<bool> {
+    match pin.is_high() {
+        Ok(high) => Some(high != active_low),
+        Err(e) => {
+            log::error!("Digital input read error: {:?}", e);
+            None
+        }
+    }
+}
+
+impl<P> Attachable<bool> for DigitalInputState<P>
+where
+    P: HalInputPin + EdgeNotify,
+{
+    fn attach(&mut self) -> Subscriber<bool> {
+        self.observable.subscribe()
+    }
+}
+
+impl<P> SensESPSensor for DigitalInputState<P>
+where
+    P: HalInputPin + EdgeNotify,
+{
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        if self.pin.take_edge() {
+            if let Some(value) = read_state(&mut self.pin, self.active_low) {
+                if value != self.value {
+                    self.value = value;
+                    self.observable.set(value);
+                    self.last_emit = now;
+                }
+            }
+        }
+
+        if let Some(heartbeat) = self.heartbeat {
+            match now.duration_since(self.last_emit) {
+                Ok(d) => {
+                    if d >= heartbeat {
+                        self.observable.set(self.value);
+                        self.last_emit = now;
+                    }
+                }
+                Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Drives an output pin, such as a relay, from a stream of boolean states.
+pub struct DigitalOutput<P: HalOutputPin> {
+    pin: P,
+    subscriber: Updates<bool>,
+    inverted: bool,
+    pulse: Option<Duration>,
+    failsafe: bool,
+    timeout: Option<Duration>,
+    last_update: SystemTime,
+    pulse_start: Option<SystemTime>,
+    failsafe_active: bool,
+    started: bool,
+}
+
+impl<P: HalOutputPin> DigitalOutput<P> {
+    /// Create an output following `subscriber`.
+    ///
+    /// The output is held in its fail-safe state, inactive unless configured otherwise, until
+    /// the first value arrives.
+    pub fn new(pin: P, subscriber: Subscriber<bool>) -> Self {
+        DigitalOutput::<P> {
+            pin,
+            subscriber: subscriber.into(),
+            inverted: false,
+            pulse: None,
+            failsafe: false,
+            timeout: None,
+            last_update: SystemTime::now(),
+            pulse_start: None,
+            failsafe_active: false,
+            started: false,
+        }
+    }
+
+    /// Drive the pin low for `true` and high for `false`.
+    pub fn inverted(mut self, inverted: bool) -> Self {
+        self.inverted = inverted;
+        self
+    }
+
+    /// Treat a `true` value as a momentary press, activating the output for `duration` only.
+    pub fn pulse(mut self, duration: Duration) -> Self {
+        self.pulse = Some(duration);
+        self
+    }
+
+    /// Fall back to `state` when the source is dropped, or after `timeout` without an update.
+    pub fn failsafe(mut self, state: bool, timeout: Option<Duration>) -> Self {
+        self.failsafe = state;
+        self.timeout = timeout;
+        self
+    }
+
+    /// Consume the actuator and return the underlying pin
+    pub fn release(self) -> P {
+        self.pin
+    }
+
+    fn drive(&mut self, state: bool) {
+        let level = PinState::from(state != self.inverted);
+        if let Err(e) = self.pin.set_state(level) {
+            log::error!("Digital output write error: {:?}", e);
+        }
+    }
+
+    fn enter_failsafe(&mut self) {
+        if !self.failsafe_active {
+            log::warn!(
+                "Digital output lost its source, failing safe to {}",
+                self.failsafe
+            );
+            self.failsafe_active = true;
+            self.pulse_start = None;
+            self.drive(self.failsafe);
+        }
+    }
+}
+
+impl<P: HalOutputPin> SensESPSensor for DigitalOutput<P> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        if !self.started {
+            self.started = true;
+            self.drive(self.failsafe);
+        }
+
+        match poll_update(&mut self.subscriber) {
+            Poll::Ready(Some(value)) => {
+                self.last_update = now;
+                self.failsafe_active = false;
+                match self.pulse {
+                    Some(_) => {
+                        if value {
+                            self.pulse_start = Some(now);
+                            self.drive(true);
+                        }
+                    }
+                    None => self.drive(value),
+                }
+            }
+            Poll::Ready(None) => self.enter_failsafe(),
+            Poll::Pending => {
+                if let Some(timeout) = self.timeout {
+                    match now.duration_since(self.last_update) {
+                        Ok(d) => {
+                            if d >= timeout {
+                                self.enter_failsafe();
+                            }
+                        }
+                        Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+                    }
+                }
+            }
+        }
+
+        if let (Some(start), Some(pulse)) = (self.pulse_start, self.pulse) {
+            match now.duration_since(start) {
+                Ok(d) => {
+                    if d >= pulse {
+                        self.pulse_start = None;
+                        self.drive(false);
+                    }
+                }
+                Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::digital::ErrorType;
+    use std::convert::Infallible;
+    use std::time::Instant;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Tick until `done`, failing after a generous deadline rather than guessing a sleep.
+    fn tick_until<S: SensESPSensor>(sensor: &mut S, mut done: impl FnMut(&mut S) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(sensor) {
+            assert!(Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+            sensor.tick();
+        }
+    }
+
+    /// The value published since the last call, if any.
+    fn published(subscriber: &mut Subscriber<bool>) -> Option<bool> {
+        smol::future::block_on(smol::future::poll_once(subscriber.next())).flatten()
+    }
+
+    /// Pulses arrive in bursts queued up by the test, as they would from an interrupt.
+    #[derive(Default)]
+    struct FakePulses {
+        pending: u32,
+    }
+
+    impl PulseSource for FakePulses {
+        fn take_count(&mut self) -> u32 {
+            std::mem::take(&mut self.pending)
+        }
+    }
+
+    #[test]
+    fn pulse_rate_scales_by_elapsed_time() {
+        assert_eq!(pulse_rate(50, Duration::from_secs(2), 1.0), 25.0);
+        // Two pulses per revolution
+        assert_eq!(pulse_rate(100, Duration::from_secs(1), 30.0), 3000.0);
+        assert_eq!(pulse_rate(100, Duration::ZERO, 1.0), 0.0);
+    }
+
+    #[test]
+    fn counter_publishes_count_and_frequency() {
+        let mut counter =
+            DigitalInputCounter::new(FakePulses::default(), 60.0, Duration::from_secs(1));
+        let counts = counter.attach();
+        let frequency = counter.attach_frequency();
+
+        counter.source.pending = 20;
+        counter.update(Duration::from_millis(500));
+        assert_eq!(counts.get(), 20);
+        assert_eq!(frequency.get(), 2400.0);
+
+        counter.update(Duration::from_millis(500));
+        assert_eq!(counts.get(), 0);
+        assert_eq!(frequency.get(), 0.0);
+    }
+
+    #[test]
+    fn counter_waits_for_its_interval() {
+        let mut counter =
+            DigitalInputCounter::new(FakePulses::default(), 1.0, Duration::from_secs(3600));
+        let counts = counter.attach();
+
+        counter.source.pending = 7;
+        counter.tick();
+        assert_eq!(counts.get(), 0);
+        assert_eq!(counter.source.pending, 7);
+    }
+
+    /// Simulates the PCNT unit: a counter that restarts from zero at its limit and an
+    /// interrupt that counts the restarts, possibly late.
+    struct FakePcnt {
+        counter: i16,
+        wraps: u32,
+        pending_wraps: u32,
+        last: u32,
+    }
+
+    impl FakePcnt {
+        fn new() -> Self {
+            FakePcnt {
+                counter: 0,
+                wraps: 0,
+                pending_wraps: 0,
+                last: 0,
+            }
+        }
+
+        fn pulse(&mut self, n: u32) {
+            for _ in 0..n {
+                self.counter += 1;
+                if self.counter == PCNT_LIMIT {
+                    self.counter = 0;
+                    self.pending_wraps += 1;
+                }
+            }
+        }
+
+        fn interrupt(&mut self) {
+            self.wraps += std::mem::take(&mut self.pending_wraps);
+        }
+    }
+
+    impl PulseSource for FakePcnt {
+        fn take_count(&mut self) -> u32 {
+            count_since(&mut self.last, pcnt_total(self.wraps, self.counter))
+        }
+    }
+
+    #[test]
+    fn pcnt_counts_across_its_limit() {
+        let mut pcnt = FakePcnt::new();
+        pcnt.pulse(30_000);
+        assert_eq!(pcnt.take_count(), 30_000);
+
+        pcnt.pulse(5_000);
+        pcnt.interrupt();
+        assert_eq!(pcnt.take_count(), 5_000);
+
+        // Several wraps between two reads
+        pcnt.pulse(100_000);
+        pcnt.interrupt();
+        assert_eq!(pcnt.take_count(), 100_000);
+    }
+
+    #[test]
+    fn pcnt_catches_up_with_a_late_wrap_interrupt() {
+        let mut pcnt = FakePcnt::new();
+        pcnt.pulse(32_000);
+        assert_eq!(pcnt.take_count(), 32_000);
+
+        pcnt.pulse(1_000);
+        assert_eq!(pcnt.take_count(), 0);
+
+        pcnt.interrupt();
+        pcnt.pulse(10);
+        assert_eq!(pcnt.take_count(), 1_010);
+    }
+
+    #[test]
+    fn pcnt_total_wraps_at_32_bits() {
+        let mut last = u32::MAX - 4;
+        assert_eq!(count_since(&mut last, 5), 10);
+        assert_eq!(last, 5);
+    }
+
+    /// A pin whose level is set by the test, flagging an edge like the interrupt would.
+    struct FakePin {
+        high: bool,
+        edge: bool,
+    }
+
+    impl FakePin {
+        fn new(high: bool) -> Self {
+            FakePin { high, edge: false }
+        }
+
+        fn set(&mut self, high: bool) {
+            self.high = high;
+            self.edge = true;
+        }
+    }
+
+    impl ErrorType for FakePin {
+        type Error = Infallible;
+    }
+
+    impl HalInputPin for FakePin {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.high)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.high)
+        }
+    }
+
+    impl EdgeNotify for FakePin {
+        fn take_edge(&mut self) -> bool {
+            std::mem::take(&mut self.edge)
+        }
+    }
+
+    #[test]
+    fn input_state_reads_the_initial_level() {
+        let mut state = DigitalInputState::new(FakePin::new(true), false, None);
+        assert!(state.attach().get());
+        let mut state = DigitalInputState::new(FakePin::new(true), true, None);
+        assert!(!state.attach().get());
+    }
+
+    #[test]
+    fn input_state_inverts_active_low_pins() {
+        let mut state = DigitalInputState::new(FakePin::new(true), true, None);
+        let mut subscriber = state.attach();
+
+        state.pin.set(false);
+        state.tick();
+        assert_eq!(published(&mut subscriber), Some(true));
+
+        state.pin.set(true);
+        state.tick();
+        assert_eq!(published(&mut subscriber), Some(false));
+    }
+
+    #[test]
+    fn input_state_emits_on_edges_only() {
+        let mut state = DigitalInputState::new(FakePin::new(false), false, None);
+        let mut subscriber = state.attach();
+
+        state.tick();
+        assert_eq!(published(&mut subscriber), None);
+
+        // A level change is only read once the interrupt has flagged it
+        state.pin.high = true;
+        state.tick();
+        assert_eq!(published(&mut subscriber), None);
+        state.pin.edge = true;
+        state.tick();
+        assert_eq!(published(&mut subscriber), Some(true));
+
+        // A bounce that settled back to the same level is not a change
+        state.pin.set(true);
+        state.tick();
+        assert_eq!(published(&mut subscriber), None);
+    }
+
+    #[test]
+    fn input_state_repeats_on_heartbeat() {
+        let heartbeat = Some(Duration::from_millis(10));
+        let mut state = DigitalInputState::new(FakePin::new(true), false, heartbeat);
+        let mut subscriber = state.attach();
+
+        tick_until(&mut state, |_| published(&mut subscriber) == Some(true));
+    }
+
+    #[test]
+    fn input_state_waits_for_its_heartbeat() {
+        let mut state = DigitalInputState::new(FakePin::new(true), false, Some(HOUR));
+        let mut subscriber = state.attach();
+
+        state.tick();
+        state.tick();
+        assert_eq!(published(&mut subscriber), None);
+    }
+
+    #[test]
+    fn input_state_without_heartbeat_stays_quiet() {
+        let mut state = DigitalInputState::new(FakePin::new(true), false, None);
+        let mut subscriber = state.attach();
+
+        state.tick();
+        state.tick();
+        assert_eq!(published(&mut subscriber), None);
+    }
+
+    /// An output pin that records every level it was driven to.
+    #[derive(Default)]
+    struct FakeOutput {
+        levels: Vec<bool>,
+    }
+
+    impl ErrorType for FakeOutput {
+        type Error = Infallible;
+    }
+
+    impl HalOutputPin for FakeOutput {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.levels.push(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.levels.push(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_follows_its_source() {
+        let source = Observable::new(false);
+        let mut output = DigitalOutput::new(FakeOutput::default(), source.subscribe());
+
+        output.tick();
+        source.set(true);
+        output.tick();
+        output.tick();
+        source.set(false);
+        output.tick();
+        assert_eq!(output.release().levels, [false, true, false]);
+    }
+
+    #[test]
+    fn output_inverts_levels() {
+        let source = Observable::new(false);
+        let mut output =
+            DigitalOutput::new(FakeOutput::default(), source.subscribe()).inverted(true);
+
+        output.tick();
+        source.set(true);
+        output.tick();
+        assert_eq!(output.release().levels, [true, false]);
+    }
+
+    #[test]
+    fn output_pulses_on_true() {
+        let source = Observable::new(false);
+        let mut output = DigitalOutput::new(FakeOutput::default(), source.subscribe()).pulse(HOUR);
+
+        output.tick();
+        source.set(true);
+        output.tick();
+        // A false value doesn't cut the pulse short
+        source.set(false);
+        output.tick();
+        assert_eq!(output.release().levels, [false, true]);
+    }
+
+    #[test]
+    fn output_pulse_ends_after_its_duration() {
+        let pulse = Duration::from_millis(10);
+        let source = Observable::new(false);
+        let mut output = DigitalOutput::new(FakeOutput::default(), source.subscribe()).pulse(pulse);
+
+        source.set(true);
+        output.tick();
+        tick_until(&mut output, |output| output.pin.levels.len() == 3);
+        assert_eq!(output.release().levels, [false, true, false]);
+    }
+
+    #[test]
+    fn output_fails_safe_when_its_source_is_dropped() {
+        let source = Observable::new(false);
+        let mut output =
+            DigitalOutput::new(FakeOutput::default(), source.subscribe()).failsafe(true, None);
+
+        output.tick();
+        source.set(false);
+        output.tick();
+        drop(source);
+        output.tick();
+        output.tick();
+        assert_eq!(output.release().levels, [true, false, true]);
+    }
+
+    #[test]
+    fn output_holds_until_its_timeout() {
+        let source = Observable::new(false);
+        let mut output = DigitalOutput::new(FakeOutput::default(), source.subscribe())
+            .failsafe(false, Some(HOUR));
+
+        source.set(true);
+        output.tick();
+        output.tick();
+        assert_eq!(output.release().levels, [false, true]);
+    }
+
+    #[test]
+    fn output_fails_safe_after_a_timeout() {
+        let timeout = Some(Duration::from_millis(10));
+        let source = Observable::new(false);
+        let mut output =
+            DigitalOutput::new(FakeOutput::default(), source.subscribe()).failsafe(false, timeout);
+
+        source.set(true);
+        output.tick();
+        tick_until(&mut output, |output| output.pin.levels.len() == 3);
+        // Recovers with the next value
+        source.set(true);
+        output.tick();
+        assert_eq!(output.release().levels, [false, true, false, true]);
+    }
+}
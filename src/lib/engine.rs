@@ -0,0 +1,425 @@
+//! Engine monitoring: revolutions, coolant temperature, oil pressure and running hours
+use crate::clock::{Clock, SystemClock};
+use crate::config::{self, ConfigStore};
+use crate::curve::Curve;
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, Update, Value};
+use eyeball::{shared::Observable, Subscriber};
+use std::task::Poll;
+use std::time::Duration;
+
+/// How often the running total is saved while the engine runs.
+pub const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Counts the time an engine spends turning and keeps the total in a [`ConfigStore`].
+///
+/// Publishes the total in seconds with every revolutions reading while running, as an f64 so
+/// years of running time keep their resolution. The total is saved every save interval while
+/// running and when the engine stops.
+pub struct EngineHours<S: ConfigStore, C: Clock = SystemClock> {
+    revolutions: Updates<f32>,
+    store: S,
+    key: String,
+    clock: C,
+    save_interval: Duration,
+    running: bool,
+    run_time: Duration,
+    saved: Duration,
+    last_tick: Duration,
+    observable: Observable<f64>,
+}
+
+impl<S: ConfigStore> EngineHours<S> {
+    pub fn new(revolutions: Subscriber<f32>, store: S, key: &str) -> Self {
+        Self::with_clock(revolutions, store, key, SystemClock::new())
+    }
+}
+
+impl<S: ConfigStore, C: Clock> EngineHours<S, C> {
+    /// Continue from the total saved under `key`, or start from zero if none was saved or it
+    /// is not a valid duration.
+    pub fn with_clock(revolutions: Subscriber<f32>, store: S, key: &str, clock: C) -> Self {
+        let seconds: f64 = config::load(&store, key).unwrap_or(0.0);
+        let run_time = match Duration::try_from_secs_f64(seconds) {
+            Ok(run_time) => run_time,
+            Err(_) => {
+                log::error!("Ignoring saved running time {} for {}", seconds, key);
+                Duration::ZERO
+            }
+        };
+        EngineHours::<S, C> {
+            running: revolutions.get() > 0.0,
+            revolutions: revolutions.into(),
+            store,
+            key: key.to_string(),
+            save_interval: DEFAULT_SAVE_INTERVAL,
+            run_time,
+            saved: run_time,
+            last_tick: clock.now(),
+            clock,
+            observable: Observable::new(run_time.as_secs_f64()),
+        }
+    }
+
+    pub fn save_interval(mut self, interval: Duration) -> Self {
+        self.save_interval = interval;
+        self
+    }
+
+    /// Total running time
+    pub fn run_time(&self) -> Duration {
+        self.run_time
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Start counting from `run_time`, e.g. to match the engine's own hour meter.
+    pub fn reset(&mut self, run_time: Duration) {
+        self.run_time = run_time;
+        self.save();
+        self.observable.set(run_time.as_secs_f64());
+    }
+
+    fn save(&mut self) {
+        match config::save(&self.store, &self.key, &self.run_time.as_secs_f64()) {
+            Ok(_) => self.saved = self.run_time,
+            Err(e) => log::error!("Engine hours save error: {:?}", e),
+        }
+    }
+}
+
+impl<S: ConfigStore, C: Clock> Attachable<f64> for EngineHours<S, C> {
+    fn attach(&mut self) -> Subscriber<f64> {
+        self.observable.subscribe()
+    }
+}
+
+impl<S: ConfigStore, C: Clock> SensESPSensor for EngineHours<S, C> {
+    fn tick(&mut self) {
+        let now = self.clock.now();
+        if self.running {
+            self.run_time += now.saturating_sub(self.last_tick);
+        }
+        self.last_tick = now;
+
+        // Updated with each reading rather than each tick to keep the output quiet
+        if let Poll::Ready(Some(revolutions)) = poll_update(&mut self.revolutions) {
+            let was_running = self.running;
+            self.running = revolutions > 0.0;
+            if was_running {
+                self.observable.set(self.run_time.as_secs_f64());
+                let stopped = !self.running;
+                if stopped || self.run_time.saturating_sub(self.saved) >= self.save_interval {
+                    self.save();
+                }
+            }
+        }
+    }
+}
+
+/// Publishes an engine's readings to `propulsion.<id>`: `revolutions`, `temperature`,
+/// `oilPressure` and `runTime`.
+///
+/// Revolutions come in Hz, e.g. from a `DigitalInputCounter` with a multiplier of one over the
+/// pulses per revolution. Senders give raw readings that their curves turn into K and Pa.
+/// Register the input sensors ahead of the engine so readings are published on the same tick.
+pub struct Engine<S: ConfigStore, C: Clock = SystemClock> {
+    path: String,
+    output: SignalKOutput,
+    revolutions: Updates<f32>,
+    temperature: Option<(Updates<f32>, Curve)>,
+    oil_pressure: Option<(Updates<f32>, Curve)>,
+    hours: EngineHours<S, C>,
+    run_time: Updates<f64>,
+}
+
+impl<S: ConfigStore> Engine<S> {
+    pub fn new(output: &SignalKOutput, id: &str, revolutions: Subscriber<f32>, store: S) -> Self {
+        Self::with_clock(output, id, revolutions, store, SystemClock::new())
+    }
+}
+
+impl<S: ConfigStore, C: Clock> Engine<S, C> {
+    /// Running hours are kept under the key `hours.<id>`, so ids can be up to 9 characters.
+    pub fn with_clock(
+        output: &SignalKOutput,
+        id: &str,
+        revolutions: Subscriber<f32>,
+        store: S,
+        clock: C,
+    ) -> Self {
+        let key = format!("hours.{}", id);
+        let mut hours = EngineHours::with_clock(revolutions.clone(), store, &key, clock);
+        let run_time = hours.attach();
+        Engine::<S, C> {
+            path: format!("propulsion.{}", id),
+            output: output.clone(),
+            revolutions: revolutions.into(),
+            temperature: None,
+            oil_pressure: None,
+            hours,
+            run_time: run_time.into(),
+        }
+    }
+
+    /// Add a coolant temperature sender, with a curve from its readings to K.
+    pub fn temperature(mut self, subscriber: Subscriber<f32>, curve: Curve) -> Self {
+        self.temperature = Some((subscriber.into(), curve));
+        self
+    }
+
+    /// Add an oil pressure sender, with a curve from its readings to Pa.
+    pub fn oil_pressure(mut self, subscriber: Subscriber<f32>, curve: Curve) -> Self {
+        self.oil_pressure = Some((subscriber.into(), curve));
+        self
+    }
+
+    pub fn save_interval(mut self, interval: Duration) -> Self {
+        self.hours = self.hours.save_interval(interval);
+        self
+    }
+
+    pub fn hours(&mut self) -> &mut EngineHours<S, C> {
+        &mut self.hours
+    }
+
+    fn publish(&self, name: &str, value: impl Into<Value>) {
+        self.output
+            .publish(Update::new(format!("{}.{}", self.path, name), value));
+    }
+}
+
+/// A new reading from an optional sender, converted by its curve.
+fn read_sender(sender: &mut Option<(Updates<f32>, Curve)>) -> Option<f32> {
+    let (subscriber, curve) = sender.as_mut()?;
+    match poll_update(subscriber) {
+        Poll::Ready(Some(raw)) => Some(curve.apply(raw)),
+        _ => None,
+    }
+}
+
+impl<S: ConfigStore, C: Clock> SensESPSensor for Engine<S, C> {
+    fn tick(&mut self) {
+        self.hours.tick();
+
+        if let Poll::Ready(Some(revolutions)) = poll_update(&mut self.revolutions) {
+            self.publish("revolutions", revolutions);
+        }
+        if let Poll::Ready(Some(seconds)) = poll_update(&mut self.run_time) {
+            self.publish("runTime", seconds);
+        }
+        if let Some(value) = read_sender(&mut self.temperature) {
+            self.publish("temperature", value);
+        }
+        if let Some(value) = read_sender(&mut self.oil_pressure) {
+            self.publish("oilPressure", value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::config::MemoryStore;
+
+    const SECOND: Duration = Duration::from_secs(1);
+    const KEY: &str = "hours.port";
+
+    struct Run {
+        clock: ManualClock,
+        revolutions: Observable<f32>,
+    }
+
+    impl Run {
+        fn new() -> Self {
+            Run {
+                clock: ManualClock::new(),
+                revolutions: Observable::new(0.0),
+            }
+        }
+
+        fn hours(&self, store: &MemoryStore) -> EngineHours<MemoryStore, ManualClock> {
+            EngineHours::with_clock(
+                self.revolutions.subscribe(),
+                store.clone(),
+                KEY,
+                self.clock.clone(),
+            )
+        }
+
+        /// A reading every second for `seconds`.
+        fn readings<S: ConfigStore>(
+            &self,
+            hours: &mut EngineHours<S, ManualClock>,
+            revolutions: f32,
+            seconds: u32,
+        ) {
+            for _ in 0..seconds {
+                self.clock.advance(SECOND);
+                self.revolutions.set(revolutions);
+                hours.tick();
+            }
+        }
+    }
+
+    fn saved(store: &MemoryStore) -> Option<f64> {
+        config::load(store, KEY)
+    }
+
+    #[test]
+    fn hours_count_only_while_running() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        let mut hours = run.hours(&store);
+        run.readings(&mut hours, 0.0, 10);
+        assert!(!hours.is_running());
+        assert_eq!(hours.run_time(), Duration::ZERO);
+
+        run.revolutions.set(30.0);
+        hours.tick();
+        assert!(hours.is_running());
+        run.readings(&mut hours, 30.0, 100);
+        assert_eq!(hours.run_time(), SECOND * 100);
+
+        // Time until the reading that shows the engine stopped still counts
+        run.readings(&mut hours, 0.0, 1);
+        assert!(!hours.is_running());
+        run.readings(&mut hours, 0.0, 100);
+        assert_eq!(hours.run_time(), SECOND * 101);
+    }
+
+    #[test]
+    fn hours_are_saved_periodically_and_on_stop() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        let mut hours = run.hours(&store).save_interval(SECOND * 60);
+        run.revolutions.set(30.0);
+        hours.tick();
+        run.readings(&mut hours, 30.0, 59);
+        assert_eq!(saved(&store), None);
+        run.readings(&mut hours, 30.0, 1);
+        assert_eq!(saved(&store), Some(60.0));
+        run.readings(&mut hours, 30.0, 90);
+        assert_eq!(saved(&store), Some(120.0));
+
+        run.readings(&mut hours, 0.0, 1);
+        assert_eq!(saved(&store), Some(151.0));
+        // Stopped, nothing more to save
+        store.remove(KEY).unwrap();
+        run.readings(&mut hours, 0.0, 100);
+        assert_eq!(saved(&store), None);
+    }
+
+    #[test]
+    fn hours_continue_from_the_saved_total() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        config::save(&store, KEY, &151.5).unwrap();
+        let mut hours = run.hours(&store);
+        assert_eq!(hours.run_time(), Duration::from_secs_f64(151.5));
+        assert_eq!(hours.attach().get(), 151.5);
+
+        // Running at boot
+        run.revolutions.set(30.0);
+        let mut hours = run.hours(&store);
+        assert!(hours.is_running());
+        run.readings(&mut hours, 30.0, 10);
+        assert_eq!(hours.run_time(), Duration::from_secs_f64(161.5));
+    }
+
+    #[test]
+    fn invalid_saved_hours_start_from_zero() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        store.set(KEY, "lots").unwrap();
+        assert_eq!(run.hours(&store).run_time(), Duration::ZERO);
+        for seconds in ["-5", "inf", "NaN", "1e30"] {
+            store.set(KEY, seconds).unwrap();
+            assert_eq!(run.hours(&store).run_time(), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn reset_saves_and_publishes() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        let mut hours = run.hours(&store);
+        let mut published = Updates::new(hours.attach());
+        hours.reset(SECOND * 3600 * 1000);
+        assert_eq!(saved(&store), Some(3_600_000.0));
+        assert_eq!(poll_update(&mut published), Poll::Ready(Some(3_600_000.0)));
+    }
+
+    #[test]
+    fn run_time_keeps_whole_seconds_after_years() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        // Ten years of running, beyond the range f32 holds to the second
+        let years = 10 * 365 * 24 * 3600;
+        config::save(&store, KEY, &(years as f64)).unwrap();
+        run.revolutions.set(30.0);
+        let mut hours = run.hours(&store);
+        let mut published = Updates::new(hours.attach());
+        run.readings(&mut hours, 30.0, 1);
+        assert_eq!(
+            poll_update(&mut published),
+            Poll::Ready(Some(years as f64 + 1.0))
+        );
+    }
+
+    #[test]
+    fn engine_publishes_its_readings() {
+        let run = Run::new();
+        let store = MemoryStore::new();
+        store.set("hours.main", "36000").unwrap();
+        let output = SignalKOutput::new();
+        let temperature = Observable::new(0.0);
+        let oil_pressure = Observable::new(0.0);
+        let mut engine = Engine::with_clock(
+            &output,
+            "main",
+            run.revolutions.subscribe(),
+            store.clone(),
+            run.clock.clone(),
+        )
+        .temperature(
+            temperature.subscribe(),
+            Curve::new().point(30.0, 393.15).point(1000.0, 313.15),
+        )
+        .oil_pressure(
+            oil_pressure.subscribe(),
+            Curve::new().point(10.0, 0.0).point(180.0, 500_000.0),
+        );
+        engine.tick();
+        assert!(output.take_pending().is_empty());
+
+        run.revolutions.set(25.0);
+        temperature.set(30.0);
+        oil_pressure.set(95.0);
+        engine.tick();
+        let updates = output.take_pending();
+        let paths: Vec<&str> = updates.iter().map(|u| u.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "propulsion.main.revolutions",
+                "propulsion.main.temperature",
+                "propulsion.main.oilPressure",
+            ]
+        );
+        assert_eq!(updates[0].value.as_f64(), Some(25.0));
+        assert_eq!(updates[1].value.as_f64(), Some(393.15f32 as f64));
+        assert_eq!(updates[2].value.as_f64(), Some(250_000.0));
+
+        run.clock.advance(SECOND * 2);
+        run.revolutions.set(25.0);
+        engine.tick();
+        let updates = output.take_pending();
+        assert_eq!(updates[1].path, "propulsion.main.runTime");
+        assert_eq!(updates[1].value.as_f64(), Some(36_002.0));
+        assert_eq!(engine.hours().run_time(), SECOND * 36_002);
+    }
+}
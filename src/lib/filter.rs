@@ -0,0 +1,420 @@
+//! Transforms that control how often a stream publishes
+use crate::clock::{Clock, SystemClock};
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use eyeball::{shared::Observable, Subscriber};
+use std::task::Poll;
+use std::time::Duration;
+
+/// Publishes at most once per interval.
+///
+/// A value arriving too soon is held back and published once the interval has passed, unless
+/// a newer one replaces it first, so the latest value always gets through.
+pub struct Throttle<T, C: Clock = SystemClock> {
+    subscriber: Updates<T>,
+    interval: Duration,
+    clock: C,
+    last_publish: Option<Duration>,
+    pending: Option<T>,
+    observable: Observable<T>,
+}
+
+impl<T: Clone> Throttle<T> {
+    pub fn new(subscriber: Subscriber<T>, interval: Duration) -> Self {
+        Self::with_clock(subscriber, interval, SystemClock::new())
+    }
+}
+
+impl<T: Clone, C: Clock> Throttle<T, C> {
+    pub fn with_clock(subscriber: Subscriber<T>, interval: Duration, clock: C) -> Self {
+        Throttle::<T, C> {
+            observable: Observable::new(subscriber.get()),
+            subscriber: subscriber.into(),
+            interval,
+            clock,
+            last_publish: None,
+            pending: None,
+        }
+    }
+}
+
+impl<T: Clone, C: Clock> Attachable<T> for Throttle<T, C> {
+    fn attach(&mut self) -> Subscriber<T> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T: Clone, C: Clock> SensESPSensor for Throttle<T, C> {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(value)) = poll_update(&mut self.subscriber) {
+            self.pending = Some(value);
+        }
+        let now = self.clock.now();
+        let due = self
+            .last_publish
+            .is_none_or(|last| now.saturating_sub(last) >= self.interval);
+        match self.pending.take() {
+            Some(value) if due => {
+                self.observable.set(value);
+                self.last_publish = Some(now);
+            }
+            pending => self.pending = pending,
+        }
+    }
+}
+
+/// Publishes only values that differ from the last published one by more than a threshold.
+pub struct ChangeFilter {
+    subscriber: Updates<f32>,
+    threshold: f32,
+    last: f32,
+    observable: Observable<f32>,
+}
+
+impl ChangeFilter {
+    /// A threshold of 0 passes every change and drops repeats of the same value.
+    pub fn new(subscriber: Subscriber<f32>, threshold: f32) -> Self {
+        let last = subscriber.get();
+        ChangeFilter {
+            subscriber: subscriber.into(),
+            threshold,
+            last,
+            observable: Observable::new(last),
+        }
+    }
+}
+
+impl Attachable<f32> for ChangeFilter {
+    fn attach(&mut self) -> Subscriber<f32> {
+        self.observable.subscribe()
+    }
+}
+
+impl SensESPSensor for ChangeFilter {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(value)) = poll_update(&mut self.subscriber) {
+            // NaN never compares greater, so a reading starting or stopping to fail counts too
+            let changed =
+                (value - self.last).abs() > self.threshold || value.is_nan() != self.last.is_nan();
+            if changed {
+                self.last = value;
+                self.observable.set(value);
+            }
+        }
+    }
+}
+
+/// Passes every value through and repeats the last one when nothing new arrives for an
+/// interval, so receivers can tell a steady reading from a dead sensor.
+pub struct Heartbeat<T, C: Clock = SystemClock> {
+    subscriber: Updates<T>,
+    interval: Duration,
+    clock: C,
+    last_publish: Duration,
+    last: T,
+    observable: Observable<T>,
+}
+
+impl<T: Clone> Heartbeat<T> {
+    pub fn new(subscriber: Subscriber<T>, interval: Duration) -> Self {
+        Self::with_clock(subscriber, interval, SystemClock::new())
+    }
+}
+
+impl<T: Clone, C: Clock> Heartbeat<T, C> {
+    pub fn with_clock(subscriber: Subscriber<T>, interval: Duration, clock: C) -> Self {
+        let last = subscriber.get();
+        Heartbeat::<T, C> {
+            observable: Observable::new(last.clone()),
+            subscriber: subscriber.into(),
+            interval,
+            last_publish: clock.now(),
+            clock,
+            last,
+        }
+    }
+}
+
+impl<T: Clone, C: Clock> Attachable<T> for Heartbeat<T, C> {
+    fn attach(&mut self) -> Subscriber<T> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T: Clone, C: Clock> SensESPSensor for Heartbeat<T, C> {
+    fn tick(&mut self) {
+        let now = self.clock.now();
+        match poll_update(&mut self.subscriber) {
+            Poll::Ready(Some(value)) => {
+                self.last = value.clone();
+                self.observable.set(value);
+                self.last_publish = now;
+            }
+            _ => {
+                if now.saturating_sub(self.last_publish) >= self.interval {
+                    self.observable.set(self.last.clone());
+                    self.last_publish = now;
+                }
+            }
+        }
+    }
+}
+
+/// Publishes `Some` for every value and `None` once no value has arrived for a timeout.
+///
+/// `None` becomes null on a Signal K path, marking the reading invalid.
+pub struct Staleness<T, C: Clock = SystemClock> {
+    subscriber: Updates<T>,
+    timeout: Duration,
+    clock: C,
+    last_update: Duration,
+    stale: bool,
+    observable: Observable<Option<T>>,
+}
+
+impl<T: Clone> Staleness<T> {
+    pub fn new(subscriber: Subscriber<T>, timeout: Duration) -> Self {
+        Self::with_clock(subscriber, timeout, SystemClock::new())
+    }
+}
+
+impl<T: Clone, C: Clock> Staleness<T, C> {
+    pub fn with_clock(subscriber: Subscriber<T>, timeout: Duration, clock: C) -> Self {
+        Staleness::<T, C> {
+            observable: Observable::new(Some(subscriber.get())),
+            subscriber: subscriber.into(),
+            timeout,
+            last_update: clock.now(),
+            clock,
+            stale: false,
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+}
+
+impl<T: Clone, C: Clock> Attachable<Option<T>> for Staleness<T, C> {
+    fn attach(&mut self) -> Subscriber<Option<T>> {
+        self.observable.subscribe()
+    }
+}
+
+impl<T: Clone, C: Clock> SensESPSensor for Staleness<T, C> {
+    fn tick(&mut self) {
+        let now = self.clock.now();
+        match poll_update(&mut self.subscriber) {
+            Poll::Ready(Some(value)) => {
+                self.stale = false;
+                self.last_update = now;
+                self.observable.set(Some(value));
+            }
+            _ => {
+                if !self.stale && now.saturating_sub(self.last_update) >= self.timeout {
+                    self.stale = true;
+                    self.observable.set(None);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::testing::updates;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn throttle_publishes_the_first_value_at_once() {
+        let clock = ManualClock::new();
+        let input = Observable::new(0);
+        let mut throttle = Throttle::with_clock(input.subscribe(), SECOND, clock.clone());
+        let mut output = updates(&mut throttle);
+
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        input.set(1);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(1)));
+    }
+
+    #[test]
+    fn throttle_holds_back_the_trailing_value() {
+        let clock = ManualClock::new();
+        let input = Observable::new(0);
+        let mut throttle = Throttle::with_clock(input.subscribe(), SECOND, clock.clone());
+        let mut output = updates(&mut throttle);
+
+        input.set(1);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(1)));
+
+        clock.advance(SECOND / 4);
+        input.set(2);
+        throttle.tick();
+        clock.advance(SECOND / 4);
+        input.set(3);
+        throttle.tick();
+        clock.advance(SECOND / 4);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+
+        // Only the latest of the held back values is published, once the interval is up
+        clock.advance(SECOND / 4);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(3)));
+
+        // Nothing new, nothing to publish
+        clock.advance(SECOND * 5);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+    }
+
+    #[test]
+    fn throttle_interval_runs_from_the_last_publish() {
+        let clock = ManualClock::new();
+        let input = Observable::new(0);
+        let mut throttle = Throttle::with_clock(input.subscribe(), SECOND, clock.clone());
+        let mut output = updates(&mut throttle);
+
+        input.set(1);
+        throttle.tick();
+        clock.advance(SECOND * 3);
+        input.set(2);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(2)));
+        clock.advance(SECOND / 2);
+        input.set(3);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        clock.advance(SECOND / 2);
+        throttle.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(3)));
+    }
+
+    #[test]
+    fn change_filter_drops_changes_within_the_threshold() {
+        let input = Observable::new(10.0);
+        let mut filter = ChangeFilter::new(input.subscribe(), 0.5);
+        let mut output = updates(&mut filter);
+
+        input.set(10.3);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        input.set(9.6);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        // Compared with the last published value, not the last input
+        input.set(10.6);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(10.6)));
+        input.set(10.0);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(10.0)));
+    }
+
+    #[test]
+    fn change_filter_passes_a_reading_failing_and_recovering() {
+        let input = Observable::new(10.0);
+        let mut filter = ChangeFilter::new(input.subscribe(), 0.5);
+        let mut output = updates(&mut filter);
+
+        input.set(f32::NAN);
+        filter.tick();
+        assert!(matches!(poll_update(&mut output), Poll::Ready(Some(v)) if v.is_nan()));
+        input.set(f32::NAN);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        input.set(10.0);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(10.0)));
+    }
+
+    #[test]
+    fn change_filter_with_no_threshold_drops_only_repeats() {
+        let input = Observable::new(1.0);
+        let mut filter = ChangeFilter::new(input.subscribe(), 0.0);
+        let mut output = updates(&mut filter);
+
+        input.set(1.0);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        input.set(1.001);
+        filter.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(1.001)));
+    }
+
+    #[test]
+    fn heartbeat_repeats_a_quiet_input() {
+        let clock = ManualClock::new();
+        let input = Observable::new(true);
+        let mut heartbeat = Heartbeat::with_clock(input.subscribe(), SECOND * 10, clock.clone());
+        let mut output = updates(&mut heartbeat);
+
+        heartbeat.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        clock.advance(SECOND * 10);
+        heartbeat.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(true)));
+        clock.advance(SECOND * 10);
+        heartbeat.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(true)));
+    }
+
+    #[test]
+    fn heartbeat_restarts_on_each_new_value() {
+        let clock = ManualClock::new();
+        let input = Observable::new(true);
+        let mut heartbeat = Heartbeat::with_clock(input.subscribe(), SECOND * 10, clock.clone());
+        let mut output = updates(&mut heartbeat);
+
+        clock.advance(SECOND * 5);
+        input.set(false);
+        heartbeat.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(false)));
+        clock.advance(SECOND * 9);
+        heartbeat.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        clock.advance(SECOND);
+        heartbeat.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(false)));
+    }
+
+    #[test]
+    fn staleness_publishes_none_once_and_recovers() {
+        let clock = ManualClock::new();
+        let input = Observable::new(1.0);
+        let mut staleness = Staleness::with_clock(input.subscribe(), SECOND * 3, clock.clone());
+        assert_eq!(staleness.attach().get(), Some(1.0));
+        let mut output = updates(&mut staleness);
+
+        clock.advance(SECOND * 2);
+        staleness.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        assert!(!staleness.is_stale());
+
+        clock.advance(SECOND);
+        staleness.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(None)));
+        assert!(staleness.is_stale());
+        clock.advance(SECOND * 3);
+        staleness.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+
+        input.set(2.0);
+        staleness.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(Some(2.0))));
+        assert!(!staleness.is_stale());
+
+        // The timeout restarts from the recovered value
+        clock.advance(SECOND * 2);
+        staleness.tick();
+        assert_eq!(poll_update(&mut output), Poll::Pending);
+        clock.advance(SECOND);
+        staleness.tick();
+        assert_eq!(poll_update(&mut output), Poll::Ready(Some(None)));
+    }
+}
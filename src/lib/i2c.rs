@@ -1,11 +1,22 @@
 //! I2C interface factory
+pub mod ads1115;
+pub mod bme280;
+pub mod bus;
+pub mod health;
+pub mod ina219;
+pub mod mpu6050;
+pub mod provision;
+pub mod scan;
+
 use embedded_hal::i2c::I2c;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
 /// Helper struct to create preconfigured I2C interfaces for the display.
 #[derive(Debug, Copy, Clone)]
 pub struct I2CDisplayInterface(());
 
 impl I2CDisplayInterface {
     /// Create new builder with a default I2C address of 0x3C
+    #[allow(clippy::new_ret_no_self)]
     pub fn new<I>(i2c: I) -> I2CInterface<I>
     where
         I: I2c,
@@ -28,15 +39,78 @@ impl I2CDisplayInterface {
     {
         I2CInterface::new(i2c, address, 0x40)
     }
+
+    /// Create a new async I2C interface with the default address 0x3C
+    pub fn new_async<I>(i2c: I) -> AsyncI2CInterface<I>
+    where
+        I: AsyncI2c,
+    {
+        Self::new_async_custom_address(i2c, 0x3C)
+    }
+
+    /// Create a new async I2C interface with a custom address.
+    pub fn new_async_custom_address<I>(i2c: I, address: u8) -> AsyncI2CInterface<I>
+    where
+        I: AsyncI2c,
+    {
+        AsyncI2CInterface::new(i2c, address, 0x40)
+    }
+}
+
+use display_interface::{
+    AsyncWriteOnlyDataCommand, DataFormat, DisplayError, WriteOnlyDataCommand,
+};
+
+/// Bytes sent after the control byte in one I2C transaction unless configured otherwise.
+pub const DEFAULT_CHUNK_SIZE: usize = 16;
+
+/// Control byte announcing that the rest of the transaction is commands.
+const COMMAND_BYTE: u8 = 0x00;
+
+/// Splits a byte stream into transactions of a control byte followed by at most `size` bytes,
+/// reusing one buffer for every transaction.
+struct Chunker {
+    size: usize,
+    buf: Vec<u8>,
 }
 
-use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+impl Chunker {
+    fn new(size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            size,
+            buf: Vec::with_capacity(size + 1),
+        }
+    }
+
+    /// Start a transfer prefixed with `control`.
+    fn start(&mut self, control: u8) {
+        self.buf.clear();
+        self.buf.push(control);
+    }
+
+    /// Add `byte`, returning the transaction to send once it is full.
+    fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        // The previous full transaction has been sent, keep only its control byte
+        if self.buf.len() > self.size {
+            self.buf.truncate(1);
+        }
+        self.buf.push(byte);
+        (self.buf.len() > self.size).then_some(&self.buf[..])
+    }
+
+    /// The last, partly filled transaction, if any bytes are left to send.
+    fn finish(&self) -> Option<&[u8]> {
+        (self.buf.len() > 1 && self.buf.len() <= self.size).then_some(&self.buf[..])
+    }
+}
 
 /// I2C communication interface
 pub struct I2CInterface<I2C> {
     i2c: I2C,
     addr: u8,
     data_byte: u8,
+    chunker: Chunker,
 }
 
 impl<I2C> I2CInterface<I2C>
@@ -49,14 +123,48 @@ where
             i2c,
             addr,
             data_byte,
+            chunker: Chunker::new(DEFAULT_CHUNK_SIZE),
         }
     }
 
+    /// Split commands and data into transactions of at most `chunk_size` bytes plus the
+    /// control byte. Larger chunks mean fewer transactions but longer bus occupancy.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunker = Chunker::new(chunk_size);
+        self
+    }
+
     /// Consume the display interface and return
     /// the underlying peripherial driver
     pub fn release(self) -> I2C {
         self.i2c
     }
+
+    /// Write `bytes` in chunks, each prefixed with the `control` byte.
+    fn write_framed(
+        &mut self,
+        control: u8,
+        bytes: impl IntoIterator<Item = u8>,
+    ) -> Result<(), DisplayError> {
+        self.chunker.start(control);
+
+        for byte in bytes {
+            if let Some(chunk) = self.chunker.push(byte) {
+                self.i2c
+                    .write(self.addr, chunk)
+                    .map_err(|_| DisplayError::BusWriteError)?;
+            }
+        }
+
+        // Send the remainder, if any
+        if let Some(chunk) = self.chunker.finish() {
+            self.i2c
+                .write(self.addr, chunk)
+                .map_err(|_| DisplayError::BusWriteError)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<I2C> WriteOnlyDataCommand for I2CInterface<I2C>
@@ -64,74 +172,378 @@ where
     I2C: I2c,
 {
     fn send_commands(&mut self, cmds: DataFormat<'_>) -> Result<(), DisplayError> {
-        // Copy over given commands to new aray to prefix with command identifier
         match cmds {
-            DataFormat::U8(slice) => {
-                let mut writebuf: [u8; 8] = [0; 8];
-                writebuf[1..=slice.len()].copy_from_slice(&slice[0..slice.len()]);
+            DataFormat::U8(slice) => self.write_framed(COMMAND_BYTE, slice.iter().copied()),
+            DataFormat::U8Iter(iter) => self.write_framed(COMMAND_BYTE, iter),
+            _ => Err(DisplayError::DataFormatNotImplemented),
+        }
+    }
+
+    fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+        let data_byte = self.data_byte;
+        match buf {
+            DataFormat::U8(slice) => self.write_framed(data_byte, slice.iter().copied()),
+            DataFormat::U16(slice) => {
+                self.write_framed(data_byte, slice.iter().flat_map(|v| v.to_ne_bytes()))
+            }
+            DataFormat::U16BE(slice) => {
+                self.write_framed(data_byte, slice.iter().flat_map(|v| v.to_be_bytes()))
+            }
+            DataFormat::U16LE(slice) => {
+                self.write_framed(data_byte, slice.iter().flat_map(|v| v.to_le_bytes()))
+            }
+            DataFormat::U8Iter(iter) => self.write_framed(data_byte, iter),
+            DataFormat::U16BEIter(iter) => {
+                self.write_framed(data_byte, iter.flat_map(|v| v.to_be_bytes()))
+            }
+            DataFormat::U16LEIter(iter) => {
+                self.write_framed(data_byte, iter.flat_map(|v| v.to_le_bytes()))
+            }
+            _ => Err(DisplayError::DataFormatNotImplemented),
+        }
+    }
+}
+
+/// Async I2C communication interface, so display refreshes yield to other tasks while the
+/// bus is busy
+pub struct AsyncI2CInterface<I2C> {
+    i2c: I2C,
+    addr: u8,
+    data_byte: u8,
+    chunker: Chunker,
+}
+
+impl<I2C> AsyncI2CInterface<I2C>
+where
+    I2C: AsyncI2c,
+{
+    /// Create new async I2C interface for communication with a display driver
+    pub fn new(i2c: I2C, addr: u8, data_byte: u8) -> Self {
+        Self {
+            i2c,
+            addr,
+            data_byte,
+            chunker: Chunker::new(DEFAULT_CHUNK_SIZE),
+        }
+    }
+
+    /// Split commands and data into transactions of at most `chunk_size` bytes plus the
+    /// control byte.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunker = Chunker::new(chunk_size);
+        self
+    }
+
+    /// Consume the display interface and return
+    /// the underlying peripherial driver
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
 
+    /// Write `bytes` in chunks, each prefixed with the `control` byte.
+    async fn write_framed(
+        &mut self,
+        control: u8,
+        bytes: impl IntoIterator<Item = u8>,
+    ) -> Result<(), DisplayError> {
+        self.chunker.start(control);
+
+        for byte in bytes {
+            if let Some(chunk) = self.chunker.push(byte) {
                 self.i2c
-                    .write(self.addr, &writebuf[..=slice.len()])
-                    .map_err(|_| DisplayError::BusWriteError)
+                    .write(self.addr, chunk)
+                    .await
+                    .map_err(|_| DisplayError::BusWriteError)?;
             }
+        }
+
+        // Send the remainder, if any
+        if let Some(chunk) = self.chunker.finish() {
+            self.i2c
+                .write(self.addr, chunk)
+                .await
+                .map_err(|_| DisplayError::BusWriteError)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<I2C> AsyncWriteOnlyDataCommand for AsyncI2CInterface<I2C>
+where
+    I2C: AsyncI2c,
+{
+    async fn send_commands(&mut self, cmds: DataFormat<'_>) -> Result<(), DisplayError> {
+        match cmds {
+            DataFormat::U8(slice) => self.write_framed(COMMAND_BYTE, slice.iter().copied()).await,
+            DataFormat::U8Iter(iter) => self.write_framed(COMMAND_BYTE, iter).await,
             _ => Err(DisplayError::DataFormatNotImplemented),
         }
     }
 
-    fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+    async fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+        let data_byte = self.data_byte;
         match buf {
-            DataFormat::U8(slice) => {
-                // No-op if the data buffer is empty
-                if slice.is_empty() {
-                    return Ok(());
+            DataFormat::U8(slice) => self.write_framed(data_byte, slice.iter().copied()).await,
+            DataFormat::U16(slice) => {
+                self.write_framed(data_byte, slice.iter().flat_map(|v| v.to_ne_bytes()))
+                    .await
+            }
+            DataFormat::U16BE(slice) => {
+                self.write_framed(data_byte, slice.iter().flat_map(|v| v.to_be_bytes()))
+                    .await
+            }
+            DataFormat::U16LE(slice) => {
+                self.write_framed(data_byte, slice.iter().flat_map(|v| v.to_le_bytes()))
+                    .await
+            }
+            DataFormat::U8Iter(iter) => self.write_framed(data_byte, iter).await,
+            DataFormat::U16BEIter(iter) => {
+                self.write_framed(data_byte, iter.flat_map(|v| v.to_be_bytes()))
+                    .await
+            }
+            DataFormat::U16LEIter(iter) => {
+                self.write_framed(data_byte, iter.flat_map(|v| v.to_le_bytes()))
+                    .await
+            }
+            _ => Err(DisplayError::DataFormatNotImplemented),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::{ErrorKind, ErrorType, Operation};
+
+    /// Records every write, failing them all once `fail` is set.
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<(u8, Vec<u8>)>,
+        fail: bool,
+    }
+
+    impl ErrorType for Recorder {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Recorder {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if self.fail {
+                return Err(ErrorKind::Bus);
+            }
+            for op in operations {
+                if let Operation::Write(bytes) = op {
+                    self.writes.push((address, bytes.to_vec()));
                 }
+            }
+            Ok(())
+        }
+    }
+
+    fn writes(interface: I2CInterface<Recorder>) -> Vec<Vec<u8>> {
+        interface
+            .release()
+            .writes
+            .into_iter()
+            .map(|w| w.1)
+            .collect()
+    }
 
-                let mut writebuf = [0; 17];
+    /// `control` followed by `bytes`, as one transaction.
+    fn frame(control: u8, bytes: impl IntoIterator<Item = u8>) -> Vec<u8> {
+        std::iter::once(control).chain(bytes).collect()
+    }
 
-                // Data mode
-                writebuf[0] = self.data_byte;
+    #[test]
+    fn long_commands_are_split_into_chunks() {
+        let mut interface = I2CDisplayInterface::new(Recorder::default());
+        let commands: Vec<u8> = (0..40).collect();
+        interface.send_commands(DataFormat::U8(&commands)).unwrap();
+        assert_eq!(
+            writes(interface),
+            [
+                frame(COMMAND_BYTE, 0..16),
+                frame(COMMAND_BYTE, 16..32),
+                frame(COMMAND_BYTE, 32..40),
+            ]
+        );
+    }
 
-                slice
-                    .chunks(16)
-                    .try_for_each(|c| {
-                        let chunk_len = c.len();
+    #[test]
+    fn commands_longer_than_seven_bytes_stay_together() {
+        // SSD1306 init sequences run past 7 bytes and must not be split early
+        let mut interface = I2CDisplayInterface::new(Recorder::default());
+        let init = [0xAE, 0xD5, 0x80, 0xA8, 0x3F, 0xD3, 0x00, 0x40, 0x8D, 0x14];
+        interface.send_commands(DataFormat::U8(&init)).unwrap();
+        let mut iter = init.iter().copied();
+        interface
+            .send_commands(DataFormat::U8Iter(&mut iter))
+            .unwrap();
+        assert_eq!(
+            writes(interface),
+            [frame(COMMAND_BYTE, init), frame(COMMAND_BYTE, init)]
+        );
+    }
 
-                        // Copy over all data from buffer, leaving the data command byte intact
-                        writebuf[1..=chunk_len].copy_from_slice(c);
+    #[test]
+    fn chunks_end_exactly_on_the_boundary() {
+        let mut interface = I2CInterface::new(Recorder::default(), 0x3C, 0x40).chunk_size(8);
+        interface.send_data(DataFormat::U8(&[7; 8])).unwrap();
+        interface.send_data(DataFormat::U8(&[9; 16])).unwrap();
+        interface.send_data(DataFormat::U8(&[1; 17])).unwrap();
+        // Nothing at all is sent for an empty buffer
+        interface.send_data(DataFormat::U8(&[])).unwrap();
+        assert_eq!(
+            writes(interface),
+            [
+                frame(0x40, [7; 8]),
+                frame(0x40, [9; 8]),
+                frame(0x40, [9; 8]),
+                frame(0x40, [1; 8]),
+                frame(0x40, [1; 8]),
+                frame(0x40, [1]),
+            ]
+        );
+    }
 
-                        self.i2c.write(self.addr, &writebuf[0..=chunk_len])
-                    })
-                    .map_err(|_| DisplayError::BusWriteError)
-            }
-            DataFormat::U8Iter(iter) => {
-                let mut writebuf = [0; 17];
-                let mut i = 1;
-                let len = writebuf.len();
+    #[test]
+    fn chunk_size_is_at_least_one() {
+        let mut interface = I2CInterface::new(Recorder::default(), 0x3C, 0x40).chunk_size(0);
+        interface.send_data(DataFormat::U8(&[1, 2])).unwrap();
+        assert_eq!(writes(interface), [frame(0x40, [1]), frame(0x40, [2])]);
+    }
 
-                // Data mode
-                writebuf[0] = self.data_byte;
+    #[test]
+    fn u16_data_keeps_its_byte_order() {
+        let mut interface = I2CInterface::new(Recorder::default(), 0x3C, 0x40).chunk_size(3);
+        interface
+            .send_data(DataFormat::U16BE(&mut [0x0102, 0x0304]))
+            .unwrap();
+        interface
+            .send_data(DataFormat::U16LE(&mut [0x0102, 0x0304]))
+            .unwrap();
+        interface.send_data(DataFormat::U16(&[0x0102])).unwrap();
+        let mut iter = [0x0506, 0x0708].into_iter();
+        interface
+            .send_data(DataFormat::U16BEIter(&mut iter))
+            .unwrap();
+        let mut iter = [0x0506].into_iter();
+        interface
+            .send_data(DataFormat::U16LEIter(&mut iter))
+            .unwrap();
+        assert_eq!(
+            writes(interface),
+            [
+                frame(0x40, [1, 2, 3]),
+                frame(0x40, [4]),
+                frame(0x40, [2, 1, 4]),
+                frame(0x40, [3]),
+                frame(0x40, 0x0102u16.to_ne_bytes()),
+                frame(0x40, [5, 6, 7]),
+                frame(0x40, [8]),
+                frame(0x40, [6, 5]),
+            ]
+        );
+    }
 
-                for byte in iter.into_iter() {
-                    writebuf[i] = byte;
-                    i += 1;
+    #[test]
+    fn writes_go_to_the_display_address() {
+        let mut interface = I2CDisplayInterface::new_alternate_address(Recorder::default());
+        interface.send_data(DataFormat::U8(&[1])).unwrap();
+        assert_eq!(interface.release().writes, [(0x3D, vec![0x40, 1])]);
+    }
 
-                    if i == len {
-                        self.i2c
-                            .write(self.addr, &writebuf[0..=len])
-                            .map_err(|_| DisplayError::BusWriteError)?;
-                        i = 1;
-                    }
-                }
+    #[test]
+    fn bus_errors_and_unsupported_formats_are_reported() {
+        let mut interface = I2CDisplayInterface::new(Recorder {
+            fail: true,
+            ..Default::default()
+        });
+        assert!(matches!(
+            interface.send_commands(DataFormat::U8(&[0xAF])),
+            Err(DisplayError::BusWriteError)
+        ));
+        assert!(matches!(
+            interface.send_commands(DataFormat::U16BE(&mut [0xAF])),
+            Err(DisplayError::DataFormatNotImplemented)
+        ));
+    }
 
-                if i > 1 {
-                    self.i2c
-                        .write(self.addr, &writebuf[0..=i])
-                        .map_err(|_| DisplayError::BusWriteError)?;
-                }
+    /// The async counterpart of [`Recorder`].
+    #[derive(Default)]
+    struct AsyncRecorder {
+        writes: Vec<Vec<u8>>,
+        fail: bool,
+    }
+
+    impl ErrorType for AsyncRecorder {
+        type Error = ErrorKind;
+    }
 
-                Ok(())
+    impl AsyncI2c for AsyncRecorder {
+        async fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if self.fail {
+                return Err(ErrorKind::Bus);
             }
-            _ => Err(DisplayError::DataFormatNotImplemented),
+            for op in operations {
+                if let Operation::Write(bytes) = op {
+                    self.writes.push(bytes.to_vec());
+                }
+            }
+            Ok(())
         }
     }
+
+    #[test]
+    fn async_interface_frames_like_the_sync_one() {
+        smol::block_on(async {
+            let mut interface =
+                I2CDisplayInterface::new_async(AsyncRecorder::default()).chunk_size(4);
+            interface
+                .send_commands(DataFormat::U8(&[1, 2, 3, 4, 5]))
+                .await
+                .unwrap();
+            interface
+                .send_data(DataFormat::U16BE(&mut [0x0102, 0x0304]))
+                .await
+                .unwrap();
+            interface
+                .send_data(DataFormat::U16LE(&mut [0x0506]))
+                .await
+                .unwrap();
+            interface.send_data(DataFormat::U8(&[])).await.unwrap();
+            assert_eq!(
+                interface.release().writes,
+                [
+                    frame(COMMAND_BYTE, [1, 2, 3, 4]),
+                    frame(COMMAND_BYTE, [5]),
+                    frame(0x40, [1, 2, 3, 4]),
+                    frame(0x40, [6, 5]),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn async_interface_reports_bus_errors() {
+        smol::block_on(async {
+            let mut interface = I2CDisplayInterface::new_async(AsyncRecorder {
+                fail: true,
+                ..Default::default()
+            });
+            assert!(matches!(
+                interface.send_data(DataFormat::U8(&[1])).await,
+                Err(DisplayError::BusWriteError)
+            ));
+        });
+    }
 }
@@ -0,0 +1,192 @@
+//! Texas Instruments ADS1115 four channel 16-bit ADC
+use crate::sensor::SensESPSensor;
+use embedded_hal::i2c::I2c;
+use eyeball::{shared::Observable, Subscriber};
+use std::time::{Duration, SystemTime};
+
+pub const REG_CONVERSION: u8 = 0x00;
+pub const REG_CONFIG: u8 = 0x01;
+pub const REG_LO_THRESH: u8 = 0x02;
+pub const REG_HI_THRESH: u8 = 0x03;
+
+/// Time for one conversion at 128 samples per second, with some margin.
+const CONVERSION_TIME: Duration = Duration::from_millis(9);
+
+/// Full scale of the +/-4.096 V gain setting.
+const FULL_SCALE_VOLTS: f32 = 4.096;
+
+/// Config register value starting a single-shot conversion of `channel` against ground at
+/// +/-4.096 V and 128 SPS, comparator disabled.
+pub fn single_shot_config(channel: u8) -> u16 {
+    let os = 1 << 15;
+    let mux = (0b100 | (channel as u16 & 0b11)) << 12;
+    let pga = 0b001 << 9;
+    let mode = 1 << 8;
+    let data_rate = 0b100 << 5;
+    let comparator = 0b11;
+    os | mux | pga | mode | data_rate | comparator
+}
+
+/// Convert a conversion register value to volts.
+pub fn to_volts(raw: u16) -> f32 {
+    raw as i16 as f32 * FULL_SCALE_VOLTS / 32768.0
+}
+
+/// Reads all four single-ended inputs in turn, one conversion per tick.
+pub struct Ads1115<I> {
+    i2c: I,
+    address: u8,
+    channels: [Observable<f32>; 4],
+    pending: Option<(u8, SystemTime)>,
+    duration: Duration,
+    last_measurement: SystemTime,
+}
+
+impl<I: I2c> Ads1115<I> {
+    /// Create an ADC reading every channel once per `duration`.
+    pub fn new(i2c: I, address: u8, duration: Duration) -> Self {
+        Self {
+            i2c,
+            address,
+            channels: Default::default(),
+            pending: None,
+            duration,
+            last_measurement: SystemTime::now() - duration,
+        }
+    }
+
+    /// Voltage of input `channel` (0 - 3)
+    pub fn attach_channel(&mut self, channel: u8) -> Subscriber<f32> {
+        self.channels[channel as usize & 0b11].subscribe()
+    }
+
+    /// Consume the sensor and return the underlying bus
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    fn start(&mut self, channel: u8, now: SystemTime) -> Result<(), I::Error> {
+        let config = single_shot_config(channel).to_be_bytes();
+        self.i2c
+            .write(self.address, &[REG_CONFIG, config[0], config[1]])?;
+        self.pending = Some((channel, now));
+        Ok(())
+    }
+
+    fn read(&mut self, channel: u8) -> Result<(), I::Error> {
+        let mut raw = [0; 2];
+        self.i2c
+            .write_read(self.address, &[REG_CONVERSION], &mut raw)?;
+        self.channels[channel as usize].set(to_volts(u16::from_be_bytes(raw)));
+        Ok(())
+    }
+
+    fn step(&mut self, now: SystemTime) -> Result<(), I::Error> {
+        match self.pending {
+            Some((channel, started)) => {
+                let done = now
+                    .duration_since(started)
+                    .map_or(true, |d| d >= CONVERSION_TIME);
+                if done {
+                    self.pending = None;
+                    self.read(channel)?;
+                    if channel < 3 {
+                        self.start(channel + 1, now)?;
+                    }
+                }
+                Ok(())
+            }
+            None => match now.duration_since(self.last_measurement) {
+                Ok(d) if d >= self.duration => {
+                    self.last_measurement = now;
+                    self.start(0, now)
+                }
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    log::error!("System time error on SensESP-rs tick: {:?}", e);
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+impl<I: I2c> SensESPSensor for Ads1115<I> {
+    fn tick(&mut self) {
+        if let Err(e) = self.step(SystemTime::now()) {
+            self.pending = None;
+            log::error!("ADS1115 error on SensESP-rs tick: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeBus;
+
+    const ADDRESS: u8 = 0x48;
+
+    #[test]
+    fn config_selects_the_channel_against_ground() {
+        assert_eq!(single_shot_config(0), 0xC383);
+        assert_eq!(single_shot_config(1), 0xD383);
+        assert_eq!(single_shot_config(3), 0xF383);
+    }
+
+    #[test]
+    fn conversions_are_signed_at_4_volts_full_scale() {
+        assert_eq!(to_volts(0x4000), 2.048);
+        assert_eq!(to_volts(0x8000), -4.096);
+        assert_eq!(to_volts(1), 0.000_125);
+    }
+
+    #[test]
+    fn channels_are_converted_in_turn() {
+        let bus = FakeBus::default().with(ADDRESS, &[]);
+        let duration = Duration::from_secs(1);
+        let mut adc = Ads1115::new(bus.clone(), ADDRESS, duration);
+        let channels: Vec<_> = (0..4).map(|ch| adc.attach_channel(ch)).collect();
+        let start = SystemTime::now();
+        let config = |ch| {
+            let [high, low] = single_shot_config(ch).to_be_bytes();
+            (ADDRESS, vec![REG_CONFIG, high, low])
+        };
+
+        adc.step(start).unwrap();
+        assert_eq!(bus.take_writes(), vec![config(0)]);
+        // Not read before the conversion has finished
+        adc.step(start + CONVERSION_TIME / 2).unwrap();
+        assert_eq!(bus.take_writes(), vec![]);
+
+        let mut now = start;
+        for ch in 0..4 {
+            now += CONVERSION_TIME;
+            bus.set(
+                ADDRESS,
+                REG_CONVERSION,
+                &(0x1000 * (ch as u16 + 1)).to_be_bytes(),
+            );
+            adc.step(now).unwrap();
+            assert_eq!(channels[ch as usize].get(), 0.512 * (ch + 1) as f32);
+            let next = match ch < 3 {
+                true => vec![config(ch + 1)],
+                false => vec![],
+            };
+            assert_eq!(bus.take_writes(), next);
+        }
+
+        // The next round starts once the interval has passed
+        adc.step(now + CONVERSION_TIME).unwrap();
+        assert_eq!(bus.take_writes(), vec![]);
+        adc.step(start + duration).unwrap();
+        assert_eq!(bus.take_writes(), vec![config(0)]);
+    }
+
+    #[test]
+    fn failed_conversion_starts_over() {
+        let mut adc = Ads1115::new(FakeBus::default(), ADDRESS, Duration::from_secs(1));
+        adc.tick();
+        assert!(adc.pending.is_none());
+    }
+}
@@ -0,0 +1,297 @@
+//! Bosch BME280 temperature, pressure and humidity sensor
+use crate::sensor::SensESPSensor;
+use embedded_hal::i2c::I2c;
+use eyeball::{shared::Observable, Subscriber};
+use std::time::{Duration, SystemTime};
+
+pub const PRIMARY_ADDRESS: u8 = 0x76;
+pub const SECONDARY_ADDRESS: u8 = 0x77;
+
+const REG_CALIB_00: u8 = 0x88;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+
+/// Factory trimming values read once from the sensor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Calibration {
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    p: [f64; 9],
+    h1: f64,
+    h2: f64,
+    h3: f64,
+    h4: f64,
+    h5: f64,
+    h6: f64,
+}
+
+impl Calibration {
+    /// Parse the calibration registers starting at 0x88 and 0xE1.
+    pub fn from_registers(low: &[u8; 26], high: &[u8; 7]) -> Self {
+        let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]) as f64;
+        let i16_at = |b: &[u8], i: usize| i16::from_le_bytes([b[i], b[i + 1]]) as f64;
+        let mut p = [0.0; 9];
+        p[0] = u16_at(low, 6);
+        for (n, v) in p.iter_mut().enumerate().skip(1) {
+            *v = i16_at(low, 6 + 2 * n);
+        }
+        Self {
+            t1: u16_at(low, 0),
+            t2: i16_at(low, 2),
+            t3: i16_at(low, 4),
+            p,
+            h1: low[25] as f64,
+            h2: i16_at(high, 0),
+            h3: high[2] as f64,
+            h4: (((high[3] as i8 as i16) << 4) | (high[4] & 0x0F) as i16) as f64,
+            h5: (((high[5] as i8 as i16) << 4) | (high[4] >> 4) as i16) as f64,
+            h6: high[6] as i8 as f64,
+        }
+    }
+
+    /// Convert the burst read of the data registers to kelvin, pascal and a relative
+    /// humidity ratio, following the floating point formulas of the datasheet.
+    pub fn compensate(&self, data: &[u8; 8]) -> (f64, f64, f64) {
+        let adc_p = ((data[0] as u32) << 12 | (data[1] as u32) << 4 | (data[2] as u32) >> 4) as f64;
+        let adc_t = ((data[3] as u32) << 12 | (data[4] as u32) << 4 | (data[5] as u32) >> 4) as f64;
+        let adc_h = ((data[6] as u32) << 8 | data[7] as u32) as f64;
+
+        let var1 = (adc_t / 16384.0 - self.t1 / 1024.0) * self.t2;
+        let var2 = (adc_t / 131072.0 - self.t1 / 8192.0).powi(2) * self.t3;
+        let t_fine = var1 + var2;
+        let celsius = t_fine / 5120.0;
+
+        let p = &self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p[5] / 32768.0;
+        var2 += var1 * p[4] * 2.0;
+        var2 = var2 / 4.0 + p[3] * 65536.0;
+        var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p[0];
+        let pressure = match var1 == 0.0 {
+            true => 0.0,
+            false => {
+                let mut pa = 1048576.0 - adc_p;
+                pa = (pa - var2 / 4096.0) * 6250.0 / var1;
+                let var1 = p[8] * pa * pa / 2147483648.0;
+                let var2 = pa * p[7] / 32768.0;
+                pa + (var1 + var2 + p[6]) / 16.0
+            }
+        };
+
+        let mut h = t_fine - 76800.0;
+        h = (adc_h - (self.h4 * 64.0 + self.h5 / 16384.0 * h))
+            * (self.h2 / 65536.0
+                * (1.0 + self.h6 / 67108864.0 * h * (1.0 + self.h3 / 67108864.0 * h)));
+        h *= 1.0 - self.h1 * h / 524288.0;
+        let humidity = h.clamp(0.0, 100.0) / 100.0;
+
+        (celsius + 273.15, pressure, humidity)
+    }
+}
+
+/// Periodically reads a BME280 running in normal mode.
+pub struct Bme280<I> {
+    i2c: I,
+    address: u8,
+    calibration: Calibration,
+    temperature: Observable<f32>,
+    pressure: Observable<f32>,
+    humidity: Observable<f32>,
+    duration: Duration,
+    last_measurement: SystemTime,
+}
+
+impl<I: I2c> Bme280<I> {
+    /// Read the calibration and start continuous 1x oversampled measurements.
+    pub fn new(mut i2c: I, address: u8, duration: Duration) -> Result<Self, I::Error> {
+        let mut low = [0; 26];
+        let mut high = [0; 7];
+        i2c.write_read(address, &[REG_CALIB_00], &mut low)?;
+        i2c.write_read(address, &[REG_CALIB_26], &mut high)?;
+        // Humidity settings only take effect after a write to ctrl_meas
+        i2c.write(address, &[REG_CTRL_HUM, 0x01])?;
+        i2c.write(address, &[REG_CONFIG, 0xA0])?;
+        i2c.write(address, &[REG_CTRL_MEAS, 0x27])?;
+
+        Ok(Self {
+            i2c,
+            address,
+            calibration: Calibration::from_registers(&low, &high),
+            temperature: Observable::new(0.0),
+            pressure: Observable::new(0.0),
+            humidity: Observable::new(0.0),
+            duration,
+            last_measurement: SystemTime::now() - duration,
+        })
+    }
+
+    /// Temperature in kelvin
+    pub fn attach_temperature(&mut self) -> Subscriber<f32> {
+        self.temperature.subscribe()
+    }
+
+    /// Pressure in pascal
+    pub fn attach_pressure(&mut self) -> Subscriber<f32> {
+        self.pressure.subscribe()
+    }
+
+    /// Relative humidity as a ratio from 0 to 1
+    pub fn attach_humidity(&mut self) -> Subscriber<f32> {
+        self.humidity.subscribe()
+    }
+
+    /// Consume the sensor and return the underlying bus
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    fn measure(&mut self) -> Result<(), I::Error> {
+        let mut data = [0; 8];
+        self.i2c.write_read(self.address, &[REG_DATA], &mut data)?;
+        let (temperature, pressure, humidity) = self.calibration.compensate(&data);
+        self.temperature.set(temperature as f32);
+        self.pressure.set(pressure as f32);
+        self.humidity.set(humidity as f32);
+        Ok(())
+    }
+}
+
+impl<I: I2c> SensESPSensor for Bme280<I> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        match now.duration_since(self.last_measurement) {
+            Ok(d) => {
+                if d >= self.duration {
+                    if let Err(e) = self.measure() {
+                        log::error!("BME280 read error on SensESP-rs tick: {:?}", e);
+                    }
+                    self.last_measurement = now;
+                }
+            }
+            Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeBus;
+
+    /// The worked example of the BMP280 datasheet, with humidity trimming from a BME280.
+    const T: [i32; 3] = [27504, 26435, -1000];
+    const P: [i32; 9] = [36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000];
+    const H: [i32; 6] = [75, 362, 0, 324, 50, 30];
+
+    /// Pack trimming values into the two calibration blocks.
+    fn registers(t: [i32; 3], p: [i32; 9], h: [i32; 6]) -> ([u8; 26], [u8; 7]) {
+        let mut low = [0; 26];
+        for (i, v) in t.iter().chain(&p).enumerate() {
+            low[2 * i..2 * i + 2].copy_from_slice(&(*v as u16).to_le_bytes());
+        }
+        low[25] = h[0] as u8;
+        let mut high = [0; 7];
+        high[..2].copy_from_slice(&(h[1] as i16).to_le_bytes());
+        high[2] = h[2] as u8;
+        // H4 and H5 are 12 bits each, sharing the nibbles of 0xE5
+        high[3] = (h[3] >> 4) as u8;
+        high[4] = (h[3] & 0x0F) as u8 | ((h[4] & 0x0F) << 4) as u8;
+        high[5] = (h[4] >> 4) as u8;
+        high[6] = h[5] as u8;
+        (low, high)
+    }
+
+    /// Data registers holding 20-bit pressure and temperature and 16-bit humidity readings.
+    fn data(adc_p: u32, adc_t: u32, adc_h: u16) -> [u8; 8] {
+        let [_, p0, p1, p2] = (adc_p << 4).to_be_bytes();
+        let [_, t0, t1, t2] = (adc_t << 4).to_be_bytes();
+        let [h0, h1] = adc_h.to_be_bytes();
+        [p0, p1, p2, t0, t1, t2, h0, h1]
+    }
+
+    fn calibration() -> Calibration {
+        let (low, high) = registers(T, P, H);
+        Calibration::from_registers(&low, &high)
+    }
+
+    #[test]
+    fn calibration_reads_signed_and_packed_values() {
+        let c = calibration();
+        assert_eq!((c.t1, c.t2, c.t3), (27504.0, 26435.0, -1000.0));
+        assert_eq!(c.p, P.map(|v| v as f64));
+        assert_eq!((c.h1, c.h2, c.h3), (75.0, 362.0, 0.0));
+        assert_eq!((c.h4, c.h5, c.h6), (324.0, 50.0, 30.0));
+
+        let (low, high) = registers(T, P, [75, -362, 0, -324, -30, -8]);
+        let c = Calibration::from_registers(&low, &high);
+        assert_eq!((c.h2, c.h4, c.h5, c.h6), (-362.0, -324.0, -30.0, -8.0));
+    }
+
+    #[test]
+    fn compensation_matches_the_datasheet_example() {
+        let (kelvin, pascal, _) = calibration().compensate(&data(415148, 519888, 0));
+        assert!((kelvin - (25.0825 + 273.15)).abs() < 1e-4, "{}", kelvin);
+        assert!((pascal - 100653.27).abs() < 0.01, "{}", pascal);
+    }
+
+    #[test]
+    fn humidity_is_compensated_for_temperature_and_clamped() {
+        let humidity = |adc_h| calibration().compensate(&data(415148, 519888, adc_h)).2;
+        assert!(
+            (humidity(30000) - 0.510831).abs() < 1e-6,
+            "{}",
+            humidity(30000)
+        );
+        assert_eq!(humidity(0), 0.0);
+        assert_eq!(humidity(u16::MAX), 1.0);
+    }
+
+    #[test]
+    fn zero_pressure_trimming_reads_zero() {
+        let (low, high) = registers(T, [0; 9], H);
+        let calibration = Calibration::from_registers(&low, &high);
+        assert_eq!(calibration.compensate(&data(415148, 519888, 0)).1, 0.0);
+    }
+
+    #[test]
+    fn sensor_starts_normal_mode_and_publishes_readings() {
+        let (low, high) = registers(T, P, H);
+        let bus = FakeBus::default().with(
+            PRIMARY_ADDRESS,
+            &[
+                (REG_CALIB_00, &low),
+                (REG_CALIB_26, &high),
+                (REG_DATA, &data(415148, 519888, 30000)),
+            ],
+        );
+        let mut sensor = Bme280::new(bus.clone(), PRIMARY_ADDRESS, Duration::from_secs(1)).unwrap();
+        // ctrl_hum must come before ctrl_meas to take effect
+        assert_eq!(
+            bus.take_writes(),
+            vec![
+                (PRIMARY_ADDRESS, vec![REG_CTRL_HUM, 0x01]),
+                (PRIMARY_ADDRESS, vec![REG_CONFIG, 0xA0]),
+                (PRIMARY_ADDRESS, vec![REG_CTRL_MEAS, 0x27]),
+            ]
+        );
+
+        let temperature = sensor.attach_temperature();
+        let pressure = sensor.attach_pressure();
+        let humidity = sensor.attach_humidity();
+        sensor.tick();
+        assert!((temperature.get() - 298.2325).abs() < 1e-3);
+        assert!((pressure.get() - 100653.27).abs() < 0.01);
+        assert!((humidity.get() - 0.510831).abs() < 1e-6);
+    }
+
+    #[test]
+    fn missing_sensor_fails_to_start() {
+        assert!(Bme280::new(FakeBus::default(), PRIMARY_ADDRESS, Duration::from_secs(1)).is_err());
+    }
+}
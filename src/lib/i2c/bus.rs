@@ -0,0 +1,426 @@
+//! Sharing one I2C bus between devices on different threads and tasks
+use embedded_hal::i2c::{ErrorType, I2c, Operation};
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+use smol::lock::Mutex as AsyncMutex;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Transaction timeout and clock frequency for one device on a shared bus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceSettings {
+    pub timeout: Duration,
+    pub frequency: u32,
+}
+
+impl DeviceSettings {
+    /// 100 kHz standard mode with a 50 ms timeout.
+    pub fn new() -> Self {
+        Self {
+            timeout: Duration::from_millis(50),
+            frequency: 100_000,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Clock frequency in Hz
+    pub fn frequency(mut self, frequency: u32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A bus whose timeout and clock can be changed between transactions.
+pub trait BusSettings: ErrorType {
+    fn apply(&mut self, settings: &DeviceSettings) -> Result<(), Self::Error>;
+}
+
+struct Shared<B> {
+    bus: B,
+    /// Settings of the device that used the bus last
+    applied: Option<DeviceSettings>,
+}
+
+impl<B: BusSettings> Shared<B> {
+    fn prepare(&mut self, settings: &DeviceSettings) -> Result<(), B::Error> {
+        if self.applied.as_ref() != Some(settings) {
+            // Forget the previous settings first in case applying fails halfway
+            self.applied = None;
+            self.bus.apply(settings)?;
+            self.applied = Some(*settings);
+        }
+        Ok(())
+    }
+}
+
+/// Hands out device handles to a bus shared between threads.
+///
+/// Every transaction holds the bus for its whole duration, so a write-read is never
+/// interleaved with another device's traffic.
+pub struct SharedBus<B> {
+    shared: Arc<Mutex<Shared<B>>>,
+}
+
+impl<B: I2c + BusSettings> SharedBus<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared { bus, applied: None })),
+        }
+    }
+
+    /// A handle for a device using the default settings.
+    pub fn device(&self) -> BusDevice<B> {
+        self.device_with(DeviceSettings::default())
+    }
+
+    pub fn device_with(&self, settings: DeviceSettings) -> BusDevice<B> {
+        BusDevice {
+            shared: self.shared.clone(),
+            settings,
+        }
+    }
+}
+
+impl<B> Clone for SharedBus<B> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// One device's handle to a [`SharedBus`], usable from any thread.
+pub struct BusDevice<B> {
+    shared: Arc<Mutex<Shared<B>>>,
+    settings: DeviceSettings,
+}
+
+impl<B> BusDevice<B> {
+    pub fn settings(&self) -> &DeviceSettings {
+        &self.settings
+    }
+}
+
+impl<B: ErrorType> ErrorType for BusDevice<B> {
+    type Error = B::Error;
+}
+
+impl<B: I2c + BusSettings> I2c for BusDevice<B> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        // A thread that panicked mid-transaction leaves the bus usable, it is re-configured below
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        shared.prepare(&self.settings)?;
+        shared.bus.transaction(address, operations)
+    }
+}
+
+/// Hands out device handles to a bus shared between async tasks, which wait for the bus
+/// without blocking their executor.
+///
+/// Wrap a blocking driver such as `health::TimeoutI2c` or `health::MonitoredBus` in
+/// [`Blocking`] to share it here.
+pub struct AsyncSharedBus<B> {
+    shared: Arc<AsyncMutex<Shared<B>>>,
+}
+
+impl<B: AsyncI2c + BusSettings> AsyncSharedBus<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            shared: Arc::new(AsyncMutex::new(Shared { bus, applied: None })),
+        }
+    }
+
+    /// A handle for a device using the default settings.
+    pub fn device(&self) -> AsyncBusDevice<B> {
+        self.device_with(DeviceSettings::default())
+    }
+
+    pub fn device_with(&self, settings: DeviceSettings) -> AsyncBusDevice<B> {
+        AsyncBusDevice {
+            shared: self.shared.clone(),
+            settings,
+        }
+    }
+}
+
+impl<B> Clone for AsyncSharedBus<B> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// One device's handle to an [`AsyncSharedBus`].
+pub struct AsyncBusDevice<B> {
+    shared: Arc<AsyncMutex<Shared<B>>>,
+    settings: DeviceSettings,
+}
+
+impl<B> AsyncBusDevice<B> {
+    pub fn settings(&self) -> &DeviceSettings {
+        &self.settings
+    }
+}
+
+impl<B: ErrorType> ErrorType for AsyncBusDevice<B> {
+    type Error = B::Error;
+}
+
+impl<B: AsyncI2c + BusSettings> AsyncI2c for AsyncBusDevice<B> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut shared = self.shared.lock().await;
+        shared.prepare(&self.settings)?;
+        shared.bus.transaction(address, operations).await
+    }
+}
+
+/// Runs a blocking bus from async tasks.
+///
+/// Each transaction blocks the task polling it, for at most the driver's timeout, while
+/// tasks waiting for the bus in an [`AsyncSharedBus`] stay free to run.
+pub struct Blocking<B>(pub B);
+
+impl<B> Blocking<B> {
+    /// Return the underlying bus
+    pub fn into_inner(self) -> B {
+        self.0
+    }
+}
+
+impl<B: ErrorType> ErrorType for Blocking<B> {
+    type Error = B::Error;
+}
+
+impl<B: BusSettings> BusSettings for Blocking<B> {
+    fn apply(&mut self, settings: &DeviceSettings) -> Result<(), Self::Error> {
+        self.0.apply(settings)
+    }
+}
+
+impl<B: I2c> AsyncI2c for Blocking<B> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.0.transaction(address, operations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::ErrorKind;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Reads return the address, so replies can be checked against the device that asked.
+    #[derive(Default)]
+    struct FakeBus {
+        busy: Arc<AtomicBool>,
+        settings: Option<DeviceSettings>,
+        /// Every settings change
+        applied: Arc<Mutex<Vec<DeviceSettings>>>,
+        /// Address and settings of every transaction
+        log: Arc<Mutex<Vec<(u8, DeviceSettings)>>>,
+    }
+
+    impl ErrorType for FakeBus {
+        type Error = ErrorKind;
+    }
+
+    impl BusSettings for FakeBus {
+        fn apply(&mut self, settings: &DeviceSettings) -> Result<(), ErrorKind> {
+            if settings.frequency == 0 {
+                return Err(ErrorKind::Other);
+            }
+            self.settings = Some(*settings);
+            self.applied.lock().unwrap().push(*settings);
+            Ok(())
+        }
+    }
+
+    impl I2c for FakeBus {
+        fn transaction(&mut self, address: u8, ops: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+            assert!(
+                !self.busy.swap(true, Ordering::SeqCst),
+                "overlapping transactions"
+            );
+            if address == PANICS {
+                panic!("driver bug");
+            }
+            std::thread::sleep(Duration::from_micros(50));
+            for op in ops.iter_mut() {
+                if let Operation::Read(buf) = op {
+                    buf.fill(address);
+                }
+            }
+            let settings = self.settings.expect("transaction before settings");
+            self.log.lock().unwrap().push((address, settings));
+            self.busy.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    /// Address that panics the driver mid-transaction.
+    const PANICS: u8 = 0x7F;
+
+    fn settings(n: u8) -> DeviceSettings {
+        DeviceSettings::new()
+            .frequency(100_000 * (n as u32 + 1))
+            .timeout(Duration::from_millis(10 * (n as u64 + 1)))
+    }
+
+    #[test]
+    fn threads_take_turns_with_their_own_settings() {
+        let fake = FakeBus::default();
+        let log = fake.log.clone();
+        let shared = SharedBus::new(fake);
+        let threads: Vec<_> = (0..4u8)
+            .map(|n| {
+                let mut device = shared.device_with(settings(n));
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut buf = [0; 4];
+                        device.write_read(0x40 + n, &[0], &mut buf).unwrap();
+                        assert_eq!(buf, [0x40 + n; 4]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 400);
+        for (address, applied) in log.iter() {
+            assert_eq!(*applied, settings(address - 0x40));
+        }
+    }
+
+    #[test]
+    fn settings_are_applied_only_on_change() {
+        let fake = FakeBus::default();
+        let applied = fake.applied.clone();
+        let shared = SharedBus::new(fake);
+        let mut slow = shared.device();
+        let mut fast = shared.device_with(settings(3));
+        let mut also_slow = shared.clone().device();
+
+        slow.write(0x10, &[]).unwrap();
+        also_slow.write(0x11, &[]).unwrap();
+        fast.write(0x12, &[]).unwrap();
+        fast.write(0x12, &[]).unwrap();
+        slow.write(0x10, &[]).unwrap();
+        assert_eq!(
+            *applied.lock().unwrap(),
+            vec![DeviceSettings::new(), settings(3), DeviceSettings::new()]
+        );
+        assert_eq!(fast.settings(), &settings(3));
+    }
+
+    #[test]
+    fn failed_settings_are_retried() {
+        let fake = FakeBus::default();
+        let applied = fake.applied.clone();
+        let shared = SharedBus::new(fake);
+        let mut slow = shared.device();
+        let mut broken = shared.device_with(DeviceSettings::new().frequency(0));
+
+        slow.write(0x10, &[]).unwrap();
+        assert_eq!(broken.write(0x11, &[]), Err(ErrorKind::Other));
+        // The bus may be half configured, so the next device applies its settings again
+        slow.write(0x10, &[]).unwrap();
+        assert_eq!(applied.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn a_panicking_thread_leaves_the_bus_usable() {
+        let fake = FakeBus::default();
+        let busy = fake.busy.clone();
+        let log = fake.log.clone();
+        let shared = SharedBus::new(fake);
+        let mut panics = shared.device();
+        let result = std::thread::spawn(move || panics.write(PANICS, &[])).join();
+        assert!(result.is_err());
+
+        // The fake saw its transaction cut short
+        busy.store(false, Ordering::SeqCst);
+        let mut device = shared.device_with(settings(1));
+        device.write(0x10, &[]).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec![(0x10, settings(1))]);
+    }
+
+    #[test]
+    fn tasks_take_turns_with_their_own_settings() {
+        let fake = FakeBus::default();
+        let log = fake.log.clone();
+        let shared = AsyncSharedBus::new(Blocking(fake));
+        smol::block_on(async {
+            let tasks: Vec<_> = (0..4u8)
+                .map(|n| {
+                    let mut device = shared.device_with(settings(n % 2));
+                    smol::spawn(async move {
+                        for _ in 0..50 {
+                            let mut buf = [0; 2];
+                            device.write_read(0x40 + n, &[0], &mut buf).await.unwrap();
+                            assert_eq!(buf, [0x40 + n; 2]);
+                            smol::future::yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await;
+            }
+        });
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 200);
+        for (address, applied) in log.iter() {
+            assert_eq!(*applied, settings((address - 0x40) % 2));
+        }
+    }
+
+    #[test]
+    fn tasks_sharing_settings_apply_them_once() {
+        let fake = FakeBus::default();
+        let applied = fake.applied.clone();
+        let shared = AsyncSharedBus::new(Blocking(fake));
+        smol::block_on(async {
+            let tasks: Vec<_> = (0..4u8)
+                .map(|n| {
+                    let mut device = shared.device_with(settings(2));
+                    smol::spawn(async move {
+                        for _ in 0..10 {
+                            device.write(0x40 + n, &[0]).await.unwrap();
+                            smol::future::yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await;
+            }
+        });
+        assert_eq!(*applied.lock().unwrap(), vec![settings(2)]);
+    }
+}
@@ -0,0 +1,756 @@
+//! I2C bus error statistics and lockup recovery
+use super::bus::{BusSettings, DeviceSettings};
+use crate::sensor::Attachable;
+#[cfg(target_os = "espidf")]
+use embedded_hal::i2c::NoAcknowledgeSource;
+use embedded_hal::i2c::{Error, ErrorKind, ErrorType, I2c, Operation};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::delay::{Ets, TickType};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::gpio::{AnyIOPin, PinDriver};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::i2c::{config::Config, I2c as I2cPeripheral, I2cDriver};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::peripheral::Peripheral;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::sys::{
+    esp, i2c_get_period, i2c_set_period, EspError, TickType_t, ESP_ERR_INVALID_ARG,
+    ESP_ERR_TIMEOUT, ESP_FAIL,
+};
+use eyeball::{shared::Observable, Subscriber};
+use std::collections::BTreeMap;
+#[cfg(target_os = "espidf")]
+use std::time::Duration;
+
+/// Clock pulses that let any slave finish the byte it is stuck sending.
+pub const RECOVERY_PULSES: u8 = 9;
+
+/// Consecutive bus errors, other than NACKs, before the bus is recovered.
+pub const DEFAULT_FAILURES_BEFORE_RECOVERY: u32 = 3;
+
+/// The hardware side of bus recovery: bit-banging the lines and re-creating the driver.
+pub trait BusRecovery {
+    type Bus: I2c + BusSettings;
+    type Error: core::fmt::Debug;
+
+    /// Create a driver for the bus.
+    fn init(&mut self) -> Result<Self::Bus, Self::Error>;
+
+    /// Take back the lines from a driver before they are bit-banged.
+    fn release(&mut self, bus: Self::Bus);
+
+    /// Whether a slave is holding SDA low.
+    fn sda_is_low(&mut self) -> Result<bool, Self::Error>;
+
+    /// Pulse SCL low then high once.
+    fn clock(&mut self) -> Result<(), Self::Error>;
+
+    /// Generate a stop condition, SDA rising while SCL is high.
+    fn stop(&mut self) -> Result<(), Self::Error>;
+}
+
+/// What a recovery attempt did.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Recovery {
+    /// Clock pulses needed for the slave to release SDA
+    pub pulses: u8,
+    /// False if SDA was still low after every pulse
+    pub released: bool,
+}
+
+/// The standard nine-clock recovery: pulse SCL until the slave lets go of SDA, then send a
+/// stop so every device returns to idle.
+pub fn unstick<R: BusRecovery>(recovery: &mut R) -> Result<Recovery, R::Error> {
+    let mut pulses = 0;
+    while pulses < RECOVERY_PULSES && recovery.sda_is_low()? {
+        recovery.clock()?;
+        pulses += 1;
+    }
+    let released = !recovery.sda_is_low()?;
+    recovery.stop()?;
+    Ok(Recovery { pulses, released })
+}
+
+/// Errors seen talking to one address.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct AddressStats {
+    pub transactions: u32,
+    pub nacks: u32,
+    /// Timeouts, arbitration losses and other bus errors
+    pub errors: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BusStats {
+    pub addresses: BTreeMap<u8, AddressStats>,
+    pub recoveries: u32,
+    /// Recoveries after which SDA was still stuck or the driver failed to start
+    pub failed_recoveries: u32,
+}
+
+impl BusStats {
+    pub fn nacks(&self) -> u32 {
+        self.addresses.values().map(|a| a.nacks).sum()
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.addresses.values().map(|a| a.errors).sum()
+    }
+}
+
+/// An I2C bus that counts failures per address and recovers itself when it locks up.
+///
+/// Share it between devices like any other bus, e.g. behind a mutex.
+pub struct MonitoredBus<R: BusRecovery> {
+    recovery: R,
+    bus: Option<R::Bus>,
+    failures: u32,
+    failures_before_recovery: u32,
+    /// Re-applied to the driver after a recovery
+    settings: Option<DeviceSettings>,
+    stats: BusStats,
+    observable: Observable<BusStats>,
+}
+
+impl<R: BusRecovery> MonitoredBus<R> {
+    pub fn new(mut recovery: R) -> Result<Self, R::Error> {
+        let bus = recovery.init()?;
+        Ok(Self {
+            recovery,
+            bus: Some(bus),
+            failures: 0,
+            failures_before_recovery: DEFAULT_FAILURES_BEFORE_RECOVERY,
+            settings: None,
+            stats: BusStats::default(),
+            observable: Observable::new(BusStats::default()),
+        })
+    }
+
+    /// Recover after `failures` consecutive bus errors instead of the default 3.
+    pub fn failures_before_recovery(mut self, failures: u32) -> Self {
+        self.failures_before_recovery = failures.max(1);
+        self
+    }
+
+    pub fn stats(&self) -> &BusStats {
+        &self.stats
+    }
+
+    /// Release the lines, unstick the bus and start a new driver.
+    pub fn recover(&mut self) {
+        if let Some(bus) = self.bus.take() {
+            self.recovery.release(bus);
+        }
+        self.stats.recoveries += 1;
+        self.failures = 0;
+
+        let unstuck = match unstick(&mut self.recovery) {
+            Ok(Recovery {
+                released: true,
+                pulses,
+            }) => {
+                log::warn!("I2C bus recovered after {} clock pulses", pulses);
+                true
+            }
+            Ok(_) => {
+                log::error!("I2C bus recovery failed, SDA is still held low");
+                false
+            }
+            Err(e) => {
+                log::error!("I2C bus recovery error: {:?}", e);
+                false
+            }
+        };
+
+        let restarted = match self.recovery.init() {
+            Ok(mut bus) => {
+                let applied = match self.settings {
+                    Some(settings) => bus.apply(&settings).map_err(|e| e.kind()),
+                    None => Ok(()),
+                };
+                self.bus = Some(bus);
+                match applied {
+                    Ok(_) => true,
+                    Err(e) => {
+                        log::error!("I2C driver settings failed after restart: {:?}", e);
+                        false
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("I2C driver failed to restart: {:?}", e);
+                false
+            }
+        };
+
+        if !(unstuck && restarted) {
+            self.stats.failed_recoveries += 1;
+        }
+        self.observable.set(self.stats.clone());
+    }
+
+    fn record(&mut self, address: u8, result: Result<(), ErrorKind>) -> Result<(), ErrorKind> {
+        let entry = self.stats.addresses.entry(address).or_default();
+        entry.transactions += 1;
+        match result {
+            Ok(_) => {
+                self.failures = 0;
+                return Ok(());
+            }
+            // An absent or busy device, the bus itself is fine
+            Err(ErrorKind::NoAcknowledge(_)) => {
+                entry.nacks += 1;
+                self.failures = 0;
+            }
+            Err(_) => {
+                entry.errors += 1;
+                self.failures += 1;
+            }
+        }
+        self.observable.set(self.stats.clone());
+
+        if self.failures >= self.failures_before_recovery {
+            self.recover();
+        }
+        result
+    }
+
+    /// Consume the wrapper and return the underlying driver, if one is running
+    pub fn release(self) -> Option<R::Bus> {
+        self.bus
+    }
+}
+
+impl<R: BusRecovery> Attachable<BusStats> for MonitoredBus<R> {
+    fn attach(&mut self) -> Subscriber<BusStats> {
+        self.observable.subscribe()
+    }
+}
+
+impl<R: BusRecovery> ErrorType for MonitoredBus<R> {
+    type Error = ErrorKind;
+}
+
+impl<R: BusRecovery> I2c for MonitoredBus<R> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if self.bus.is_none() {
+            self.recover();
+        }
+        let result = match self.bus.as_mut() {
+            Some(bus) => bus.transaction(address, operations).map_err(|e| e.kind()),
+            None => Err(ErrorKind::Bus),
+        };
+        self.record(address, result)
+    }
+}
+
+impl<R: BusRecovery> BusSettings for MonitoredBus<R> {
+    fn apply(&mut self, settings: &DeviceSettings) -> Result<(), Self::Error> {
+        self.settings = Some(*settings);
+        match self.bus.as_mut() {
+            Some(bus) => bus.apply(settings).map_err(|e| e.kind()),
+            // Applied once recovery starts a new driver
+            None => Ok(()),
+        }
+    }
+}
+
+/// An ESP32 I2C driver that gives up after a timeout rather than blocking forever.
+#[cfg(target_os = "espidf")]
+pub struct TimeoutI2c<'d> {
+    driver: I2cDriver<'d>,
+    timeout: TickType_t,
+    /// Clock frequency the driver was configured with, in Hz
+    configured: u32,
+    frequency: u32,
+    /// SCL high and low periods at the configured frequency
+    period: Option<(i32, i32)>,
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d> TimeoutI2c<'d> {
+    /// `frequency` is the baudrate in the driver's config.
+    pub fn new(driver: I2cDriver<'d>, timeout: Duration, frequency: u32) -> Self {
+        Self {
+            driver,
+            timeout: TickType::new_millis(timeout.as_millis() as u64).ticks(),
+            configured: frequency,
+            frequency,
+            period: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = TickType::new_millis(timeout.as_millis() as u64).ticks();
+    }
+
+    /// Change the clock frequency by scaling the SCL periods the driver set up for its
+    /// configured frequency. A frequency of 0 is rejected.
+    pub fn set_frequency(&mut self, frequency: u32) -> Result<(), EspError> {
+        if frequency == 0 {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_ARG>());
+        }
+        if frequency == self.frequency {
+            return Ok(());
+        }
+        let port = self.driver.port();
+        let (high, low) = match self.period {
+            Some(period) => period,
+            None => {
+                let (mut high, mut low) = (0, 0);
+                esp!(unsafe { i2c_get_period(port, &mut high, &mut low) })?;
+                self.period = Some((high, low));
+                (high, low)
+            }
+        };
+        let scale =
+            |cycles: i32| (cycles as u64 * self.configured as u64 / frequency as u64) as i32;
+        esp!(unsafe { i2c_set_period(port, scale(high), scale(low)) })?;
+        self.frequency = frequency;
+        Ok(())
+    }
+
+    /// Consume the wrapper and return the underlying driver
+    pub fn release(self) -> I2cDriver<'d> {
+        self.driver
+    }
+}
+
+#[cfg(target_os = "espidf")]
+fn error_kind(e: EspError) -> ErrorKind {
+    match e.code() {
+        // The legacy driver reports a missing ACK as a generic failure
+        ESP_FAIL => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+        ESP_ERR_TIMEOUT => ErrorKind::Other,
+        _ => ErrorKind::Bus,
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl ErrorType for TimeoutI2c<'_> {
+    type Error = ErrorKind;
+}
+
+#[cfg(target_os = "espidf")]
+impl BusSettings for TimeoutI2c<'_> {
+    fn apply(&mut self, settings: &DeviceSettings) -> Result<(), Self::Error> {
+        self.set_timeout(settings.timeout);
+        self.set_frequency(settings.frequency).map_err(error_kind)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl I2c for TimeoutI2c<'_> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.driver
+            .transaction(address, operations, self.timeout)
+            .map_err(error_kind)
+    }
+}
+
+/// Owns an ESP32 I2C controller and its pins so the bus can be bit-banged and restarted.
+#[cfg(target_os = "espidf")]
+pub struct EspBusRecovery<I: I2cPeripheral> {
+    i2c: I,
+    sda: AnyIOPin,
+    scl: AnyIOPin,
+    config: Config,
+    timeout: Duration,
+}
+
+#[cfg(target_os = "espidf")]
+impl<I: I2cPeripheral> EspBusRecovery<I> {
+    pub fn new(i2c: I, sda: AnyIOPin, scl: AnyIOPin, config: Config, timeout: Duration) -> Self {
+        Self {
+            i2c,
+            sda,
+            scl,
+            config,
+            timeout,
+        }
+    }
+}
+
+// SAFETY for the clone_unchecked calls below: MonitoredBus drops the running driver through
+// release() before the pins are bit-banged, and each PinDriver is dropped before the next
+// use of its pin, so the peripherals are never driven by two owners at once.
+#[cfg(target_os = "espidf")]
+impl<I: I2cPeripheral> BusRecovery for EspBusRecovery<I> {
+    type Bus = TimeoutI2c<'static>;
+    type Error = EspError;
+
+    fn init(&mut self) -> Result<Self::Bus, Self::Error> {
+        let driver = unsafe {
+            I2cDriver::new(
+                self.i2c.clone_unchecked(),
+                self.sda.clone_unchecked(),
+                self.scl.clone_unchecked(),
+                &self.config,
+            )?
+        };
+        Ok(TimeoutI2c::new(
+            driver,
+            self.timeout,
+            self.config.baudrate.0,
+        ))
+    }
+
+    fn release(&mut self, bus: Self::Bus) {
+        drop(bus);
+    }
+
+    fn sda_is_low(&mut self) -> Result<bool, Self::Error> {
+        let sda = PinDriver::input(unsafe { self.sda.clone_unchecked() })?;
+        Ok(sda.is_low())
+    }
+
+    fn clock(&mut self) -> Result<(), Self::Error> {
+        let mut scl = PinDriver::input_output_od(unsafe { self.scl.clone_unchecked() })?;
+        scl.set_low()?;
+        Ets::delay_us(5);
+        scl.set_high()?;
+        Ets::delay_us(5);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        let mut sda = PinDriver::input_output_od(unsafe { self.sda.clone_unchecked() })?;
+        let mut scl = PinDriver::input_output_od(unsafe { self.scl.clone_unchecked() })?;
+        sda.set_low()?;
+        Ets::delay_us(5);
+        scl.set_high()?;
+        Ets::delay_us(5);
+        sda.set_high()?;
+        Ets::delay_us(5);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::{poll_update, Updates};
+    use embedded_hal::i2c::NoAcknowledgeSource;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::task::Poll;
+
+    /// Address of a device that never acknowledges.
+    const ABSENT: u8 = 0x10;
+    const SENSOR: u8 = 0x76;
+
+    /// Faults to inject and a record of what recovery did about them.
+    #[derive(Default)]
+    struct Wires {
+        /// Transactions that fail with a bus error before the bus works again
+        failing: u32,
+        /// Clock pulses before the stuck slave releases SDA
+        sda_low_for: u8,
+        init_fails: bool,
+        apply_fails: bool,
+        clocks: u32,
+        stops: u32,
+        inits: u32,
+        releases: u32,
+        applied: Vec<DeviceSettings>,
+    }
+
+    struct FakeBus(Rc<RefCell<Wires>>);
+
+    impl ErrorType for FakeBus {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for FakeBus {
+        fn transaction(&mut self, address: u8, _: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+            let mut wires = self.0.borrow_mut();
+            match (address, wires.failing) {
+                (ABSENT, _) => Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+                (_, 0) => Ok(()),
+                _ => {
+                    wires.failing -= 1;
+                    Err(ErrorKind::Other)
+                }
+            }
+        }
+    }
+
+    impl BusSettings for FakeBus {
+        fn apply(&mut self, settings: &DeviceSettings) -> Result<(), ErrorKind> {
+            let mut wires = self.0.borrow_mut();
+            wires.applied.push(*settings);
+            match wires.apply_fails {
+                true => Err(ErrorKind::Bus),
+                false => Ok(()),
+            }
+        }
+    }
+
+    struct FakeRecovery(Rc<RefCell<Wires>>);
+
+    impl BusRecovery for FakeRecovery {
+        type Bus = FakeBus;
+        type Error = ();
+
+        fn init(&mut self) -> Result<FakeBus, ()> {
+            let mut wires = self.0.borrow_mut();
+            wires.inits += 1;
+            match wires.init_fails {
+                true => Err(()),
+                false => Ok(FakeBus(self.0.clone())),
+            }
+        }
+
+        fn release(&mut self, _: FakeBus) {
+            self.0.borrow_mut().releases += 1;
+        }
+
+        fn sda_is_low(&mut self) -> Result<bool, ()> {
+            Ok(self.0.borrow().sda_low_for > 0)
+        }
+
+        fn clock(&mut self) -> Result<(), ()> {
+            let mut wires = self.0.borrow_mut();
+            wires.clocks += 1;
+            wires.sda_low_for = wires.sda_low_for.saturating_sub(1);
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<(), ()> {
+            self.0.borrow_mut().stops += 1;
+            Ok(())
+        }
+    }
+
+    fn monitored() -> (MonitoredBus<FakeRecovery>, Rc<RefCell<Wires>>) {
+        let wires = Rc::new(RefCell::new(Wires::default()));
+        let bus = MonitoredBus::new(FakeRecovery(wires.clone())).unwrap();
+        (bus, wires)
+    }
+
+    #[test]
+    fn unstick_clocks_until_sda_is_released() {
+        let wires = Rc::new(RefCell::new(Wires::default()));
+        let mut recovery = FakeRecovery(wires.clone());
+        assert_eq!(
+            unstick(&mut recovery),
+            Ok(Recovery {
+                pulses: 0,
+                released: true
+            })
+        );
+        assert_eq!(wires.borrow().stops, 1);
+
+        wires.borrow_mut().sda_low_for = 4;
+        assert_eq!(
+            unstick(&mut recovery),
+            Ok(Recovery {
+                pulses: 4,
+                released: true
+            })
+        );
+        assert_eq!(wires.borrow().clocks, 4);
+
+        wires.borrow_mut().sda_low_for = 100;
+        assert_eq!(
+            unstick(&mut recovery),
+            Ok(Recovery {
+                pulses: RECOVERY_PULSES,
+                released: false
+            })
+        );
+        assert_eq!(wires.borrow().clocks, 4 + RECOVERY_PULSES as u32);
+        assert_eq!(wires.borrow().stops, 3);
+    }
+
+    #[test]
+    fn nacks_are_counted_without_recovering() {
+        let (mut bus, wires) = monitored();
+        for _ in 0..10 {
+            assert_eq!(
+                bus.write(ABSENT, &[0]),
+                Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+            );
+        }
+        assert_eq!(bus.stats().nacks(), 10);
+        assert_eq!(bus.stats().errors(), 0);
+        assert_eq!(bus.stats().recoveries, 0);
+        assert_eq!(wires.borrow().inits, 1);
+    }
+
+    #[test]
+    fn consecutive_errors_recover_the_bus() {
+        let (mut bus, wires) = monitored();
+        wires.borrow_mut().failing = 3;
+        wires.borrow_mut().sda_low_for = 4;
+        for n in 1..=3 {
+            assert_eq!(bus.write(SENSOR, &[0]), Err(ErrorKind::Other));
+            assert_eq!(bus.stats().recoveries, n / 3);
+        }
+        assert_eq!(bus.stats().failed_recoveries, 0);
+        {
+            let wires = wires.borrow();
+            assert_eq!(wires.releases, 1);
+            assert_eq!(wires.clocks, 4);
+            assert_eq!(wires.stops, 1);
+            assert_eq!(wires.inits, 2);
+        }
+
+        assert_eq!(bus.write(SENSOR, &[0]), Ok(()));
+        assert_eq!(
+            bus.stats().addresses[&SENSOR],
+            AddressStats {
+                transactions: 4,
+                nacks: 0,
+                errors: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn success_or_nack_resets_the_error_count() {
+        let (mut bus, wires) = monitored();
+        wires.borrow_mut().failing = 2;
+        let _ = bus.write(SENSOR, &[0]);
+        let _ = bus.write(SENSOR, &[0]);
+        assert!(bus.write(ABSENT, &[0]).is_err());
+        wires.borrow_mut().failing = 2;
+        let _ = bus.write(SENSOR, &[0]);
+        let _ = bus.write(SENSOR, &[0]);
+        assert_eq!(bus.write(SENSOR, &[0]), Ok(()));
+        assert_eq!(bus.stats().errors(), 4);
+        assert_eq!(bus.stats().recoveries, 0);
+    }
+
+    #[test]
+    fn failures_before_recovery_is_configurable() {
+        let wires = Rc::new(RefCell::new(Wires::default()));
+        let mut bus = MonitoredBus::new(FakeRecovery(wires.clone()))
+            .unwrap()
+            .failures_before_recovery(1);
+        wires.borrow_mut().failing = 2;
+        let _ = bus.write(SENSOR, &[0]);
+        let _ = bus.write(SENSOR, &[0]);
+        assert_eq!(bus.stats().recoveries, 2);
+
+        // Zero would recover before every transaction
+        let mut bus = MonitoredBus::new(FakeRecovery(wires.clone()))
+            .unwrap()
+            .failures_before_recovery(0);
+        assert_eq!(bus.write(SENSOR, &[0]), Ok(()));
+        assert_eq!(bus.stats().recoveries, 0);
+    }
+
+    #[test]
+    fn sda_stuck_low_is_a_failed_recovery() {
+        let (mut bus, wires) = monitored();
+        wires.borrow_mut().failing = 3;
+        wires.borrow_mut().sda_low_for = RECOVERY_PULSES + 1;
+        for _ in 0..3 {
+            let _ = bus.write(SENSOR, &[0]);
+        }
+        assert_eq!(bus.stats().recoveries, 1);
+        assert_eq!(bus.stats().failed_recoveries, 1);
+        assert_eq!(wires.borrow().clocks, RECOVERY_PULSES as u32);
+
+        // The slave lets go on the next attempt
+        wires.borrow_mut().failing = 3;
+        for _ in 0..3 {
+            let _ = bus.write(SENSOR, &[0]);
+        }
+        assert_eq!(bus.stats().recoveries, 2);
+        assert_eq!(bus.stats().failed_recoveries, 1);
+        assert_eq!(wires.borrow().clocks, RECOVERY_PULSES as u32 + 1);
+    }
+
+    #[test]
+    fn driver_failing_to_restart_is_retried_on_the_next_transaction() {
+        let (mut bus, wires) = monitored();
+        wires.borrow_mut().failing = 3;
+        wires.borrow_mut().init_fails = true;
+        for _ in 0..3 {
+            let _ = bus.write(SENSOR, &[0]);
+        }
+        assert_eq!(bus.stats().failed_recoveries, 1);
+
+        // Without a driver every transaction recovers first
+        assert_eq!(bus.write(SENSOR, &[0]), Err(ErrorKind::Bus));
+        assert_eq!(bus.stats().recoveries, 2);
+        assert_eq!(bus.stats().failed_recoveries, 2);
+
+        wires.borrow_mut().init_fails = false;
+        assert_eq!(bus.write(SENSOR, &[0]), Ok(()));
+        assert_eq!(bus.stats().recoveries, 3);
+        assert_eq!(bus.stats().failed_recoveries, 2);
+        // Only running drivers are released
+        assert_eq!(wires.borrow().releases, 1);
+    }
+
+    #[test]
+    fn settings_are_reapplied_after_recovery() {
+        let (mut bus, wires) = monitored();
+        bus.recover();
+        assert!(wires.borrow().applied.is_empty());
+
+        let fast = DeviceSettings::new().frequency(400_000);
+        bus.apply(&fast).unwrap();
+        assert_eq!(wires.borrow().applied, vec![fast]);
+
+        wires.borrow_mut().failing = 3;
+        for _ in 0..3 {
+            let _ = bus.write(SENSOR, &[0]);
+        }
+        assert_eq!(wires.borrow().applied, vec![fast, fast]);
+        assert_eq!(bus.stats().failed_recoveries, 0);
+
+        wires.borrow_mut().apply_fails = true;
+        bus.recover();
+        assert_eq!(wires.borrow().applied.len(), 3);
+        assert_eq!(bus.stats().failed_recoveries, 1);
+    }
+
+    #[test]
+    fn settings_wait_for_a_running_driver() {
+        let (mut bus, wires) = monitored();
+        wires.borrow_mut().init_fails = true;
+        bus.recover();
+        let fast = DeviceSettings::new().frequency(400_000);
+        assert_eq!(bus.apply(&fast), Ok(()));
+        assert!(wires.borrow().applied.is_empty());
+
+        wires.borrow_mut().init_fails = false;
+        assert_eq!(bus.write(SENSOR, &[0]), Ok(()));
+        assert_eq!(wires.borrow().applied, vec![fast]);
+    }
+
+    #[test]
+    fn stats_are_published() {
+        let (mut bus, wires) = monitored();
+        let mut stats = Updates::new(bus.attach());
+        let _ = bus.write(ABSENT, &[0]);
+        match poll_update(&mut stats) {
+            Poll::Ready(Some(s)) => assert_eq!(s.nacks(), 1),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        wires.borrow_mut().failing = 3;
+        for _ in 0..3 {
+            let _ = bus.write(SENSOR, &[0]);
+        }
+        match poll_update(&mut stats) {
+            Poll::Ready(Some(s)) => {
+                assert_eq!(s.errors(), 3);
+                assert_eq!(s.recoveries, 1);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
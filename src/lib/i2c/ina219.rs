@@ -0,0 +1,179 @@
+//! Texas Instruments INA219 high-side current and bus voltage monitor
+use crate::sensor::SensESPSensor;
+use embedded_hal::i2c::I2c;
+use eyeball::{shared::Observable, Subscriber};
+use std::time::{Duration, SystemTime};
+
+pub const REG_CONFIG: u8 = 0x00;
+pub const REG_SHUNT_VOLTAGE: u8 = 0x01;
+pub const REG_BUS_VOLTAGE: u8 = 0x02;
+
+/// Config register value after power-on: 32 V bus range, +/-320 mV shunt range, 12-bit
+/// continuous conversions.
+pub const POWER_ON_CONFIG: u16 = 0x399F;
+
+/// The 0.1 ohm shunt fitted to most breakout boards.
+pub const DEFAULT_SHUNT_OHMS: f32 = 0.1;
+
+/// Bus voltage in volts from the bus voltage register.
+pub fn bus_volts(raw: u16) -> f32 {
+    (raw >> 3) as f32 * 0.004
+}
+
+/// Shunt voltage in volts from the shunt voltage register.
+pub fn shunt_volts(raw: u16) -> f32 {
+    raw as i16 as f32 * 0.000_01
+}
+
+/// Periodically reads bus voltage and shunt current using the power-on configuration.
+pub struct Ina219<I> {
+    i2c: I,
+    address: u8,
+    shunt_ohms: f32,
+    voltage: Observable<f32>,
+    current: Observable<f32>,
+    power: Observable<f32>,
+    duration: Duration,
+    last_measurement: SystemTime,
+}
+
+impl<I: I2c> Ina219<I> {
+    pub fn new(i2c: I, address: u8, duration: Duration) -> Self {
+        Self {
+            i2c,
+            address,
+            shunt_ohms: DEFAULT_SHUNT_OHMS,
+            voltage: Observable::new(0.0),
+            current: Observable::new(0.0),
+            power: Observable::new(0.0),
+            duration,
+            last_measurement: SystemTime::now() - duration,
+        }
+    }
+
+    /// Use a shunt other than the 0.1 ohm default.
+    pub fn shunt(mut self, ohms: f32) -> Self {
+        self.shunt_ohms = ohms;
+        self
+    }
+
+    /// Bus voltage in volts
+    pub fn attach_voltage(&mut self) -> Subscriber<f32> {
+        self.voltage.subscribe()
+    }
+
+    /// Current through the shunt in amperes
+    pub fn attach_current(&mut self) -> Subscriber<f32> {
+        self.current.subscribe()
+    }
+
+    /// Power in watts
+    pub fn attach_power(&mut self) -> Subscriber<f32> {
+        self.power.subscribe()
+    }
+
+    /// Consume the sensor and return the underlying bus
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u16, I::Error> {
+        let mut raw = [0; 2];
+        self.i2c.write_read(self.address, &[register], &mut raw)?;
+        Ok(u16::from_be_bytes(raw))
+    }
+
+    fn measure(&mut self) -> Result<(), I::Error> {
+        let voltage = bus_volts(self.read_register(REG_BUS_VOLTAGE)?);
+        let current = shunt_volts(self.read_register(REG_SHUNT_VOLTAGE)?) / self.shunt_ohms;
+        self.voltage.set(voltage);
+        self.current.set(current);
+        self.power.set(voltage * current);
+        Ok(())
+    }
+}
+
+impl<I: I2c> SensESPSensor for Ina219<I> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        match now.duration_since(self.last_measurement) {
+            Ok(d) => {
+                if d >= self.duration {
+                    if let Err(e) = self.measure() {
+                        log::error!("INA219 read error on SensESP-rs tick: {:?}", e);
+                    }
+                    self.last_measurement = now;
+                }
+            }
+            Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_close, FakeBus};
+
+    const ADDRESS: u8 = 0x40;
+
+    #[test]
+    fn bus_voltage_skips_the_status_bits() {
+        // 12 V in 4 mV steps, with the conversion ready and overflow bits set
+        assert_close(bus_volts(3000 << 3 | 0b011), 12.0, 1e-5);
+        assert_close(bus_volts(0xFFF8), 32.764, 1e-5);
+        assert_eq!(bus_volts(0), 0.0);
+    }
+
+    #[test]
+    fn shunt_voltage_is_signed() {
+        assert_close(shunt_volts(0x7D00), 0.32, 1e-5);
+        assert_close(shunt_volts(0x8300), -0.32, 1e-5);
+        assert_close(shunt_volts(1), 0.000_01, 1e-5);
+    }
+
+    fn bus(bus_raw: u16, shunt_raw: u16) -> FakeBus {
+        FakeBus::default().with(
+            ADDRESS,
+            &[
+                (REG_BUS_VOLTAGE, &bus_raw.to_be_bytes()),
+                (REG_SHUNT_VOLTAGE, &shunt_raw.to_be_bytes()),
+            ],
+        )
+    }
+
+    #[test]
+    fn current_and_power_use_the_default_shunt() {
+        let mut sensor = Ina219::new(bus(3000 << 3, 1000), ADDRESS, Duration::from_secs(1));
+        let (voltage, current, power) = (
+            sensor.attach_voltage(),
+            sensor.attach_current(),
+            sensor.attach_power(),
+        );
+        sensor.tick();
+        assert_close(voltage.get(), 12.0, 1e-5);
+        assert_close(current.get(), 0.1, 1e-5);
+        assert_close(power.get(), 1.2, 1e-5);
+    }
+
+    #[test]
+    fn current_scales_with_the_shunt() {
+        // A 10 mohm shunt reads 10 mV at 1 A, charging shows as negative current
+        let raw = (-1000i16) as u16;
+        let mut sensor =
+            Ina219::new(bus(3000 << 3, raw), ADDRESS, Duration::from_secs(1)).shunt(0.01);
+        let (current, power) = (sensor.attach_current(), sensor.attach_power());
+        sensor.tick();
+        assert_close(current.get(), -1.0, 1e-5);
+        assert_close(power.get(), -12.0, 1e-5);
+    }
+
+    #[test]
+    fn failed_reads_keep_the_last_values() {
+        let mut sensor = Ina219::new(FakeBus::default(), ADDRESS, Duration::from_secs(1));
+        let voltage = sensor.attach_voltage();
+        sensor.tick();
+        assert_eq!(voltage.get(), 0.0);
+    }
+}
@@ -0,0 +1,172 @@
+//! InvenSense MPU6050 accelerometer and gyroscope
+use crate::sensor::{Attachable, SensESPSensor};
+use embedded_hal::i2c::I2c;
+use eyeball::{shared::Observable, Subscriber};
+use std::time::{Duration, SystemTime};
+
+pub const REG_ACCEL_XOUT_H: u8 = 0x3B;
+pub const REG_PWR_MGMT_1: u8 = 0x6B;
+pub const REG_WHO_AM_I: u8 = 0x75;
+
+const STANDARD_GRAVITY: f32 = 9.80665;
+/// LSB per g at the default +/-2 g range
+const ACCEL_SCALE: f32 = 16384.0;
+/// LSB per degree per second at the default +/-250 deg/s range
+const GYRO_SCALE: f32 = 131.0;
+
+/// One burst read of the sensor, in SI units.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Motion {
+    /// m/s^2 along x, y and z
+    pub acceleration: [f32; 3],
+    /// rad/s around x, y and z
+    pub rotation: [f32; 3],
+    /// Die temperature in kelvin
+    pub temperature: f32,
+}
+
+impl Motion {
+    /// Decode the 14 data registers starting at ACCEL_XOUT_H.
+    pub fn from_registers(data: &[u8; 14]) -> Self {
+        let word = |i: usize| i16::from_be_bytes([data[2 * i], data[2 * i + 1]]) as f32;
+        Self {
+            acceleration: [0, 1, 2].map(|i| word(i) / ACCEL_SCALE * STANDARD_GRAVITY),
+            rotation: [4, 5, 6].map(|i| (word(i) / GYRO_SCALE).to_radians()),
+            temperature: word(3) / 340.0 + 36.53 + 273.15,
+        }
+    }
+
+    /// Roll and pitch in radians, from the direction of gravity.
+    pub fn attitude(&self) -> (f32, f32) {
+        let [x, y, z] = self.acceleration;
+        (y.atan2(z), (-x).atan2((y * y + z * z).sqrt()))
+    }
+}
+
+/// Periodically reads acceleration and rotation rates at the default ranges.
+pub struct Mpu6050<I> {
+    i2c: I,
+    address: u8,
+    motion: Observable<Motion>,
+    duration: Duration,
+    last_measurement: SystemTime,
+}
+
+impl<I: I2c> Mpu6050<I> {
+    /// Wake the sensor from sleep.
+    pub fn new(mut i2c: I, address: u8, duration: Duration) -> Result<Self, I::Error> {
+        i2c.write(address, &[REG_PWR_MGMT_1, 0x00])?;
+        Ok(Self {
+            i2c,
+            address,
+            motion: Observable::new(Motion::default()),
+            duration,
+            last_measurement: SystemTime::now() - duration,
+        })
+    }
+
+    /// Consume the sensor and return the underlying bus
+    pub fn release(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I: I2c> Attachable<Motion> for Mpu6050<I> {
+    fn attach(&mut self) -> Subscriber<Motion> {
+        self.motion.subscribe()
+    }
+}
+
+impl<I: I2c> SensESPSensor for Mpu6050<I> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        match now.duration_since(self.last_measurement) {
+            Ok(d) => {
+                if d >= self.duration {
+                    let mut data = [0; 14];
+                    match self
+                        .i2c
+                        .write_read(self.address, &[REG_ACCEL_XOUT_H], &mut data)
+                    {
+                        Ok(_) => {
+                            self.motion.set(Motion::from_registers(&data));
+                        }
+                        Err(e) => log::error!("MPU6050 read error on SensESP-rs tick: {:?}", e),
+                    }
+                    self.last_measurement = now;
+                }
+            }
+            Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_close, FakeBus};
+
+    const ADDRESS: u8 = 0x68;
+
+    /// Data registers for accelerometer, temperature and gyro readings.
+    fn registers(accel: [i16; 3], temperature: i16, gyro: [i16; 3]) -> [u8; 14] {
+        let mut data = [0; 14];
+        let words = accel.into_iter().chain([temperature]).chain(gyro);
+        for (i, w) in words.enumerate() {
+            data[2 * i..2 * i + 2].copy_from_slice(&w.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn readings_are_scaled_to_si_units() {
+        let motion =
+            Motion::from_registers(&registers([-16384, 8192, 16384], -521, [131, -262, 0]));
+        assert_close(motion.acceleration[0], -STANDARD_GRAVITY, 1e-5);
+        assert_close(motion.acceleration[1], STANDARD_GRAVITY / 2.0, 1e-5);
+        assert_close(motion.acceleration[2], STANDARD_GRAVITY, 1e-5);
+        assert_close(motion.rotation[0], 1f32.to_radians(), 1e-5);
+        assert_close(motion.rotation[1], -2f32.to_radians(), 1e-5);
+        assert_eq!(motion.rotation[2], 0.0);
+        // 35 degrees, from the offset and sensitivity of the register map
+        assert!((motion.temperature - 308.15).abs() < 0.01);
+    }
+
+    #[test]
+    fn attitude_follows_gravity() {
+        let level = Motion::from_registers(&registers([0, 0, 16384], 0, [0; 3]));
+        assert_eq!(level.attitude(), (0.0, 0.0));
+
+        // Heeled 30 degrees to starboard, then bow down 30 degrees
+        let g = 16384.0f32;
+        let (sin, cos) = 30f32.to_radians().sin_cos();
+        let heeled = Motion::from_registers(&registers(
+            [0, (g * sin) as i16, (g * cos) as i16],
+            0,
+            [0; 3],
+        ));
+        assert!((heeled.attitude().0 - 30f32.to_radians()).abs() < 1e-3);
+        let pitched = Motion::from_registers(&registers(
+            [(g * sin) as i16, 0, (g * cos) as i16],
+            0,
+            [0; 3],
+        ));
+        assert!((pitched.attitude().1 + 30f32.to_radians()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sensor_wakes_and_publishes_readings() {
+        let data = registers([0, 0, 16384], 0, [0, 0, 131]);
+        let bus = FakeBus::default().with(ADDRESS, &[(REG_ACCEL_XOUT_H, &data)]);
+        let mut sensor = Mpu6050::new(bus.clone(), ADDRESS, Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            bus.take_writes(),
+            vec![(ADDRESS, vec![REG_PWR_MGMT_1, 0x00])]
+        );
+
+        let motion = sensor.attach();
+        sensor.tick();
+        assert_eq!(motion.get(), Motion::from_registers(&data));
+    }
+}
@@ -0,0 +1,464 @@
+//! Opt-in automatic set-up of known sensors found by a bus scan
+use super::ads1115::{self, Ads1115};
+use super::bme280::{self, Bme280};
+use super::ina219::{self, Ina219};
+use super::mpu6050::{Motion, Mpu6050};
+use super::scan::Found;
+use super::I2CInterface;
+use crate::application::Application;
+use crate::dashboard::{Dashboard, Page, Widget};
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, SignalKPath, Update};
+use embedded_hal::i2c::I2c;
+use eyeball::Subscriber;
+use ssd1306::{mode::BufferedGraphicsMode, prelude::*, Ssd1306};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+/// Interval between reads of provisioned sensors.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most widgets that fit on a 128x64 display.
+const MAX_WIDGETS: usize = 5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Driver {
+    Bme280,
+    Ads1115,
+    Ina219,
+    Ssd1306,
+    Mpu6050,
+}
+
+/// A driver to start at an address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Detected {
+    pub driver: Driver,
+    pub address: u8,
+}
+
+fn read_register<I: I2c>(i2c: &mut I, address: u8, register: u8) -> Option<u16> {
+    let mut raw = [0; 2];
+    i2c.write_read(address, &[register], &mut raw).ok()?;
+    Some(u16::from_be_bytes(raw))
+}
+
+/// Choose a driver for each scan result, reading registers to tell apart devices that share
+/// addresses.
+///
+/// Devices that can't be positively identified are skipped: BME280 and MPU6050 need a matching
+/// chip ID from the scan, ADS1115 its power-on comparator thresholds and INA219 its power-on
+/// configuration.
+pub fn detect<I: I2c>(i2c: &mut I, found: &[Found]) -> Vec<Detected> {
+    let mut detected = Vec::new();
+    for f in found {
+        let identified = f.chip_id.is_some();
+        let driver = if identified && f.could_be("BME280") {
+            Some(Driver::Bme280)
+        } else if identified && f.could_be("MPU6050") {
+            Some(Driver::Mpu6050)
+        } else if f.could_be("SSD1306") {
+            Some(Driver::Ssd1306)
+        } else if f.could_be("ADS1115")
+            && read_register(i2c, f.address, ads1115::REG_LO_THRESH) == Some(0x8000)
+            && read_register(i2c, f.address, ads1115::REG_HI_THRESH) == Some(0x7FFF)
+        {
+            Some(Driver::Ads1115)
+        } else if f.could_be("INA219")
+            && read_register(i2c, f.address, ina219::REG_CONFIG) == Some(ina219::POWER_ON_CONFIG)
+        {
+            Some(Driver::Ina219)
+        } else {
+            None
+        };
+
+        match driver {
+            Some(driver) => detected.push(Detected {
+                driver,
+                address: f.address,
+            }),
+            None => log::info!("No driver for I2C device at {:#04x}", f.address),
+        }
+    }
+    detected
+}
+
+/// The Signal K paths a provisioned driver publishes to.
+///
+/// A BME280 at its primary address reports the inside environment and at its secondary
+/// address the outside one.
+pub fn default_paths(detected: &Detected) -> Vec<String> {
+    let id = format!("{:#04x}", detected.address);
+    match detected.driver {
+        Driver::Bme280 => {
+            let zone = match detected.address {
+                bme280::SECONDARY_ADDRESS => "outside",
+                _ => "inside",
+            };
+            ["temperature", "pressure", "relativeHumidity"]
+                .iter()
+                .map(|p| format!("environment.{}.{}", zone, p))
+                .collect()
+        }
+        Driver::Ads1115 => (0..4)
+            .map(|ch| format!("electrical.adc.{}.channel{}.voltage", id, ch))
+            .collect(),
+        Driver::Ina219 => ["voltage", "current", "power"]
+            .iter()
+            .map(|p| format!("electrical.batteries.{}.{}", id, p))
+            .collect(),
+        Driver::Mpu6050 => vec![
+            "navigation.attitude.roll".to_string(),
+            "navigation.attitude.pitch".to_string(),
+            "navigation.rateOfTurn".to_string(),
+        ],
+        Driver::Ssd1306 => Vec::new(),
+    }
+}
+
+/// Publishes roll, pitch and rate of turn from an MPU6050 mounted with z up.
+struct MotionPaths {
+    subscriber: Updates<Motion>,
+    paths: Vec<String>,
+    output: SignalKOutput,
+}
+
+impl SensESPSensor for MotionPaths {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(motion)) = poll_update(&mut self.subscriber) {
+            let (roll, pitch) = motion.attitude();
+            // The gyro turns counter-clockwise seen from above, Signal K turns to starboard
+            let values = [roll, pitch, -motion.rotation[2]];
+            for (path, value) in self.paths.iter().zip(values) {
+                self.output.publish(Update::new(path.as_str(), value));
+            }
+        }
+    }
+}
+
+type BufferedDisplay<D> =
+    Ssd1306<I2CInterface<D>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+
+/// Shows the provisioned sensors on an SSD1306.
+struct DashboardDisplay<D: I2c> {
+    display: BufferedDisplay<D>,
+    dashboard: Dashboard,
+    start: Instant,
+}
+
+impl<D: I2c> SensESPSensor for DashboardDisplay<D> {
+    fn tick(&mut self) {
+        match self
+            .dashboard
+            .refresh(&mut self.display, self.start.elapsed())
+        {
+            Ok(true) => {
+                if let Err(e) = self.display.flush() {
+                    log::error!("SSD1306 flush error on SensESP-rs tick: {:?}", e);
+                }
+            }
+            Ok(false) => (),
+            Err(e) => log::error!("SSD1306 draw error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+/// The last segment of `path`, or for the channels of an ADC, which all end in `voltage`, the
+/// channel segment.
+fn label(path: &str) -> &str {
+    let mut segments = path.rsplit('.');
+    let last = segments.next().unwrap_or(path);
+    match segments.next() {
+        Some(channel) if channel.starts_with("channel") => channel,
+        _ => last,
+    }
+}
+
+/// Start the detected drivers, register them with `app` and publish their readings to the
+/// default Signal K paths. Displays show the readings of every other provisioned sensor.
+///
+/// `device` is called once per driver for a handle to the shared bus, for example an
+/// `embedded_hal_bus::i2c::MutexDevice`. Drivers that fail to initialise are logged and
+/// skipped.
+pub fn provision<D, F>(
+    mut app: Application,
+    output: &SignalKOutput,
+    detected: &[Detected],
+    mut device: F,
+) -> Application
+where
+    D: I2c + 'static,
+    F: FnMut() -> D,
+{
+    let mut widgets: Vec<(String, &'static str, Subscriber<f32>)> = Vec::new();
+
+    for d in detected {
+        let paths = default_paths(d);
+
+        // Sensors are registered ahead of their paths so a reading is forwarded on the same tick
+        let readings: Vec<(&'static str, Subscriber<f32>)> = match d.driver {
+            Driver::Bme280 => match Bme280::new(device(), d.address, DEFAULT_INTERVAL) {
+                Ok(mut sensor) => {
+                    let readings = vec![
+                        ("K", sensor.attach_temperature()),
+                        ("Pa", sensor.attach_pressure()),
+                        ("", sensor.attach_humidity()),
+                    ];
+                    app = app.register(sensor);
+                    readings
+                }
+                Err(e) => {
+                    log::error!("BME280 at {:#04x} failed to start: {:?}", d.address, e);
+                    continue;
+                }
+            },
+            Driver::Ads1115 => {
+                let mut sensor = Ads1115::new(device(), d.address, DEFAULT_INTERVAL);
+                let readings = (0..4).map(|ch| ("V", sensor.attach_channel(ch))).collect();
+                app = app.register(sensor);
+                readings
+            }
+            Driver::Ina219 => {
+                let mut sensor = Ina219::new(device(), d.address, DEFAULT_INTERVAL);
+                let readings = vec![
+                    ("V", sensor.attach_voltage()),
+                    ("A", sensor.attach_current()),
+                    ("W", sensor.attach_power()),
+                ];
+                app = app.register(sensor);
+                readings
+            }
+            Driver::Mpu6050 => match Mpu6050::new(device(), d.address, DEFAULT_INTERVAL) {
+                Ok(mut sensor) => {
+                    let subscriber = sensor.attach();
+                    app = app.register(sensor).register(MotionPaths {
+                        subscriber: subscriber.into(),
+                        paths,
+                        output: output.clone(),
+                    });
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("MPU6050 at {:#04x} failed to start: {:?}", d.address, e);
+                    continue;
+                }
+            },
+            // Displays are started last, once every reading is known
+            Driver::Ssd1306 => continue,
+        };
+
+        for (path, (unit, subscriber)) in paths.iter().zip(readings) {
+            widgets.push((label(path).to_string(), unit, subscriber.clone()));
+            app = app.register(SignalKPath::new(output, path, subscriber));
+        }
+    }
+
+    for d in detected.iter().filter(|d| d.driver == Driver::Ssd1306) {
+        let interface = I2CInterface::new(device(), d.address, 0x40);
+        let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+            .into_buffered_graphics_mode();
+        if let Err(e) = display.init() {
+            log::error!("SSD1306 at {:#04x} failed to start: {:?}", d.address, e);
+            continue;
+        }
+
+        let mut dashboard = Dashboard::new().rotate(Duration::from_secs(5));
+        for chunk in widgets.chunks(MAX_WIDGETS) {
+            let mut page = Page::new();
+            for (label, unit, subscriber) in chunk {
+                page = page.widget(Widget::value(label, unit, subscriber.clone()));
+            }
+            dashboard = dashboard.page(page);
+        }
+        app = app.register(DashboardDisplay {
+            display,
+            dashboard,
+            start: Instant::now(),
+        });
+    }
+
+    app
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i2c::scan::{scan, Probe};
+    use crate::signalk::Value;
+    use crate::testing::FakeBus;
+    use eyeball::shared::Observable;
+
+    fn detected(bus: &FakeBus) -> Vec<(Driver, u8)> {
+        let mut bus = bus.clone();
+        let found = scan(&mut bus, Probe::Auto, true);
+        detect(&mut bus, &found)
+            .iter()
+            .map(|d| (d.driver, d.address))
+            .collect()
+    }
+
+    #[test]
+    fn detect_identifies_supported_devices() {
+        let bus = FakeBus::default()
+            .with(0x3C, &[])
+            .with(0x40, &[(ina219::REG_CONFIG, &[0x39, 0x9F])])
+            .with(
+                0x48,
+                &[
+                    (ads1115::REG_LO_THRESH, &[0x80, 0x00]),
+                    (ads1115::REG_HI_THRESH, &[0x7F, 0xFF]),
+                ],
+            )
+            .with(0x68, &[(0x75, &[0x68])])
+            .with(0x76, &[(0xD0, &[0x60])]);
+        assert_eq!(
+            detected(&bus),
+            vec![
+                (Driver::Ssd1306, 0x3C),
+                (Driver::Ina219, 0x40),
+                (Driver::Ads1115, 0x48),
+                (Driver::Mpu6050, 0x68),
+                (Driver::Bme280, 0x76),
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_skips_devices_it_cannot_identify() {
+        let bus = FakeBus::default()
+            // A BMP280 shares the BME280's addresses
+            .with(0x77, &[(0xD0, &[0x58])])
+            // An MPU-9250 answers at the MPU6050's address
+            .with(0x68, &[(0x75, &[0x71])])
+            // No chip ID at all
+            .with(0x69, &[])
+            // Configured away from their power-on state
+            .with(0x40, &[(ina219::REG_CONFIG, &[0x01, 0x9F])])
+            .with(0x48, &[(ads1115::REG_LO_THRESH, &[0x00, 0x00])])
+            // Nothing known at this address
+            .with(0x10, &[]);
+        assert_eq!(detected(&bus), vec![]);
+    }
+
+    #[test]
+    fn default_paths_name_each_reading() {
+        let paths = |driver, address| default_paths(&Detected { driver, address });
+        assert_eq!(
+            paths(Driver::Bme280, bme280::PRIMARY_ADDRESS),
+            vec![
+                "environment.inside.temperature",
+                "environment.inside.pressure",
+                "environment.inside.relativeHumidity",
+            ]
+        );
+        assert_eq!(
+            paths(Driver::Bme280, bme280::SECONDARY_ADDRESS)[0],
+            "environment.outside.temperature"
+        );
+        assert_eq!(
+            paths(Driver::Ads1115, 0x49),
+            vec![
+                "electrical.adc.0x49.channel0.voltage",
+                "electrical.adc.0x49.channel1.voltage",
+                "electrical.adc.0x49.channel2.voltage",
+                "electrical.adc.0x49.channel3.voltage",
+            ]
+        );
+        assert_eq!(
+            paths(Driver::Ina219, 0x40),
+            vec![
+                "electrical.batteries.0x40.voltage",
+                "electrical.batteries.0x40.current",
+                "electrical.batteries.0x40.power",
+            ]
+        );
+        assert_eq!(
+            paths(Driver::Mpu6050, 0x68),
+            vec![
+                "navigation.attitude.roll",
+                "navigation.attitude.pitch",
+                "navigation.rateOfTurn",
+            ]
+        );
+        assert!(paths(Driver::Ssd1306, 0x3C).is_empty());
+    }
+
+    #[test]
+    fn labels_tell_readings_apart() {
+        let labels = |driver, address| -> Vec<String> {
+            let paths = default_paths(&Detected { driver, address });
+            paths.iter().map(|p| label(p).to_string()).collect()
+        };
+        assert_eq!(
+            labels(Driver::Ads1115, 0x48),
+            vec!["channel0", "channel1", "channel2", "channel3"]
+        );
+        assert_eq!(
+            labels(Driver::Bme280, bme280::PRIMARY_ADDRESS),
+            vec!["temperature", "pressure", "relativeHumidity"]
+        );
+        assert_eq!(
+            labels(Driver::Ina219, 0x40),
+            vec!["voltage", "current", "power"]
+        );
+    }
+
+    #[test]
+    fn rate_of_turn_is_positive_to_starboard() {
+        let motion = Observable::new(Motion::default());
+        let output = SignalKOutput::new();
+        let mut paths = MotionPaths {
+            subscriber: motion.subscribe().into(),
+            paths: default_paths(&Detected {
+                driver: Driver::Mpu6050,
+                address: 0x68,
+            }),
+            output: output.clone(),
+        };
+
+        // Level, turning clockwise seen from above
+        motion.set(Motion {
+            acceleration: [0.0, 0.0, 9.8],
+            rotation: [0.0, 0.0, -0.1],
+            temperature: 293.15,
+        });
+        paths.tick();
+        let updates = output.take_pending();
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[2].path, "navigation.rateOfTurn");
+        match updates[2].value {
+            Value::Number(v) => assert!((v - 0.1).abs() < 1e-6),
+            ref v => panic!("unexpected {:?}", v),
+        }
+    }
+
+    #[test]
+    fn provision_publishes_every_reading() {
+        let bus = FakeBus::default()
+            .with(0x40, &[(ina219::REG_CONFIG, &[0x39, 0x9F])])
+            .with(
+                0x48,
+                &[
+                    (ads1115::REG_LO_THRESH, &[0x80, 0x00]),
+                    (ads1115::REG_HI_THRESH, &[0x7F, 0xFF]),
+                ],
+            );
+        let found = scan(&mut bus.clone(), Probe::Auto, true);
+        let detected = detect(&mut bus.clone(), &found);
+        let output = SignalKOutput::new();
+        let mut app = provision(Application::new(), &output, &detected, || bus.clone());
+
+        // The ADC converts one channel at a time
+        let mut paths = Vec::new();
+        for _ in 0..5 {
+            app.tick();
+            paths.extend(output.take_pending().into_iter().map(|u| u.path));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        paths.sort();
+        paths.dedup();
+        let mut expected: Vec<String> = detected.iter().flat_map(default_paths).collect();
+        expected.sort();
+        assert_eq!(paths, expected);
+    }
+}
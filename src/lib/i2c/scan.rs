@@ -0,0 +1,660 @@
+//! I2C bus scanning and device identification
+use crate::signalk::escape;
+use embedded_hal::i2c::I2c;
+use std::collections::BTreeSet;
+
+/// First and last addresses probed, skipping the reserved ranges at either end.
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// A device that may answer at one of `addresses`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KnownDevice {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub addresses: &'static [u8],
+}
+
+impl KnownDevice {
+    const fn new(name: &'static str, description: &'static str, addresses: &'static [u8]) -> Self {
+        Self {
+            name,
+            description,
+            addresses,
+        }
+    }
+}
+
+/// An identification register and the value a device returns from it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChipId {
+    pub name: &'static str,
+    pub register: u8,
+    pub id: u8,
+}
+
+impl ChipId {
+    const fn new(name: &'static str, register: u8, id: u8) -> Self {
+        Self { name, register, id }
+    }
+}
+
+/// Chip-ID registers for devices that share addresses with others.
+pub const CHIP_IDS: [ChipId; 16] = [
+    ChipId::new("BME280", 0xD0, 0x60),
+    ChipId::new("BMP280", 0xD0, 0x56),
+    ChipId::new("BMP280", 0xD0, 0x57),
+    ChipId::new("BMP280", 0xD0, 0x58),
+    ChipId::new("BME680", 0xD0, 0x61),
+    ChipId::new("BME688", 0xD0, 0x61),
+    ChipId::new("BMP085", 0xD0, 0x55),
+    ChipId::new("BMP180", 0xD0, 0x55),
+    ChipId::new("MPU6050", 0x75, 0x68),
+    ChipId::new("MPU-9250", 0x75, 0x71),
+    ChipId::new("ICM-20948", 0x00, 0xEA),
+    ChipId::new("ADXL345", 0x00, 0xE5),
+    ChipId::new("BNO055", 0x00, 0xA0),
+    ChipId::new("HTS221", 0x0F, 0xBC),
+    ChipId::new("LIS3DH", 0x0F, 0x33),
+    ChipId::new("LSM303", 0x0F, 0x33),
+];
+
+#[rustfmt::skip]
+pub const KNOWN_DEVICES: [KnownDevice; 220] = [
+    KnownDevice::new("47L04/47C04/47L16/47C16", "4K/16K I2C Serial EERAM - Control register", &[0x1c, 0x18, 0x1a, 0x1e]),
+    KnownDevice::new("47L04/47C04/47L16/47C16", "4K/16K I2C Serial EERAM - SRAM Memory with EEPROM backup", &[0x54, 0x50, 0x56, 0x52]),
+    KnownDevice::new("AD5243", "Dual, 256-Position, I2 C-Compatible Digital Potentiometer", &[0x2f]),
+    KnownDevice::new("AD5248", "Dual, 256-Position, I2 C-Compatible Digital Potentiometer", &[0x2c, 0x2e, 0x2f, 0x2d]),
+    KnownDevice::new("AD5251", "Dual 64-Position I2 C Nonvolatile Memory Digital Potentiometers", &[0x2c, 0x2e, 0x2f, 0x2d]),
+    KnownDevice::new("AD5252", "Dual 256-Position I2C Nonvolatile Memory Digital Potentiometers", &[0x2c, 0x2e, 0x2f, 0x2d]),
+    KnownDevice::new("ADS1015", "4-channel 12-bit ADC", &[0x49, 0x48, 0x4b, 0x4a]),
+    KnownDevice::new("ADS1115", "4-channel 16-bit ADC", &[0x49, 0x48, 0x4b, 0x4a]),
+    KnownDevice::new("ADS7828", "12-Bit, 8-Channel Sampling ANALOG-TO-DIGITAL CONVERTER", &[0x49, 0x48, 0x4b, 0x4a]),
+    KnownDevice::new("ADXL345", "3-axis accelerometer", &[0x53, 0x1d]),
+    KnownDevice::new("AHT10", "ASAIR Humidity and Temperature sensor", &[0x38]),
+    KnownDevice::new("AHT20", "Humidity and Temperature Sensor", &[0x38]),
+    KnownDevice::new("AK8975", "3-axis magnetometer", &[0xe, 0xd, 0xc, 0xf]),
+    KnownDevice::new("AM2315", "Humidity/Temp sensor", &[0x5c]),
+    KnownDevice::new("AMG8833", "IR Thermal Camera Breakout", &[0x68, 0x69]),
+    KnownDevice::new("APDS-9250", "Digital RGB, IR and Ambient Light Sensor", &[0x52]),
+    KnownDevice::new("APDS-9960", "IR/Color/Proximity Sensor", &[0x39]),
+    KnownDevice::new("AS7262", "6-channel visible spectral_ID device with electronic shutter and smart interface", &[0x49]),
+    KnownDevice::new("AT24C02N", "Two-wire Serial EEPROM 2K (256 x 8)", &[0x57, 0x54, 0x50, 0x56, 0x53, 0x55, 0x52, 0x51]),
+    KnownDevice::new("AT24C64", "2-Wire Serial EEPROM 64K (8192 x 8)", &[0x57, 0x54, 0x50, 0x56, 0x53, 0x55, 0x52, 0x51]),
+    KnownDevice::new("ATECC508A", "Crypto Element", &[0x60]),
+    KnownDevice::new("ATECC608A", "Microchip CryptoAuthentication™ Device", &[0x60]),
+    KnownDevice::new("BH1750FVI", "Digital 16bit Serial Output Type Ambient Light Sensor IC", &[0x5c, 0x23]),
+    KnownDevice::new("BMA150", "Digital triaxial acceleration sensor", &[0x38]),
+    KnownDevice::new("BMA180", "Accelerometer", &[0x77]),
+    KnownDevice::new("BME280", "Temp/Barometric/Humidity", &[0x77, 0x76]),
+    KnownDevice::new("BME680", "Low power gas, pressure, temperature & humidity sensor", &[0x77, 0x76]),
+    KnownDevice::new("BME688", "Digital low power gas, pressure, temperature and humidity sensor with AI", &[0x77, 0x76]),
+    KnownDevice::new("BMP085", "Temp/Barometric", &[0x77]),
+    KnownDevice::new("BMP180", "Temp/Barometric", &[0x77]),
+    KnownDevice::new("BMP280", "Temp/Barometric", &[0x77, 0x76]),
+    KnownDevice::new("BNO055", "Absolute Orientation Sensor", &[0x28, 0x29]),
+    KnownDevice::new("BQ32000", "Real-Time Clock (RTC)", &[0x68]),
+    KnownDevice::new("BU9796", "Low Duty LCD Segment Drivers", &[0x3e]),
+    KnownDevice::new("CAP1188", "8-channel Capacitive Touch", &[0x2c, 0x2a, 0x2b, 0x2d, 0x28, 0x29]),
+    KnownDevice::new("CAT24C512", "EEPROM - 512Kbit - 64KB", &[0x57, 0x54, 0x50, 0x56, 0x53, 0x55, 0x52, 0x51]),
+    KnownDevice::new("CAT5171", "256‐position I2C Compatible Digital Potentiometer", &[0x2c, 0x2d]),
+    KnownDevice::new("CCS811", "Volatile organics (VOC) and equivalent CO2 (eCO2) sensor", &[0x5b, 0x5a]),
+    KnownDevice::new("CCS811", "Ultra-Low Power Digital Gas Sensor for Monitoring Indoor Air Quality TVOC eCO2", &[0x5b, 0x5a]),
+    KnownDevice::new("Chirp!", "Water sensor", &[0x20]),
+    KnownDevice::new("COM-15093", "SparkFun Qwiic Single Relay", &[0x18, 0x19]),
+    KnownDevice::new("CS43L22", "Low Power Stereo DAC w/ Headphone & Speaker Amps", &[0x4a]),
+    KnownDevice::new("D7S", "D7S Vibration Sensor", &[0x55]),
+    KnownDevice::new("DRV2605", "Haptic Motor Driver", &[0x5a]),
+    KnownDevice::new("DS1307", "64 x 8 Serial Real-Time Clock", &[0x68]),
+    KnownDevice::new("DS1371", "I2C, 32-Bit Binary Counter Watchdog Clock", &[0x68]),
+    KnownDevice::new("DS1841", "Temperature-Controlled, NV, I2C, Logarithmic Resistor", &[0x2a, 0x2b, 0x28, 0x29]),
+    KnownDevice::new("DS1881", "Dual NV Audio Taper Digital Potentiometer", &[0x2c, 0x2a, 0x2e, 0x2f, 0x2b, 0x2d, 0x28, 0x29]),
+    KnownDevice::new("DS3231", "Extremely Accurate RTC/TCXO/Crystal", &[0x68]),
+    KnownDevice::new("DS3502", "High-Voltage, NV, I2C POT", &[0x2a, 0x2b, 0x28, 0x29]),
+    KnownDevice::new("EMC2101", "SMBus Fan Control with 1°C Accurate Temperature Monitoring", &[0x4c]),
+    KnownDevice::new("FS1015", "Air Velocity Sensor Module -- 0-5, 0-15m/sec", &[0x50]),
+    KnownDevice::new("FS3000", "Air Velocity Sensor Module - 3.3V - 0-7, 0-15m/sec", &[0x28]),
+    KnownDevice::new("FT6x06", "Capacitive Touch Driver", &[0x38]),
+    KnownDevice::new("FXAS21002", "3-axis gyroscope", &[0x21, 0x20]),
+    KnownDevice::new("FXOS8700", "6-axis sensor with integrated linear accelerometer and magnetometer", &[0x1c, 0x1d, 0x1f, 0x1e]),
+    KnownDevice::new("HDC1008", "Low Power, High Accuracy Digital Humidity Sensor with Temperature Sensor", &[0x43, 0x42]),
+    KnownDevice::new("HDC1080", "Low Power, High Accuracy Digital Humidity Sensor with Temperature Sensor", &[0x40]),
+    KnownDevice::new("HIH6130", "HumidIcon", &[0x27]),
+    KnownDevice::new("HMC5883", "3-Axis Digital Compass/Magnetometer IC", &[0x1e]),
+    KnownDevice::new("HT16K33", "LED Matrix Driver", &[0x71, 0x72, 0x77, 0x73, 0x70, 0x76, 0x75, 0x74]),
+    KnownDevice::new("HTS221", "Capacitive digital sensor for relative humidity and temperature", &[0x5f]),
+    KnownDevice::new("HTU21D-F", "Humidity/Temp Sensor", &[0x40]),
+    KnownDevice::new("HTU31D", "Digital Relative Humidity & Temperature Sensor", &[0x41, 0x40]),
+    KnownDevice::new("HW-061", "I2C Serial Interface LCD1602 Adapter", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+    KnownDevice::new("ICM-20948", "9-Axis Motion Tracking device", &[0x68, 0x69]),
+    KnownDevice::new("INA219", "26V Bi-Directional High-Side Current/Power/Voltage Monitor", &[0x46, 0x49, 0x47, 0x48, 0x4c, 0x4d, 0x43, 0x4b, 0x44, 0x41, 0x4a, 0x45, 0x42, 0x4f, 0x4e, 0x40]),
+    KnownDevice::new("INA260", "Precision Digital Current and Power Monitor With Low-Drift, Precision Integrated Shunt", &[0x46, 0x49, 0x47, 0x48, 0x4c, 0x4d, 0x43, 0x4b, 0x44, 0x41, 0x4a, 0x45, 0x42, 0x4f, 0x4e, 0x40]),
+    KnownDevice::new("IS31FL3731", "144-LED Audio Modulated Matrix LED Driver (CharliePlex)", &[0x77, 0x66]),
+    KnownDevice::new("ISL29125", "Digital Red, Green and Blue Color Light Sensor with IR Blocking Filter", &[0x44]),
+    KnownDevice::new("IST-8310", "Three-axis Magnetometer", &[0xe]),
+    KnownDevice::new("ITG3200", "Gyro", &[0x68, 0x69]),
+    KnownDevice::new("L3GD20H", "gyroscope", &[0x6b, 0x6a]),
+    KnownDevice::new("LC709203F", "Smart LiB Gauge Battery Fuel Gauge LSI For 1‐Cell Lithium‐ion/ Polymer (Li+)", &[0x11]),
+    KnownDevice::new("LIS3DH", "3-axis accelerometer", &[0x18, 0x19]),
+    KnownDevice::new("LM25066", "PMBus power management IC", &[0x46, 0x57, 0x11, 0x54, 0x47, 0x16, 0x50, 0x10, 0x13, 0x43, 0x56, 0x5a, 0x53, 0x14, 0x44, 0x59, 0x17, 0x55, 0x41, 0x45, 0x52, 0x42, 0x58, 0x12, 0x51, 0x15, 0x40]),
+    KnownDevice::new("LM75b", "Digital temperature sensor and thermal watchdog", &[0x49, 0x48, 0x4c, 0x4d, 0x4b, 0x4a, 0x4f, 0x4e]),
+    KnownDevice::new("LPS22HB", "MEMS nano pressure sensor", &[0x2e]),
+    KnownDevice::new("LSM303", "Triple-axis Accelerometer+Magnetometer (Compass)", &[0x19, 0x1e]),
+    KnownDevice::new("LSM303", "Triple-axis Accelerometer+Magnetometer (Compass)", &[0x18, 0x1e]),
+    KnownDevice::new("LTC4151", "High voltage (7-80V) current and voltage monitor", &[0x6c, 0x6e, 0x6b, 0x6a, 0x68, 0x6f, 0x6d, 0x66, 0x69, 0x67]),
+    KnownDevice::new("MA12070P", "Merus Multi level Class D Interated amplifier", &[0x23, 0x22, 0x21, 0x20]),
+    KnownDevice::new("MAG3110", "3-Axis Magnetometer", &[0xe]),
+    KnownDevice::new("MAX17048", "3μA 1-Cell/2-Cell Fuel Gauge with ModelGauge", &[0x36]),
+    KnownDevice::new("MAX17048", "3μA 1-Cell/2-Cell Fuel Gauge with ModelGauge", &[0x36]),
+    KnownDevice::new("MAX30101", "High-Sensitivity Pulse Oximeter and Heart-Rate Sensor for Wearable Health", &[0x55]),
+    KnownDevice::new("MAX3010x", "Pulse & Oximetry sensor", &[0x57]),
+    KnownDevice::new("MAX31341", "Low-Current, Real-Time Clock with I2C Interface and Power Management", &[0x69]),
+    KnownDevice::new("MAX44009", "Ambient Light Sensor with ADC", &[0x4b, 0x4a]),
+    KnownDevice::new("MB85RC", "Ferroelectric RAM", &[0x57, 0x54, 0x50, 0x56, 0x53, 0x55, 0x52, 0x51]),
+    KnownDevice::new("MCP23008", "8-Bit I/O Expander with Serial Interface I2C GPIO expander", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+    KnownDevice::new("MCP23017", "I2C GPIO expander", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+    KnownDevice::new("MCP3422", "18-Bit, Multi-Channel ΔΣ Analog-to-Digital Converter with I2CTM Interface and On-Board Reference", &[0x68]),
+    KnownDevice::new("MCP4532", "7/8-Bit Single/Dual I2C Digital POT with Volatile Memory", &[0x2c, 0x2a, 0x2e, 0x2f, 0x2b, 0x2d, 0x28, 0x29]),
+    KnownDevice::new("MCP4725A0", "12-bit DAC", &[0x60, 0x61]),
+    KnownDevice::new("MCP4725A1", "12-Bit Digital-to-Analog Converter with EEPROM Memory", &[0x64, 0x60, 0x61, 0x65, 0x63, 0x66, 0x62, 0x67]),
+    KnownDevice::new("MCP4725A2", "12-Bit Digital-to-Analog Converter with EEPROM Memory", &[0x64, 0x65]),
+    KnownDevice::new("MCP4725A3", "12-Bit Digital-to-Analog Converter with EEPROM Memory", &[0x66, 0x67]),
+    KnownDevice::new("MCP4728", "12-Bit 4-Channel Digital-to-Analog Converter (DAC) with EEPROM", &[0x64, 0x60, 0x61, 0x65, 0x63, 0x66, 0x62, 0x67]),
+    KnownDevice::new("MCP7940N", "Battery-Backed I2C Real-Time Clock/Calendar with SRAM", &[0x6f]),
+    KnownDevice::new("MCP9808", "±0.5°C Maximum Accuracy Digital Temperature Sensor", &[0x1c, 0x1d, 0x18, 0x1b, 0x1f, 0x1a, 0x19, 0x1e]),
+    KnownDevice::new("MLX90614", "IR temperature sensor", &[0x5a]),
+    KnownDevice::new("MLX90632", "FIR temperature sensor", &[0x3a]),
+    KnownDevice::new("MLX90640", "Far infrared thermal sensor array (32x24 RES)", &[0x33]),
+    KnownDevice::new("MMA845x", "3-axis, 14-bit/8-bit digital accelerometer", &[0x1c, 0x1d]),
+    KnownDevice::new("MPL115A2", "Miniature I2C digital barometer, 50 to 115 kPa", &[0x60]),
+    KnownDevice::new("MPL3115A2", "Barometric Pressure", &[0x60]),
+    KnownDevice::new("MPR121", "12-point capacitive touch sensor", &[0x5c, 0x5b, 0x5a, 0x5d]),
+    KnownDevice::new("MPU6050", "Six-Axis (Gyro + Accelerometer) MEMS MotionTracking™ Devices", &[0x68, 0x69]),
+    KnownDevice::new("MPU-9250", "9-DoF IMU Gyroscope, Accelerometer and Magnetometer", &[0x68, 0x69]),
+    KnownDevice::new("MPU-9250", "3-Axis Gyroscope and Accelerometer", &[0x68]),
+    KnownDevice::new("MS5607", "Barometric Pressure", &[0x77, 0x76]),
+    KnownDevice::new("MS5611", "Barometric Pressure", &[0x77, 0x76]),
+    KnownDevice::new("NE5751", "Audio processor for IV communication", &[0x41, 0x40]),
+    KnownDevice::new("Nunchuck controller", "Nintendo", &[0x52]),
+    KnownDevice::new("PCA1070", "Multistandard programmable analog CMOS speech transmission IC", &[0x22]),
+    KnownDevice::new("PCA6408A", "Low-voltage, 8-bit I2C-bus and SMBus I/O expander", &[0x21, 0x20]),
+    KnownDevice::new("PCA9536", "4-bit 2.3- to 5.5-V I2C/SMBus I/O expander with config registers", &[0x41]),
+    KnownDevice::new("PCA9539", "16-bit I/O expander with interrupt and reset", &[0x77, 0x76, 0x75, 0x74]),
+    KnownDevice::new("PCA9541", "2-1 I2C bus arbiter", &[0x71, 0x72, 0x77, 0x73, 0x70, 0x76, 0x75, 0x74]),
+    KnownDevice::new("PCA9685", "16-channel PWM driver default address", &[0x5e, 0x64, 0x6c, 0x60, 0x6e, 0x46, 0x6b, 0x57, 0x49, 0x6a, 0x5c, 0x5b, 0x54, 0x47, 0x48, 0x4c, 0x50, 0x4d, 0x68, 0x43, 0x61, 0x56, 0x4b, 0x5a, 0x53, 0x65, 0x44, 0x7f, 0x71, 0x72, 0x78, 0x7a, 0x77, 0x59, 0x55, 0x41, 0x4a, 0x73, 0x63, 0x6f, 0x6d, 0x66, 0x5d, 0x45, 0x5f, 0x7b, 0x70, 0x69, 0x76, 0x52, 0x42, 0x4f, 0x58, 0x7e, 0x75, 0x74, 0x4e, 0x79, 0x62, 0x51, 0x67, 0x7d, 0x7c, 0x40]),
+    KnownDevice::new("PCD3311C", "DTMF/modem/musical tone generator", &[0x24, 0x25]),
+    KnownDevice::new("PCD3312C", "DTMF/modem/musical-tone generator", &[0x24, 0x25]),
+    KnownDevice::new("PCF8523", "RTC", &[0x68]),
+    KnownDevice::new("PCF8563", "Real-time clock/calendar", &[0x51]),
+    KnownDevice::new("PCF8569", "LCD column driver for dot matrix displays", &[0x3c, 0x3b]),
+    KnownDevice::new("PCF8573", "Clock/calendar with Power Fail Detector", &[0x6b, 0x6a, 0x68, 0x69]),
+    KnownDevice::new("PCF8574", "Remote 8-Bit I/O Expander", &[0x46, 0x49, 0x47, 0x48, 0x4c, 0x4d, 0x43, 0x4b, 0x44, 0x41, 0x4a, 0x45, 0x42, 0x4f, 0x4e, 0x40]),
+    KnownDevice::new("PCF8574", "Remote 8-Bit I/O Expander for I2C Bus", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+    KnownDevice::new("PCF8574AP", "I²C-bus to parallel port expander", &[0x38, 0x3c, 0x3b, 0x3e, 0x3f, 0x39, 0x3a, 0x3d]),
+    KnownDevice::new("PCF8575", "Remote16-BIT I2C AND SMBus I/O Expander withInterrupt Output", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+    KnownDevice::new("PCF8577C", "32/64-segment LCD display driver", &[0x3a]),
+    KnownDevice::new("PCF8578", "Row/column LCD dot matrix driver/display", &[0x3c, 0x3d]),
+    KnownDevice::new("PM2008", "Laser particle sensor", &[0x28]),
+    KnownDevice::new("PMSA003I", "Digital universal partical concentration sensor", &[0x12]),
+    KnownDevice::new("PN532", "NFC/RFID reader", &[0x48]),
+    KnownDevice::new("SAA1064", "4-digit LED driver", &[0x38, 0x3b, 0x39, 0x3a]),
+    KnownDevice::new("SAA2502", "MPEG audio source decoder", &[0x30, 0x31]),
+    KnownDevice::new("SAA4700", "VPS Dataline Processor", &[0x23, 0x21]),
+    KnownDevice::new("SAA5243P/E", "Computer controlled teletext circuit", &[0x11]),
+    KnownDevice::new("SAA5243P/H", "Computer controlled teletext circuit", &[0x11]),
+    KnownDevice::new("SAA5243P/K", "Computer controlled teletext circuit", &[0x11]),
+    KnownDevice::new("SAA5243P/L", "Computer controlled teletext circuit", &[0x11]),
+    KnownDevice::new("SAA5246", "Integrated VIP and teletext", &[0x11]),
+    KnownDevice::new("SAA7706H", "Car radio Digital Signal Processor (DSP)", &[0x1c]),
+    KnownDevice::new("SAB3035", "Digital tuning circuit for computer-controlled TV", &[0x60, 0x61, 0x63, 0x62]),
+    KnownDevice::new("SAB3037", "Digital tuning circuit for computer-controlled TV", &[0x60, 0x61, 0x63, 0x62]),
+    KnownDevice::new("SCD30", "CO2, humidity, and temperature sensor", &[0x61]),
+    KnownDevice::new("SCD40", "CO2 sensor - 2000ppm", &[0x62]),
+    KnownDevice::new("SCD40-D-R2", "Miniaturized CO2 Sensor", &[0x62]),
+    KnownDevice::new("SCD41", "CO2 sensor", &[0x62]),
+    KnownDevice::new("SEN-15892", "Zio Qwiic Loudness Sensor", &[0x38]),
+    KnownDevice::new("SEN-17374", "Sparkfun EKMC4607112K PIR", &[0x13, 0x12]),
+    KnownDevice::new("SFA30", "Formaldehyde Sensor Module for HVAC and Indoor Air Quality Applications", &[0x5d]),
+    KnownDevice::new("SGP30", "Gas Sensor", &[0x58]),
+    KnownDevice::new("SGP40", "Indoor Air Quality Sensor for VOC Measurements", &[0x59]),
+    KnownDevice::new("SH1106", "132 X 64 Dot Matrix OLED/PLED  Preliminary Segment/Common Driver with Controller", &[0x3c, 0x3d]),
+    KnownDevice::new("SHT31", "Humidity/Temp sensor", &[0x44, 0x45]),
+    KnownDevice::new("SHTC3", "Humidity & Temperature Sensor", &[0x70]),
+    KnownDevice::new("SI1132", "UV Index and Ambient Light Sensor", &[0x60]),
+    KnownDevice::new("SI1133", "UV Index and Ambient Light Sensor", &[0x55, 0x52]),
+    KnownDevice::new("Si1145", "Proximity/UV/Ambient Light Sensor IC With I2C Interface", &[0x60]),
+    KnownDevice::new("Si4713", "FM Radio Transmitter with Receive Power Scan", &[0x11, 0x63]),
+    KnownDevice::new("Si5351A", "Clock Generator", &[0x60, 0x61]),
+    KnownDevice::new("Si7021", "Humidity/Temp sensor", &[0x40]),
+    KnownDevice::new("SPL06-007", "Digital Temperature/Pressure Sensor", &[0x77, 0x76]),
+    KnownDevice::new("SPS30", "Particulate Matter Sensor for Air Quality Monitoring and Control", &[0x69]),
+    KnownDevice::new("SSD1305", "132 x 64 Dot Matrix OLED/PLED Segment/Common Driver with Controller", &[0x3c, 0x3d]),
+    KnownDevice::new("SSD1306", "128 x 64 Dot Matrix Monochrome OLED/PLED Segment/Common Driver with Controller", &[0x3c, 0x3d]),
+    KnownDevice::new("ST25DV16K", "Dynamic NFC/RFID tag IC with 4-, 16-, or 64-Kbit EEPROM, and fast transfer mode capability", &[0x57, 0x53, 0x2d]),
+    KnownDevice::new("STDS75", "STDS75 temperature sensor", &[0x49, 0x48, 0x4c, 0x4d, 0x4b, 0x4a, 0x4f, 0x4e]),
+    KnownDevice::new("STMPE610", "Resistive Touch controller", &[0x44, 0x41]),
+    KnownDevice::new("STMPE811", "Resistive touchscreen controller", &[0x44, 0x41]),
+    KnownDevice::new("TCA9548", "1-to-8 I2C Multiplexer", &[0x71, 0x72, 0x77, 0x73, 0x70, 0x76, 0x75, 0x74]),
+    KnownDevice::new("TCA9548A", "Low-Voltage8-Channel I2CSwitchwithReset", &[0x71, 0x72, 0x77, 0x73, 0x70, 0x76, 0x75, 0x74]),
+    KnownDevice::new("TCA9554", "4 Low Voltage 8-Bit I 2C and SMBus Low-Power I/O Expander With Interrupt Output and Configuration Registers", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+    KnownDevice::new("TCS34725", "Color sensor", &[0x29]),
+    KnownDevice::new("TDA4670", "Picture signal improvement circuit", &[0x44]),
+    KnownDevice::new("TDA4671", "Picture signal improvement circuit", &[0x44]),
+    KnownDevice::new("TDA4672", "Picture signal improvement (PSI) circuit", &[0x44]),
+    KnownDevice::new("TDA4680", "Video processor", &[0x44]),
+    KnownDevice::new("TDA4687", "Video processor", &[0x44]),
+    KnownDevice::new("TDA4688", "Video processor", &[0x44]),
+    KnownDevice::new("TDA4780", "Video control with gamma control", &[0x44]),
+    KnownDevice::new("TDA7433", "Basic function audio processor", &[0x45]),
+    KnownDevice::new("TDA8370", "High/medium perf. sync. processor", &[0x46]),
+    KnownDevice::new("TDA8376", "One-chip multistandard video", &[0x45]),
+    KnownDevice::new("TDA8415", "TVNCR stereo/dual sound processor", &[0x42]),
+    KnownDevice::new("TDA8417", "TVNCR stereo/dual sound processor", &[0x42]),
+    KnownDevice::new("TDA8421", "Audio processor with loudspeaker and headphone channel", &[0x41, 0x40]),
+    KnownDevice::new("TDA8424", "Audio processor with loudspeaker channel", &[0x41]),
+    KnownDevice::new("TDA8425", "Audio processor with loudspeaker channel", &[0x41]),
+    KnownDevice::new("TDA8426", "Hi-fi stereo audio processor", &[0x41]),
+    KnownDevice::new("TDA8442", "Interface for colour decoder", &[0x44]),
+    KnownDevice::new("TDA9150", "Deflection processor", &[0x46]),
+    KnownDevice::new("TDA9860", "Hi-fi audio processor", &[0x41, 0x40]),
+    KnownDevice::new("TEA5767", "Radio receiver", &[0x60]),
+    KnownDevice::new("TEA6100", "FM/IF for computer-controlled radio", &[0x61]),
+    KnownDevice::new("TEA6300", "Sound fader control and preamplifier/source selector", &[0x40]),
+    KnownDevice::new("TEA6320", "4-input tone/volume controller with fader control", &[0x40]),
+    KnownDevice::new("TEA6330", "Sound fader control circuit for car radios", &[0x40]),
+    KnownDevice::new("TMP006", "Infrared Thermopile Sensor in Chip-Scale Package", &[0x46, 0x47, 0x43, 0x44, 0x41, 0x45, 0x42, 0x40]),
+    KnownDevice::new("TMP007", "IR Temperature sensor", &[0x46, 0x47, 0x43, 0x44, 0x41, 0x45, 0x42, 0x40]),
+    KnownDevice::new("TMP102", "Temperature sensor", &[0x49, 0x48, 0x4b, 0x4a]),
+    KnownDevice::new("TPA2016", "2.8-W/Ch Stereo Class-D Audio Amplifier With Dynamic Range Compression and Automatic Gain Control", &[0x58]),
+    KnownDevice::new("TSA5511", "1.3 GHz PLL frequency synthesizer for TV", &[0x60, 0x61, 0x63, 0x62]),
+    KnownDevice::new("TSL2561", "Light sensor", &[0x49, 0x39]),
+    KnownDevice::new("TSL2591", "Light sensor", &[0x29]),
+    KnownDevice::new("UMA1014T", "Low-power frequency synthesizer for mobile radio communications", &[0x63, 0x62]),
+    KnownDevice::new("VCNL40x0", "Proximity sensor", &[0x13]),
+    KnownDevice::new("VCNL4200", "High Sensitivity Long Distance Proximity and Ambient Light Sensor With I2C Interface", &[0x51]),
+    KnownDevice::new("VEML6070", "UVA Light Sensor with I2C Interface", &[0x38, 0x39]),
+    KnownDevice::new("VEML6075", "UVA and UVB Light Sensor", &[0x10]),
+    KnownDevice::new("VEML7700", "High Accuracy Ambient Light Sensor", &[0x10]),
+    KnownDevice::new("VL53L0x", "Time Of Flight distance sensor", &[0x29]),
+    KnownDevice::new("VL6180X", "Time Of Flight distance sensor", &[0x29]),
+    KnownDevice::new("VML6075", "UVA and UVB Light Sensor with I2C Interface", &[0x10]),
+    KnownDevice::new("WITTY PI 3", "WITTY PI 3 (Mini) - REALTIME CLOCK (DS3231SN) AND POWER MANAGEMENT FOR RASPBERRY PI", &[0x68, 0x69]),
+    KnownDevice::new("XD8574", "I²C 8-Bit I/O Expander", &[0x71, 0x72, 0x77, 0x73, 0x70, 0x76, 0x75, 0x74]),
+    KnownDevice::new("XD8574A", "I²C 8-Bit I/O Expander", &[0x23, 0x24, 0x26, 0x22, 0x27, 0x21, 0x25, 0x20]),
+];
+
+/// The devices known to answer at `address`.
+pub fn lookup(address: u8) -> Vec<&'static KnownDevice> {
+    KNOWN_DEVICES
+        .iter()
+        .filter(|d| d.addresses.contains(&address))
+        .collect()
+}
+
+/// How to decide whether a device is present.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Probe {
+    /// Address the device with a zero-length write
+    QuickWrite,
+    /// Read a single byte from the device
+    Read,
+    /// Read where a write could corrupt the device (EEPROMs at 0x30-0x37 and 0x50-0x5F),
+    /// quick write everywhere else, as i2cdetect does
+    Auto,
+}
+
+/// The probe that found a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProbeMethod {
+    QuickWrite,
+    Read,
+}
+
+impl ProbeMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeMethod::QuickWrite => "quick-write",
+            ProbeMethod::Read => "read",
+        }
+    }
+}
+
+/// A device that acknowledged its address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub address: u8,
+    pub method: ProbeMethod,
+    /// Devices that may be at this address, narrowed down when a chip ID matched
+    pub candidates: Vec<&'static KnownDevice>,
+    /// The register and value that identified the device, if any
+    pub chip_id: Option<(u8, u8)>,
+}
+
+impl Found {
+    /// True when `name` is one of the candidates.
+    pub fn could_be(&self, name: &str) -> bool {
+        self.candidates.iter().any(|d| d.name == name)
+    }
+}
+
+/// Check whether a device acknowledges `address`, returning the probe that succeeded.
+pub fn probe<I: I2c>(i2c: &mut I, address: u8, probe: Probe) -> Option<ProbeMethod> {
+    let method = match probe {
+        Probe::QuickWrite => ProbeMethod::QuickWrite,
+        Probe::Read => ProbeMethod::Read,
+        Probe::Auto => match address {
+            0x30..=0x37 | 0x50..=0x5F => ProbeMethod::Read,
+            _ => ProbeMethod::QuickWrite,
+        },
+    };
+    let result = match method {
+        ProbeMethod::QuickWrite => i2c.write(address, &[]),
+        ProbeMethod::Read => i2c.read(address, &mut [0]),
+    };
+    result.ok().map(|_| method)
+}
+
+/// Read the chip-ID registers of the candidates at `address`, returning the register, the
+/// value read and the names of the devices it matches.
+pub fn identify<I: I2c>(
+    i2c: &mut I,
+    address: u8,
+    candidates: &[&'static KnownDevice],
+) -> Option<(u8, u8, Vec<&'static str>)> {
+    let registers: BTreeSet<u8> = CHIP_IDS
+        .iter()
+        .filter(|c| candidates.iter().any(|d| d.name == c.name))
+        .map(|c| c.register)
+        .collect();
+
+    for register in registers {
+        let mut id = [0];
+        if i2c.write_read(address, &[register], &mut id).is_err() {
+            continue;
+        }
+        let names: Vec<&'static str> = CHIP_IDS
+            .iter()
+            .filter(|c| c.register == register && c.id == id[0])
+            .filter(|c| candidates.iter().any(|d| d.name == c.name))
+            .map(|c| c.name)
+            .collect();
+        if !names.is_empty() {
+            return Some((register, id[0], names));
+        }
+    }
+    None
+}
+
+/// Probe every address on the bus, optionally reading chip IDs to tell apart devices that
+/// share an address.
+pub fn scan<I: I2c>(i2c: &mut I, method: Probe, chip_id: bool) -> Vec<Found> {
+    let mut found = Vec::new();
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        let method = match probe(i2c, address, method) {
+            Some(m) => m,
+            None => continue,
+        };
+        let mut device = Found {
+            address,
+            method,
+            candidates: lookup(address),
+            chip_id: None,
+        };
+        let identified = match chip_id {
+            true => identify(i2c, address, &device.candidates),
+            false => None,
+        };
+        if let Some((register, id, names)) = identified {
+            device.candidates.retain(|d| names.contains(&d.name));
+            device.chip_id = Some((register, id));
+        }
+        found.push(device);
+    }
+    found
+}
+
+/// Serialize scan results as a JSON array.
+pub fn to_json(found: &[Found]) -> String {
+    let devices: Vec<String> = found
+        .iter()
+        .map(|f| {
+            let candidates: Vec<String> = f
+                .candidates
+                .iter()
+                .map(|d| {
+                    format!(
+                        "{{\"name\":\"{}\",\"description\":\"{}\"}}",
+                        escape(d.name),
+                        escape(d.description)
+                    )
+                })
+                .collect();
+            let chip_id = match f.chip_id {
+                Some((register, id)) => format!("{{\"register\":{},\"id\":{}}}", register, id),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"address\":{},\"method\":\"{}\",\"candidates\":[{}],\"chipId\":{}}}",
+                f.address,
+                f.method.as_str(),
+                candidates.join(","),
+                chip_id
+            )
+        })
+        .collect();
+    format!("[{}]", devices.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+    /// A bus with devices answering `id` from chip-ID register `register`.
+    struct FakeBus {
+        devices: Vec<(u8, u8, u8)>,
+        /// Registers that fail to read, for devices without a readable chip ID
+        failing: Vec<u8>,
+        /// Address and whether each operation was a write
+        log: Vec<(u8, bool)>,
+    }
+
+    impl FakeBus {
+        fn new(devices: &[(u8, u8, u8)]) -> Self {
+            Self {
+                devices: devices.to_vec(),
+                failing: Vec::new(),
+                log: Vec::new(),
+            }
+        }
+
+        fn written(&self, address: u8) -> bool {
+            self.log.iter().any(|&(a, write)| a == address && write)
+        }
+    }
+
+    impl ErrorType for FakeBus {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for FakeBus {
+        fn transaction(&mut self, address: u8, ops: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+            let (_, id_register, id) = match self.devices.iter().find(|d| d.0 == address) {
+                Some(d) => *d,
+                None => return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+            };
+            let mut register = None;
+            for op in ops {
+                match op {
+                    Operation::Write(bytes) => {
+                        self.log.push((address, true));
+                        register = bytes.first().copied();
+                    }
+                    Operation::Read(buf) => {
+                        self.log.push((address, false));
+                        if let Some(r) = register {
+                            if self.failing.contains(&r) {
+                                return Err(ErrorKind::Bus);
+                            }
+                        }
+                        buf[0] = match register == Some(id_register) {
+                            true => id,
+                            false => 0,
+                        };
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn names(found: &Found) -> Vec<&'static str> {
+        found.candidates.iter().map(|d| d.name).collect()
+    }
+
+    #[test]
+    fn lookup_finds_every_device_at_an_address() {
+        let at_3c = lookup(0x3C);
+        assert!(at_3c.iter().any(|d| d.name == "SSD1306"));
+        assert!(at_3c.iter().all(|d| d.addresses.contains(&0x3C)));
+        assert!(lookup(0x00).is_empty());
+    }
+
+    #[test]
+    fn auto_probe_reads_eeprom_ranges_and_writes_elsewhere() {
+        let mut bus = FakeBus::new(&[(0x50, 0, 0), (0x34, 0, 0), (0x3C, 0, 0)]);
+        assert_eq!(probe(&mut bus, 0x50, Probe::Auto), Some(ProbeMethod::Read));
+        assert_eq!(probe(&mut bus, 0x34, Probe::Auto), Some(ProbeMethod::Read));
+        assert_eq!(
+            probe(&mut bus, 0x3C, Probe::Auto),
+            Some(ProbeMethod::QuickWrite)
+        );
+        assert!(!bus.written(0x50));
+        assert!(!bus.written(0x34));
+        assert!(bus.written(0x3C));
+    }
+
+    #[test]
+    fn probe_uses_the_requested_method() {
+        let mut bus = FakeBus::new(&[(0x50, 0, 0)]);
+        assert_eq!(
+            probe(&mut bus, 0x50, Probe::QuickWrite),
+            Some(ProbeMethod::QuickWrite)
+        );
+        assert!(bus.written(0x50));
+        assert_eq!(probe(&mut bus, 0x50, Probe::Read), Some(ProbeMethod::Read));
+        assert_eq!(probe(&mut bus, 0x51, Probe::Read), None);
+        assert_eq!(probe(&mut bus, 0x51, Probe::QuickWrite), None);
+    }
+
+    #[test]
+    fn identify_tells_apart_devices_sharing_an_address() {
+        let candidates = lookup(0x77);
+        let mut bus = FakeBus::new(&[(0x77, 0xD0, 0x60)]);
+        assert_eq!(
+            identify(&mut bus, 0x77, &candidates),
+            Some((0xD0, 0x60, vec!["BME280"]))
+        );
+
+        let mut bus = FakeBus::new(&[(0x77, 0xD0, 0x58)]);
+        assert_eq!(
+            identify(&mut bus, 0x77, &candidates),
+            Some((0xD0, 0x58, vec!["BMP280"]))
+        );
+
+        // Both share an ID, and only candidates at the address are reported
+        let mut bus = FakeBus::new(&[(0x77, 0xD0, 0x55)]);
+        assert_eq!(
+            identify(&mut bus, 0x77, &candidates),
+            Some((0xD0, 0x55, vec!["BMP085", "BMP180"]))
+        );
+    }
+
+    #[test]
+    fn identify_gives_up_on_unknown_or_unreadable_ids() {
+        let candidates = lookup(0x77);
+        let mut bus = FakeBus::new(&[(0x77, 0xD0, 0x42)]);
+        assert_eq!(identify(&mut bus, 0x77, &candidates), None);
+
+        let mut bus = FakeBus::new(&[(0x77, 0xD0, 0x60)]);
+        bus.failing.push(0xD0);
+        assert_eq!(identify(&mut bus, 0x77, &candidates), None);
+
+        // No candidate has a chip-ID register, so nothing is read
+        let mut bus = FakeBus::new(&[(0x3C, 0, 0)]);
+        assert_eq!(identify(&mut bus, 0x3C, &lookup(0x3C)), None);
+        assert!(bus.log.is_empty());
+    }
+
+    #[test]
+    fn identify_tries_the_next_register_when_one_fails() {
+        // The MPU-9250 and ICM-20948 share 0x68 but keep their IDs in different registers
+        let candidates = lookup(0x68);
+        let mut bus = FakeBus::new(&[(0x68, 0x75, 0x71)]);
+        bus.failing.push(0x00);
+        assert_eq!(
+            identify(&mut bus, 0x68, &candidates),
+            Some((0x75, 0x71, vec!["MPU-9250"]))
+        );
+    }
+
+    #[test]
+    fn scan_finds_and_identifies_devices_in_address_order() {
+        let mut bus = FakeBus::new(&[
+            (0x77, 0xD0, 0x60),
+            (0x76, 0xD0, 0x58),
+            (0x50, 0, 0),
+            (0x3C, 0, 0),
+            // Reserved addresses are never probed
+            (0x03, 0, 0),
+            (0x78, 0, 0),
+        ]);
+        let found = scan(&mut bus, Probe::Auto, true);
+        let addresses: Vec<u8> = found.iter().map(|f| f.address).collect();
+        assert_eq!(addresses, vec![0x3C, 0x50, 0x76, 0x77]);
+
+        assert_eq!(found[0].method, ProbeMethod::QuickWrite);
+        assert!(found[0].could_be("SSD1306"));
+        assert_eq!(found[0].chip_id, None);
+        assert_eq!(found[1].method, ProbeMethod::Read);
+        assert!(!bus.written(0x50));
+        assert_eq!(names(&found[2]), vec!["BMP280"]);
+        assert_eq!(found[2].chip_id, Some((0xD0, 0x58)));
+        assert_eq!(names(&found[3]), vec!["BME280"]);
+        assert_eq!(found[3].chip_id, Some((0xD0, 0x60)));
+    }
+
+    #[test]
+    fn scan_without_chip_id_keeps_every_candidate() {
+        let mut bus = FakeBus::new(&[(0x77, 0xD0, 0x60)]);
+        let found = scan(&mut bus, Probe::Read, false);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].candidates, lookup(0x77));
+        assert_eq!(found[0].chip_id, None);
+        assert!(!bus.written(0x77));
+    }
+
+    #[test]
+    fn json_lists_candidates_and_chip_id() {
+        let mut bus = FakeBus::new(&[(0x76, 0xD0, 0x58)]);
+        let found = scan(&mut bus, Probe::Auto, true);
+        assert_eq!(
+            to_json(&found),
+            "[{\"address\":118,\"method\":\"quick-write\",\"candidates\":[\
+             {\"name\":\"BMP280\",\"description\":\"Temp/Barometric\"}],\
+             \"chipId\":{\"register\":208,\"id\":88}}]"
+        );
+
+        let unidentified = Found {
+            address: 0x50,
+            method: ProbeMethod::Read,
+            candidates: Vec::new(),
+            chip_id: None,
+        };
+        assert_eq!(
+            to_json(&[unidentified]),
+            "[{\"address\":80,\"method\":\"read\",\"candidates\":[],\"chipId\":null}]"
+        );
+        assert_eq!(to_json(&[]), "[]");
+    }
+}
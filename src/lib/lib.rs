@@ -1,5 +1,27 @@
+pub mod analog;
 pub mod application;
+pub mod battery;
+pub mod bilge;
+pub mod calc;
+pub mod clock;
+pub mod combine;
+pub mod config;
+pub mod curve;
+pub mod dashboard;
+pub mod digital;
+pub mod engine;
+pub mod filter;
 pub mod i2c;
+pub mod nmea0183;
+pub mod nmea2000;
+pub mod pwm;
+pub mod rest;
 pub mod rgbled;
 pub mod sensor;
+pub mod signalk;
+pub mod status;
+pub mod tank;
+#[cfg(test)]
+mod testing;
+#[cfg(target_os = "espidf")]
 pub mod wifi;
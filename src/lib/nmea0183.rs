@@ -0,0 +1,1943 @@
+//! NMEA 0183 sentence parsing and generation over UART
+use crate::sensor::{poll_update, Attachable, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, Update, Value};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::delay::NON_BLOCK;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::sys::EspError;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::uart::UartDriver;
+use eyeball::{shared::Observable, Subscriber};
+use std::fmt;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+/// Longest sentence allowed by the standard, including `$` and `<CR><LF>`.
+pub const MAX_SENTENCE_LENGTH: usize = 82;
+
+const KNOTS_TO_MS: f64 = 1852.0 / 3600.0;
+const KMH_TO_MS: f64 = 1000.0 / 3600.0;
+const FEET_TO_M: f64 = 0.3048;
+const FATHOMS_TO_M: f64 = 1.8288;
+const CELSIUS_TO_KELVIN: f64 = 273.15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The line does not start with `$` or `!` or lacks a talker and sentence id
+    BadFrame,
+    /// The checksum is missing or not two hex digits
+    MissingChecksum,
+    BadChecksum {
+        expected: u8,
+        found: u8,
+    },
+    UnknownSentence(String),
+    /// A field required by the sentence is empty or absent
+    MissingField(&'static str),
+    /// A field could not be parsed
+    BadField(&'static str),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::BadFrame => write!(f, "malformed NMEA 0183 sentence"),
+            ParseError::MissingChecksum => write!(f, "missing or malformed checksum"),
+            ParseError::BadChecksum { expected, found } => write!(
+                f,
+                "checksum mismatch, expected {:02X} found {:02X}",
+                expected, found
+            ),
+            ParseError::UnknownSentence(s) => write!(f, "unsupported sentence {}", s),
+            ParseError::MissingField(name) => write!(f, "missing field {}", name),
+            ParseError::BadField(name) => write!(f, "invalid field {}", name),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodeError {
+    /// The framed sentence would be this many characters, over [`MAX_SENTENCE_LENGTH`]
+    TooLong(usize),
+    /// A field holds a character that would break the framing, such as `,` or `*`
+    ReservedCharacter(char),
+    /// A value to encode is NaN or infinite
+    NotFinite,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooLong(length) => write!(
+                f,
+                "sentence of {} characters exceeds {}",
+                length, MAX_SENTENCE_LENGTH
+            ),
+            EncodeError::ReservedCharacter(c) => write!(f, "reserved character {:?}", c),
+            EncodeError::NotFinite => write!(f, "value is not finite"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// XOR of every byte between the start delimiter and the `*`.
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |acc, b| acc ^ b)
+}
+
+/// A checksum-validated sentence split into its fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawSentence<'a> {
+    pub talker: &'a str,
+    pub sentence: &'a str,
+    pub fields: Vec<&'a str>,
+}
+
+impl<'a> RawSentence<'a> {
+    /// Split a line into talker, sentence id and fields, verifying its checksum.
+    ///
+    /// Sentences without a checksum are rejected, as every sentence we parse requires one.
+    pub fn parse(line: &'a str) -> Result<Self, ParseError> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let body = line
+            .strip_prefix('$')
+            .or_else(|| line.strip_prefix('!'))
+            .ok_or(ParseError::BadFrame)?;
+
+        let (body, sum) = body.rsplit_once('*').ok_or(ParseError::MissingChecksum)?;
+        if sum.len() != 2 {
+            return Err(ParseError::MissingChecksum);
+        }
+        let found = u8::from_str_radix(sum, 16).map_err(|_| ParseError::MissingChecksum)?;
+        let expected = checksum(body);
+        if expected != found {
+            return Err(ParseError::BadChecksum { expected, found });
+        }
+
+        let mut fields = body.split(',');
+        let address = fields.next().ok_or(ParseError::BadFrame)?;
+        if !address.is_ascii() || address.len() < 4 {
+            return Err(ParseError::BadFrame);
+        }
+        // Proprietary sentences use a single P as talker
+        let split = if address.starts_with('P') { 1 } else { 2 };
+        let (talker, sentence) = address.split_at(split);
+
+        Ok(Self {
+            talker,
+            sentence,
+            fields: fields.collect(),
+        })
+    }
+
+    fn field(&self, index: usize) -> &'a str {
+        self.fields.get(index).copied().unwrap_or("")
+    }
+}
+
+/// UTC time of day
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: f32,
+}
+
+/// UTC calendar date
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+/// Latitude and longitude in decimal degrees, north and east positive.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// GGA - GNSS fix data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gga {
+    pub time: Option<Time>,
+    pub position: Option<Position>,
+    pub fix_quality: u8,
+    pub satellites: Option<u8>,
+    pub hdop: Option<f64>,
+    /// Antenna altitude above mean sea level in meters
+    pub altitude: Option<f64>,
+}
+
+/// RMC - Recommended minimum navigation information
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmc {
+    pub time: Option<Time>,
+    pub valid: bool,
+    pub position: Option<Position>,
+    pub speed_knots: Option<f64>,
+    /// Course over ground in degrees true
+    pub course: Option<f64>,
+    pub date: Option<Date>,
+    /// Magnetic variation in degrees, east positive
+    pub variation: Option<f64>,
+}
+
+/// VTG - Course and speed over ground
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vtg {
+    pub course_true: Option<f64>,
+    pub course_magnetic: Option<f64>,
+    pub speed_knots: Option<f64>,
+    pub speed_kmh: Option<f64>,
+}
+
+/// HDG - Heading, deviation and variation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hdg {
+    /// Magnetic sensor heading in degrees
+    pub heading: Option<f64>,
+    /// Deviation in degrees, east positive
+    pub deviation: Option<f64>,
+    /// Variation in degrees, east positive
+    pub variation: Option<f64>,
+}
+
+/// DBT - Depth below transducer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dbt {
+    pub depth_feet: Option<f64>,
+    pub depth_meters: Option<f64>,
+    pub depth_fathoms: Option<f64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WindReference {
+    Relative,
+    True,
+}
+
+/// MWV - Wind speed and angle
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mwv {
+    /// Wind angle in degrees, 0 - 359 clockwise from the bow
+    pub angle: Option<f64>,
+    pub reference: WindReference,
+    /// Wind speed in meters per second
+    pub speed: Option<f64>,
+    pub valid: bool,
+}
+
+/// A single transducer reading from an XDR sentence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    /// Transducer type, e.g. `C` temperature, `P` pressure, `H` humidity
+    pub kind: char,
+    pub value: Option<f64>,
+    pub unit: String,
+    pub name: String,
+}
+
+/// XDR - Transducer measurements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Xdr {
+    pub measurements: Vec<Measurement>,
+}
+
+/// A parsed NMEA 0183 sentence
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Vtg(Vtg),
+    Hdg(Hdg),
+    Dbt(Dbt),
+    Mwv(Mwv),
+    Xdr(Xdr),
+}
+
+/// Parse a single line into a typed sentence.
+pub fn parse(line: &str) -> Result<Sentence, ParseError> {
+    let raw = RawSentence::parse(line)?;
+    match raw.sentence {
+        "GGA" => parse_gga(&raw).map(Sentence::Gga),
+        "RMC" => parse_rmc(&raw).map(Sentence::Rmc),
+        "VTG" => parse_vtg(&raw).map(Sentence::Vtg),
+        "HDG" => parse_hdg(&raw).map(Sentence::Hdg),
+        "DBT" => parse_dbt(&raw).map(Sentence::Dbt),
+        "MWV" => parse_mwv(&raw).map(Sentence::Mwv),
+        "XDR" => parse_xdr(&raw).map(Sentence::Xdr),
+        other => Err(ParseError::UnknownSentence(other.to_string())),
+    }
+}
+
+fn opt_f64(field: &str, name: &'static str) -> Result<Option<f64>, ParseError> {
+    match field {
+        "" => Ok(None),
+        f => f
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .map(Some)
+            .ok_or(ParseError::BadField(name)),
+    }
+}
+
+fn opt_u8(field: &str, name: &'static str) -> Result<Option<u8>, ParseError> {
+    match field {
+        "" => Ok(None),
+        f => f
+            .parse::<u8>()
+            .map(Some)
+            .map_err(|_| ParseError::BadField(name)),
+    }
+}
+
+/// Apply a hemisphere or direction letter, where `negative` is S, W or similar.
+fn signed(
+    value: Option<f64>,
+    dir: &str,
+    negative: &str,
+    name: &'static str,
+) -> Result<Option<f64>, ParseError> {
+    match value {
+        Some(v) if dir == negative => Ok(Some(-v)),
+        Some(_) if dir.is_empty() => Err(ParseError::MissingField(name)),
+        v => Ok(v),
+    }
+}
+
+fn parse_time(field: &str) -> Result<Option<Time>, ParseError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() < 6 || !field.is_char_boundary(2) || !field.is_char_boundary(4) {
+        return Err(ParseError::BadField("time"));
+    }
+    let hour = field[0..2]
+        .parse::<u8>()
+        .map_err(|_| ParseError::BadField("time"))?;
+    let minute = field[2..4]
+        .parse::<u8>()
+        .map_err(|_| ParseError::BadField("time"))?;
+    let second = field[4..]
+        .parse::<f32>()
+        .map_err(|_| ParseError::BadField("time"))?;
+    if hour > 23 || minute > 59 || !(0.0..61.0).contains(&second) {
+        return Err(ParseError::BadField("time"));
+    }
+    Ok(Some(Time {
+        hour,
+        minute,
+        second,
+    }))
+}
+
+fn parse_date(field: &str) -> Result<Option<Date>, ParseError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() != 6 || !field.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::BadField("date"));
+    }
+    let day = field[0..2]
+        .parse::<u8>()
+        .map_err(|_| ParseError::BadField("date"))?;
+    let month = field[2..4]
+        .parse::<u8>()
+        .map_err(|_| ParseError::BadField("date"))?;
+    let year = field[4..6]
+        .parse::<u16>()
+        .map_err(|_| ParseError::BadField("date"))?;
+    if !(1..=31).contains(&day) || !(1..=12).contains(&month) {
+        return Err(ParseError::BadField("date"));
+    }
+    // Two digit years pivot at 1980, the start of GPS time
+    let year = if year < 80 { 2000 + year } else { 1900 + year };
+    Ok(Some(Date { year, month, day }))
+}
+
+/// Parse `ddmm.mmm` or `dddmm.mmm` into decimal degrees.
+fn parse_coordinate(
+    field: &str,
+    degree_digits: usize,
+    max: f64,
+    name: &'static str,
+) -> Result<Option<f64>, ParseError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() < degree_digits + 2 || !field.is_char_boundary(degree_digits) {
+        return Err(ParseError::BadField(name));
+    }
+    let degrees = field[..degree_digits]
+        .parse::<u16>()
+        .map_err(|_| ParseError::BadField(name))? as f64;
+    let minutes = field[degree_digits..]
+        .parse::<f64>()
+        .map_err(|_| ParseError::BadField(name))?;
+    if !(0.0..60.0).contains(&minutes) {
+        return Err(ParseError::BadField(name));
+    }
+    let value = degrees + minutes / 60.0;
+    if value > max {
+        return Err(ParseError::BadField(name));
+    }
+    Ok(Some(value))
+}
+
+fn parse_position(raw: &RawSentence, index: usize) -> Result<Option<Position>, ParseError> {
+    let latitude = parse_coordinate(raw.field(index), 2, 90.0, "latitude")?;
+    let latitude = signed(latitude, raw.field(index + 1), "S", "latitude")?;
+    let longitude = parse_coordinate(raw.field(index + 2), 3, 180.0, "longitude")?;
+    let longitude = signed(longitude, raw.field(index + 3), "W", "longitude")?;
+    Ok(match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => Some(Position {
+            latitude,
+            longitude,
+        }),
+        _ => None,
+    })
+}
+
+fn parse_gga(raw: &RawSentence) -> Result<Gga, ParseError> {
+    if raw.fields.len() < 9 {
+        return Err(ParseError::MissingField("altitude"));
+    }
+    Ok(Gga {
+        time: parse_time(raw.field(0))?,
+        position: parse_position(raw, 1)?,
+        fix_quality: opt_u8(raw.field(5), "fix quality")?.unwrap_or(0),
+        satellites: opt_u8(raw.field(6), "satellites")?,
+        hdop: opt_f64(raw.field(7), "hdop")?,
+        altitude: opt_f64(raw.field(8), "altitude")?,
+    })
+}
+
+fn parse_rmc(raw: &RawSentence) -> Result<Rmc, ParseError> {
+    if raw.fields.len() < 9 {
+        return Err(ParseError::MissingField("date"));
+    }
+    let valid = match raw.field(1) {
+        "A" => true,
+        "V" => false,
+        "" => return Err(ParseError::MissingField("status")),
+        _ => return Err(ParseError::BadField("status")),
+    };
+    let variation = opt_f64(raw.field(9), "variation")?;
+    Ok(Rmc {
+        time: parse_time(raw.field(0))?,
+        valid,
+        position: parse_position(raw, 2)?,
+        speed_knots: opt_f64(raw.field(6), "speed")?,
+        course: opt_f64(raw.field(7), "course")?,
+        date: parse_date(raw.field(8))?,
+        variation: signed(variation, raw.field(10), "W", "variation")?,
+    })
+}
+
+fn parse_vtg(raw: &RawSentence) -> Result<Vtg, ParseError> {
+    // Pre NMEA 2.0 talkers omit the unit letters
+    if raw.fields.len() == 4 {
+        return Ok(Vtg {
+            course_true: opt_f64(raw.field(0), "course true")?,
+            course_magnetic: opt_f64(raw.field(1), "course magnetic")?,
+            speed_knots: opt_f64(raw.field(2), "speed knots")?,
+            speed_kmh: opt_f64(raw.field(3), "speed km/h")?,
+        });
+    }
+    if raw.fields.len() < 8 {
+        return Err(ParseError::MissingField("speed km/h"));
+    }
+    Ok(Vtg {
+        course_true: opt_f64(raw.field(0), "course true")?,
+        course_magnetic: opt_f64(raw.field(2), "course magnetic")?,
+        speed_knots: opt_f64(raw.field(4), "speed knots")?,
+        speed_kmh: opt_f64(raw.field(6), "speed km/h")?,
+    })
+}
+
+fn parse_hdg(raw: &RawSentence) -> Result<Hdg, ParseError> {
+    if raw.fields.len() < 5 {
+        return Err(ParseError::MissingField("variation"));
+    }
+    let deviation = opt_f64(raw.field(1), "deviation")?;
+    let variation = opt_f64(raw.field(3), "variation")?;
+    Ok(Hdg {
+        heading: opt_f64(raw.field(0), "heading")?,
+        deviation: signed(deviation, raw.field(2), "W", "deviation")?,
+        variation: signed(variation, raw.field(4), "W", "variation")?,
+    })
+}
+
+fn parse_dbt(raw: &RawSentence) -> Result<Dbt, ParseError> {
+    if raw.fields.len() < 6 {
+        return Err(ParseError::MissingField("depth fathoms"));
+    }
+    Ok(Dbt {
+        depth_feet: opt_f64(raw.field(0), "depth feet")?,
+        depth_meters: opt_f64(raw.field(2), "depth meters")?,
+        depth_fathoms: opt_f64(raw.field(4), "depth fathoms")?,
+    })
+}
+
+fn parse_mwv(raw: &RawSentence) -> Result<Mwv, ParseError> {
+    if raw.fields.len() < 5 {
+        return Err(ParseError::MissingField("status"));
+    }
+    let reference = match raw.field(1) {
+        "R" => WindReference::Relative,
+        "T" => WindReference::True,
+        "" => return Err(ParseError::MissingField("reference")),
+        _ => return Err(ParseError::BadField("reference")),
+    };
+    let speed = opt_f64(raw.field(2), "speed")?;
+    let speed = match (speed, raw.field(3)) {
+        (None, _) => None,
+        (Some(s), "N") => Some(s * KNOTS_TO_MS),
+        (Some(s), "K") => Some(s * KMH_TO_MS),
+        (Some(s), "M") => Some(s),
+        (Some(_), "") => return Err(ParseError::MissingField("speed unit")),
+        (Some(_), _) => return Err(ParseError::BadField("speed unit")),
+    };
+    Ok(Mwv {
+        angle: opt_f64(raw.field(0), "angle")?,
+        reference,
+        speed,
+        valid: raw.field(4) == "A",
+    })
+}
+
+fn parse_xdr(raw: &RawSentence) -> Result<Xdr, ParseError> {
+    if raw.fields.len() < 4 {
+        return Err(ParseError::MissingField("measurement"));
+    }
+    let mut measurements = Vec::with_capacity(raw.fields.len() / 4);
+    for quad in raw.fields.chunks(4) {
+        if quad.len() < 4 {
+            return Err(ParseError::MissingField("measurement"));
+        }
+        let mut kind = quad[0].chars();
+        let kind = match (kind.next(), kind.next()) {
+            (Some(k), None) => k,
+            (None, _) => return Err(ParseError::MissingField("transducer type")),
+            _ => return Err(ParseError::BadField("transducer type")),
+        };
+        measurements.push(Measurement {
+            kind,
+            value: opt_f64(quad[1], "measurement")?,
+            unit: quad[2].to_string(),
+            name: quad[3].to_string(),
+        });
+    }
+    Ok(Xdr { measurements })
+}
+
+/// Normalize an angle in degrees to radians in the range -π to π.
+fn relative_radians(degrees: f64) -> f64 {
+    let r = degrees.to_radians() % std::f64::consts::TAU;
+    if r > std::f64::consts::PI {
+        r - std::f64::consts::TAU
+    } else if r < -std::f64::consts::PI {
+        r + std::f64::consts::TAU
+    } else {
+        r
+    }
+}
+
+/// Normalize an angle in degrees to radians in the range 0 to 2π.
+fn bearing_radians(degrees: f64) -> f64 {
+    degrees.to_radians().rem_euclid(std::f64::consts::TAU)
+}
+
+fn gnss_method(quality: u8) -> &'static str {
+    match quality {
+        0 => "no GPS",
+        1 => "GNSS Fix",
+        2 => "DGNSS fix",
+        3 => "Precise GNSS",
+        4 => "RTK fixed integer",
+        5 => "RTK float",
+        6 => "Estimated (DR) mode",
+        7 => "Manual input",
+        8 => "Simulator mode",
+        _ => "Error",
+    }
+}
+
+fn position_value(p: Position) -> Value {
+    Value::Position {
+        latitude: p.latitude,
+        longitude: p.longitude,
+    }
+}
+
+impl Sentence {
+    /// Signal K path updates carried by this sentence, in SI units.
+    pub fn to_signalk(&self) -> Vec<Update> {
+        let mut updates = Vec::new();
+        match self {
+            Sentence::Gga(gga) => {
+                updates.push(Update::new(
+                    "navigation.gnss.methodQuality",
+                    gnss_method(gga.fix_quality).to_string(),
+                ));
+                if gga.fix_quality == 0 {
+                    return updates;
+                }
+                if let Some(p) = gga.position {
+                    updates.push(Update::new("navigation.position", position_value(p)));
+                }
+                if let Some(s) = gga.satellites {
+                    updates.push(Update::new("navigation.gnss.satellites", s as f64));
+                }
+                if let Some(h) = gga.hdop {
+                    updates.push(Update::new("navigation.gnss.horizontalDilution", h));
+                }
+                if let Some(a) = gga.altitude {
+                    updates.push(Update::new("navigation.gnss.antennaAltitude", a));
+                }
+            }
+            Sentence::Rmc(rmc) => {
+                if !rmc.valid {
+                    return updates;
+                }
+                if let Some(p) = rmc.position {
+                    updates.push(Update::new("navigation.position", position_value(p)));
+                }
+                if let Some(s) = rmc.speed_knots {
+                    updates.push(Update::new("navigation.speedOverGround", s * KNOTS_TO_MS));
+                }
+                if let Some(c) = rmc.course {
+                    updates.push(Update::new(
+                        "navigation.courseOverGroundTrue",
+                        bearing_radians(c),
+                    ));
+                }
+                if let Some(v) = rmc.variation {
+                    updates.push(Update::new(
+                        "navigation.magneticVariation",
+                        relative_radians(v),
+                    ));
+                }
+                if let (Some(d), Some(t)) = (rmc.date, rmc.time) {
+                    updates.push(Update::new(
+                        "navigation.datetime",
+                        format!(
+                            "{:04}-{:02}-{:02}T{:02}:{:02}:{:06.3}Z",
+                            d.year, d.month, d.day, t.hour, t.minute, t.second
+                        ),
+                    ));
+                }
+            }
+            Sentence::Vtg(vtg) => {
+                if let Some(c) = vtg.course_true {
+                    updates.push(Update::new(
+                        "navigation.courseOverGroundTrue",
+                        bearing_radians(c),
+                    ));
+                }
+                if let Some(c) = vtg.course_magnetic {
+                    updates.push(Update::new(
+                        "navigation.courseOverGroundMagnetic",
+                        bearing_radians(c),
+                    ));
+                }
+                let speed = vtg
+                    .speed_knots
+                    .map(|s| s * KNOTS_TO_MS)
+                    .or(vtg.speed_kmh.map(|s| s * KMH_TO_MS));
+                if let Some(s) = speed {
+                    updates.push(Update::new("navigation.speedOverGround", s));
+                }
+            }
+            Sentence::Hdg(hdg) => {
+                if let Some(h) = hdg.heading {
+                    updates.push(Update::new(
+                        "navigation.headingMagnetic",
+                        bearing_radians(h + hdg.deviation.unwrap_or(0.0)),
+                    ));
+                }
+                if let Some(d) = hdg.deviation {
+                    updates.push(Update::new(
+                        "navigation.magneticDeviation",
+                        relative_radians(d),
+                    ));
+                }
+                if let Some(v) = hdg.variation {
+                    updates.push(Update::new(
+                        "navigation.magneticVariation",
+                        relative_radians(v),
+                    ));
+                }
+            }
+            Sentence::Dbt(dbt) => {
+                let depth = dbt
+                    .depth_meters
+                    .or(dbt.depth_feet.map(|d| d * FEET_TO_M))
+                    .or(dbt.depth_fathoms.map(|d| d * FATHOMS_TO_M));
+                if let Some(d) = depth {
+                    updates.push(Update::new("environment.depth.belowTransducer", d));
+                }
+            }
+            Sentence::Mwv(mwv) => {
+                if !mwv.valid {
+                    return updates;
+                }
+                let (angle_path, speed_path) = match mwv.reference {
+                    WindReference::Relative => (
+                        "environment.wind.angleApparent",
+                        "environment.wind.speedApparent",
+                    ),
+                    WindReference::True => (
+                        "environment.wind.angleTrueWater",
+                        "environment.wind.speedTrue",
+                    ),
+                };
+                if let Some(a) = mwv.angle {
+                    updates.push(Update::new(angle_path, relative_radians(a)));
+                }
+                if let Some(s) = mwv.speed {
+                    updates.push(Update::new(speed_path, s));
+                }
+            }
+            Sentence::Xdr(xdr) => {
+                for m in &xdr.measurements {
+                    let (Some(value), false) = (m.value, m.name.is_empty()) else {
+                        continue;
+                    };
+                    let update = match (m.kind, m.unit.as_str()) {
+                        ('C', "C") => Update::new(
+                            format!("environment.{}.temperature", m.name),
+                            value + CELSIUS_TO_KELVIN,
+                        ),
+                        ('C', "K") => {
+                            Update::new(format!("environment.{}.temperature", m.name), value)
+                        }
+                        ('P', "B") => Update::new(
+                            format!("environment.{}.pressure", m.name),
+                            value * 100_000.0,
+                        ),
+                        ('P', "P") => {
+                            Update::new(format!("environment.{}.pressure", m.name), value)
+                        }
+                        ('H', "P") => Update::new(
+                            format!("environment.{}.relativeHumidity", m.name),
+                            value / 100.0,
+                        ),
+                        _ => continue,
+                    };
+                    updates.push(update);
+                }
+            }
+        }
+        updates
+    }
+}
+
+/// Splits a byte stream into sentences, dropping noise and over-long lines.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    buf: Vec<u8>,
+    overflow: bool,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::with_capacity(MAX_SENTENCE_LENGTH),
+            overflow: false,
+        }
+    }
+
+    /// Feed bytes into the buffer, calling `f` for every complete line.
+    pub fn push(&mut self, bytes: &[u8], mut f: impl FnMut(&str)) {
+        for &b in bytes {
+            match b {
+                b'$' | b'!' => {
+                    // A start delimiter always begins a new sentence
+                    self.buf.clear();
+                    self.overflow = false;
+                    self.buf.push(b);
+                }
+                b'\r' | b'\n' => {
+                    if !self.overflow && !self.buf.is_empty() {
+                        match std::str::from_utf8(&self.buf) {
+                            Ok(line) => f(line),
+                            Err(_) => log::warn!("Dropping non UTF-8 NMEA 0183 sentence"),
+                        }
+                    }
+                    self.buf.clear();
+                    self.overflow = false;
+                }
+                _ => {
+                    if self.buf.is_empty() || self.overflow {
+                        continue;
+                    }
+                    if self.buf.len() >= MAX_SENTENCE_LENGTH {
+                        log::warn!("Dropping over-long NMEA 0183 sentence");
+                        self.overflow = true;
+                        continue;
+                    }
+                    self.buf.push(b);
+                }
+            }
+        }
+    }
+}
+
+/// A non-blocking source of serial bytes.
+pub trait SerialRead {
+    type Error: fmt::Debug;
+
+    /// Read whatever is available into `buf`, returning immediately.
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+#[cfg(target_os = "espidf")]
+impl SerialRead for UartDriver<'_> {
+    type Error = EspError;
+
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read(buf, NON_BLOCK)
+    }
+}
+
+/// The latest sentence of each type, `None` until one has been received.
+#[derive(Default)]
+struct Sentences {
+    all: Observable<Option<Sentence>>,
+    gga: Observable<Option<Gga>>,
+    rmc: Observable<Option<Rmc>>,
+    vtg: Observable<Option<Vtg>>,
+    hdg: Observable<Option<Hdg>>,
+    dbt: Observable<Option<Dbt>>,
+    mwv: Observable<Option<Mwv>>,
+    xdr: Observable<Option<Xdr>>,
+}
+
+impl Sentences {
+    fn set(&self, sentence: &Sentence) {
+        match sentence {
+            Sentence::Gga(s) => {
+                self.gga.set(Some(s.clone()));
+            }
+            Sentence::Rmc(s) => {
+                self.rmc.set(Some(s.clone()));
+            }
+            Sentence::Vtg(s) => {
+                self.vtg.set(Some(s.clone()));
+            }
+            Sentence::Hdg(s) => {
+                self.hdg.set(Some(s.clone()));
+            }
+            Sentence::Dbt(s) => {
+                self.dbt.set(Some(s.clone()));
+            }
+            Sentence::Mwv(s) => {
+                self.mwv.set(Some(s.clone()));
+            }
+            Sentence::Xdr(s) => {
+                self.xdr.set(Some(s.clone()));
+            }
+        }
+        self.all.set(Some(sentence.clone()));
+    }
+}
+
+/// Reads NMEA 0183 sentences from a serial port and publishes their values to a
+/// [`SignalKOutput`].
+///
+/// The parsed sentences are also available as typed streams, every sentence through
+/// [`Attachable`] and each type through its `attach_*` method.
+pub struct Nmea0183Input<S: SerialRead> {
+    port: S,
+    lines: LineBuffer,
+    output: SignalKOutput,
+    sentences: Sentences,
+}
+
+impl<S: SerialRead> Nmea0183Input<S> {
+    pub fn new(port: S, output: &SignalKOutput) -> Self {
+        Nmea0183Input::<S> {
+            port,
+            lines: LineBuffer::new(),
+            output: output.clone(),
+            sentences: Sentences::default(),
+        }
+    }
+
+    /// GGA - GNSS fix data
+    pub fn attach_gga(&mut self) -> Subscriber<Option<Gga>> {
+        self.sentences.gga.subscribe()
+    }
+
+    /// RMC - Recommended minimum navigation information
+    pub fn attach_rmc(&mut self) -> Subscriber<Option<Rmc>> {
+        self.sentences.rmc.subscribe()
+    }
+
+    /// VTG - Course and speed over ground
+    pub fn attach_vtg(&mut self) -> Subscriber<Option<Vtg>> {
+        self.sentences.vtg.subscribe()
+    }
+
+    /// HDG - Heading, deviation and variation
+    pub fn attach_hdg(&mut self) -> Subscriber<Option<Hdg>> {
+        self.sentences.hdg.subscribe()
+    }
+
+    /// DBT - Depth below transducer
+    pub fn attach_dbt(&mut self) -> Subscriber<Option<Dbt>> {
+        self.sentences.dbt.subscribe()
+    }
+
+    /// MWV - Wind speed and angle
+    pub fn attach_mwv(&mut self) -> Subscriber<Option<Mwv>> {
+        self.sentences.mwv.subscribe()
+    }
+
+    /// XDR - Transducer measurements
+    pub fn attach_xdr(&mut self) -> Subscriber<Option<Xdr>> {
+        self.sentences.xdr.subscribe()
+    }
+
+    /// Parse `line` and publish its values.
+    pub fn handle_line(&mut self, line: &str) {
+        match parse(line) {
+            Ok(sentence) => {
+                for update in sentence.to_signalk() {
+                    self.output.publish(update);
+                }
+                self.sentences.set(&sentence);
+            }
+            Err(ParseError::UnknownSentence(_)) => (),
+            Err(e) => log::warn!("Dropping NMEA 0183 sentence {:?}: {}", line, e),
+        }
+    }
+
+    /// Consume the input and return the underlying serial port
+    pub fn release(self) -> S {
+        self.port
+    }
+}
+
+impl<S: SerialRead> Attachable<Option<Sentence>> for Nmea0183Input<S> {
+    fn attach(&mut self) -> Subscriber<Option<Sentence>> {
+        self.sentences.all.subscribe()
+    }
+}
+
+impl<S: SerialRead> SensESPSensor for Nmea0183Input<S> {
+    fn tick(&mut self) {
+        let mut buf = [0; 128];
+        let mut lines = Vec::new();
+        loop {
+            match self.port.read_available(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => self
+                    .lines
+                    .push(&buf[..n], |line| lines.push(line.to_string())),
+                Err(e) => {
+                    log::error!("Serial read error on SensESP-rs tick: {:?}", e);
+                    break;
+                }
+            }
+        }
+        for line in lines {
+            self.handle_line(&line);
+        }
+    }
+}
+
+/// Delimiters and other characters the standard reserves, which can't appear in a field.
+const RESERVED: &[char] = &['\r', '\n', '$', '!', '*', ',', '\\', '^', '~'];
+
+fn check_field(field: &str) -> Result<(), EncodeError> {
+    let printable = |c: &char| c.is_ascii_graphic() || *c == ' ';
+    match field
+        .chars()
+        .find(|c| RESERVED.contains(c) || !printable(c))
+    {
+        Some(c) => Err(EncodeError::ReservedCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+fn finite(value: f32) -> Result<f32, EncodeError> {
+    match value.is_finite() {
+        true => Ok(value),
+        false => Err(EncodeError::NotFinite),
+    }
+}
+
+/// Frame a sentence as `$<talker><sentence>,<fields>*<checksum><CR><LF>`.
+///
+/// Fails rather than truncate a sentence longer than [`MAX_SENTENCE_LENGTH`], or send one
+/// with a reserved or non-printable character in its ids or fields.
+pub fn encode(talker: &str, sentence: &str, fields: &[&str]) -> Result<String, EncodeError> {
+    for field in [talker, sentence].iter().chain(fields) {
+        check_field(field)?;
+    }
+    let mut body = format!("{}{}", talker, sentence);
+    for field in fields {
+        body.push(',');
+        body.push_str(field);
+    }
+    let framed = format!("${}*{:02X}\r\n", body, checksum(&body));
+    match framed.len() > MAX_SENTENCE_LENGTH {
+        true => Err(EncodeError::TooLong(framed.len())),
+        false => Ok(framed),
+    }
+}
+
+/// MTW - Water temperature, from a temperature in Kelvin.
+pub fn encode_mtw(talker: &str, kelvin: f32) -> Result<String, EncodeError> {
+    let celsius = format!("{:.1}", finite(kelvin)? as f64 - CELSIUS_TO_KELVIN);
+    encode(talker, "MTW", &[&celsius, "C"])
+}
+
+/// RPM - Engine revolutions, from a speed in revolutions per minute.
+pub fn encode_rpm(talker: &str, engine: u8, rpm: f32) -> Result<String, EncodeError> {
+    let engine = engine.to_string();
+    let rpm = format!("{:.1}", finite(rpm)?);
+    encode(talker, "RPM", &["E", &engine, &rpm, "", "A"])
+}
+
+/// XDR - Transducer measurements, with empty values for unknown, NaN or infinite readings.
+pub fn encode_xdr(talker: &str, measurements: &[Measurement]) -> Result<String, EncodeError> {
+    let fields: Vec<String> = measurements
+        .iter()
+        .flat_map(|m| {
+            [
+                m.kind.to_string(),
+                m.value
+                    .filter(|v| v.is_finite())
+                    .map(|v| format!("{:.2}", v))
+                    .unwrap_or_default(),
+                m.unit.clone(),
+                m.name.clone(),
+            ]
+        })
+        .collect();
+    let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+    encode(talker, "XDR", &fields)
+}
+
+/// XDR temperature measurement named `name`, from a temperature in Kelvin.
+pub fn encode_xdr_temperature(
+    talker: &str,
+    name: &str,
+    kelvin: f32,
+) -> Result<String, EncodeError> {
+    encode_xdr(
+        talker,
+        &[Measurement {
+            kind: 'C',
+            value: Some(kelvin as f64 - CELSIUS_TO_KELVIN),
+            unit: "C".to_string(),
+            name: name.to_string(),
+        }],
+    )
+}
+
+/// A serial sink for outgoing bytes.
+pub trait SerialWrite {
+    type Error: fmt::Debug;
+
+    /// Write some of `buf`, returning how many bytes were accepted.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error>;
+}
+
+#[cfg(target_os = "espidf")]
+impl SerialWrite for UartDriver<'_> {
+    type Error = EspError;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        UartDriver::write(self, buf)
+    }
+}
+
+/// Allows an action at most once per interval.
+#[derive(Debug, Copy, Clone)]
+pub struct RateLimiter {
+    interval: Duration,
+    last: Option<SystemTime>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Return true, and restart the interval, if at least one interval has passed by `now`.
+    pub fn ready(&mut self, now: SystemTime) -> bool {
+        let ready = match self.last {
+            None => true,
+            Some(last) => match now.duration_since(last) {
+                Ok(d) => d >= self.interval,
+                // The clock stepped backwards, start over rather than stalling
+                Err(_) => true,
+            },
+        };
+        if ready {
+            self.last = Some(now);
+        }
+        ready
+    }
+}
+
+/// Formats the latest value of a stream, given the talker id
+type SentenceFormatter = Box<dyn FnMut(&str) -> Poll<Option<Result<String, EncodeError>>>>;
+
+struct OutputSource {
+    poll: SentenceFormatter,
+    sentence: Option<String>,
+}
+
+/// Writes the latest values of sensor streams as NMEA 0183 sentences at a fixed rate.
+pub struct Nmea0183Output<S: SerialWrite> {
+    port: S,
+    talker: String,
+    limiter: RateLimiter,
+    sources: Vec<OutputSource>,
+}
+
+impl<S: SerialWrite> Nmea0183Output<S> {
+    /// Create an output sending every `interval` with the given two letter talker id.
+    pub fn new(port: S, talker: &str, interval: Duration) -> Self {
+        Nmea0183Output::<S> {
+            port,
+            talker: talker.to_string(),
+            limiter: RateLimiter::new(interval),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Send a sentence built by `format` from the latest value of `subscriber`.
+    ///
+    /// `format` is given the talker id and the value. A value that cannot be encoded stops
+    /// the source being sent until the next one.
+    pub fn source<T>(
+        mut self,
+        subscriber: Subscriber<T>,
+        format: impl Fn(&str, T) -> Result<String, EncodeError> + 'static,
+    ) -> Self
+    where
+        T: Clone + 'static,
+    {
+        let mut updates = Updates::new(subscriber);
+        self.sources.push(OutputSource {
+            poll: Box::new(move |talker| match poll_update(&mut updates) {
+                Poll::Ready(Some(value)) => Poll::Ready(Some(format(talker, value))),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            }),
+            sentence: None,
+        });
+        self
+    }
+
+    /// Send water temperature in Kelvin as MTW.
+    pub fn mtw(self, subscriber: Subscriber<f32>) -> Self {
+        self.source(subscriber, encode_mtw)
+    }
+
+    /// Send engine speed in revolutions per minute as RPM.
+    pub fn rpm(self, engine: u8, subscriber: Subscriber<f32>) -> Self {
+        self.source(subscriber, move |talker, rpm| {
+            encode_rpm(talker, engine, rpm)
+        })
+    }
+
+    /// Send a temperature in Kelvin as an XDR measurement named `name`.
+    pub fn xdr_temperature(self, name: &str, subscriber: Subscriber<f32>) -> Self {
+        let name = name.to_string();
+        self.source(subscriber, move |talker, kelvin| {
+            encode_xdr_temperature(talker, &name, kelvin)
+        })
+    }
+
+    /// Take new values from the sources and return the sentences due at `now`.
+    pub fn poll(&mut self, now: SystemTime) -> Vec<String> {
+        for source in &mut self.sources {
+            match (source.poll)(&self.talker) {
+                Poll::Ready(Some(Ok(sentence))) => source.sentence = Some(sentence),
+                Poll::Ready(Some(Err(e))) => {
+                    log::warn!("Dropping NMEA 0183 output: {}", e);
+                    source.sentence = None;
+                }
+                // Stop repeating the last value of a source that has gone away
+                Poll::Ready(None) => source.sentence = None,
+                Poll::Pending => (),
+            }
+        }
+
+        if !self.limiter.ready(now) {
+            return Vec::new();
+        }
+        self.sources
+            .iter()
+            .filter_map(|s| s.sentence.clone())
+            .collect()
+    }
+
+    /// Consume the output and return the underlying serial port
+    pub fn release(self) -> S {
+        self.port
+    }
+
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<(), S::Error> {
+        while !buf.is_empty() {
+            let n = self.port.write(buf)?;
+            if n == 0 {
+                log::warn!("Serial port accepted no data, dropping NMEA 0183 output");
+                break;
+            }
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
+
+impl<S: SerialWrite> SensESPSensor for Nmea0183Output<S> {
+    fn tick(&mut self) {
+        for sentence in self.poll(SystemTime::now()) {
+            if let Err(e) = self.write_all(sentence.as_bytes()) {
+                log::error!("Serial write error on SensESP-rs tick: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame a sentence body with a valid checksum, to reach the field checks.
+    fn framed(body: &str) -> String {
+        format!("${}*{:02X}", body, checksum(body))
+    }
+
+    fn number(updates: &[Update], path: &str) -> f64 {
+        match updates.iter().find(|u| u.path == path) {
+            Some(Update {
+                value: Value::Number(n),
+                ..
+            }) => *n,
+            other => panic!("{} is {:?}", path, other),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn checksum_is_xor_of_body() {
+        assert_eq!(checksum(""), 0);
+        assert_eq!(checksum("GPVTG,054.7,T,034.4,M,005.5,N,010.2,K"), 0x48);
+    }
+
+    #[test]
+    fn raw_sentence_splits_fields() {
+        let raw = RawSentence::parse("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n").unwrap();
+        assert_eq!(raw.talker, "GP");
+        assert_eq!(raw.sentence, "VTG");
+        assert_eq!(raw.fields.len(), 8);
+
+        // Lower case checksums and encapsulation sentences are accepted
+        let raw = RawSentence::parse("$SDDBT,7.8,f,2.4,M,1.3,F*0d").unwrap();
+        assert_eq!(raw.sentence, "DBT");
+        let raw = RawSentence::parse("!AIVDM,1,1,,A,13aEOK?P00PD2wVMdLDRhgvL289?,0*26").unwrap();
+        assert_eq!(raw.talker, "AI");
+
+        let raw = RawSentence::parse("$PGRME,15.0,M,45.0,M,25.0,M*1C").unwrap();
+        assert_eq!(raw.talker, "P");
+        assert_eq!(raw.sentence, "GRME");
+    }
+
+    #[test]
+    fn parses_gga() {
+        let gga = parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47");
+        assert_eq!(
+            gga,
+            Ok(Sentence::Gga(Gga {
+                time: Some(Time {
+                    hour: 12,
+                    minute: 35,
+                    second: 19.0,
+                }),
+                position: Some(Position {
+                    latitude: 48.0 + 7.038 / 60.0,
+                    longitude: 11.0 + 31.0 / 60.0,
+                }),
+                fix_quality: 1,
+                satellites: Some(8),
+                hdop: Some(0.9),
+                altitude: Some(545.4),
+            }))
+        );
+        let updates = gga.unwrap().to_signalk();
+        assert_eq!(
+            updates[0],
+            Update::new("navigation.gnss.methodQuality", "GNSS Fix".to_string())
+        );
+        assert_eq!(number(&updates, "navigation.gnss.satellites"), 8.0);
+        assert_eq!(number(&updates, "navigation.gnss.antennaAltitude"), 545.4);
+    }
+
+    #[test]
+    fn gga_without_fix_only_reports_quality() {
+        let gga = parse("$GPGGA,,,,,,0,00,99.99,,,,,,*48").unwrap();
+        assert_eq!(
+            gga.to_signalk(),
+            [Update::new(
+                "navigation.gnss.methodQuality",
+                "no GPS".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn parses_rmc() {
+        let rmc =
+            parse("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A").unwrap();
+        let Sentence::Rmc(ref fields) = rmc else {
+            panic!("{:?}", rmc);
+        };
+        assert!(fields.valid);
+        assert_eq!(
+            fields.date,
+            Some(Date {
+                year: 1994,
+                month: 3,
+                day: 23,
+            })
+        );
+        assert_eq!(fields.variation, Some(-3.1));
+
+        let updates = rmc.to_signalk();
+        assert_close(
+            number(&updates, "navigation.speedOverGround"),
+            22.4 * 1852.0 / 3600.0,
+        );
+        assert_close(
+            number(&updates, "navigation.courseOverGroundTrue"),
+            84.4f64.to_radians(),
+        );
+        assert_close(
+            number(&updates, "navigation.magneticVariation"),
+            -3.1f64.to_radians(),
+        );
+        assert!(updates.contains(&Update::new(
+            "navigation.datetime",
+            "1994-03-23T12:35:19.000Z".to_string()
+        )));
+    }
+
+    #[test]
+    fn parses_rmc_with_mode_and_empty_fields() {
+        let rmc = parse("$GNRMC,001031.00,A,4404.13993,N,12118.86023,W,0.146,,100117,,,A*7B");
+        assert_eq!(
+            rmc,
+            Ok(Sentence::Rmc(Rmc {
+                time: Some(Time {
+                    hour: 0,
+                    minute: 10,
+                    second: 31.0,
+                }),
+                valid: true,
+                position: Some(Position {
+                    latitude: 44.0 + 4.13993 / 60.0,
+                    longitude: -(121.0 + 18.86023 / 60.0),
+                }),
+                speed_knots: Some(0.146),
+                course: None,
+                date: Some(Date {
+                    year: 2017,
+                    month: 1,
+                    day: 10,
+                }),
+                variation: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn void_rmc_publishes_nothing() {
+        let rmc = parse("$GPRMC,123519,V,,,,,,,230394,,*33").unwrap();
+        assert!(rmc.to_signalk().is_empty());
+    }
+
+    #[test]
+    fn parses_vtg() {
+        let vtg = parse("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48").unwrap();
+        assert_eq!(
+            vtg,
+            Sentence::Vtg(Vtg {
+                course_true: Some(54.7),
+                course_magnetic: Some(34.4),
+                speed_knots: Some(5.5),
+                speed_kmh: Some(10.2),
+            })
+        );
+        assert_close(
+            number(&vtg.to_signalk(), "navigation.speedOverGround"),
+            5.5 * 1852.0 / 3600.0,
+        );
+
+        // Before NMEA 2.0 there were no unit letters
+        let old = parse("$GPVTG,054.7,034.4,005.5,010.2*54").unwrap();
+        assert_eq!(old, vtg);
+
+        let stopped = parse("$GPVTG,,T,,M,0.00,N,0.00,K*4E").unwrap();
+        assert_eq!(
+            stopped.to_signalk(),
+            [Update::new("navigation.speedOverGround", 0.0)]
+        );
+    }
+
+    #[test]
+    fn parses_hdg() {
+        let hdg = parse("$HCHDG,98.3,0.0,E,12.6,W*57").unwrap();
+        assert_eq!(
+            hdg,
+            Sentence::Hdg(Hdg {
+                heading: Some(98.3),
+                deviation: Some(0.0),
+                variation: Some(-12.6),
+            })
+        );
+        let updates = hdg.to_signalk();
+        assert_close(
+            number(&updates, "navigation.headingMagnetic"),
+            98.3f64.to_radians(),
+        );
+        assert_close(
+            number(&updates, "navigation.magneticVariation"),
+            -12.6f64.to_radians(),
+        );
+    }
+
+    #[test]
+    fn parses_dbt() {
+        let dbt = parse("$SDDBT,7.8,f,2.4,M,1.3,F*0D").unwrap();
+        assert_eq!(
+            dbt.to_signalk(),
+            [Update::new("environment.depth.belowTransducer", 2.4)]
+        );
+        // Falls back to feet when there is no metric depth
+        let feet = parse(&framed("SDDBT,10.0,f,,M,,F")).unwrap();
+        assert_close(
+            number(&feet.to_signalk(), "environment.depth.belowTransducer"),
+            3.048,
+        );
+    }
+
+    #[test]
+    fn parses_mwv() {
+        let mwv = parse("$WIMWV,214.8,R,0.1,K,A*28").unwrap();
+        let updates = mwv.to_signalk();
+        assert_close(
+            number(&updates, "environment.wind.angleApparent"),
+            (214.8f64 - 360.0).to_radians(),
+        );
+        assert_close(
+            number(&updates, "environment.wind.speedApparent"),
+            0.1 / 3.6,
+        );
+
+        let knots = parse(&framed("WIMWV,45.0,T,10.0,N,A")).unwrap();
+        assert_close(
+            number(&knots.to_signalk(), "environment.wind.speedTrue"),
+            10.0 * 1852.0 / 3600.0,
+        );
+
+        let invalid = parse(&framed("WIMWV,45.0,T,10.0,M,V")).unwrap();
+        assert!(invalid.to_signalk().is_empty());
+    }
+
+    #[test]
+    fn parses_xdr() {
+        let xdr = parse("$IIXDR,C,19.52,C,TempAir,P,1.02481,B,Barometer*7E").unwrap();
+        let updates = xdr.to_signalk();
+        assert_close(
+            number(&updates, "environment.TempAir.temperature"),
+            19.52 + 273.15,
+        );
+        assert_close(
+            number(&updates, "environment.Barometer.pressure"),
+            102_481.0,
+        );
+
+        // Unnamed, empty and unknown measurements are skipped
+        let partial = parse(&framed("IIXDR,C,,C,Water,C,20.0,C,,A,5.0,D,Rudder")).unwrap();
+        assert!(partial.to_signalk().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let cases = [
+            ("GPGGA,123519*00", ParseError::BadFrame),
+            ("$GP*17", ParseError::BadFrame),
+            ("$*00", ParseError::BadFrame),
+            ("$GPGGA,123519", ParseError::MissingChecksum),
+            (
+                "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*4",
+                ParseError::MissingChecksum,
+            ),
+            (
+                "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*ZZ",
+                ParseError::MissingChecksum,
+            ),
+            (
+                "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48",
+                ParseError::BadChecksum {
+                    expected: 0x47,
+                    found: 0x48,
+                },
+            ),
+            (
+                "!AIVDM,1,1,,A,13aEOK?P00PD2wVMdLDRhgvL289?,0*26",
+                ParseError::UnknownSentence("VDM".to_string()),
+            ),
+        ];
+        for (line, error) in cases {
+            assert_eq!(parse(line), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_fields() {
+        let cases = [
+            (
+                "GPGGA,123519,4807.038,N",
+                ParseError::MissingField("altitude"),
+            ),
+            (
+                "GPGGA,123519,48a7.038,N,01131.000,E,1,08,0.9,545.4,M",
+                ParseError::BadField("latitude"),
+            ),
+            (
+                "GPGGA,123519,4807.038,,01131.000,E,1,08,0.9,545.4,M",
+                ParseError::MissingField("latitude"),
+            ),
+            (
+                "GPGGA,123519,9107.038,N,01131.000,E,1,08,0.9,545.4,M",
+                ParseError::BadField("latitude"),
+            ),
+            (
+                "GPGGA,123519,4807.038,N,01171.000,E,1,08,0.9,545.4,M",
+                ParseError::BadField("longitude"),
+            ),
+            (
+                "GPGGA,253519,4807.038,N,01131.000,E,1,08,0.9,545.4,M",
+                ParseError::BadField("time"),
+            ),
+            (
+                "GPGGA,1é3519,4807.038,N,01131.000,E,1,08,0.9,545.4,M",
+                ParseError::BadField("time"),
+            ),
+            (
+                "GPGGA,123519,4807.038,N,01131.000,E,1,300,0.9,545.4,M",
+                ParseError::BadField("satellites"),
+            ),
+            (
+                "GPGGA,123519,4807.038,N,01131.000,E,1,08,NaN,545.4,M",
+                ParseError::BadField("hdop"),
+            ),
+            (
+                "GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,inf,M",
+                ParseError::BadField("altitude"),
+            ),
+            (
+                "GPRMC,123519,,4807.038,N,01131.000,E,022.4,084.4,230394",
+                ParseError::MissingField("status"),
+            ),
+            (
+                "GPRMC,123519,X,4807.038,N,01131.000,E,022.4,084.4,230394",
+                ParseError::BadField("status"),
+            ),
+            (
+                "GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,231394",
+                ParseError::BadField("date"),
+            ),
+            (
+                "GPVTG,054.7,T,034.4,M,005.5",
+                ParseError::MissingField("speed km/h"),
+            ),
+            (
+                "HCHDG,98.3,0.0,E,12.6",
+                ParseError::MissingField("variation"),
+            ),
+            ("WIMWV,214.8,X,0.1,K,A", ParseError::BadField("reference")),
+            (
+                "WIMWV,214.8,R,0.1,,A",
+                ParseError::MissingField("speed unit"),
+            ),
+            ("WIMWV,214.8,R,0.1,Q,A", ParseError::BadField("speed unit")),
+            ("IIXDR,C,19.52,C", ParseError::MissingField("measurement")),
+            (
+                "IIXDR,C,19.52,C,TempAir,P,1.02",
+                ParseError::MissingField("measurement"),
+            ),
+            (
+                "IIXDR,CC,19.52,C,TempAir",
+                ParseError::BadField("transducer type"),
+            ),
+        ];
+        for (body, error) in cases {
+            assert_eq!(parse(&framed(body)), Err(error), "{}", body);
+        }
+    }
+
+    #[test]
+    fn line_buffer_splits_a_byte_stream() {
+        let mut lines = LineBuffer::new();
+        let mut out = Vec::new();
+        lines.push(
+            b"noise$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n$SDDBT,7.8,f,",
+            |l| out.push(l.to_string()),
+        );
+        lines.push(b"2.4,M,1.3,F*0D\r\n\n\r\n", |l| out.push(l.to_string()));
+        assert_eq!(
+            out,
+            [
+                "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48",
+                "$SDDBT,7.8,f,2.4,M,1.3,F*0D"
+            ]
+        );
+    }
+
+    #[test]
+    fn line_buffer_restarts_on_a_start_delimiter() {
+        let mut lines = LineBuffer::new();
+        let mut out = Vec::new();
+        lines.push(b"$GPVTG,054.7,T,03$SDDBT,7.8,f,2.4,M,1.3,F*0D\n", |l| {
+            out.push(l.to_string())
+        });
+        assert_eq!(out, ["$SDDBT,7.8,f,2.4,M,1.3,F*0D"]);
+    }
+
+    #[test]
+    fn line_buffer_drops_long_and_invalid_lines() {
+        let mut lines = LineBuffer::new();
+        let mut out = Vec::new();
+        let long = format!("${}\r\n", "A".repeat(MAX_SENTENCE_LENGTH));
+        lines.push(long.as_bytes(), |l| out.push(l.to_string()));
+        lines.push(b"$GP\xff\xfeVTG\r\n", |l| out.push(l.to_string()));
+        assert!(out.is_empty());
+
+        let longest = format!("${}", "A".repeat(MAX_SENTENCE_LENGTH - 1));
+        lines.push(format!("{}\r\n", longest).as_bytes(), |l| {
+            out.push(l.to_string())
+        });
+        assert_eq!(out, [longest]);
+    }
+
+    /// Hands out queued chunks of bytes, one per read.
+    struct FakeSerial {
+        chunks: Vec<Vec<u8>>,
+    }
+
+    impl SerialRead for FakeSerial {
+        type Error = ();
+
+        fn read_available(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            match self.chunks.is_empty() {
+                true => Ok(0),
+                false => {
+                    let chunk = self.chunks.remove(0);
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn input_publishes_to_the_output() {
+        let output = SignalKOutput::new();
+        let position = output.attach_path("navigation.position");
+        let port = FakeSerial {
+            chunks: vec![
+                b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,".to_vec(),
+                b"545.4,M,46.9,M,,*47\r\n$GPGGA,123519*00\r\n".to_vec(),
+                b"$GPRMB,A,0.66,L,003,004*0B\r\n$SDDBT,7.8,f,2.4,M,1.3,F*0D\r\n".to_vec(),
+            ],
+        };
+        let mut input = Nmea0183Input::new(port, &output);
+        input.tick();
+
+        assert_eq!(
+            position.get(),
+            Value::Position {
+                latitude: 48.0 + 7.038 / 60.0,
+                longitude: 11.0 + 31.0 / 60.0,
+            }
+        );
+        let paths: Vec<String> = output.take_pending().into_iter().map(|u| u.path).collect();
+        assert_eq!(
+            paths,
+            [
+                "navigation.gnss.methodQuality",
+                "navigation.position",
+                "navigation.gnss.satellites",
+                "navigation.gnss.horizontalDilution",
+                "navigation.gnss.antennaAltitude",
+                "environment.depth.belowTransducer",
+            ]
+        );
+        assert!(input.release().chunks.is_empty());
+    }
+
+    #[test]
+    fn input_exposes_typed_streams() {
+        let gga_line = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        let dbt_line = "$SDDBT,7.8,f,2.4,M,1.3,F*0D";
+        let port = FakeSerial {
+            chunks: vec![format!("{}\r\n{}\r\n", gga_line, dbt_line).into_bytes()],
+        };
+        let mut input = Nmea0183Input::new(port, &SignalKOutput::new());
+        let mut gga = Updates::new(input.attach_gga());
+        let dbt = input.attach_dbt();
+        let rmc = input.attach_rmc();
+        let all = input.attach();
+        assert_eq!(all.get(), None);
+
+        input.tick();
+        match poll_update(&mut gga) {
+            Poll::Ready(Some(Some(gga))) => {
+                assert_eq!(Sentence::Gga(gga), parse(gga_line).unwrap())
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(dbt.get().map(Sentence::Dbt), Some(parse(dbt_line).unwrap()));
+        assert_eq!(rmc.get(), None);
+        assert_eq!(all.get(), Some(parse(dbt_line).unwrap()));
+
+        // Sentences that fail to parse leave the streams alone
+        input.handle_line("$GPGGA,123519*00");
+        assert_eq!(poll_update(&mut gga), Poll::Pending);
+    }
+
+    #[test]
+    fn encodes_framed_sentences() {
+        assert_eq!(
+            encode(
+                "GP",
+                "VTG",
+                &["054.7", "T", "034.4", "M", "005.5", "N", "010.2", "K"]
+            ),
+            Ok("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n".to_string())
+        );
+        assert_eq!(encode("P", "X", &[]), Ok("$PX*08\r\n".to_string()));
+    }
+
+    #[test]
+    fn encoded_sentences_parse_back() {
+        let mtw = encode_mtw("II", 293.65).unwrap();
+        assert_eq!(
+            mtw,
+            format!("$IIMTW,20.5,C*{:02X}\r\n", checksum("IIMTW,20.5,C"))
+        );
+        assert!(RawSentence::parse(&mtw).is_ok());
+
+        let rpm = encode_rpm("II", 2, 1850.04).unwrap();
+        assert_eq!(
+            rpm,
+            format!(
+                "$IIRPM,E,2,1850.0,,A*{:02X}\r\n",
+                checksum("IIRPM,E,2,1850.0,,A")
+            )
+        );
+
+        let xdr = encode_xdr_temperature("II", "TempAir", 292.67).unwrap();
+        assert_close(
+            number(
+                &parse(&xdr).unwrap().to_signalk(),
+                "environment.TempAir.temperature",
+            ),
+            19.52 + CELSIUS_TO_KELVIN,
+        );
+
+        let unknown = encode_xdr(
+            "II",
+            &[Measurement {
+                kind: 'P',
+                value: None,
+                unit: "B".to_string(),
+                name: "Barometer".to_string(),
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            unknown,
+            format!(
+                "$IIXDR,P,,B,Barometer*{:02X}\r\n",
+                checksum("IIXDR,P,,B,Barometer")
+            )
+        );
+    }
+
+    #[test]
+    fn encode_enforces_the_maximum_length() {
+        // `$`, the ids, a comma, `*hh` and <CR><LF> leave 70 characters of fields
+        let longest = "A".repeat(70);
+        assert_eq!(
+            encode("II", "TXT", &[&longest]).map(|s| s.len()),
+            Ok(MAX_SENTENCE_LENGTH)
+        );
+        let long = "A".repeat(71);
+        assert_eq!(
+            encode("II", "TXT", &[&long]),
+            Err(EncodeError::TooLong(MAX_SENTENCE_LENGTH + 1))
+        );
+
+        let name = "A".repeat(64);
+        assert!(encode_xdr_temperature("II", &name, 293.15).is_err());
+    }
+
+    #[test]
+    fn encode_rejects_characters_that_break_the_framing() {
+        for c in [
+            '\r', '\n', '$', '!', '*', ',', '\\', '^', '~', '\u{7f}', '\t', '°',
+        ] {
+            let field = format!("A{}B", c);
+            assert_eq!(
+                encode("II", "TXT", &[&field]),
+                Err(EncodeError::ReservedCharacter(c))
+            );
+        }
+        assert_eq!(
+            encode("I,", "TXT", &[]),
+            Err(EncodeError::ReservedCharacter(','))
+        );
+        assert_eq!(
+            encode_xdr_temperature("II", "Air*Temp", 293.15),
+            Err(EncodeError::ReservedCharacter('*'))
+        );
+        assert!(encode("II", "TXT", &["Hello world 0-9 {}"]).is_ok());
+    }
+
+    #[test]
+    fn encode_rejects_values_that_are_not_finite() {
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(encode_mtw("II", value), Err(EncodeError::NotFinite));
+            assert_eq!(encode_rpm("II", 1, value), Err(EncodeError::NotFinite));
+            // XDR has empty fields for unknown readings
+            assert_eq!(
+                encode_xdr_temperature("II", "Air", value),
+                Ok(format!(
+                    "$IIXDR,C,,C,Air*{:02X}\r\n",
+                    checksum("IIXDR,C,,C,Air")
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn rate_limiter_allows_one_per_interval() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut limiter = RateLimiter::new(Duration::from_secs(1));
+        assert!(limiter.ready(start));
+        assert!(!limiter.ready(start));
+        assert!(!limiter.ready(start + Duration::from_millis(999)));
+        assert!(limiter.ready(start + Duration::from_secs(1)));
+        // The interval restarts from the last allowed time
+        assert!(!limiter.ready(start + Duration::from_millis(1500)));
+        assert!(limiter.ready(start + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn rate_limiter_restarts_when_the_clock_steps_back() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut limiter = RateLimiter::new(Duration::from_secs(1));
+        assert!(limiter.ready(start));
+        assert!(limiter.ready(start - Duration::from_secs(60)));
+        assert!(!limiter.ready(start - Duration::from_millis(59_500)));
+    }
+
+    /// Records written bytes, accepting at most `limit` per write.
+    struct FakeWriter {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl SerialWrite for FakeWriter {
+        type Error = ();
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.limit);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn output_repeats_latest_values_at_the_rate() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let water = eyeball::shared::Observable::new(293.65f32);
+        let name = eyeball::shared::Observable::new("Air".to_string());
+        let mut output = Nmea0183Output::new(
+            FakeWriter {
+                written: Vec::new(),
+                limit: 64,
+            },
+            "II",
+            Duration::from_secs(1),
+        )
+        .mtw(water.subscribe())
+        .source(name.subscribe(), |talker, name: String| {
+            encode(talker, "TXT", &[&name])
+        });
+
+        // Nothing is sent before the first value
+        assert!(output.poll(start).is_empty());
+
+        water.set(300.15);
+        name.set("Cabin".to_string());
+        let sent = output.poll(start + Duration::from_millis(100));
+        assert!(sent.is_empty(), "{:?}", sent);
+        let sent = output.poll(start + Duration::from_secs(1));
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].starts_with("$IIMTW,27.0,C*"));
+        assert!(sent[1].starts_with("$IITXT,Cabin*"));
+
+        // A value too long to send stops that source, the others keep repeating
+        name.set("A".repeat(80));
+        let sent = output.poll(start + Duration::from_secs(2));
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with("$IIMTW,27.0,C*"));
+
+        drop(water);
+        assert!(output.poll(start + Duration::from_secs(3)).is_empty());
+    }
+
+    #[test]
+    fn output_writes_whole_sentences() {
+        let rpm = eyeball::shared::Observable::new(1850.0f32);
+        let port = FakeWriter {
+            written: Vec::new(),
+            limit: 5,
+        };
+        let mut output =
+            Nmea0183Output::new(port, "II", Duration::from_secs(1)).rpm(1, rpm.subscribe());
+        rpm.set(900.0);
+        output.tick();
+        let written = output.release().written;
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            encode_rpm("II", 1, 900.0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,1806 @@
+//! NMEA 2000 over CAN, with ISO address claim, fast-packet transport and a Signal K gateway
+use crate::nmea0183::RateLimiter;
+use crate::sensor::{poll_update, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, Update, Value};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::can::{CanDriver, Flags, Frame};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::delay::NON_BLOCK;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::sys::{EspError, ESP_ERR_TIMEOUT};
+use eyeball::Subscriber;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+pub const PGN_ISO_REQUEST: u32 = 59904;
+pub const PGN_ADDRESS_CLAIM: u32 = 60928;
+pub const PGN_ENGINE_RAPID: u32 = 127488;
+pub const PGN_ENGINE_DYNAMIC: u32 = 127489;
+pub const PGN_BATTERY_STATUS: u32 = 127508;
+pub const PGN_TEMPERATURE: u32 = 130312;
+
+/// Destination address for broadcast messages
+pub const BROADCAST: u8 = 255;
+/// Source address used by a node that could not claim an address
+pub const NULL_ADDRESS: u8 = 254;
+
+/// Time a node must wait after claiming before it may use its address.
+const CLAIM_TIMEOUT: Duration = Duration::from_millis(250);
+/// Fast-packet transfers that stall longer than this are discarded.
+const FAST_PACKET_TIMEOUT: Duration = Duration::from_millis(750);
+/// Largest payload a fast-packet transfer can carry.
+pub const FAST_PACKET_MAX: usize = 223;
+
+/// An extended (29 bit) CAN data frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanFrame {
+    pub id: u32,
+    pub data: Vec<u8>,
+}
+
+/// A CAN controller, or a virtual bus standing in for one.
+pub trait CanBus {
+    type Error: fmt::Debug;
+
+    fn transmit(&mut self, frame: &CanFrame) -> Result<(), Self::Error>;
+
+    /// Return the next received frame, or `None` if nothing is waiting.
+    fn receive(&mut self) -> Result<Option<CanFrame>, Self::Error>;
+}
+
+#[cfg(target_os = "espidf")]
+impl CanBus for CanDriver<'_> {
+    type Error = EspError;
+
+    fn transmit(&mut self, frame: &CanFrame) -> Result<(), Self::Error> {
+        match Frame::new(frame.id, Flags::Extended.into(), &frame.data) {
+            Some(f) => CanDriver::transmit(self, &f, NON_BLOCK),
+            None => {
+                log::error!("Dropping invalid CAN frame {:?}", frame);
+                Ok(())
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Result<Option<CanFrame>, Self::Error> {
+        match CanDriver::receive(self, NON_BLOCK) {
+            Ok(f) => Ok(Some(CanFrame {
+                id: f.identifier(),
+                data: f.data().to_vec(),
+            })),
+            Err(e) if e.code() == ESP_ERR_TIMEOUT as i32 => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// An in-memory CAN bus connecting any number of [`VirtualCan`] endpoints.
+///
+/// Every frame transmitted by an endpoint is delivered to all other endpoints, which makes a
+/// pair of endpoints a loopback connection between two nodes.
+#[derive(Clone, Default)]
+pub struct VirtualBus {
+    queues: Arc<Mutex<Vec<VecDeque<CanFrame>>>>,
+}
+
+impl VirtualBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach a new endpoint to the bus.
+    pub fn connect(&self) -> VirtualCan {
+        let mut queues = self.queues.lock().unwrap();
+        queues.push(VecDeque::new());
+        VirtualCan {
+            bus: self.clone(),
+            index: queues.len() - 1,
+        }
+    }
+}
+
+/// An endpoint on a [`VirtualBus`].
+pub struct VirtualCan {
+    bus: VirtualBus,
+    index: usize,
+}
+
+impl CanBus for VirtualCan {
+    type Error = core::convert::Infallible;
+
+    fn transmit(&mut self, frame: &CanFrame) -> Result<(), Self::Error> {
+        let mut queues = self.bus.queues.lock().unwrap();
+        for (i, queue) in queues.iter_mut().enumerate() {
+            if i != self.index {
+                queue.push_back(frame.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Option<CanFrame>, Self::Error> {
+        Ok(self.bus.queues.lock().unwrap()[self.index].pop_front())
+    }
+}
+
+/// The fields of an NMEA 2000 CAN identifier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CanId {
+    pub priority: u8,
+    pub pgn: u32,
+    pub source: u8,
+    pub destination: u8,
+}
+
+impl CanId {
+    pub fn from_raw(id: u32) -> Self {
+        let priority = ((id >> 26) & 0x7) as u8;
+        let dp = (id >> 24) & 0x3;
+        let pf = (id >> 16) & 0xFF;
+        let ps = (id >> 8) & 0xFF;
+        let source = (id & 0xFF) as u8;
+        // PDU1 formats (PF < 240) are addressed, PDU2 use PS as part of the PGN
+        let (pgn, destination) = if pf < 240 {
+            ((dp << 16) | (pf << 8), ps as u8)
+        } else {
+            ((dp << 16) | (pf << 8) | ps, BROADCAST)
+        };
+        Self {
+            priority,
+            pgn,
+            source,
+            destination,
+        }
+    }
+
+    pub fn to_raw(&self) -> u32 {
+        let pf = (self.pgn >> 8) & 0xFF;
+        let ps = if pf < 240 {
+            self.destination as u32
+        } else {
+            self.pgn & 0xFF
+        };
+        ((self.priority as u32 & 0x7) << 26)
+            | ((self.pgn >> 16 & 0x3) << 24)
+            | (pf << 16)
+            | (ps << 8)
+            | self.source as u32
+    }
+}
+
+/// A complete NMEA 2000 message, reassembled if it was sent as a fast packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub priority: u8,
+    pub pgn: u32,
+    pub source: u8,
+    pub destination: u8,
+    pub data: Vec<u8>,
+}
+
+/// Whether `pgn` is carried with the fast-packet protocol.
+pub fn is_fast_packet(pgn: u32) -> bool {
+    matches!(
+        pgn,
+        126208
+            | 126464
+            | 126996
+            | 126998
+            | 127233
+            | 127237
+            | 127489
+            | 127496..=127498
+            | 127503..=127504
+            | 127506
+            | 127510
+            | 128275
+            | 128520
+            | 129029
+            | 129038..=129041
+            | 129044..=129045
+            | 129284..=129285
+            | 129301..=129302
+            | 129538
+            | 129540..=129542
+            | 129545
+            | 129547
+            | 129549
+            | 129551
+            | 129556
+            | 129792..=129810
+            | 130060..=130061
+            | 130064..=130067
+            | 130069..=130071
+            | 130074
+            | 130320..=130324
+            | 130567
+            | 130577..=130578
+    )
+}
+
+/// Split a payload into fast-packet frames using sequence counter `sequence` (0 - 7).
+pub fn fast_packet_frames(sequence: u8, payload: &[u8]) -> Vec<[u8; 8]> {
+    let payload = &payload[..payload.len().min(FAST_PACKET_MAX)];
+    let seq = (sequence & 0x7) << 5;
+    let mut frames = Vec::with_capacity(1 + payload.len().saturating_sub(6).div_ceil(7));
+
+    let mut first = [0xFF; 8];
+    first[0] = seq;
+    first[1] = payload.len() as u8;
+    let n = payload.len().min(6);
+    first[2..2 + n].copy_from_slice(&payload[..n]);
+    frames.push(first);
+
+    for (i, chunk) in payload[n..].chunks(7).enumerate() {
+        let mut frame = [0xFF; 8];
+        frame[0] = seq | ((i + 1) as u8 & 0x1F);
+        frame[1..1 + chunk.len()].copy_from_slice(chunk);
+        frames.push(frame);
+    }
+    frames
+}
+
+struct Partial {
+    sequence: u8,
+    expected: usize,
+    next_frame: u8,
+    data: Vec<u8>,
+    started: SystemTime,
+}
+
+/// Reassembles fast-packet transfers from multiple senders.
+#[derive(Default)]
+pub struct FastPacketAssembler {
+    partials: HashMap<(u8, u32), Partial>,
+}
+
+impl FastPacketAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a frame received at `now`, returning the payload once the transfer is complete.
+    pub fn push(&mut self, id: &CanId, data: &[u8], now: SystemTime) -> Option<Vec<u8>> {
+        let key = (id.source, id.pgn);
+        let header = *data.first()?;
+        let sequence = header >> 5;
+        let index = header & 0x1F;
+
+        if index == 0 {
+            let expected = (*data.get(1)? as usize).min(FAST_PACKET_MAX);
+            let mut buf = Vec::with_capacity(expected);
+            buf.extend_from_slice(&data[2.min(data.len())..]);
+            buf.truncate(expected);
+            if buf.len() == expected {
+                self.partials.remove(&key);
+                return Some(buf);
+            }
+            self.partials.insert(
+                key,
+                Partial {
+                    sequence,
+                    expected,
+                    next_frame: 1,
+                    data: buf,
+                    started: now,
+                },
+            );
+            return None;
+        }
+
+        let partial = self.partials.get_mut(&key)?;
+        let stale = now
+            .duration_since(partial.started)
+            .map_or(true, |d| d > FAST_PACKET_TIMEOUT);
+        if partial.sequence != sequence || partial.next_frame != index || stale {
+            // A frame went missing, the rest of this transfer is useless
+            self.partials.remove(&key);
+            return None;
+        }
+        partial.data.extend_from_slice(&data[1..]);
+        partial.next_frame += 1;
+        if partial.data.len() >= partial.expected {
+            let mut partial = self.partials.remove(&key)?;
+            partial.data.truncate(partial.expected);
+            return Some(partial.data);
+        }
+        None
+    }
+}
+
+/// The 64 bit ISO 11783 NAME identifying a device and deciding address contention.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Name {
+    pub unique_number: u32,
+    pub manufacturer_code: u16,
+    pub device_instance: u8,
+    pub device_function: u8,
+    pub device_class: u8,
+    pub system_instance: u8,
+    pub industry_group: u8,
+    pub arbitrary_address_capable: bool,
+}
+
+impl Name {
+    pub fn to_u64(&self) -> u64 {
+        (self.unique_number as u64 & 0x1F_FFFF)
+            | ((self.manufacturer_code as u64 & 0x7FF) << 21)
+            | ((self.device_instance as u64) << 32)
+            | ((self.device_function as u64) << 40)
+            | ((self.device_class as u64 & 0x7F) << 49)
+            | ((self.system_instance as u64 & 0xF) << 56)
+            | ((self.industry_group as u64 & 0x7) << 60)
+            | ((self.arbitrary_address_capable as u64) << 63)
+    }
+
+    pub fn from_u64(name: u64) -> Self {
+        Self {
+            unique_number: (name & 0x1F_FFFF) as u32,
+            manufacturer_code: ((name >> 21) & 0x7FF) as u16,
+            device_instance: (name >> 32) as u8,
+            device_function: (name >> 40) as u8,
+            device_class: ((name >> 49) & 0x7F) as u8,
+            system_instance: ((name >> 56) & 0xF) as u8,
+            industry_group: ((name >> 60) & 0x7) as u8,
+            arbitrary_address_capable: name >> 63 != 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClaimState {
+    Idle,
+    Claiming(SystemTime),
+    Claimed,
+    /// No address could be claimed, the node must stay silent
+    Failed,
+}
+
+/// ISO 11783-5 address claim procedure for a single node.
+pub struct AddressClaim {
+    name: Name,
+    address: u8,
+    state: ClaimState,
+    taken: HashSet<u8>,
+}
+
+impl AddressClaim {
+    pub fn new(name: Name, preferred_address: u8) -> Self {
+        Self {
+            name,
+            address: preferred_address.min(253),
+            state: ClaimState::Idle,
+            taken: HashSet::new(),
+        }
+    }
+
+    pub fn name(&self) -> Name {
+        self.name
+    }
+
+    pub fn state(&self) -> ClaimState {
+        self.state
+    }
+
+    /// The claimed address, once the claim has gone unchallenged.
+    pub fn address(&self) -> Option<u8> {
+        match self.state {
+            ClaimState::Claimed => Some(self.address),
+            _ => None,
+        }
+    }
+
+    /// Begin claiming the current address, returning the claim to send.
+    pub fn start(&mut self, now: SystemTime) -> Message {
+        self.state = ClaimState::Claiming(now);
+        self.claim_message()
+    }
+
+    /// Advance the claim timer.
+    pub fn update(&mut self, now: SystemTime) {
+        if let ClaimState::Claiming(since) = self.state {
+            if now
+                .duration_since(since)
+                .map_or(true, |d| d >= CLAIM_TIMEOUT)
+            {
+                self.state = ClaimState::Claimed;
+            }
+        }
+    }
+
+    /// Handle an incoming network management message, returning any reply to send.
+    pub fn handle(&mut self, message: &Message, now: SystemTime) -> Option<Message> {
+        match message.pgn {
+            PGN_ISO_REQUEST => {
+                let requested = message.data.get(..3)?;
+                let requested = u32::from_le_bytes([requested[0], requested[1], requested[2], 0]);
+                let for_us =
+                    message.destination == BROADCAST || message.destination == self.address;
+                (requested == PGN_ADDRESS_CLAIM && for_us && self.state != ClaimState::Idle)
+                    .then(|| self.claim_message())
+            }
+            PGN_ADDRESS_CLAIM => {
+                let other = u64::from_le_bytes(message.data.get(..8)?.try_into().ok()?);
+                if message.source != NULL_ADDRESS {
+                    self.taken.insert(message.source);
+                }
+                if message.source != self.address
+                    || other == self.name.to_u64()
+                    || matches!(self.state, ClaimState::Idle | ClaimState::Failed)
+                {
+                    return None;
+                }
+                if self.name.to_u64() < other {
+                    // Lower NAME has priority, defend our address
+                    return Some(self.claim_message());
+                }
+                match self.next_free_address() {
+                    Some(address) if self.name.arbitrary_address_capable => {
+                        log::info!(
+                            "NMEA 2000 address {} lost, claiming {}",
+                            self.address,
+                            address
+                        );
+                        self.address = address;
+                        Some(self.start(now))
+                    }
+                    _ => {
+                        log::warn!(
+                            "NMEA 2000 address {} lost, no address available",
+                            self.address
+                        );
+                        self.state = ClaimState::Failed;
+                        Some(self.claim_message())
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn next_free_address(&self) -> Option<u8> {
+        (1..=253)
+            .map(|offset| ((self.address as u16 + offset) % 254) as u8)
+            .find(|a| *a != self.address && !self.taken.contains(a))
+    }
+
+    fn claim_message(&self) -> Message {
+        Message {
+            priority: 6,
+            pgn: PGN_ADDRESS_CLAIM,
+            source: match self.state {
+                ClaimState::Failed => NULL_ADDRESS,
+                _ => self.address,
+            },
+            destination: BROADCAST,
+            data: self.name.to_u64().to_le_bytes().to_vec(),
+        }
+    }
+}
+
+fn u16_field(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    let v = u16::from_le_bytes([bytes[0], bytes[1]]);
+    // The top values of a field are reserved for "not available" and error codes
+    (v < 0xFFFD).then_some(v)
+}
+
+fn i16_field(data: &[u8], offset: usize) -> Option<i16> {
+    let bytes = data.get(offset..offset + 2)?;
+    let v = i16::from_le_bytes([bytes[0], bytes[1]]);
+    (v < 0x7FFD).then_some(v)
+}
+
+fn u32_field(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (v < 0xFFFF_FFFD).then_some(v)
+}
+
+fn i8_field(data: &[u8], offset: usize) -> Option<i8> {
+    let v = *data.get(offset)? as i8;
+    (v < 0x7D).then_some(v)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: Option<f64>, resolution: f64) {
+    let raw = value
+        .map(|v| (v / resolution).round())
+        .filter(|v| (0.0..0xFFFD as f64).contains(v))
+        .map_or(0xFFFF, |v| v as u16);
+    buf.extend_from_slice(&raw.to_le_bytes());
+}
+
+fn put_i16(buf: &mut Vec<u8>, value: Option<f64>, resolution: f64) {
+    let raw = value
+        .map(|v| (v / resolution).round())
+        .filter(|v| (i16::MIN as f64..0x7FFD as f64).contains(v))
+        .map_or(0x7FFF, |v| v as i16);
+    buf.extend_from_slice(&raw.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: Option<f64>, resolution: f64) {
+    let raw = value
+        .map(|v| (v / resolution).round())
+        .filter(|v| (0.0..0xFFFF_FFFD_u32 as f64).contains(v))
+        .map_or(0xFFFF_FFFF, |v| v as u32);
+    buf.extend_from_slice(&raw.to_le_bytes());
+}
+
+fn put_i8(buf: &mut Vec<u8>, value: Option<f64>) {
+    let raw = value
+        .map(f64::round)
+        .filter(|v| (i8::MIN as f64..0x7D as f64).contains(v))
+        .map_or(0x7F, |v| v as i8);
+    buf.push(raw as u8);
+}
+
+/// PGN 127488 Engine Parameters, Rapid Update
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EngineRapid {
+    pub instance: u8,
+    /// Revolutions per second
+    pub speed: Option<f64>,
+    /// Pascal
+    pub boost_pressure: Option<f64>,
+    /// Percent
+    pub tilt_trim: Option<f64>,
+}
+
+impl EngineRapid {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            instance: *data.first()?,
+            speed: u16_field(data, 1).map(|v| v as f64 * 0.25 / 60.0),
+            boost_pressure: u16_field(data, 3).map(|v| v as f64 * 100.0),
+            tilt_trim: i8_field(data, 5).map(|v| v as f64),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8);
+        buf.push(self.instance);
+        put_u16(&mut buf, self.speed.map(|s| s * 60.0), 0.25);
+        put_u16(&mut buf, self.boost_pressure, 100.0);
+        put_i8(&mut buf, self.tilt_trim);
+        buf.extend_from_slice(&[0xFF, 0xFF]);
+        buf
+    }
+}
+
+/// PGN 127489 Engine Parameters, Dynamic
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EngineDynamic {
+    pub instance: u8,
+    /// Pascal
+    pub oil_pressure: Option<f64>,
+    /// Kelvin
+    pub oil_temperature: Option<f64>,
+    /// Coolant temperature in Kelvin
+    pub temperature: Option<f64>,
+    /// Volts
+    pub alternator_voltage: Option<f64>,
+    /// Cubic meters per second
+    pub fuel_rate: Option<f64>,
+    /// Total engine hours in seconds
+    pub total_hours: Option<f64>,
+    /// Pascal
+    pub coolant_pressure: Option<f64>,
+    /// Pascal
+    pub fuel_pressure: Option<f64>,
+    pub discrete_status1: u16,
+    pub discrete_status2: u16,
+    /// Percent
+    pub load: Option<f64>,
+    /// Percent
+    pub torque: Option<f64>,
+}
+
+/// Liters per hour to cubic meters per second
+const LPH_TO_M3S: f64 = 0.001 / 3600.0;
+
+impl EngineDynamic {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 26 {
+            return None;
+        }
+        Some(Self {
+            instance: data[0],
+            oil_pressure: u16_field(data, 1).map(|v| v as f64 * 100.0),
+            oil_temperature: u16_field(data, 3).map(|v| v as f64 * 0.1),
+            temperature: u16_field(data, 5).map(|v| v as f64 * 0.01),
+            alternator_voltage: i16_field(data, 7).map(|v| v as f64 * 0.01),
+            fuel_rate: i16_field(data, 9).map(|v| v as f64 * 0.1 * LPH_TO_M3S),
+            total_hours: u32_field(data, 11).map(|v| v as f64),
+            coolant_pressure: u16_field(data, 15).map(|v| v as f64 * 100.0),
+            fuel_pressure: u16_field(data, 17).map(|v| v as f64 * 1000.0),
+            discrete_status1: u16::from_le_bytes([data[20], data[21]]),
+            discrete_status2: u16::from_le_bytes([data[22], data[23]]),
+            load: i8_field(data, 24).map(|v| v as f64),
+            torque: i8_field(data, 25).map(|v| v as f64),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(26);
+        buf.push(self.instance);
+        put_u16(&mut buf, self.oil_pressure, 100.0);
+        put_u16(&mut buf, self.oil_temperature, 0.1);
+        put_u16(&mut buf, self.temperature, 0.01);
+        put_i16(&mut buf, self.alternator_voltage, 0.01);
+        put_i16(&mut buf, self.fuel_rate.map(|r| r / LPH_TO_M3S), 0.1);
+        put_u32(&mut buf, self.total_hours, 1.0);
+        put_u16(&mut buf, self.coolant_pressure, 100.0);
+        put_u16(&mut buf, self.fuel_pressure, 1000.0);
+        buf.push(0xFF);
+        buf.extend_from_slice(&self.discrete_status1.to_le_bytes());
+        buf.extend_from_slice(&self.discrete_status2.to_le_bytes());
+        put_i8(&mut buf, self.load);
+        put_i8(&mut buf, self.torque);
+        buf
+    }
+}
+
+/// PGN 130312 Temperature
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Temperature {
+    pub sid: u8,
+    pub instance: u8,
+    /// Temperature source, e.g. 0 sea, 1 outside, 2 inside, 3 engine room
+    pub source: u8,
+    /// Kelvin
+    pub actual: Option<f64>,
+    /// Kelvin
+    pub set: Option<f64>,
+}
+
+impl Temperature {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 7 {
+            return None;
+        }
+        Some(Self {
+            sid: data[0],
+            instance: data[1],
+            source: data[2],
+            actual: u16_field(data, 3).map(|v| v as f64 * 0.01),
+            set: u16_field(data, 5).map(|v| v as f64 * 0.01),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8);
+        buf.extend_from_slice(&[self.sid, self.instance, self.source]);
+        put_u16(&mut buf, self.actual, 0.01);
+        put_u16(&mut buf, self.set, 0.01);
+        buf.push(0xFF);
+        buf
+    }
+}
+
+/// PGN 127508 Battery Status
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BatteryStatus {
+    pub instance: u8,
+    /// Volts
+    pub voltage: Option<f64>,
+    /// Amperes, positive when charging
+    pub current: Option<f64>,
+    /// Kelvin
+    pub temperature: Option<f64>,
+    pub sid: u8,
+}
+
+impl BatteryStatus {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        Some(Self {
+            instance: data[0],
+            voltage: i16_field(data, 1).map(|v| v as f64 * 0.01),
+            current: i16_field(data, 3).map(|v| v as f64 * 0.1),
+            temperature: u16_field(data, 5).map(|v| v as f64 * 0.01),
+            sid: data[7],
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8);
+        buf.push(self.instance);
+        put_i16(&mut buf, self.voltage, 0.01);
+        put_i16(&mut buf, self.current, 0.1);
+        put_u16(&mut buf, self.temperature, 0.01);
+        buf.push(self.sid);
+        buf
+    }
+}
+
+/// A payload ready to be sent, as produced by a publisher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outgoing {
+    pub priority: u8,
+    pub pgn: u32,
+    pub data: Vec<u8>,
+}
+
+impl From<EngineRapid> for Outgoing {
+    fn from(value: EngineRapid) -> Self {
+        Outgoing {
+            priority: 2,
+            pgn: PGN_ENGINE_RAPID,
+            data: value.encode(),
+        }
+    }
+}
+
+impl From<EngineDynamic> for Outgoing {
+    fn from(value: EngineDynamic) -> Self {
+        Outgoing {
+            priority: 2,
+            pgn: PGN_ENGINE_DYNAMIC,
+            data: value.encode(),
+        }
+    }
+}
+
+impl From<Temperature> for Outgoing {
+    fn from(value: Temperature) -> Self {
+        Outgoing {
+            priority: 5,
+            pgn: PGN_TEMPERATURE,
+            data: value.encode(),
+        }
+    }
+}
+
+impl From<BatteryStatus> for Outgoing {
+    fn from(value: BatteryStatus) -> Self {
+        Outgoing {
+            priority: 6,
+            pgn: PGN_BATTERY_STATUS,
+            data: value.encode(),
+        }
+    }
+}
+
+struct Publisher {
+    poll: Box<dyn FnMut() -> Poll<Option<Outgoing>>>,
+    latest: Option<Outgoing>,
+    limiter: RateLimiter,
+}
+
+/// An NMEA 2000 node: claims an address, answers network management requests and transmits
+/// sensor streams as PGNs.
+pub struct Nmea2000<B: CanBus> {
+    bus: B,
+    claim: AddressClaim,
+    assembler: FastPacketAssembler,
+    sequences: HashMap<u32, u8>,
+    publishers: Vec<Publisher>,
+}
+
+impl<B: CanBus> Nmea2000<B> {
+    pub fn new(bus: B, name: Name, preferred_address: u8) -> Self {
+        Nmea2000::<B> {
+            bus,
+            claim: AddressClaim::new(name, preferred_address),
+            assembler: FastPacketAssembler::new(),
+            sequences: HashMap::new(),
+            publishers: Vec::new(),
+        }
+    }
+
+    /// Transmit the latest value of `subscriber` every `interval`, encoded by `encode`.
+    ///
+    /// Nothing is sent until the first value arrives, and sending stops if the source is
+    /// dropped.
+    pub fn publish<T, O>(
+        mut self,
+        subscriber: Subscriber<T>,
+        interval: Duration,
+        encode: impl Fn(T) -> O + 'static,
+    ) -> Self
+    where
+        T: Clone + 'static,
+        O: Into<Outgoing>,
+    {
+        let mut updates = Updates::new(subscriber);
+        self.publishers.push(Publisher {
+            poll: Box::new(move || match poll_update(&mut updates) {
+                Poll::Ready(Some(value)) => Poll::Ready(Some(encode(value).into())),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            }),
+            latest: None,
+            limiter: RateLimiter::new(interval),
+        });
+        self
+    }
+
+    /// The claimed source address, if the claim has completed.
+    pub fn address(&self) -> Option<u8> {
+        self.claim.address()
+    }
+
+    /// Send a message from our claimed address, splitting it into fast packets as needed.
+    pub fn send(&mut self, pgn: u32, priority: u8, destination: u8, data: &[u8]) {
+        let Some(source) = self.claim.address() else {
+            log::debug!("Not sending PGN {} before address claim completes", pgn);
+            return;
+        };
+        self.transmit(&Message {
+            priority,
+            pgn,
+            source,
+            destination,
+            data: data.to_vec(),
+        });
+    }
+
+    /// Process received frames and due transmissions at `now`, returning completed messages.
+    pub fn poll(&mut self, now: SystemTime) -> Vec<Message> {
+        if self.claim.state() == ClaimState::Idle {
+            let claim = self.claim.start(now);
+            self.transmit(&claim);
+        }
+        self.claim.update(now);
+
+        let mut received = Vec::new();
+        loop {
+            let frame = match self.bus.receive() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("CAN receive error: {:?}", e);
+                    break;
+                }
+            };
+            let id = CanId::from_raw(frame.id);
+            let data = if is_fast_packet(id.pgn) {
+                match self.assembler.push(&id, &frame.data, now) {
+                    Some(data) => data,
+                    None => continue,
+                }
+            } else {
+                frame.data
+            };
+            let message = Message {
+                priority: id.priority,
+                pgn: id.pgn,
+                source: id.source,
+                destination: id.destination,
+                data,
+            };
+            if let Some(reply) = self.claim.handle(&message, now) {
+                self.transmit(&reply);
+            }
+            received.push(message);
+        }
+
+        let mut due = Vec::new();
+        for publisher in &mut self.publishers {
+            match (publisher.poll)() {
+                Poll::Ready(Some(outgoing)) => publisher.latest = Some(outgoing),
+                Poll::Ready(None) => publisher.latest = None,
+                Poll::Pending => (),
+            }
+            if let Some(outgoing) = &publisher.latest {
+                if publisher.limiter.ready(now) {
+                    due.push(outgoing.clone());
+                }
+            }
+        }
+        for outgoing in due {
+            self.send(outgoing.pgn, outgoing.priority, BROADCAST, &outgoing.data);
+        }
+
+        received
+    }
+
+    /// Consume the node and return the underlying bus
+    pub fn release(self) -> B {
+        self.bus
+    }
+
+    fn transmit(&mut self, message: &Message) {
+        let id = CanId {
+            priority: message.priority,
+            pgn: message.pgn,
+            source: message.source,
+            destination: message.destination,
+        }
+        .to_raw();
+
+        let frames = if is_fast_packet(message.pgn) {
+            let sequence = self.sequences.entry(message.pgn).or_insert(0);
+            let frames = fast_packet_frames(*sequence, &message.data)
+                .into_iter()
+                .map(|data| data.to_vec())
+                .collect();
+            *sequence = (*sequence + 1) & 0x7;
+            frames
+        } else {
+            vec![message.data.clone()]
+        };
+
+        for data in frames {
+            if let Err(e) = self.bus.transmit(&CanFrame { id, data }) {
+                log::error!("CAN transmit error for PGN {}: {:?}", message.pgn, e);
+                return;
+            }
+        }
+    }
+}
+
+impl<B: CanBus> SensESPSensor for Nmea2000<B> {
+    fn tick(&mut self) {
+        self.poll(SystemTime::now());
+    }
+}
+
+/// Maps one field of a PGN to a Signal K path.
+pub struct Mapping {
+    pub pgn: u32,
+    /// Signal K path, `{instance}` is replaced by the instance decoded from the message
+    pub path: &'static str,
+    /// Decode the instance and value from the payload, `None` if not available
+    pub value: fn(&[u8]) -> Option<(u8, Value)>,
+}
+
+fn temperature_from(source: u8, data: &[u8]) -> Option<(u8, Value)> {
+    let t = Temperature::decode(data)?;
+    (t.source == source).then_some((t.instance, t.actual?.into()))
+}
+
+/// The PGN fields translated by [`Nmea2000Gateway`] unless configured otherwise.
+pub const DEFAULT_MAPPINGS: &[Mapping] = &[
+    Mapping {
+        pgn: PGN_ENGINE_RAPID,
+        path: "propulsion.{instance}.revolutions",
+        value: |d| EngineRapid::decode(d).and_then(|e| Some((e.instance, e.speed?.into()))),
+    },
+    Mapping {
+        pgn: PGN_ENGINE_RAPID,
+        path: "propulsion.{instance}.boostPressure",
+        value: |d| {
+            EngineRapid::decode(d).and_then(|e| Some((e.instance, e.boost_pressure?.into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_RAPID,
+        path: "propulsion.{instance}.drive.trimState",
+        value: |d| {
+            EngineRapid::decode(d).and_then(|e| Some((e.instance, (e.tilt_trim? / 100.0).into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.oilPressure",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.oil_pressure?.into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.oilTemperature",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.oil_temperature?.into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.temperature",
+        value: |d| EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.temperature?.into()))),
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.alternatorVoltage",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.alternator_voltage?.into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.fuel.rate",
+        value: |d| EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.fuel_rate?.into()))),
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.runTime",
+        value: |d| EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.total_hours?.into()))),
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.coolantPressure",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.coolant_pressure?.into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.fuel.pressure",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, e.fuel_pressure?.into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.engineLoad",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, (e.load? / 100.0).into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_ENGINE_DYNAMIC,
+        path: "propulsion.{instance}.engineTorque",
+        value: |d| {
+            EngineDynamic::decode(d).and_then(|e| Some((e.instance, (e.torque? / 100.0).into())))
+        },
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.water.temperature",
+        value: |d| temperature_from(0, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.outside.temperature",
+        value: |d| temperature_from(1, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.inside.temperature",
+        value: |d| temperature_from(2, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.inside.engineRoom.temperature",
+        value: |d| temperature_from(3, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.inside.mainCabin.temperature",
+        value: |d| temperature_from(4, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.inside.refrigerator.temperature",
+        value: |d| temperature_from(7, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "environment.inside.freezer.temperature",
+        value: |d| temperature_from(13, d),
+    },
+    Mapping {
+        pgn: PGN_TEMPERATURE,
+        path: "propulsion.{instance}.exhaustTemperature",
+        value: |d| temperature_from(14, d),
+    },
+    Mapping {
+        pgn: PGN_BATTERY_STATUS,
+        path: "electrical.batteries.{instance}.voltage",
+        value: |d| BatteryStatus::decode(d).and_then(|b| Some((b.instance, b.voltage?.into()))),
+    },
+    Mapping {
+        pgn: PGN_BATTERY_STATUS,
+        path: "electrical.batteries.{instance}.current",
+        value: |d| BatteryStatus::decode(d).and_then(|b| Some((b.instance, b.current?.into()))),
+    },
+    Mapping {
+        pgn: PGN_BATTERY_STATUS,
+        path: "electrical.batteries.{instance}.temperature",
+        value: |d| BatteryStatus::decode(d).and_then(|b| Some((b.instance, b.temperature?.into()))),
+    },
+];
+
+/// Translate a received message into Signal K updates using `mappings`.
+pub fn translate(message: &Message, mappings: &[Mapping]) -> Vec<Update> {
+    mappings
+        .iter()
+        .filter(|m| m.pgn == message.pgn)
+        .filter_map(|m| {
+            let (instance, value) = (m.value)(&message.data)?;
+            let path = m.path.replace("{instance}", &instance.to_string());
+            Some(Update::new(path, value))
+        })
+        .collect()
+}
+
+/// Republishes PGNs received by an NMEA 2000 node as Signal K updates.
+///
+/// The gateway drives the node, so register the gateway with the `Application` instead of
+/// the node itself.
+pub struct Nmea2000Gateway<B: CanBus> {
+    node: Nmea2000<B>,
+    output: SignalKOutput,
+    mappings: &'static [Mapping],
+}
+
+impl<B: CanBus> Nmea2000Gateway<B> {
+    pub fn new(node: Nmea2000<B>, output: &SignalKOutput) -> Self {
+        Nmea2000Gateway::<B> {
+            node,
+            output: output.clone(),
+            mappings: DEFAULT_MAPPINGS,
+        }
+    }
+
+    /// Replace the default PGN to path table.
+    pub fn mappings(mut self, mappings: &'static [Mapping]) -> Self {
+        self.mappings = mappings;
+        self
+    }
+
+    /// Process the node at `now` and publish the translated updates.
+    pub fn poll(&mut self, now: SystemTime) {
+        for message in self.node.poll(now) {
+            for update in translate(&message, self.mappings) {
+                self.output.publish(update);
+            }
+        }
+    }
+
+    /// Consume the gateway and return the underlying node
+    pub fn release(self) -> Nmea2000<B> {
+        self.node
+    }
+}
+
+impl<B: CanBus> SensESPSensor for Nmea2000Gateway<B> {
+    fn tick(&mut self) {
+        self.poll(SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn name(unique_number: u32) -> Name {
+        Name {
+            unique_number,
+            manufacturer_code: 2046,
+            device_function: 140,
+            device_class: 50,
+            industry_group: 4,
+            arbitrary_address_capable: true,
+            ..Default::default()
+        }
+    }
+
+    /// Poll every node in turn for `steps` rounds 50 ms apart, returning the time reached.
+    fn run(
+        nodes: &mut [&mut Nmea2000<VirtualCan>],
+        mut now: SystemTime,
+        steps: usize,
+    ) -> SystemTime {
+        for _ in 0..steps {
+            for node in nodes.iter_mut() {
+                node.poll(now);
+            }
+            now += Duration::from_millis(50);
+        }
+        now
+    }
+
+    fn assert_close(value: Option<f64>, expected: f64, resolution: f64) {
+        let value = value.expect("field not available");
+        assert!(
+            (value - expected).abs() <= resolution / 2.0 + 1e-9,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn can_id_round_trips() {
+        let rapid = CanId::from_raw(0x09F2_0017);
+        assert_eq!(
+            rapid,
+            CanId {
+                priority: 2,
+                pgn: PGN_ENGINE_RAPID,
+                source: 0x17,
+                destination: BROADCAST,
+            }
+        );
+        let request = CanId::from_raw(0x18EA_2301);
+        assert_eq!(
+            request,
+            CanId {
+                priority: 6,
+                pgn: PGN_ISO_REQUEST,
+                source: 0x01,
+                destination: 0x23,
+            }
+        );
+        for raw in [
+            0x09F2_0017,
+            0x18EA_2301,
+            0x18EE_FF10,
+            0x09F1_0D23,
+            0x15FD_0805,
+        ] {
+            assert_eq!(CanId::from_raw(raw).to_raw(), raw, "{:08X}", raw);
+        }
+    }
+
+    #[test]
+    fn name_round_trips() {
+        let n = name(0x1_2345);
+        assert_eq!(Name::from_u64(n.to_u64()), n);
+        assert_eq!(n.to_u64() >> 63, 1);
+        assert_eq!(n.to_u64() & 0x1F_FFFF, 0x1_2345);
+    }
+
+    #[test]
+    fn fast_packet_frames_split_the_payload() {
+        let payload: Vec<u8> = (0..26).collect();
+        let frames = fast_packet_frames(3, &payload);
+        assert_eq!(
+            frames,
+            [
+                [0x60, 26, 0, 1, 2, 3, 4, 5],
+                [0x61, 6, 7, 8, 9, 10, 11, 12],
+                [0x62, 13, 14, 15, 16, 17, 18, 19],
+                [0x63, 20, 21, 22, 23, 24, 25, 0xFF],
+            ]
+        );
+        assert_eq!(
+            fast_packet_frames(9, &[1, 2]),
+            [[0x20, 2, 1, 2, 0xFF, 0xFF, 0xFF, 0xFF]]
+        );
+        assert_eq!(fast_packet_frames(0, &[0; 300]).len(), 32);
+    }
+
+    #[test]
+    fn fast_packets_reassemble() {
+        let id = CanId::from_raw(0x09F2_0117);
+        let mut assembler = FastPacketAssembler::new();
+        let payload: Vec<u8> = (100..126).collect();
+        let frames = fast_packet_frames(5, &payload);
+        let (last, rest) = frames.split_last().unwrap();
+        for frame in rest {
+            assert_eq!(assembler.push(&id, frame, start()), None);
+        }
+        assert_eq!(assembler.push(&id, last, start()), Some(payload));
+
+        // A transfer that fits in the first frame completes at once
+        assert_eq!(
+            assembler.push(&id, &fast_packet_frames(0, &[7, 8, 9])[0], start()),
+            Some(vec![7, 8, 9])
+        );
+    }
+
+    #[test]
+    fn fast_packets_from_two_senders_interleave() {
+        let a = CanId::from_raw(0x09F2_0117);
+        let b = CanId::from_raw(0x09F2_0118);
+        let payload_a = vec![0xAA; 20];
+        let payload_b = vec![0xBB; 20];
+        let frames_a = fast_packet_frames(1, &payload_a);
+        let frames_b = fast_packet_frames(2, &payload_b);
+        let mut assembler = FastPacketAssembler::new();
+        let mut done = Vec::new();
+        for (fa, fb) in frames_a.iter().zip(&frames_b) {
+            done.extend(assembler.push(&a, fa, start()));
+            done.extend(assembler.push(&b, fb, start()));
+        }
+        assert_eq!(done, [payload_a, payload_b]);
+    }
+
+    #[test]
+    fn fast_packets_drop_incomplete_transfers() {
+        let id = CanId::from_raw(0x09F2_0117);
+        let payload: Vec<u8> = (0..26).collect();
+        let frames = fast_packet_frames(0, &payload);
+        let mut assembler = FastPacketAssembler::new();
+
+        // A missing frame discards the transfer
+        assembler.push(&id, &frames[0], start());
+        assembler.push(&id, &frames[2], start());
+        assert_eq!(assembler.push(&id, &frames[3], start()), None);
+
+        // So does a stalled one
+        assembler.push(&id, &frames[0], start());
+        let late = start() + FAST_PACKET_TIMEOUT + Duration::from_millis(1);
+        for frame in &frames[1..] {
+            assert_eq!(assembler.push(&id, frame, late), None);
+        }
+
+        // A frame of another sequence does not complete the transfer
+        assembler.push(&id, &frames[0], start());
+        let other = fast_packet_frames(1, &payload);
+        assert_eq!(assembler.push(&id, &other[1], start()), None);
+        assert_eq!(assembler.push(&id, &[], start()), None);
+    }
+
+    #[test]
+    fn engine_rapid_round_trips() {
+        let rapid = EngineRapid {
+            instance: 1,
+            speed: Some(2500.0 / 60.0),
+            boost_pressure: Some(120_000.0),
+            tilt_trim: Some(-10.0),
+        };
+        let data = rapid.encode();
+        assert_eq!(data.len(), 8);
+        let decoded = EngineRapid::decode(&data).unwrap();
+        assert_eq!(decoded.instance, 1);
+        assert_close(decoded.speed, 2500.0 / 60.0, 0.25 / 60.0);
+        assert_close(decoded.boost_pressure, 120_000.0, 100.0);
+        assert_close(decoded.tilt_trim, -10.0, 1.0);
+
+        let unknown = EngineRapid::decode(&EngineRapid::default().encode()).unwrap();
+        assert_eq!(unknown, EngineRapid::default());
+        assert_eq!(EngineRapid::decode(&[]), None);
+    }
+
+    #[test]
+    fn engine_dynamic_round_trips() {
+        let dynamic = EngineDynamic {
+            instance: 0,
+            oil_pressure: Some(300_000.0),
+            oil_temperature: Some(368.15),
+            temperature: Some(353.15),
+            alternator_voltage: Some(14.1),
+            fuel_rate: Some(12.5 * LPH_TO_M3S),
+            total_hours: Some(1234.0 * 3600.0),
+            coolant_pressure: Some(110_000.0),
+            fuel_pressure: Some(250_000.0),
+            discrete_status1: 0x0001,
+            discrete_status2: 0x0000,
+            load: Some(65.0),
+            torque: Some(-5.0),
+        };
+        let data = dynamic.encode();
+        assert_eq!(data.len(), 26);
+        let decoded = EngineDynamic::decode(&data).unwrap();
+        assert_close(decoded.oil_pressure, 300_000.0, 100.0);
+        assert_close(decoded.oil_temperature, 368.15, 0.1);
+        assert_close(decoded.temperature, 353.15, 0.01);
+        assert_close(decoded.alternator_voltage, 14.1, 0.01);
+        assert_close(decoded.fuel_rate, 12.5 * LPH_TO_M3S, 0.1 * LPH_TO_M3S);
+        assert_close(decoded.total_hours, 1234.0 * 3600.0, 1.0);
+        assert_close(decoded.coolant_pressure, 110_000.0, 100.0);
+        assert_close(decoded.fuel_pressure, 250_000.0, 1000.0);
+        assert_eq!(decoded.discrete_status1, 0x0001);
+        assert_close(decoded.load, 65.0, 1.0);
+        assert_close(decoded.torque, -5.0, 1.0);
+
+        assert_eq!(EngineDynamic::decode(&data[..25]), None);
+        let unknown = EngineDynamic::decode(&EngineDynamic::default().encode()).unwrap();
+        assert_eq!(unknown, EngineDynamic::default());
+    }
+
+    #[test]
+    fn out_of_range_values_encode_as_not_available() {
+        let dynamic = EngineDynamic {
+            oil_pressure: Some(-500.0),
+            temperature: Some(1.0e9),
+            alternator_voltage: Some(f64::NAN),
+            load: Some(200.0),
+            ..Default::default()
+        };
+        let decoded = EngineDynamic::decode(&dynamic.encode()).unwrap();
+        assert_eq!(decoded, EngineDynamic::default());
+    }
+
+    #[test]
+    fn temperature_and_battery_round_trip() {
+        let temperature = Temperature {
+            sid: 3,
+            instance: 1,
+            source: 0,
+            actual: Some(290.15),
+            set: None,
+        };
+        let decoded = Temperature::decode(&temperature.encode()).unwrap();
+        assert_eq!((decoded.sid, decoded.instance, decoded.source), (3, 1, 0));
+        assert_close(decoded.actual, 290.15, 0.01);
+        assert_eq!(decoded.set, None);
+        assert_eq!(Temperature::decode(&[0; 6]), None);
+
+        let battery = BatteryStatus {
+            instance: 2,
+            voltage: Some(12.85),
+            current: Some(-5.3),
+            temperature: None,
+            sid: 0xFF,
+        };
+        let decoded = BatteryStatus::decode(&battery.encode()).unwrap();
+        assert_close(decoded.voltage, 12.85, 0.01);
+        assert_close(decoded.current, -5.3, 0.1);
+        assert_eq!(decoded.temperature, None);
+        assert_eq!(decoded.sid, 0xFF);
+    }
+
+    #[test]
+    fn nodes_claim_addresses_over_a_virtual_bus() {
+        let bus = VirtualBus::new();
+        let mut first = Nmea2000::new(bus.connect(), name(1), 30);
+        let mut second = Nmea2000::new(bus.connect(), name(2), 30);
+        assert_eq!(first.address(), None);
+
+        run(&mut [&mut first, &mut second], start(), 10);
+        // The lower NAME keeps the contested address, the other moves to the next one
+        assert_eq!(first.address(), Some(30));
+        assert_eq!(second.address(), Some(31));
+    }
+
+    #[test]
+    fn claim_is_not_final_before_the_timeout() {
+        let bus = VirtualBus::new();
+        let mut node = Nmea2000::new(bus.connect(), name(1), 42);
+        node.poll(start());
+        node.poll(start() + CLAIM_TIMEOUT - Duration::from_millis(1));
+        assert_eq!(node.address(), None);
+        node.poll(start() + CLAIM_TIMEOUT);
+        assert_eq!(node.address(), Some(42));
+    }
+
+    #[test]
+    fn node_without_arbitrary_address_gives_up() {
+        let fixed = |unique_number| Name {
+            arbitrary_address_capable: false,
+            ..name(unique_number)
+        };
+        let mut claim = AddressClaim::new(fixed(9), 30);
+        claim.start(start());
+        let winner = Message {
+            priority: 6,
+            pgn: PGN_ADDRESS_CLAIM,
+            source: 30,
+            destination: BROADCAST,
+            data: fixed(1).to_u64().to_le_bytes().to_vec(),
+        };
+        let reply = claim.handle(&winner, start()).unwrap();
+        assert_eq!(claim.state(), ClaimState::Failed);
+        assert_eq!(reply.source, NULL_ADDRESS);
+        claim.update(start() + Duration::from_secs(1));
+        assert_eq!(claim.address(), None);
+    }
+
+    #[test]
+    fn claim_is_repeated_on_request() {
+        let mut claim = AddressClaim::new(name(1), 30);
+        let request = |destination| Message {
+            priority: 6,
+            pgn: PGN_ISO_REQUEST,
+            source: 40,
+            destination,
+            data: PGN_ADDRESS_CLAIM.to_le_bytes()[..3].to_vec(),
+        };
+        // Nothing to repeat before claiming
+        assert_eq!(claim.handle(&request(BROADCAST), start()), None);
+        claim.start(start());
+        let reply = claim.handle(&request(BROADCAST), start()).unwrap();
+        assert_eq!(reply.pgn, PGN_ADDRESS_CLAIM);
+        assert_eq!(reply.data, name(1).to_u64().to_le_bytes());
+        assert!(claim.handle(&request(30), start()).is_some());
+        assert_eq!(claim.handle(&request(31), start()), None);
+    }
+
+    #[test]
+    fn fast_packet_messages_cross_the_bus() {
+        let bus = VirtualBus::new();
+        let mut sender = Nmea2000::new(bus.connect(), name(1), 30);
+        let mut receiver = Nmea2000::new(bus.connect(), name(2), 40);
+        let now = run(&mut [&mut sender, &mut receiver], start(), 10);
+
+        let dynamic = EngineDynamic {
+            instance: 1,
+            temperature: Some(353.15),
+            ..Default::default()
+        };
+        sender.send(PGN_ENGINE_DYNAMIC, 2, BROADCAST, &dynamic.encode());
+        let received = receiver.poll(now);
+        assert_eq!(
+            received,
+            [Message {
+                priority: 2,
+                pgn: PGN_ENGINE_DYNAMIC,
+                source: 30,
+                destination: BROADCAST,
+                data: dynamic.encode(),
+            }]
+        );
+    }
+
+    #[test]
+    fn nothing_is_sent_before_the_claim() {
+        let bus = VirtualBus::new();
+        let mut sender = Nmea2000::new(bus.connect(), name(1), 30);
+        let mut receiver = bus.connect();
+        sender.send(
+            PGN_ENGINE_RAPID,
+            2,
+            BROADCAST,
+            &EngineRapid::default().encode(),
+        );
+        assert_eq!(receiver.receive(), Ok(None));
+    }
+
+    #[test]
+    fn publishers_send_at_their_interval() {
+        let bus = VirtualBus::new();
+        let rpm = eyeball::shared::Observable::new(0.0f64);
+        let mut sender = Nmea2000::new(bus.connect(), name(1), 30).publish(
+            rpm.subscribe(),
+            Duration::from_millis(100),
+            |speed| EngineRapid {
+                instance: 0,
+                speed: Some(speed),
+                ..Default::default()
+            },
+        );
+        let mut receiver = Nmea2000::new(bus.connect(), name(2), 40);
+        let now = run(&mut [&mut sender, &mut receiver], start(), 10);
+
+        rpm.set(30.0);
+        let mut speeds = Vec::new();
+        let mut t = now;
+        for _ in 0..10 {
+            sender.poll(t);
+            for message in receiver.poll(t) {
+                if message.pgn == PGN_ENGINE_RAPID {
+                    speeds.push(EngineRapid::decode(&message.data).unwrap().speed);
+                }
+            }
+            t += Duration::from_millis(50);
+        }
+        // Every other poll is due, repeating the latest value
+        assert_eq!(speeds, [Some(30.0); 5]);
+
+        drop(rpm);
+        sender.poll(t + Duration::from_secs(1));
+        assert!(receiver.poll(t + Duration::from_secs(1)).is_empty());
+    }
+
+    /// Engine 0 at 2234 rpm, boost and trim not available.
+    const RAPID_FRAME: (u32, [u8; 8]) = (
+        0x09F2_0017,
+        [0x00, 0xE8, 0x22, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF],
+    );
+    /// Engine 0 at 3 bar oil pressure, 80 °C, 14.1 V, 12.5 l/h, 1234 h and 65 % load.
+    const DYNAMIC_FRAMES: [(u32, [u8; 8]); 4] = [
+        (
+            0x09F2_0117,
+            [0x40, 0x1A, 0x00, 0xB8, 0x0B, 0xFF, 0xFF, 0xF3],
+        ),
+        (
+            0x09F2_0117,
+            [0x41, 0x89, 0x82, 0x05, 0x7D, 0x00, 0x20, 0xC9],
+        ),
+        (
+            0x09F2_0117,
+            [0x42, 0x43, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        ),
+        (
+            0x09F2_0117,
+            [0x43, 0x00, 0x00, 0x00, 0x00, 0x41, 0x7F, 0xFF],
+        ),
+    ];
+    /// Sea temperature 297.88 K, no set temperature.
+    const SEA_TEMPERATURE_FRAME: (u32, [u8; 8]) = (
+        0x15FD_0824,
+        [0x01, 0x01, 0x00, 0x5C, 0x74, 0xFF, 0xFF, 0xFF],
+    );
+    /// Battery 0 at 12.66 V discharging 1 A, temperature not available.
+    const BATTERY_FRAME: (u32, [u8; 8]) = (
+        0x19F2_1430,
+        [0x00, 0xF2, 0x04, 0xF6, 0xFF, 0xFF, 0xFF, 0x00],
+    );
+
+    fn message(frame: (u32, [u8; 8])) -> Message {
+        let id = CanId::from_raw(frame.0);
+        Message {
+            priority: id.priority,
+            pgn: id.pgn,
+            source: id.source,
+            destination: id.destination,
+            data: frame.1.to_vec(),
+        }
+    }
+
+    fn numbers(updates: &[Update]) -> Vec<(&str, f64)> {
+        updates
+            .iter()
+            .map(|u| (u.path.as_str(), u.value.as_f64().unwrap()))
+            .collect()
+    }
+
+    fn assert_numbers(updates: &[Update], expected: &[(&str, f64)]) {
+        let actual = numbers(updates);
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for ((path, value), (expected_path, expected_value)) in actual.iter().zip(expected) {
+            assert_eq!(path, expected_path);
+            assert!(
+                (value - expected_value).abs() < 1e-9,
+                "{}: {} != {}",
+                path,
+                value,
+                expected_value
+            );
+        }
+    }
+
+    #[test]
+    fn translates_engine_rapid() {
+        assert_numbers(
+            &translate(&message(RAPID_FRAME), DEFAULT_MAPPINGS),
+            &[("propulsion.0.revolutions", 8936.0 * 0.25 / 60.0)],
+        );
+    }
+
+    #[test]
+    fn translates_reassembled_engine_dynamic() {
+        let mut assembler = FastPacketAssembler::new();
+        let mut data = None;
+        for (id, frame) in DYNAMIC_FRAMES {
+            data = assembler.push(&CanId::from_raw(id), &frame, start());
+        }
+        let message = Message {
+            data: data.unwrap(),
+            ..message(DYNAMIC_FRAMES[0])
+        };
+        assert_numbers(
+            &translate(&message, DEFAULT_MAPPINGS),
+            &[
+                ("propulsion.0.oilPressure", 300_000.0),
+                ("propulsion.0.temperature", 353.15),
+                ("propulsion.0.alternatorVoltage", 14.1),
+                ("propulsion.0.fuel.rate", 12.5 * LPH_TO_M3S),
+                ("propulsion.0.runTime", 1234.0 * 3600.0),
+                ("propulsion.0.engineLoad", 0.65),
+            ],
+        );
+    }
+
+    #[test]
+    fn translates_temperatures_by_source() {
+        assert_numbers(
+            &translate(&message(SEA_TEMPERATURE_FRAME), DEFAULT_MAPPINGS),
+            &[("environment.water.temperature", 297.88)],
+        );
+
+        let exhaust = Temperature {
+            sid: 0,
+            instance: 1,
+            source: 14,
+            actual: Some(450.0),
+            set: None,
+        };
+        let message = Message {
+            data: exhaust.encode(),
+            ..message(SEA_TEMPERATURE_FRAME)
+        };
+        assert_numbers(
+            &translate(&message, DEFAULT_MAPPINGS),
+            &[("propulsion.1.exhaustTemperature", 450.0)],
+        );
+
+        // Sources without a mapping are ignored
+        let message = Message {
+            data: Temperature {
+                source: 5,
+                ..exhaust
+            }
+            .encode(),
+            ..message
+        };
+        assert!(translate(&message, DEFAULT_MAPPINGS).is_empty());
+    }
+
+    #[test]
+    fn translates_battery_status() {
+        assert_numbers(
+            &translate(&message(BATTERY_FRAME), DEFAULT_MAPPINGS),
+            &[
+                ("electrical.batteries.0.voltage", 12.66),
+                ("electrical.batteries.0.current", -1.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn translate_skips_unmapped_and_short_messages() {
+        let heading = message((
+            0x09F1_1223,
+            [0xFF, 0x6E, 0x46, 0xFF, 0x7F, 0xFF, 0x7F, 0xFD],
+        ));
+        assert!(translate(&heading, DEFAULT_MAPPINGS).is_empty());
+
+        let short = Message {
+            data: vec![0x00, 0xF2],
+            ..message(BATTERY_FRAME)
+        };
+        assert!(translate(&short, DEFAULT_MAPPINGS).is_empty());
+    }
+
+    #[test]
+    fn default_mappings_cover_each_field_once() {
+        let mut paths: Vec<(u32, &str)> =
+            DEFAULT_MAPPINGS.iter().map(|m| (m.pgn, m.path)).collect();
+        let count = paths.len();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), count);
+        for mapping in DEFAULT_MAPPINGS {
+            // Fields encoded as not available publish nothing
+            let unavailable = match mapping.pgn {
+                PGN_ENGINE_RAPID => EngineRapid::default().encode(),
+                PGN_ENGINE_DYNAMIC => EngineDynamic::default().encode(),
+                PGN_TEMPERATURE => Temperature::default().encode(),
+                PGN_BATTERY_STATUS => BatteryStatus::default().encode(),
+                pgn => panic!("untested PGN {}", pgn),
+            };
+            assert_eq!((mapping.value)(&unavailable), None, "{}", mapping.path);
+            assert_eq!((mapping.value)(&[]), None, "{}", mapping.path);
+        }
+    }
+
+    #[test]
+    fn translate_uses_custom_mappings() {
+        const MAPPINGS: &[Mapping] = &[Mapping {
+            pgn: PGN_BATTERY_STATUS,
+            path: "electrical.batteries.house{instance}.voltage",
+            value: |d| BatteryStatus::decode(d).and_then(|b| Some((b.instance, b.voltage?.into()))),
+        }];
+        assert_numbers(
+            &translate(&message(BATTERY_FRAME), MAPPINGS),
+            &[("electrical.batteries.house0.voltage", 12.66)],
+        );
+    }
+
+    #[test]
+    fn gateway_publishes_received_frames() {
+        let bus = VirtualBus::new();
+        let mut engine = bus.connect();
+        let output = SignalKOutput::new();
+        let node = Nmea2000::new(bus.connect(), name(1), 30);
+        let mut gateway = Nmea2000Gateway::new(node, &output);
+
+        for (id, data) in [RAPID_FRAME, SEA_TEMPERATURE_FRAME, BATTERY_FRAME]
+            .into_iter()
+            .chain(DYNAMIC_FRAMES)
+        {
+            engine
+                .transmit(&CanFrame {
+                    id,
+                    data: data.to_vec(),
+                })
+                .unwrap();
+        }
+        gateway.poll(start());
+
+        let paths: Vec<String> = output.take_pending().into_iter().map(|u| u.path).collect();
+        assert_eq!(
+            paths,
+            [
+                "propulsion.0.revolutions",
+                "environment.water.temperature",
+                "electrical.batteries.0.voltage",
+                "electrical.batteries.0.current",
+                "propulsion.0.oilPressure",
+                "propulsion.0.temperature",
+                "propulsion.0.alternatorVoltage",
+                "propulsion.0.fuel.rate",
+                "propulsion.0.runTime",
+                "propulsion.0.engineLoad",
+            ]
+        );
+        // The gateway's own node claimed its address on the bus
+        let claim = engine.receive().unwrap().unwrap();
+        assert_eq!(CanId::from_raw(claim.id).pgn, PGN_ADDRESS_CLAIM);
+    }
+}
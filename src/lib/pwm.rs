@@ -0,0 +1,250 @@
+//! PWM output actuators
+use crate::sensor::{poll_update, SensESPSensor, Updates};
+use embedded_hal::pwm::SetDutyCycle;
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::ledc::{config::TimerConfig, Resolution};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::units::Hertz;
+use eyeball::Subscriber;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+/// LEDC timer configuration for the given PWM frequency and duty resolution.
+#[cfg(target_os = "espidf")]
+pub fn timer_config(frequency: Hertz, resolution: Resolution) -> TimerConfig {
+    TimerConfig::new()
+        .frequency(frequency)
+        .resolution(resolution)
+}
+
+/// Moves a level towards a target at a limited rate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ramp {
+    current: f32,
+    target: f32,
+    rate: Option<f32>,
+}
+
+impl Ramp {
+    /// Create a ramp starting at `level`.
+    ///
+    /// `rate` is the maximum change per second as a fraction of full scale, `None` jumps
+    /// straight to the target.
+    pub fn new(level: f32, rate: Option<f32>) -> Self {
+        let level = match level.is_finite() {
+            true => level.clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        Self {
+            current: level,
+            target: level,
+            rate: rate.filter(|r| !r.is_nan()),
+        }
+    }
+
+    /// Ramp towards `target`, clamped to 0.0 - 1.0. A NaN or infinite target is ignored,
+    /// it would otherwise stick in the current level for good.
+    pub fn set_target(&mut self, target: f32) {
+        match target.is_finite() {
+            true => self.target = target.clamp(0.0, 1.0),
+            false => log::warn!("Ignoring PWM target {}", target),
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    /// Advance the ramp by `elapsed` and return the new level.
+    pub fn step(&mut self, elapsed: Duration) -> f32 {
+        self.current = match self.rate {
+            Some(rate) => {
+                let max_step = rate.abs() * elapsed.as_secs_f32();
+                let delta = (self.target - self.current).clamp(-max_step, max_step);
+                self.current + delta
+            }
+            None => self.target,
+        };
+        self.current
+    }
+}
+
+/// Convert a 0.0 - 1.0 level into a duty cycle for a channel with `max_duty`.
+pub fn duty_for_level(level: f32, max_duty: u16) -> u16 {
+    (level.clamp(0.0, 1.0) * max_duty as f32).round() as u16
+}
+
+/// Drives a PWM channel, such as an LEDC dimmer, from a stream of 0.0 - 1.0 levels.
+pub struct PwmOutput<P: SetDutyCycle> {
+    pwm: P,
+    subscriber: Updates<f32>,
+    ramp: Ramp,
+    duty: Option<u16>,
+    last_tick: SystemTime,
+}
+
+impl<P: SetDutyCycle> PwmOutput<P> {
+    /// Create an output following `subscriber`, ramping at `rate` full scales per second.
+    pub fn new(pwm: P, subscriber: Subscriber<f32>, rate: Option<f32>) -> Self {
+        PwmOutput::<P> {
+            pwm,
+            subscriber: subscriber.into(),
+            ramp: Ramp::new(0.0, rate),
+            duty: None,
+            last_tick: SystemTime::now(),
+        }
+    }
+
+    /// The level currently applied to the channel
+    pub fn level(&self) -> f32 {
+        self.ramp.current()
+    }
+
+    /// Consume the actuator and return the underlying PWM channel
+    pub fn release(self) -> P {
+        self.pwm
+    }
+
+    /// Take any new target from the source and advance the ramp by `elapsed`.
+    pub fn update(&mut self, elapsed: Duration) {
+        match poll_update(&mut self.subscriber) {
+            Poll::Ready(Some(level)) => self.ramp.set_target(level),
+            Poll::Ready(None) | Poll::Pending => (),
+        }
+
+        let level = self.ramp.step(elapsed);
+        let duty = duty_for_level(level, self.pwm.max_duty_cycle());
+        if self.duty != Some(duty) {
+            match self.pwm.set_duty_cycle(duty) {
+                Ok(_) => self.duty = Some(duty),
+                Err(e) => log::error!("PWM duty cycle write error: {:?}", e),
+            }
+        }
+    }
+}
+
+impl<P: SetDutyCycle> SensESPSensor for PwmOutput<P> {
+    fn tick(&mut self) {
+        let now = SystemTime::now();
+
+        match now.duration_since(self.last_tick) {
+            Ok(d) => {
+                self.update(d);
+                self.last_tick = now;
+            }
+            Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::pwm::ErrorType;
+    use eyeball::shared::Observable;
+    use std::convert::Infallible;
+
+    /// A channel that records every duty cycle written to it.
+    struct FakePwm {
+        max_duty: u16,
+        duties: Vec<u16>,
+    }
+
+    impl FakePwm {
+        fn new(max_duty: u16) -> Self {
+            FakePwm {
+                max_duty,
+                duties: Vec::new(),
+            }
+        }
+    }
+
+    impl ErrorType for FakePwm {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for FakePwm {
+        fn max_duty_cycle(&self) -> u16 {
+            self.max_duty
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+            self.duties.push(duty);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ramp_without_rate_jumps_to_target() {
+        let mut ramp = Ramp::new(0.2, None);
+        ramp.set_target(0.9);
+        assert_eq!(ramp.step(Duration::ZERO), 0.9);
+    }
+
+    #[test]
+    fn ramp_limits_change_per_second() {
+        let mut ramp = Ramp::new(0.0, Some(0.5));
+        ramp.set_target(1.0);
+        assert_eq!(ramp.step(Duration::from_millis(500)), 0.25);
+        assert_eq!(ramp.step(Duration::from_secs(1)), 0.75);
+        assert_eq!(ramp.step(Duration::from_secs(1)), 1.0);
+
+        ramp.set_target(0.5);
+        assert_eq!(ramp.step(Duration::from_millis(200)), 0.9);
+    }
+
+    #[test]
+    fn ramp_clamps_targets() {
+        let mut ramp = Ramp::new(1.5, None);
+        assert_eq!(ramp.current(), 1.0);
+        ramp.set_target(-3.0);
+        assert_eq!(ramp.target(), 0.0);
+    }
+
+    #[test]
+    fn ramp_ignores_non_finite_targets() {
+        let mut ramp = Ramp::new(0.5, Some(1.0));
+        for target in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            ramp.set_target(target);
+            assert_eq!(ramp.target(), 0.5);
+        }
+        ramp.set_target(0.6);
+        assert_eq!(ramp.step(Duration::from_secs(1)), 0.6);
+        assert_eq!(Ramp::new(f32::NAN, None).current(), 0.0);
+        assert_eq!(
+            Ramp::new(0.0, Some(f32::NAN)).step(Duration::from_secs(1)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn duty_scales_and_rounds() {
+        assert_eq!(duty_for_level(0.0, 255), 0);
+        assert_eq!(duty_for_level(0.5, 255), 128);
+        assert_eq!(duty_for_level(1.0, 8191), 8191);
+        assert_eq!(duty_for_level(2.0, 255), 255);
+        assert_eq!(duty_for_level(-1.0, 255), 0);
+    }
+
+    #[test]
+    fn output_writes_only_changed_duty_cycles() {
+        let source = Observable::new(0.0);
+        let mut output = PwmOutput::new(FakePwm::new(100), source.subscribe(), Some(0.5));
+
+        output.update(Duration::ZERO);
+        source.set(1.0);
+        output.update(Duration::from_millis(200));
+        output.update(Duration::from_millis(200));
+        output.update(Duration::ZERO);
+        assert_eq!(output.level(), 0.2);
+
+        source.set(f32::NAN);
+        output.update(Duration::from_secs(2));
+        assert_eq!(output.level(), 1.0);
+        assert_eq!(output.release().duties, [0, 10, 20, 100]);
+    }
+}
@@ -0,0 +1,92 @@
+//! REST endpoints served by the device
+use crate::i2c::scan::{scan, to_json, Probe};
+#[cfg(target_os = "espidf")]
+use anyhow::Result;
+use embedded_hal::i2c::I2c;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::server::EspHttpServer;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::Method;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::io::Write;
+#[cfg(target_os = "espidf")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+pub const I2C_SCAN_URI: &str = "/api/i2c/scan";
+
+/// Answer `GET /api/i2c/scan` with a fresh scan of `bus` as JSON.
+///
+/// The bus is locked for the whole scan, so sensors sharing it pause until it completes.
+#[cfg(target_os = "espidf")]
+pub fn serve_i2c_scan<I>(server: &mut EspHttpServer<'static>, bus: Arc<Mutex<I>>) -> Result<()>
+where
+    I: I2c + Send + 'static,
+{
+    server.fn_handler(I2C_SCAN_URI, Method::Get, move |req| -> Result<()> {
+        let (status, body) = scan_response(&bus);
+        let mut response =
+            req.into_response(status, None, &[("Content-Type", "application/json")])?;
+        response.write_all(body.as_bytes())?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// The HTTP status and JSON body answering a scan of `bus`, for serving from any server.
+pub fn scan_response<I: I2c>(bus: &Mutex<I>) -> (u16, String) {
+    match bus.lock() {
+        Ok(mut bus) => (200, to_json(&scan(&mut *bus, Probe::Auto, true))),
+        // Another user of the bus panicked mid-transaction, leaving its state unknown
+        Err(_) => {
+            log::error!("I2C bus lock poisoned, not scanning");
+            (500, "{\"error\":\"I2C bus unavailable\"}".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+    use std::sync::Arc;
+
+    /// A bus with a single device that acknowledges 0x3C.
+    struct FakeBus;
+
+    impl ErrorType for FakeBus {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for FakeBus {
+        fn transaction(&mut self, address: u8, _: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+            match address {
+                0x3C => Ok(()),
+                _ => Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+            }
+        }
+    }
+
+    #[test]
+    fn scan_answers_with_json() {
+        let (status, body) = scan_response(&Mutex::new(FakeBus));
+        assert_eq!(status, 200);
+        assert!(body.starts_with("[{\"address\":60,\"method\":\"quick-write\""));
+    }
+
+    #[test]
+    fn poisoned_bus_answers_500() {
+        let bus = Arc::new(Mutex::new(FakeBus));
+        let holder = bus.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = holder.lock().unwrap();
+            panic!("sensor driver panicked holding the bus");
+        })
+        .join();
+        assert!(bus.is_poisoned());
+
+        let (status, body) = scan_response(&bus);
+        assert_eq!(status, 500);
+        assert_eq!(body, "{\"error\":\"I2C bus unavailable\"}");
+    }
+}
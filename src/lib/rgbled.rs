@@ -1,16 +1,135 @@
+#[cfg(target_os = "espidf")]
 use anyhow::Result;
+#[cfg(target_os = "espidf")]
 use core::time::Duration;
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::hal::{
     gpio::OutputPin,
     peripheral::Peripheral,
-    rmt::{config::TransmitConfig, FixedLengthSignal, PinState, Pulse, RmtChannel, TxRmtDriver},
+    rmt::{config::TransmitConfig, PinState, Pulse, RmtChannel, TxRmtDriver, VariableLengthSignal},
 };
 
 pub use rgb::RGB8;
+
+/// Order in which a pixel's color channels are sent down the wire.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelOrder {
+    /// WS2812B and most strips
+    Grb,
+    Rgb,
+    /// SK6812 RGBW strips
+    Grbw,
+    Rgbw,
+}
+
+impl PixelOrder {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelOrder::Grb | PixelOrder::Rgb => 3,
+            PixelOrder::Grbw | PixelOrder::Rgbw => 4,
+        }
+    }
+}
+
+/// Lookup table mapping linear intensity to perceived brightness.
+pub fn gamma_table(gamma: f32) -> [u8; 256] {
+    let mut table = [0; 256];
+    for (i, v) in table.iter_mut().enumerate() {
+        *v = ((i as f32 / 255.0).powf(gamma) * 255.0).round() as u8;
+    }
+    table
+}
+
+/// Wire bytes for a single pixel after gamma correction and global brightness.
+///
+/// For RGBW orders the white channel takes the common part of red, green and blue.
+pub fn pixel_bytes(
+    rgb: RGB8,
+    order: PixelOrder,
+    brightness: u8,
+    gamma: Option<&[u8; 256]>,
+) -> Vec<u8> {
+    let level = |c: u8| {
+        let c = gamma.map_or(c, |g| g[c as usize]);
+        ((c as u16 * brightness as u16 + 127) / 255) as u8
+    };
+    let (r, g, b) = (level(rgb.r), level(rgb.g), level(rgb.b));
+    match order {
+        PixelOrder::Grb => vec![g, r, b],
+        PixelOrder::Rgb => vec![r, g, b],
+        PixelOrder::Grbw | PixelOrder::Rgbw => {
+            let w = r.min(g).min(b);
+            let (r, g, b) = (r - w, g - w, b - w);
+            match order {
+                PixelOrder::Grbw => vec![g, r, b, w],
+                _ => vec![r, g, b, w],
+            }
+        }
+    }
+}
+
+/// Wire bytes for a whole strip, first pixel first.
+pub fn frame_bytes(
+    pixels: &[RGB8],
+    order: PixelOrder,
+    brightness: u8,
+    gamma: Option<&[u8; 256]>,
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(pixels.len() * order.bytes_per_pixel());
+    for p in pixels {
+        bytes.extend(pixel_bytes(*p, order, brightness, gamma));
+    }
+    bytes
+}
+
+/// The bits of a byte stream in transmission order, most significant bit first.
+pub fn bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| byte & (1 << i) != 0))
+}
+
+/// RMT pulses for the WS2812 one and zero symbols, computed once per driver.
+#[cfg(target_os = "espidf")]
+#[derive(Copy, Clone)]
+struct Timing {
+    zero: (Pulse, Pulse),
+    one: (Pulse, Pulse),
+}
+
+#[cfg(target_os = "espidf")]
+impl Timing {
+    fn new(tx: &TxRmtDriver) -> Result<Self> {
+        let ticks_hz = tx.counter_clock()?;
+        Ok(Self {
+            zero: (
+                Pulse::new_with_duration(ticks_hz, PinState::High, &ns(350))?,
+                Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(800))?,
+            ),
+            one: (
+                Pulse::new_with_duration(ticks_hz, PinState::High, &ns(700))?,
+                Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(600))?,
+            ),
+        })
+    }
+
+    fn signal(&self, bytes: &[u8]) -> Result<VariableLengthSignal> {
+        let mut signal = VariableLengthSignal::with_capacity(bytes.len() * 8 * 2);
+        for bit in bits(bytes) {
+            let (high, low) = if bit { &self.one } else { &self.zero };
+            signal.push([high, low])?;
+        }
+        Ok(signal)
+    }
+}
+
+#[cfg(target_os = "espidf")]
 pub struct WS2812RMT<'a> {
     tx_rtm_driver: TxRmtDriver<'a>,
+    timing: Timing,
 }
 
+#[cfg(target_os = "espidf")]
 impl<'d> WS2812RMT<'d> {
     // Rust ESP Board gpio2,  ESP32-C3-DevKitC-02 gpio8
     pub fn new(
@@ -19,29 +138,195 @@ impl<'d> WS2812RMT<'d> {
     ) -> Result<Self> {
         let config = TransmitConfig::new().clock_divider(2);
         let tx = TxRmtDriver::new(channel, led, &config)?;
-        Ok(Self { tx_rtm_driver: tx })
+        let timing = Timing::new(&tx)?;
+        Ok(Self {
+            tx_rtm_driver: tx,
+            timing,
+        })
     }
 
     pub fn set_pixel(&mut self, rgb: RGB8) -> Result<()> {
-        let color: u32 = ((rgb.g as u32) << 16) | ((rgb.r as u32) << 8) | rgb.b as u32;
-        let ticks_hz = self.tx_rtm_driver.counter_clock()?;
-        let t0h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(350))?;
-        let t0l = Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(800))?;
-        let t1h = Pulse::new_with_duration(ticks_hz, PinState::High, &ns(700))?;
-        let t1l = Pulse::new_with_duration(ticks_hz, PinState::Low, &ns(600))?;
-        let mut signal = FixedLengthSignal::<24>::new();
-        for i in (0..24).rev() {
-            let p = 2_u32.pow(i);
-            let bit = p & color != 0;
-            let (high_pulse, low_pulse) = if bit { (t1h, t1l) } else { (t0h, t0l) };
-            signal.set(23 - i as usize, &(high_pulse, low_pulse))?;
+        let signal = self
+            .timing
+            .signal(&pixel_bytes(rgb, PixelOrder::Grb, 255, None))?;
+        self.tx_rtm_driver.start_blocking(&signal)?;
+
+        Ok(())
+    }
+}
+
+/// A strip of addressable LEDs with a frame buffer, sent in a single RMT transaction.
+#[cfg(target_os = "espidf")]
+pub struct WS2812Strip<'d> {
+    tx_rtm_driver: TxRmtDriver<'d>,
+    timing: Timing,
+    pixels: Vec<RGB8>,
+    order: PixelOrder,
+    brightness: u8,
+    gamma: Option<[u8; 256]>,
+}
+
+#[cfg(target_os = "espidf")]
+impl<'d> WS2812Strip<'d> {
+    /// Create a strip of `length` pixels, all off, at full brightness without gamma correction.
+    pub fn new(
+        led: impl Peripheral<P = impl OutputPin> + 'd,
+        channel: impl Peripheral<P = impl RmtChannel> + 'd,
+        length: usize,
+        order: PixelOrder,
+    ) -> Result<Self> {
+        let config = TransmitConfig::new().clock_divider(2);
+        let tx = TxRmtDriver::new(channel, led, &config)?;
+        let timing = Timing::new(&tx)?;
+        Ok(Self {
+            tx_rtm_driver: tx,
+            timing,
+            pixels: vec![RGB8::default(); length],
+            order,
+            brightness: 255,
+            gamma: None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    /// Scale every pixel by `brightness` / 255 when shown.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    /// Apply gamma correction with the given exponent, 2.8 suits most strips.
+    pub fn set_gamma(&mut self, gamma: Option<f32>) {
+        self.gamma = gamma.map(gamma_table);
+    }
+
+    /// Set a pixel in the frame buffer, ignoring indexes past the end of the strip.
+    pub fn set(&mut self, index: usize, rgb: RGB8) {
+        if let Some(p) = self.pixels.get_mut(index) {
+            *p = rgb;
         }
+    }
+
+    pub fn fill(&mut self, rgb: RGB8) {
+        self.pixels.fill(rgb);
+    }
+
+    /// The frame buffer, to be written directly.
+    pub fn pixels_mut(&mut self) -> &mut [RGB8] {
+        &mut self.pixels
+    }
+
+    /// Send the frame buffer to the strip.
+    pub fn show(&mut self) -> Result<()> {
+        let bytes = frame_bytes(
+            &self.pixels,
+            self.order,
+            self.brightness,
+            self.gamma.as_ref(),
+        );
+        let signal = self.timing.signal(&bytes)?;
         self.tx_rtm_driver.start_blocking(&signal)?;
 
         Ok(())
     }
 }
 
+#[cfg(target_os = "espidf")]
 fn ns(nanos: u64) -> Duration {
     Duration::from_nanos(nanos)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_are_sent_most_significant_first() {
+        let sent: Vec<bool> = bits(&[0xA5]).collect();
+        assert_eq!(sent, [true, false, true, false, false, true, false, true]);
+        let sent: Vec<bool> = bits(&[0x80, 0x01]).collect();
+        assert_eq!(sent.len(), 16);
+        assert!(sent[0] && sent[15]);
+        assert_eq!(sent.iter().filter(|b| **b).count(), 2);
+        assert_eq!(bits(&[]).count(), 0);
+    }
+
+    #[test]
+    fn gamma_table_is_monotonic_and_keeps_the_ends() {
+        let identity = gamma_table(1.0);
+        assert!(identity.iter().enumerate().all(|(i, v)| i == *v as usize));
+
+        let table = gamma_table(2.8);
+        assert_eq!(table[0], 0);
+        assert_eq!(table[255], 255);
+        assert_eq!(
+            [table[1], table[64], table[128], table[192], table[254]],
+            [0, 5, 37, 115, 252]
+        );
+        assert!(table.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn pixel_bytes_follow_the_order() {
+        let rgb = RGB8::new(10, 20, 30);
+        assert_eq!(pixel_bytes(rgb, PixelOrder::Grb, 255, None), [20, 10, 30]);
+        assert_eq!(pixel_bytes(rgb, PixelOrder::Rgb, 255, None), [10, 20, 30]);
+        // White takes the common part of the three colors
+        assert_eq!(
+            pixel_bytes(rgb, PixelOrder::Grbw, 255, None),
+            [10, 0, 20, 10]
+        );
+        assert_eq!(
+            pixel_bytes(rgb, PixelOrder::Rgbw, 255, None),
+            [0, 10, 20, 10]
+        );
+        assert_eq!(
+            pixel_bytes(RGB8::new(200, 200, 200), PixelOrder::Rgbw, 255, None),
+            [0, 0, 0, 200]
+        );
+    }
+
+    #[test]
+    fn pixel_bytes_scale_by_brightness() {
+        let white = RGB8::new(255, 255, 255);
+        assert_eq!(pixel_bytes(white, PixelOrder::Rgb, 128, None), [128; 3]);
+        assert_eq!(pixel_bytes(white, PixelOrder::Rgb, 0, None), [0; 3]);
+        // Rounded rather than truncated
+        assert_eq!(
+            pixel_bytes(RGB8::new(1, 2, 3), PixelOrder::Rgb, 128, None),
+            [1, 1, 2]
+        );
+    }
+
+    #[test]
+    fn pixel_bytes_apply_gamma_before_brightness() {
+        let table = gamma_table(2.8);
+        let rgb = RGB8::new(128, 64, 255);
+        assert_eq!(
+            pixel_bytes(rgb, PixelOrder::Rgb, 255, Some(&table)),
+            [37, 5, 255]
+        );
+        assert_eq!(
+            pixel_bytes(rgb, PixelOrder::Rgb, 51, Some(&table)),
+            [7, 1, 51]
+        );
+    }
+
+    #[test]
+    fn frame_bytes_concatenate_pixels() {
+        let pixels = [RGB8::new(1, 2, 3), RGB8::new(4, 5, 6)];
+        assert_eq!(
+            frame_bytes(&pixels, PixelOrder::Grb, 255, None),
+            [2, 1, 3, 5, 4, 6]
+        );
+        assert_eq!(frame_bytes(&pixels, PixelOrder::Grbw, 255, None).len(), 8);
+        assert!(frame_bytes(&[], PixelOrder::Grb, 255, None).is_empty());
+        assert_eq!(PixelOrder::Rgbw.bytes_per_pixel(), 4);
+    }
+}
@@ -1,4 +1,9 @@
 use eyeball::{shared::Observable, Subscriber};
+use smol::stream::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
 use std::time::{Duration, SystemTime};
 
 pub trait SensESPSensor {
@@ -9,6 +14,70 @@ pub trait Attachable<T> {
     fn attach(&mut self) -> Subscriber<T>;
 }
 
+/// A subscriber checked for new values from `tick` with [`poll_update`].
+pub struct Updates<T> {
+    subscriber: Subscriber<T>,
+    notified: Arc<Notified>,
+}
+
+impl<T> Updates<T> {
+    pub fn new(subscriber: Subscriber<T>) -> Self {
+        Updates::<T> {
+            subscriber,
+            // Nothing has been polled yet, so the first poll must reach the subscriber
+            notified: Arc::new(Notified(AtomicBool::new(true))),
+        }
+    }
+
+    /// The current value, whether or not it has been polled.
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.subscriber.get()
+    }
+}
+
+impl<T> From<Subscriber<T>> for Updates<T> {
+    fn from(subscriber: Subscriber<T>) -> Self {
+        Updates::new(subscriber)
+    }
+}
+
+/// Set by the source when it publishes or is dropped.
+struct Notified(AtomicBool);
+
+impl Wake for Notified {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Check a subscriber for a new value without blocking.
+///
+/// Returns `Ready(Some(value))` when the source published since the last poll, `Pending` when
+/// it has not, and `Ready(None)` once the source has been dropped.
+///
+/// The source keeps every waker it was polled with until it next publishes, so the subscriber
+/// is only polled again after its last waker has fired.
+pub fn poll_update<T: Clone>(updates: &mut Updates<T>) -> Poll<Option<T>> {
+    if !updates.notified.0.swap(false, Ordering::Acquire) {
+        return Poll::Pending;
+    }
+    let waker = Waker::from(updates.notified.clone());
+    let mut cx = Context::from_waker(&waker);
+    let poll = Pin::new(&mut updates.subscriber).poll_next(&mut cx);
+    if poll.is_ready() {
+        // No waker was stored, so there may be more to read on the next poll
+        updates.notified.0.store(true, Ordering::Release);
+    }
+    poll
+}
+
 pub struct ConstantSensor<T> {
     observable: Observable<T>,
     duration: Duration,
@@ -34,14 +103,12 @@ impl<T: Copy> SensESPSensor for ConstantSensor<T> {
         let now = SystemTime::now();
 
         match now.duration_since(self.last_measurement) {
-            Ok(d) => match d >= self.duration {
-                true => {
+            Ok(d) => {
+                if d >= self.duration {
                     self.observable.set(self.value);
                     self.last_measurement = now;
-                    ()
                 }
-                false => (),
-            },
+            }
             Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
         }
     }
@@ -101,16 +168,79 @@ where
         let now = SystemTime::now();
 
         match now.duration_since(self.last_measurement) {
-            Ok(d) => match d >= self.duration {
-                true => {
+            Ok(d) => {
+                if d >= self.duration {
                     let val = (self.func)();
                     self.observable.set(val);
                     self.last_measurement = now;
-                    ()
                 }
-                false => (),
-            },
+            }
             Err(e) => log::error!("System time error on SensESP-rs tick: {:?}", e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_update_sees_every_publish_once() {
+        let observable = Observable::new(0);
+        let mut updates = Updates::new(observable.subscribe());
+        assert_eq!(poll_update(&mut updates), Poll::Pending);
+
+        observable.set(1);
+        assert_eq!(poll_update(&mut updates), Poll::Ready(Some(1)));
+        assert_eq!(poll_update(&mut updates), Poll::Pending);
+
+        observable.set(2);
+        observable.set(3);
+        assert_eq!(poll_update(&mut updates), Poll::Ready(Some(3)));
+        assert_eq!(poll_update(&mut updates), Poll::Pending);
+
+        // The same value published again is still an update
+        observable.set(3);
+        assert_eq!(poll_update(&mut updates), Poll::Ready(Some(3)));
+    }
+
+    #[test]
+    fn poll_update_does_not_pile_up_wakers() {
+        let observable = Observable::new(0);
+        let mut updates = Updates::new(observable.subscribe());
+
+        for _ in 0..10_000 {
+            assert_eq!(poll_update(&mut updates), Poll::Pending);
+        }
+        // Ours plus the single clone held by the source
+        assert_eq!(Arc::strong_count(&updates.notified), 2);
+
+        observable.set(1);
+        assert_eq!(Arc::strong_count(&updates.notified), 1);
+        assert_eq!(poll_update(&mut updates), Poll::Ready(Some(1)));
+        for _ in 0..10_000 {
+            assert_eq!(poll_update(&mut updates), Poll::Pending);
+        }
+        assert_eq!(Arc::strong_count(&updates.notified), 2);
+    }
+
+    #[test]
+    fn poll_update_reports_a_dropped_source() {
+        let observable = Observable::new(0);
+        let mut updates = Updates::new(observable.subscribe());
+        assert_eq!(poll_update(&mut updates), Poll::Pending);
+
+        drop(observable);
+        assert_eq!(poll_update(&mut updates), Poll::Ready(None));
+        assert_eq!(poll_update(&mut updates), Poll::Ready(None));
+    }
+
+    #[test]
+    fn updates_get_reads_without_consuming() {
+        let observable = Observable::new(5);
+        let mut updates = Updates::new(observable.subscribe());
+        observable.set(6);
+        assert_eq!(updates.get(), 6);
+        assert_eq!(poll_update(&mut updates), Poll::Ready(Some(6)));
+    }
+}
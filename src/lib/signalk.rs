@@ -0,0 +1,405 @@
+//! Signal K values, path updates and the device's delta output
+use crate::sensor::{poll_update, SensESPSensor, Updates};
+use eyeball::{shared::Observable, Subscriber};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+
+/// A value as carried in a Signal K delta, always in SI units.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Number(f64),
+    Bool(bool),
+    Text(String),
+    Position {
+        latitude: f64,
+        longitude: f64,
+    },
+    /// Published under `notifications.`, a `Normal` state clears an earlier alarm
+    Notification {
+        state: AlarmState,
+        message: String,
+    },
+}
+
+/// Severity of a Signal K notification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlarmState {
+    Normal,
+    Alert,
+    Warn,
+    Alarm,
+    Emergency,
+}
+
+impl AlarmState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlarmState::Normal => "normal",
+            AlarmState::Alert => "alert",
+            AlarmState::Warn => "warn",
+            AlarmState::Alarm => "alarm",
+            AlarmState::Emergency => "emergency",
+        }
+    }
+
+    /// How receivers should get the user's attention.
+    fn methods(&self) -> &'static str {
+        match self {
+            AlarmState::Normal => "[]",
+            AlarmState::Alert | AlarmState::Warn => "[\"visual\"]",
+            AlarmState::Alarm | AlarmState::Emergency => "[\"visual\",\"sound\"]",
+        }
+    }
+}
+
+impl Value {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Value {
+    /// Formats the value as JSON
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Text(s) => write!(f, "\"{}\"", escape(s)),
+            Value::Position {
+                latitude,
+                longitude,
+            } => write!(
+                f,
+                "{{\"latitude\":{},\"longitude\":{}}}",
+                latitude, longitude
+            ),
+            Value::Notification { state, message } => write!(
+                f,
+                "{{\"state\":\"{}\",\"method\":{},\"message\":\"{}\"}}",
+                state.as_str(),
+                state.methods(),
+                escape(message)
+            ),
+        }
+    }
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A new value for a single Signal K path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub path: String,
+    pub value: Value,
+}
+
+impl Update {
+    pub fn new(path: impl Into<String>, value: impl Into<Value>) -> Self {
+        Self {
+            path: path.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// Updates kept for a transport before the oldest are dropped.
+const MAX_PENDING: usize = 256;
+
+#[derive(Default)]
+struct OutputState {
+    paths: HashMap<String, Observable<Value>>,
+    pending: Vec<Update>,
+}
+
+/// The device's Signal K output: every path produced locally or by a gateway is published here.
+///
+/// Clones share the same state, so one output can be handed to every producer while a
+/// transport drains the queued updates.
+#[derive(Clone, Default)]
+pub struct SignalKOutput {
+    state: Arc<Mutex<OutputState>>,
+}
+
+impl SignalKOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish a new value for a path.
+    pub fn publish(&self, update: Update) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(observable) = state.paths.get(&update.path) {
+            observable.set(update.value.clone());
+        }
+        if state.pending.len() >= MAX_PENDING {
+            state.pending.remove(0);
+        }
+        state.pending.push(update);
+    }
+
+    /// Subscribe to the values published for `path`.
+    pub fn attach_path(&self, path: &str) -> Subscriber<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .paths
+            .entry(path.to_string())
+            .or_insert_with(|| Observable::new(Value::Null))
+            .subscribe()
+    }
+
+    /// Remove and return the updates published since the last call, oldest first.
+    pub fn take_pending(&self) -> Vec<Update> {
+        std::mem::take(&mut self.state.lock().unwrap().pending)
+    }
+
+    /// Remove the pending updates and serialize them as one delta for `vessels.self` from the
+    /// source `label`, or `None` when nothing was published since the last call.
+    pub fn take_delta(&self, label: &str) -> Option<String> {
+        let updates = self.take_pending();
+        match updates.is_empty() {
+            true => None,
+            false => Some(delta_json(label, &updates)),
+        }
+    }
+}
+
+fn delta_json(label: &str, updates: &[Update]) -> String {
+    let values: Vec<String> = updates
+        .iter()
+        .map(|u| format!("{{\"path\":\"{}\",\"value\":{}}}", escape(&u.path), u.value))
+        .collect();
+    format!(
+        "{{\"context\":\"vessels.self\",\"updates\":[{{\"source\":{{\"label\":\"{}\"}},\"values\":[{}]}}]}}",
+        escape(label),
+        values.join(",")
+    )
+}
+
+/// Forwards every value of a sensor stream to a Signal K path.
+pub struct SignalKPath<T> {
+    subscriber: Updates<T>,
+    path: String,
+    output: SignalKOutput,
+}
+
+impl<T> SignalKPath<T>
+where
+    T: Clone + Into<Value>,
+{
+    pub fn new(output: &SignalKOutput, path: &str, subscriber: Subscriber<T>) -> Self {
+        SignalKPath::<T> {
+            subscriber: subscriber.into(),
+            path: path.to_string(),
+            output: output.clone(),
+        }
+    }
+}
+
+impl<T> SensESPSensor for SignalKPath<T>
+where
+    T: Clone + Into<Value>,
+{
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(value)) = poll_update(&mut self.subscriber) {
+            self.output
+                .publish(Update::new(self.path.as_str(), value.into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_format_as_json() {
+        assert_eq!(Value::Null.to_string(), "null");
+        assert_eq!(Value::from(1.5f64).to_string(), "1.5");
+        assert_eq!(Value::from(-2.0f32).to_string(), "-2");
+        assert_eq!(Value::from(true).to_string(), "true");
+        assert_eq!(Value::from(None::<f64>).to_string(), "null");
+        assert_eq!(
+            Value::Position {
+                latitude: 48.1173,
+                longitude: -11.5167,
+            }
+            .to_string(),
+            r#"{"latitude":48.1173,"longitude":-11.5167}"#
+        );
+    }
+
+    #[test]
+    fn numbers_that_json_cannot_carry_are_null() {
+        for n in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(Value::Number(n).to_string(), "null");
+        }
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        let text = Value::from("say \"hi\"\\\n\r\t\u{1}°".to_string());
+        assert_eq!(text.to_string(), r#""say \"hi\"\\\n\r\t\u0001°""#);
+    }
+
+    #[test]
+    fn published_values_reach_their_path_subscribers() {
+        let output = SignalKOutput::new();
+        let depth = output.attach_path("environment.depth.belowTransducer");
+        assert_eq!(depth.get(), Value::Null);
+
+        output.publish(Update::new("environment.depth.belowTransducer", 4.2));
+        output.publish(Update::new("navigation.speedOverGround", 3.1));
+        assert_eq!(depth.get(), Value::Number(4.2));
+        // Clones share the subscribers and the queue
+        output
+            .clone()
+            .publish(Update::new("environment.depth.belowTransducer", 4.0));
+        assert_eq!(depth.get(), Value::Number(4.0));
+        assert_eq!(output.take_pending().len(), 3);
+        assert!(output.take_pending().is_empty());
+    }
+
+    #[test]
+    fn pending_queue_drops_the_oldest_updates() {
+        let output = SignalKOutput::new();
+        for i in 0..MAX_PENDING + 10 {
+            output.publish(Update::new("test.counter", i as f64));
+        }
+        let pending = output.take_pending();
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert_eq!(pending[0].value, Value::Number(10.0));
+        assert_eq!(
+            pending[MAX_PENDING - 1].value,
+            Value::Number((MAX_PENDING + 9) as f64)
+        );
+    }
+
+    #[test]
+    fn paths_forward_every_value_of_their_stream() {
+        let output = SignalKOutput::new();
+        let source = Observable::new(1.0f32);
+        let mut path = SignalKPath::new(
+            &output,
+            "electrical.batteries.house.voltage",
+            source.subscribe(),
+        );
+        let voltage = output.attach_path("electrical.batteries.house.voltage");
+
+        path.tick();
+        assert!(output.take_pending().is_empty());
+        source.set(12.5);
+        path.tick();
+        path.tick();
+        // A repeated value is still a new reading
+        source.set(12.5);
+        path.tick();
+        let pending = output.take_pending();
+        assert_eq!(
+            pending,
+            vec![
+                Update::new("electrical.batteries.house.voltage", 12.5f32),
+                Update::new("electrical.batteries.house.voltage", 12.5f32),
+            ]
+        );
+        assert_eq!(voltage.get(), Value::Number(12.5));
+    }
+
+    #[test]
+    fn deltas_carry_every_pending_update() {
+        let output = SignalKOutput::new();
+        assert_eq!(output.take_delta("sensesp"), None);
+
+        output.publish(Update::new("navigation.speedOverGround", 3.5));
+        output.publish(Update::new("navigation.headingMagnetic", f64::NAN));
+        assert_eq!(
+            output.take_delta("sensesp \"bow\"").as_deref(),
+            Some(concat!(
+                r#"{"context":"vessels.self","updates":[{"source":{"label":"sensesp \"bow\""},"#,
+                r#""values":[{"path":"navigation.speedOverGround","value":3.5},"#,
+                r#"{"path":"navigation.headingMagnetic","value":null}]}]}"#
+            ))
+        );
+        assert_eq!(output.take_delta("sensesp"), None);
+    }
+
+    #[test]
+    fn notifications_carry_their_state_and_methods() {
+        let notification = |state| Value::Notification {
+            state,
+            message: "Bilge \"main\" running".to_string(),
+        };
+        assert_eq!(
+            notification(AlarmState::Normal).to_string(),
+            r#"{"state":"normal","method":[],"message":"Bilge \"main\" running"}"#
+        );
+        assert_eq!(
+            notification(AlarmState::Warn).to_string(),
+            r#"{"state":"warn","method":["visual"],"message":"Bilge \"main\" running"}"#
+        );
+        assert_eq!(
+            notification(AlarmState::Emergency).to_string(),
+            r#"{"state":"emergency","method":["visual","sound"],"message":"Bilge \"main\" running"}"#
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Status indicator LED driven by the system state
+use crate::rgbled::RGB8;
+#[cfg(target_os = "espidf")]
+use crate::rgbled::WS2812RMT;
+use crate::sensor::{poll_update, Updates};
+use anyhow::Result;
+use eyeball::{shared::Observable, Subscriber};
+use std::task::Poll;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Anything that can show a single color, such as the on-board WS2812.
+pub trait PixelSink {
+    fn set_pixel(&mut self, rgb: RGB8) -> Result<()>;
+}
+
+#[cfg(target_os = "espidf")]
+impl PixelSink for WS2812RMT<'_> {
+    fn set_pixel(&mut self, rgb: RGB8) -> Result<()> {
+        WS2812RMT::set_pixel(self, rgb)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SystemState {
+    Booting,
+    WifiConnecting,
+    WifiConnected,
+    SignalKAuthorizing,
+    Connected,
+    OtaInProgress,
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Solid,
+    /// On for the first half of every period
+    Blink(Duration),
+    /// Fade smoothly in and out once per period
+    Breathe(Duration),
+}
+
+/// The color and pattern shown for each state.
+pub fn indication(state: SystemState) -> (RGB8, Pattern) {
+    match state {
+        SystemState::Booting => (RGB8::new(50, 50, 0), Pattern::Solid),
+        SystemState::WifiConnecting => (
+            RGB8::new(0, 0, 50),
+            Pattern::Blink(Duration::from_millis(500)),
+        ),
+        SystemState::WifiConnected => (RGB8::new(0, 0, 50), Pattern::Solid),
+        SystemState::SignalKAuthorizing => (
+            RGB8::new(50, 25, 0),
+            Pattern::Blink(Duration::from_millis(1000)),
+        ),
+        SystemState::Connected => (
+            RGB8::new(0, 50, 0),
+            Pattern::Breathe(Duration::from_secs(4)),
+        ),
+        SystemState::OtaInProgress => (
+            RGB8::new(40, 0, 50),
+            Pattern::Blink(Duration::from_millis(200)),
+        ),
+        SystemState::Error => (
+            RGB8::new(50, 0, 0),
+            Pattern::Blink(Duration::from_millis(250)),
+        ),
+    }
+}
+
+fn scale(color: RGB8, level: f32) -> RGB8 {
+    let level = level.clamp(0.0, 1.0);
+    RGB8::new(
+        (color.r as f32 * level).round() as u8,
+        (color.g as f32 * level).round() as u8,
+        (color.b as f32 * level).round() as u8,
+    )
+}
+
+/// The color to show `elapsed` into a pattern.
+pub fn color_at(color: RGB8, pattern: Pattern, elapsed: Duration) -> RGB8 {
+    let phase = |period: Duration| match period.as_nanos() {
+        0 => 0.0,
+        p => (elapsed.as_nanos() % p) as f32 / p as f32,
+    };
+    match pattern {
+        Pattern::Solid => color,
+        Pattern::Blink(period) => match phase(period) < 0.5 {
+            true => color,
+            false => RGB8::new(0, 0, 0),
+        },
+        Pattern::Breathe(period) => {
+            let level = (1.0 - (phase(period) * std::f32::consts::TAU).cos()) / 2.0;
+            scale(color, level)
+        }
+    }
+}
+
+/// Animates a pixel according to the current system state.
+pub struct StatusLed<S: PixelSink> {
+    sink: S,
+    state: SystemState,
+    since: Duration,
+    shown: Option<RGB8>,
+}
+
+impl<S: PixelSink> StatusLed<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            state: SystemState::Booting,
+            since: Duration::ZERO,
+            shown: None,
+        }
+    }
+
+    pub fn state(&self) -> SystemState {
+        self.state
+    }
+
+    /// Switch to `state` at time `now`, restarting its pattern.
+    pub fn set_state(&mut self, state: SystemState, now: Duration) {
+        if state != self.state {
+            self.state = state;
+            self.since = now;
+        }
+    }
+
+    /// Render the frame for `now`, only writing to the pixel when the color changes.
+    pub fn update(&mut self, now: Duration) {
+        let (color, pattern) = indication(self.state);
+        self.show(color_at(color, pattern, now.saturating_sub(self.since)));
+    }
+
+    /// Show the state's color without its pattern, for a pixel that stops being animated.
+    pub fn hold(&mut self) {
+        self.show(indication(self.state).0);
+    }
+
+    fn show(&mut self, color: RGB8) {
+        if self.shown != Some(color) {
+            match self.sink.set_pixel(color) {
+                Ok(_) => self.shown = Some(color),
+                Err(e) => log::error!("Status LED write error: {:?}", e),
+            }
+        }
+    }
+
+    /// Consume the indicator and return the underlying pixel
+    pub fn release(self) -> S {
+        self.sink
+    }
+}
+
+/// Handle to a status LED animated on its own thread.
+///
+/// Dropping the handle stops the animation, leaving the last state's color showing.
+pub struct StatusIndicator {
+    state: Observable<SystemState>,
+    _thread: JoinHandle<()>,
+}
+
+impl StatusIndicator {
+    /// Start animating `sink` at one frame per `frame` interval.
+    pub fn spawn<S>(sink: S, frame: Duration) -> Result<Self>
+    where
+        S: PixelSink + Send + 'static,
+    {
+        let state = Observable::new(SystemState::Booting);
+        let subscriber = Updates::new(state.subscribe());
+        let thread = std::thread::Builder::new()
+            .name("status-led".to_string())
+            .stack_size(4096)
+            .spawn(move || animate(StatusLed::new(sink), subscriber, frame))?;
+        Ok(Self {
+            state,
+            _thread: thread,
+        })
+    }
+
+    pub fn set(&self, state: SystemState) {
+        self.state.set(state);
+    }
+
+    /// Show `state` and stop animating, returning once the pixel has been written.
+    ///
+    /// Use this before bailing out, so the state is shown however soon the program ends.
+    pub fn finish(self, state: SystemState) {
+        let StatusIndicator {
+            state: observable,
+            _thread: thread,
+        } = self;
+        observable.set(state);
+        drop(observable);
+        if thread.join().is_err() {
+            log::error!("Status LED thread panicked");
+        }
+    }
+
+    /// Subscribe to state changes, e.g. to mirror them on a display.
+    pub fn attach(&self) -> Subscriber<SystemState> {
+        self.state.subscribe()
+    }
+}
+
+fn animate<S: PixelSink>(
+    mut led: StatusLed<S>,
+    mut subscriber: Updates<SystemState>,
+    frame: Duration,
+) {
+    let start = Instant::now();
+    loop {
+        match poll_update(&mut subscriber) {
+            Poll::Ready(Some(state)) => led.set_state(state, start.elapsed()),
+            // The handle was dropped, possibly before its last state was seen
+            Poll::Ready(None) => {
+                led.set_state(subscriber.get(), start.elapsed());
+                led.hold();
+                return;
+            }
+            Poll::Pending => (),
+        }
+        led.update(start.elapsed());
+        std::thread::sleep(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Records every color written, optionally failing the writes.
+    #[derive(Clone, Default)]
+    struct FakePixel {
+        written: Arc<Mutex<Vec<RGB8>>>,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl FakePixel {
+        fn written(&self) -> Vec<RGB8> {
+            self.written.lock().unwrap().clone()
+        }
+    }
+
+    impl PixelSink for FakePixel {
+        fn set_pixel(&mut self, rgb: RGB8) -> Result<()> {
+            match self.failing.load(Ordering::Relaxed) {
+                true => anyhow::bail!("no pixel"),
+                false => {
+                    self.written.lock().unwrap().push(rgb);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    const OFF: RGB8 = RGB8::new(0, 0, 0);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn solid_is_written_once() {
+        let pixel = FakePixel::default();
+        let mut led = StatusLed::new(pixel.clone());
+        for t in 0..10 {
+            led.update(ms(t * 100));
+        }
+        assert_eq!(pixel.written(), [indication(SystemState::Booting).0]);
+    }
+
+    #[test]
+    fn blink_is_on_for_the_first_half_of_each_period() {
+        let color = RGB8::new(0, 0, 50);
+        let pattern = Pattern::Blink(ms(500));
+        assert_eq!(color_at(color, pattern, ms(0)), color);
+        assert_eq!(color_at(color, pattern, ms(249)), color);
+        assert_eq!(color_at(color, pattern, ms(250)), OFF);
+        assert_eq!(color_at(color, pattern, ms(499)), OFF);
+        assert_eq!(color_at(color, pattern, ms(500)), color);
+        assert_eq!(
+            color_at(color, Pattern::Blink(Duration::ZERO), ms(7)),
+            color
+        );
+    }
+
+    #[test]
+    fn breathe_fades_in_and_out() {
+        let color = RGB8::new(0, 50, 0);
+        let pattern = Pattern::Breathe(Duration::from_secs(4));
+        assert_eq!(color_at(color, pattern, ms(0)), OFF);
+        assert_eq!(color_at(color, pattern, ms(1000)), RGB8::new(0, 25, 0));
+        assert_eq!(color_at(color, pattern, ms(2000)), color);
+        assert_eq!(color_at(color, pattern, ms(3000)), RGB8::new(0, 25, 0));
+        assert_eq!(color_at(color, pattern, ms(4000)), OFF);
+    }
+
+    #[test]
+    fn state_change_restarts_the_pattern() {
+        let pixel = FakePixel::default();
+        let mut led = StatusLed::new(pixel.clone());
+        led.set_state(SystemState::WifiConnecting, ms(100));
+        // Blinking at 500 ms from 100 ms: on, off, on
+        for t in [100, 200, 350, 450, 600] {
+            led.update(ms(t));
+        }
+        let blue = indication(SystemState::WifiConnecting).0;
+        assert_eq!(pixel.written(), [blue, OFF, blue]);
+
+        // Setting the same state again does not restart it
+        led.set_state(SystemState::WifiConnecting, ms(800));
+        led.update(ms(850));
+        assert_eq!(pixel.written().last(), Some(&OFF));
+        assert_eq!(led.state(), SystemState::WifiConnecting);
+    }
+
+    #[test]
+    fn failed_writes_are_retried() {
+        let pixel = FakePixel::default();
+        pixel.failing.store(true, Ordering::Relaxed);
+        let mut led = StatusLed::new(pixel.clone());
+        led.update(ms(0));
+        assert!(pixel.written().is_empty());
+
+        pixel.failing.store(false, Ordering::Relaxed);
+        led.update(ms(10));
+        led.update(ms(20));
+        assert_eq!(pixel.written(), [indication(SystemState::Booting).0]);
+    }
+
+    #[test]
+    fn hold_shows_the_full_color() {
+        let pixel = FakePixel::default();
+        let mut led = StatusLed::new(pixel.clone());
+        led.set_state(SystemState::Connected, ms(0));
+        led.update(ms(0));
+        led.hold();
+        assert_eq!(pixel.written(), [OFF, indication(SystemState::Connected).0]);
+    }
+
+    #[test]
+    fn finish_shows_the_state_before_returning() {
+        let pixel = FakePixel::default();
+        let status = StatusIndicator::spawn(pixel.clone(), ms(5)).unwrap();
+        status.set(SystemState::WifiConnecting);
+        status.finish(SystemState::Error);
+        assert_eq!(
+            pixel.written().last(),
+            Some(&indication(SystemState::Error).0)
+        );
+    }
+
+    #[test]
+    fn dropped_indicator_shows_its_last_state() {
+        let pixel = FakePixel::default();
+        // A long frame, so the handle is dropped before the thread sees the new state
+        let status = StatusIndicator::spawn(pixel.clone(), ms(50)).unwrap();
+        let states = status.attach();
+        status.set(SystemState::OtaInProgress);
+        assert_eq!(states.get(), SystemState::OtaInProgress);
+        drop(status);
+        let red = indication(SystemState::OtaInProgress).0;
+        for _ in 0..100 {
+            if pixel.written().last() == Some(&red) {
+                return;
+            }
+            std::thread::sleep(ms(10));
+        }
+        panic!("last state not shown: {:?}", pixel.written());
+    }
+}
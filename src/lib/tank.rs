@@ -0,0 +1,370 @@
+//! Tank level and volume from a level sender
+use crate::config::{self, ConfigStore};
+use crate::curve::{Curve, ParseCurveError};
+use crate::sensor::{poll_update, SensESPSensor, Updates};
+use crate::signalk::{SignalKOutput, Update};
+use eyeball::{shared::Observable, Subscriber};
+use std::fmt;
+use std::str::FromStr;
+use std::task::Poll;
+
+/// The tank groups Signal K knows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TankType {
+    Fuel,
+    FreshWater,
+    WasteWater,
+    BlackWater,
+    LiveWell,
+    Lubrication,
+    Ballast,
+    Gas,
+}
+
+impl TankType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TankType::Fuel => "fuel",
+            TankType::FreshWater => "freshWater",
+            TankType::WasteWater => "wasteWater",
+            TankType::BlackWater => "blackWater",
+            TankType::LiveWell => "liveWell",
+            TankType::Lubrication => "lubrication",
+            TankType::Ballast => "ballast",
+            TankType::Gas => "gas",
+        }
+    }
+}
+
+/// How a tank's sender readings relate to its contents.
+///
+/// The curve maps the raw reading, ohms or volts, to the fraction of the tank that is full,
+/// which takes care of the tank's shape when the points are taken at known volumes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TankCalibration {
+    /// Volume when full, in m³
+    pub capacity: f32,
+    pub curve: Curve,
+}
+
+impl TankCalibration {
+    pub fn new(capacity: f32) -> Self {
+        TankCalibration {
+            capacity,
+            curve: Curve::new(),
+        }
+    }
+
+    /// Add a point where the tank is `level` full, 0 to 1, at a `raw` reading.
+    pub fn point(mut self, raw: f32, level: f32) -> Self {
+        self.curve = self.curve.point(raw, level);
+        self
+    }
+
+    /// Fraction full and volume in m³ for a raw reading.
+    pub fn apply(&self, raw: f32) -> (f32, f32) {
+        let level = self.curve.apply(raw).clamp(0.0, 1.0);
+        (level, level * self.capacity)
+    }
+}
+
+/// Written as the capacity and the curve separated by a semicolon, e.g. `0.2;240:0,33:1`, so
+/// a calibration can be kept in a [`ConfigStore`](crate::config::ConfigStore).
+impl fmt::Display for TankCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{};{}", self.capacity, self.curve)
+    }
+}
+
+impl FromStr for TankCalibration {
+    type Err = ParseCurveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (capacity, curve) = s.split_once(';').ok_or(ParseCurveError)?;
+        Ok(TankCalibration {
+            capacity: capacity.trim().parse().map_err(|_| ParseCurveError)?,
+            curve: curve.parse()?,
+        })
+    }
+}
+
+/// Publishes a tank's `currentLevel`, `currentVolume` and `capacity` under
+/// `tanks.<type>.<id>`.
+pub struct TankLevel {
+    subscriber: Updates<f32>,
+    calibration: TankCalibration,
+    path: String,
+    output: SignalKOutput,
+    level: Observable<f32>,
+    volume: Observable<f32>,
+    /// Capacity is published with the first reading after a calibration change
+    capacity_sent: bool,
+}
+
+impl TankLevel {
+    /// `subscriber` gives the sender's raw readings.
+    pub fn new(
+        output: &SignalKOutput,
+        tank_type: TankType,
+        id: &str,
+        subscriber: Subscriber<f32>,
+        calibration: TankCalibration,
+    ) -> Self {
+        let (level, volume) = calibration.apply(subscriber.get());
+        TankLevel {
+            subscriber: subscriber.into(),
+            calibration,
+            path: format!("tanks.{}.{}", tank_type.as_str(), id),
+            output: output.clone(),
+            level: Observable::new(level),
+            volume: Observable::new(volume),
+            capacity_sent: false,
+        }
+    }
+
+    pub fn calibration(&self) -> &TankCalibration {
+        &self.calibration
+    }
+
+    /// Use a new calibration from the next reading on.
+    pub fn set_calibration(&mut self, calibration: TankCalibration) {
+        self.calibration = calibration;
+        self.capacity_sent = false;
+    }
+
+    /// Use the calibration saved under `key`, keeping the one given to `new` if there is none.
+    pub fn load_calibration<S: ConfigStore>(mut self, store: &S, key: &str) -> Self {
+        if let Some(calibration) = config::load(store, key) {
+            self.set_calibration(calibration);
+        }
+        self
+    }
+
+    /// Use a new calibration from the next reading on and save it under `key`.
+    pub fn save_calibration<S: ConfigStore>(
+        &mut self,
+        calibration: TankCalibration,
+        store: &S,
+        key: &str,
+    ) -> Result<(), S::Error> {
+        self.set_calibration(calibration);
+        config::save(store, key, &self.calibration)
+    }
+
+    /// Subscribe to the fraction full, 0 to 1.
+    pub fn attach_level(&mut self) -> Subscriber<f32> {
+        self.level.subscribe()
+    }
+
+    /// Subscribe to the volume in m³.
+    pub fn attach_volume(&mut self) -> Subscriber<f32> {
+        self.volume.subscribe()
+    }
+
+    /// Convert one raw reading and publish the results.
+    pub fn update(&mut self, raw: f32) {
+        let (level, volume) = self.calibration.apply(raw);
+        self.level.set(level);
+        self.volume.set(volume);
+        self.output
+            .publish(Update::new(format!("{}.currentLevel", self.path), level));
+        self.output
+            .publish(Update::new(format!("{}.currentVolume", self.path), volume));
+        if !self.capacity_sent {
+            self.output.publish(Update::new(
+                format!("{}.capacity", self.path),
+                self.calibration.capacity,
+            ));
+            self.capacity_sent = true;
+        }
+    }
+}
+
+impl SensESPSensor for TankLevel {
+    fn tick(&mut self) {
+        if let Poll::Ready(Some(raw)) = poll_update(&mut self.subscriber) {
+            self.update(raw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MemoryStore;
+    use crate::signalk::Value;
+    use crate::testing::assert_close;
+
+    /// A 200 l tank whose sender reads 240 ohms empty and 33 ohms full, with a narrow top.
+    fn calibration() -> TankCalibration {
+        TankCalibration::new(0.2)
+            .point(240.0, 0.0)
+            .point(120.0, 0.6)
+            .point(33.0, 1.0)
+    }
+
+    fn numbers(output: &SignalKOutput) -> Vec<(String, f64)> {
+        output
+            .take_pending()
+            .into_iter()
+            .map(|u| match u.value {
+                Value::Number(n) => (u.path, n),
+                other => panic!("{} is {:?}", u.path, other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calibration_interpolates_level_and_volume() {
+        let calibration = calibration();
+        let (level, volume) = calibration.apply(180.0);
+        assert_close(level, 0.3, 1e-5);
+        assert_close(volume, 0.06, 1e-5);
+        let (level, volume) = calibration.apply(76.5);
+        assert_close(level, 0.8, 1e-5);
+        assert_close(volume, 0.16, 1e-5);
+    }
+
+    #[test]
+    fn calibration_clamps_to_empty_and_full() {
+        let calibration = calibration();
+        assert_eq!(calibration.apply(300.0), (0.0, 0.0));
+        assert_eq!(calibration.apply(10.0), (1.0, 0.2));
+        // A curve overshooting its end stops is still clamped
+        let overshoot = TankCalibration::new(0.1).point(0.0, -0.1).point(5.0, 1.2);
+        assert_eq!(overshoot.apply(0.0), (0.0, 0.0));
+        assert_eq!(overshoot.apply(5.0), (1.0, 0.1));
+    }
+
+    #[test]
+    fn calibration_round_trips_through_text() {
+        let text = calibration().to_string();
+        assert_eq!(text, "0.2;33:1,120:0.6,240:0");
+        assert_eq!(text.parse(), Ok(calibration()));
+        assert_eq!(
+            " 0.2 ; 240:0, 33:1 ".parse(),
+            Ok(TankCalibration::new(0.2).point(240.0, 0.0).point(33.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn calibration_rejects_malformed_text() {
+        for text in [
+            "",
+            "0.2",
+            "x;240:0",
+            "0.2;240",
+            "0.2;240:x",
+            "0.2;240:0;33:1",
+        ] {
+            assert_eq!(
+                text.parse::<TankCalibration>(),
+                Err(ParseCurveError),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn tank_publishes_level_volume_and_capacity_once() {
+        let output = SignalKOutput::new();
+        let sender = Observable::new(240.0);
+        let mut tank = TankLevel::new(
+            &output,
+            TankType::FreshWater,
+            "0",
+            sender.subscribe(),
+            calibration(),
+        );
+        let level = tank.attach_level();
+        assert_eq!(level.get(), 0.0);
+
+        sender.set(180.0);
+        tank.tick();
+        let published = numbers(&output);
+        let paths: Vec<&str> = published.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "tanks.freshWater.0.currentLevel",
+                "tanks.freshWater.0.currentVolume",
+                "tanks.freshWater.0.capacity",
+            ]
+        );
+        assert_close(published[0].1 as f32, 0.3, 1e-5);
+        assert_close(published[1].1 as f32, 0.06, 1e-5);
+        assert_close(published[2].1 as f32, 0.2, 1e-5);
+        assert_close(level.get(), 0.3, 1e-5);
+
+        sender.set(120.0);
+        tank.tick();
+        assert_eq!(numbers(&output).len(), 2);
+
+        // A new calibration sends the capacity again
+        tank.set_calibration(TankCalibration::new(0.3).point(240.0, 0.0).point(33.0, 1.0));
+        tank.update(33.0);
+        assert_eq!(
+            numbers(&output),
+            vec![
+                ("tanks.freshWater.0.currentLevel".to_string(), 1.0),
+                (
+                    "tanks.freshWater.0.currentVolume".to_string(),
+                    0.3f32 as f64
+                ),
+                ("tanks.freshWater.0.capacity".to_string(), 0.3f32 as f64),
+            ]
+        );
+    }
+
+    #[test]
+    fn calibration_is_saved_and_loaded() {
+        let output = SignalKOutput::new();
+        let store = MemoryStore::new();
+        let sender = Observable::new(240.0);
+        let mut tank = TankLevel::new(
+            &output,
+            TankType::Fuel,
+            "main",
+            sender.subscribe(),
+            TankCalibration::new(0.1),
+        )
+        .load_calibration(&store, "tank.main");
+        // Nothing saved yet, the given calibration is kept
+        assert_eq!(tank.calibration(), &TankCalibration::new(0.1));
+
+        tank.save_calibration(calibration(), &store, "tank.main")
+            .unwrap();
+        assert_eq!(tank.calibration(), &calibration());
+        assert_eq!(
+            store.get("tank.main"),
+            Ok(Some("0.2;33:1,120:0.6,240:0".to_string()))
+        );
+
+        let restarted = TankLevel::new(
+            &output,
+            TankType::Fuel,
+            "main",
+            sender.subscribe(),
+            TankCalibration::new(0.1),
+        )
+        .load_calibration(&store, "tank.main");
+        assert_eq!(restarted.calibration(), &calibration());
+    }
+
+    #[test]
+    fn invalid_saved_calibration_is_ignored() {
+        let store = MemoryStore::new();
+        store.set("tank.main", "0.2;240").unwrap();
+        let sender = Observable::new(240.0);
+        let tank = TankLevel::new(
+            &SignalKOutput::new(),
+            TankType::Fuel,
+            "main",
+            sender.subscribe(),
+            calibration(),
+        )
+        .load_calibration(&store, "tank.main");
+        assert_eq!(tank.calibration(), &calibration());
+    }
+}
@@ -6,10 +6,13 @@ use std::task::{ContextBuilder, LocalWaker};
 use std::time::Duration;
 
 use anyhow::Result;
+use esp_idf_hal::adc::oneshot::{AdcChannelDriver, AdcDriver};
 use esp_idf_hal::gpio::PinDriver;
 use esp_idf_hal::prelude::Peripherals;
+use sensesp::analog::{channel_config, AnalogInput, Attenuation};
 use sensesp::application::Application;
-use sensesp::sensor::{Attachable, ConstantSensor, TimedSensor};
+use sensesp::digital::{DigitalInputState, InterruptInput};
+use sensesp::sensor::{Attachable, ConstantSensor};
 use smol::stream::StreamExt;
 use toml_cfg::toml_config;
 
@@ -38,21 +41,27 @@ fn main() -> Result<()> {
     let mut constant_sensor = ConstantSensor::new(42, Duration::from_secs(2));
     let mut constant_subscriber = constant_sensor.attach();
 
-    let digital_input = PinDriver::input(peripherals.pins.gpio18)?;
+    let digital_input = InterruptInput::new(PinDriver::input(peripherals.pins.gpio18)?)?;
 
-    //bool sensor maps from Level enums
-    let mut digital_sensor = TimedSensor::new(
-        move || match digital_input.get_level() {
-            esp_idf_hal::gpio::Level::Low => true,
-            esp_idf_hal::gpio::Level::High => false,
-        },
-        Duration::from_millis(500),
-    );
+    //float switch pulls the pin low when active
+    let mut digital_sensor =
+        DigitalInputState::new(digital_input, true, Some(Duration::from_secs(10)));
 
     let mut digital_subscriber = digital_sensor.attach();
+
+    let adc = AdcDriver::new(peripherals.adc1)?;
+    let analog_channel = AdcChannelDriver::new(
+        adc,
+        peripherals.pins.gpio36,
+        &channel_config(Attenuation::DB11),
+    )?;
+    let mut analog_sensor = AnalogInput::new(analog_channel, 16, Duration::from_secs(1));
+    let mut analog_subscriber = analog_sensor.attach();
+
     let mut app = Application::new()
         .register(constant_sensor)
-        .register(digital_sensor);
+        .register(digital_sensor)
+        .register(analog_sensor);
 
     let _handle = std::thread::spawn(move || {
         let local_waker = LocalWaker::noop();
@@ -79,6 +88,14 @@ fn main() -> Result<()> {
                 Pending => (),
             }
 
+            match analog_subscriber.poll_next(&mut cx) {
+                Ready(val) => match val {
+                    Some(mv) => log::info!("New analog value found: {} mV", mv),
+                    None => log::warn!("No new analog value found."),
+                },
+                Pending => (),
+            }
+
             std::thread::sleep(Duration::from_millis(100));
         }
     });
@@ -0,0 +1,88 @@
+//! Fakes and helpers shared by the unit tests
+use crate::sensor::{Attachable, Updates};
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Fail unless `a` is within `tolerance` of `b`.
+pub fn assert_close(a: f32, b: f32, tolerance: f32) {
+    assert!((a - b).abs() < tolerance, "{} != {}", a, b);
+}
+
+/// Updates from a new subscriber to `sensor`.
+pub fn updates<T: Clone>(sensor: &mut impl Attachable<T>) -> Updates<T> {
+    Updates::new(sensor.attach())
+}
+
+/// Register contents by register address.
+pub type Registers = HashMap<u8, Vec<u8>>;
+
+#[derive(Default)]
+struct Devices {
+    registers: HashMap<u8, Registers>,
+    writes: Vec<(u8, Vec<u8>)>,
+}
+
+/// An I2C bus of register-mapped devices, shared between every handle.
+///
+/// A write sets the register pointer from its first byte and stores any further bytes in
+/// that register. Reads return the register's contents, padded with zeros. Devices without
+/// registers are not acknowledged.
+#[derive(Clone, Default)]
+pub struct FakeBus(Rc<RefCell<Devices>>);
+
+impl FakeBus {
+    pub fn with(self, address: u8, registers: &[(u8, &[u8])]) -> Self {
+        let registers = registers.iter().map(|(r, v)| (*r, v.to_vec())).collect();
+        self.0.borrow_mut().registers.insert(address, registers);
+        self
+    }
+
+    pub fn set(&self, address: u8, register: u8, value: &[u8]) {
+        let mut devices = self.0.borrow_mut();
+        let registers = devices.registers.entry(address).or_default();
+        registers.insert(register, value.to_vec());
+    }
+
+    /// Register writes since the last call, with their address.
+    pub fn take_writes(&self) -> Vec<(u8, Vec<u8>)> {
+        std::mem::take(&mut self.0.borrow_mut().writes)
+    }
+}
+
+impl ErrorType for FakeBus {
+    type Error = ErrorKind;
+}
+
+impl I2c for FakeBus {
+    fn transaction(&mut self, address: u8, ops: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+        let mut devices = self.0.borrow_mut();
+        let Devices { registers, writes } = &mut *devices;
+        let registers = match registers.get_mut(&address) {
+            Some(r) => r,
+            None => return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+        };
+        let mut register = 0;
+        for op in ops {
+            match op {
+                Operation::Write(bytes) => {
+                    if let Some((r, value)) = bytes.split_first() {
+                        register = *r;
+                        if !value.is_empty() {
+                            registers.insert(register, value.to_vec());
+                            writes.push((address, bytes.to_vec()));
+                        }
+                    }
+                }
+                Operation::Read(buf) => {
+                    let value = registers.get(&register).cloned().unwrap_or_default();
+                    for (i, b) in buf.iter_mut().enumerate() {
+                        *b = value.get(i).copied().unwrap_or(0);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}